@@ -0,0 +1,38 @@
+# Compares copying NIF output into a fresh binary with handing the Rust buffer
+# over as a resource binary, then times a 50MB encrypt/decrypt round trip.
+#
+# The comparison uses a test hook, so run it in the test environment:
+#
+#     MIX_ENV=test mix run bench/output_binary.exs
+
+size = 50 * 1024 * 1024
+runs = 10
+
+time_ms = fn fun ->
+  timings =
+    for _ <- 1..runs do
+      {us, _} = :timer.tc(fun)
+      us / 1000
+    end
+
+  Enum.sum(timings) / runs
+end
+
+copy_ms = time_ms.(fn -> ExTholosPq.output_binary(size, true) end)
+handover_ms = time_ms.(fn -> ExTholosPq.output_binary(size, false) end)
+
+IO.puts("50MB output, mean of #{runs} runs")
+IO.puts("  copy into new binary:   #{Float.round(copy_ms, 2)} ms")
+IO.puts("  resource binary:        #{Float.round(handover_ms, 2)} ms")
+
+{:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("bench_recipient")
+{:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("bench_sender")
+message = :crypto.strong_rand_bytes(size)
+{:ok, ciphertext} = ExTholosPq.encrypt(message, sid, [recipient_pub])
+
+encrypt_ms = time_ms.(fn -> ExTholosPq.encrypt(message, sid, [recipient_pub]) end)
+decrypt_ms = time_ms.(fn -> ExTholosPq.decrypt(ciphertext, kid, [sender_pub]) end)
+
+IO.puts("50MB round trip, mean of #{runs} runs")
+IO.puts("  encrypt/3:              #{Float.round(encrypt_ms, 2)} ms")
+IO.puts("  decrypt/3:              #{Float.round(decrypt_ms, 2)} ms")
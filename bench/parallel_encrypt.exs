@@ -0,0 +1,37 @@
+# Times encrypting one message for growing recipient sets. Run it once with
+# the NIF built with the `parallel` feature and once without to compare the
+# serial and parallel encapsulation paths:
+#
+#     MIX_ENV=test mix run bench/parallel_encrypt.exs
+#
+# The test environment enables `parallel`; drop it from config/test.exs for
+# the serial numbers.
+
+runs = 10
+message = :crypto.strong_rand_bytes(1024)
+{:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("bench_sender")
+
+recipient_pubs =
+  for i <- 1..200 do
+    {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("bench_recipient_#{i}")
+    pub
+  end
+
+time_ms = fn fun ->
+  timings =
+    for _ <- 1..runs do
+      {us, _} = :timer.tc(fun)
+      us / 1000
+    end
+
+  Enum.sum(timings) / runs
+end
+
+IO.puts("1KB message by number of recipients, mean of #{runs} runs")
+
+for count <- [1, 8, 50, 200] do
+  recipients = Enum.take(recipient_pubs, count)
+  encrypt_ms = time_ms.(fn -> ExTholosPq.encrypt(message, sid, recipients) end)
+  label = String.pad_trailing("  #{count}:", 26)
+  IO.puts("#{label}#{Float.round(encrypt_ms, 2)} ms")
+end
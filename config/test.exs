@@ -0,0 +1,5 @@
+import Config
+
+# Build the NIF with the test-only hooks used by the ExUnit suite, and with
+# every optional feature so the suite covers it
+config :ex_tholos_pq, ExTholosPq, features: ["test-hooks", "test-vectors", "zstd", "parallel"]
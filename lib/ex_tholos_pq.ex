@@ -11,7 +11,39 @@ defmodule ExTholosPq do
   - **Key Encapsulation:** ML-KEM-1024 (Kyber-1024) for per-recipient key wrapping
   - **Symmetric Encryption:** XChaCha20-Poly1305 for payload encryption
   - **Digital Signatures:** Dilithium-3 for sender authentication
-  - **Wire Format:** Canonical CBOR with versioning
+  - **Wire Format:** A format version byte followed by canonical CBOR, see `wire_version/1`
+
+  ## Security Levels
+
+  The algorithms above are the defaults. The keygen functions take a
+  `security_level: :level1 | :level3 | :level5` option that picks the
+  parameter set of the key by NIST category instead:
+
+  | level     | recipient key | sender key  |
+  |-----------|---------------|-------------|
+  | `:level1` | ML-KEM-512    | Dilithium-2 |
+  | `:level3` | ML-KEM-768    | Dilithium-3 |
+  | `:level5` | ML-KEM-1024   | Dilithium-5 |
+
+  Recipient keys default to `:level5` and sender keys to `:level3`. The level
+  is carried by the key itself, since each parameter set has its own key size,
+  so the other functions pick the matching algorithm on their own. Decrypting
+  with a key of another level than the ciphertext was made for fails with
+  `:security_level_mismatch`. All recipient keys of one encryption must be of
+  the same level, or it fails with `{:param_mismatch, expected, got}` before
+  anything is encrypted. Only ciphertexts between keys of the default
+  levels can be read by other tholos-pq implementations.
+
+  ## Hybrid Keys
+
+  Recipient keys generated with `hybrid: true` carry an X25519 key next to
+  their ML-KEM key. Encrypting with `hybrid: true` then encapsulates to both
+  and derives each envelope's key from both shared secrets, so the message
+  stays confidential unless ML-KEM and X25519 are broken alike. The header
+  records that the ciphertext is hybrid, and decrypting it with a key that
+  has no X25519 half fails with `:hybrid_mismatch`. Hybrid keys are of any
+  security level and work for plain encryptions too. Hybrid ciphertexts
+  cannot be read by other tholos-pq implementations.
 
   ## Installation
 
@@ -47,16 +79,119 @@ defmodule ExTholosPq do
   {:ok, plaintext_a} = ExTholosPq.decrypt(ciphertext, "Alice", priv_a, [])
   {:ok, plaintext_b} = ExTholosPq.decrypt(ciphertext, "Bob", priv_b, [])
   ```
+
+  ## Scheduling
+
+  All cryptographic NIFs run on dirty CPU schedulers, except `encrypt_file/4`
+  and `decrypt_file/4`, which spend most of their time on disk and run on dirty
+  IO schedulers. Encryption performs one Kyber
+  encapsulation per recipient and a Dilithium signature per message, which
+  easily exceeds the time budget of a normal scheduler, so calls with large
+  payloads or many recipients do not stall other processes on the node.
+
+  With the `parallel` cargo feature, `encrypt/3,4`, `encrypt_with_keys/3` and
+  `encrypt_batch/3` spread the encapsulations for 8 or more recipients over a
+  rayon thread pool sized to the number of cores. The content key, payload
+  encryption and signature stay on the calling thread, and recipients keep
+  their order in the ciphertext. Enable it in the application config:
+
+      config :ex_tholos_pq, ExTholosPq, features: ["parallel"]
+
+  ## Errors
+
+  Failures are returned as `{:error, {kind, detail}}`, where `kind` is an atom
+  to match on and `detail` is a human-readable string for logging:
+
+    * `:sender_not_found` / `:recipient_not_found` - no key is stored under the
+      given id; `detail` is the id
+    * `:deserialize_recipient` - a recipient public key is not valid CBOR
+    * `:deserialize_sender` - a sender public key or keypair is not valid CBOR
+    * `:invalid_private_key` - a private key or keypair cannot be deserialized
+    * `:serialization_failed` - a key could not be encoded
+    * `:encrypt_failed` - encryption failed
+    * `:malformed_wire` - the ciphertext cannot be parsed, or declares more
+      bytes than it holds or more recipients than allowed
+    * `:security_level_mismatch` - the ciphertext was made for, or signed by, a
+      key of another security level than the one given
+    * `:hybrid_mismatch` - the ciphertext is hybrid but the recipient key is
+      not, see the Hybrid Keys section
+    * `:kid_exists` / `:sid_exists` - keygen found a key already stored under
+      the given id; `detail` is the id
+    * `:too_many_recipients` - more recipients than allowed; `detail` is the count
+    * `:invalid_identifier` - a kid or sid, given directly or in a public key,
+      is empty, longer than 256 bytes or contains a NUL byte; `detail` says
+      which. Keygen, key loading and the encrypt and decrypt functions all
+      check it, since every kid and sid is written into each ciphertext. A new
+      recipient kid may not end in `#v<digits>` either, the suffix
+      `rotate_recipient_key/1` names later versions with
+    * `:unsupported_wire_version` - the ciphertext uses a wire format version
+      this build does not understand; `detail` is the version
+    * `:invalid_option` - an unknown option, or a value it does not accept, was
+      passed; `detail` is the option name
+    * `:io` - a file could not be read or written; `detail` names the path and
+      the OS error
+    * `:allocation_failed` - the VM could not allocate a result binary;
+      `detail` is the size requested in bytes
+
+  The decrypt functions tell forgery, tampering and misdelivery apart with
+  three errors that carry no detail:
+
+    * `{:error, :signature_invalid}` - the Dilithium signature does not
+      verify: the ciphertext was forged or altered, or comes from a sender
+      outside the allow-list. The signature is checked before anything else
+      and covers every byte of the ciphertext that decryption uses, so an
+      altered ciphertext fails here first
+    * `{:error, :auth_tag_mismatch}` - the AEAD tag does not verify under the
+      content key: a frame of a stream was altered, dropped or reordered, or
+      an authentic ciphertext meets a key stored under its kid since it was
+      encrypted
+    * `{:error, :no_envelope_for_recipient}` - the ciphertext is authentic but
+      was not encrypted for this kid
+
+  Recipient keys of different security levels in one encryption are reported
+  as `{:error, {:param_mismatch, expected, got}}`, where `expected` is the
+  level of the first recipient key and `got` that of the first one that
+  differs, see the Security Levels section.
+
+  A few failures carry no detail and are returned as a bare atom, e.g.
+  `{:error, :not_found}` from the delete functions, `{:error, :aad_mismatch}`,
+  `{:error, :bad_password}`, `{:error, :bad_psk}`, `{:error, :corrupt_store}`,
+  `{:error, :expired}`, `{:error, :now_required}` for a ciphertext with a
+  time to live decrypted without `:now`, see `decrypt/4`,
+  `{:error, :key_mismatch}` for key halves that do not belong together, see
+  `load_recipient_keypair/3`, `{:error, :key_commitment_failed}` for a
+  ciphertext whose content key does not match its key commitment, see
+  `encrypt/4`, `{:error, :no_recipients}` from the encrypt functions for an
+  empty recipient list, and `{:error, :invalid_recipient_key}` for a
+  recipient public key that decodes but is no valid ML-KEM key, see
+  `validate_recipient_pub/1`.
+
+  Should a cryptographic primitive panic, the encrypt, decrypt, streaming,
+  file, recipient editing, `validate_wire/2`, `sign/2` and `verify/3`
+  functions return `{:error, :internal_crypto_panic}` instead of raising, so
+  a malformed input fails only its own call. A stream that panics is closed.
   """
 
   use Rustler,
     otp_app: :ex_tholos_pq,
     crate: :ex_tholos_pq_nif
 
+  @typedoc "A failure reason, see the Errors section of the module documentation."
+  @type error() ::
+          {atom(), String.t() | non_neg_integer()}
+          | {:param_mismatch, security_level(), security_level()}
+          | atom()
+
+  @typedoc "A security level, see the Security Levels section of the module documentation."
+  @type security_level() :: :level1 | :level3 | :level5
+
   @doc """
   Generates a new recipient keypair for post-quantum encryption.
 
   The keypair is stored internally in the NIF and referenced by the key identifier.
+  A keypair already stored under `kid` is never replaced, since it may be the
+  only key that opens messages still in flight; pass `overwrite: true` to
+  `gen_recipient_keypair/2` to replace it on purpose.
 
   ## Parameters
 
@@ -65,6 +200,9 @@ defmodule ExTholosPq do
   ## Returns
 
     * `{:ok, {kid, public_key}}` on success where public_key is CBOR-encoded
+    * `{:error, {:kid_exists, kid}}` if a keypair is already stored under `kid`
+    * `{:error, {:invalid_identifier, reason}}` if `kid` is empty, longer than
+      256 bytes, contains a NUL byte or ends in `#v<digits>`
     * `{:error, reason}` on failure
 
   ## Examples
@@ -74,13 +212,358 @@ defmodule ExTholosPq do
       true
 
   """
-  @spec gen_recipient_keypair(String.t()) :: {:ok, {String.t(), binary()}} | {:error, String.t()}
+  @spec gen_recipient_keypair(String.t()) :: {:ok, {String.t(), binary()}} | {:error, error()}
   def gen_recipient_keypair(_kid), do: :erlang.nif_error(:nif_not_loaded)
 
+  @doc """
+  Generates a new recipient keypair with options.
+
+  Works like `gen_recipient_keypair/1`.
+
+  ## Options
+
+    * `:security_level` - `:level1` (ML-KEM-512), `:level3` (ML-KEM-768) or
+      `:level5` (ML-KEM-1024, the default); see the Security Levels section of
+      the module documentation. The same option is taken by the other keygen
+      functions, where for sender keys it picks the Dilithium parameter set.
+    * `:format` - `:cbor` (default) or `:json`, the serialization of the
+      returned public key. JSON is for clients that cannot parse CBOR, as
+      `{"kid": ..., "pk_kyber": ...}` with the key in unpadded base64url; the
+      key still has to be converted with `recipient_pub_from_json/1` before it
+      is passed to `encrypt/3`. Taken by the other recipient keygen functions
+      as well, but not for sender keys.
+    * `:hybrid` - When `true`, the key gets an X25519 half as well (default
+      `false`), see the Hybrid Keys section of the module documentation. Its
+      public key is 32 bytes longer. Taken by the other recipient keygen
+      functions as well, but not for sender keys.
+    * `:overwrite` - When `true`, replaces a keypair already stored under the
+      same identifier instead of failing with `{:error, {:kid_exists, kid}}`
+      (default `false`). Taken by every keygen function that stores its key,
+      and rejected by those that return a key reference.
+    * `:ttl_seconds` - How long the stored keypair lives, for ephemeral
+      per-session keys. It expires that many seconds after it is generated
+      and `sweep_expired/1` then removes it; until a sweep does, it keeps
+      working. Without it a keypair never expires. Taken and rejected like
+      `:overwrite`.
+
+  ## Returns
+
+    * `{:ok, {kid, public_key}}` on success where public_key is CBOR- or JSON-encoded
+    * `{:error, {:invalid_option, name}}` for an unknown option or an unsupported value
+    * `{:error, reason}` on other failures
+
+  ## Examples
+
+      iex> {:ok, {kid, pub_l1}} = ExTholosPq.gen_recipient_keypair("level1-doc", security_level: :level1)
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("level1-doc-sender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("secret", sid, [pub_l1])
+      iex> ExTholosPq.decrypt(ct, kid, [sender_pub])
+      {:ok, "secret"}
+
+  """
+  @spec gen_recipient_keypair(String.t(), keyword()) ::
+          {:ok, {String.t(), binary()}} | {:error, error()}
+  def gen_recipient_keypair(_kid, _opts), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Generates and stores a recipient keypair for every kid in one call.
+
+  Meant for provisioning many keys at once: the key store is locked once for
+  the whole batch instead of once per key, and with the `parallel` feature
+  large batches are generated on a thread pool. As with
+  `gen_recipient_keypair/1`, a kid that is already stored is not replaced: it
+  fails on its own, as does a kid given twice, without failing the batch.
+
+  ## Parameters
+
+    * `kids` - Key identifier strings for the recipients
+
+  ## Returns
+
+    * `{:ok, results}` where each result, in the order of `kids`, is
+      `{:ok, {kid, public_key}}`, `{:error, {:kid_exists, kid}}` or
+      `{:error, {:invalid_identifier, reason}}`
+
+  ## Examples
+
+      iex> {:ok, [{:ok, {"Bulk1", _}}, {:ok, {"Bulk2", _}}, duplicate]} =
+      ...>   ExTholosPq.gen_recipient_keypairs(["Bulk1", "Bulk2", "Bulk1"])
+      iex> duplicate
+      {:error, {:kid_exists, "Bulk1"}}
+
+  """
+  @spec gen_recipient_keypairs(list(String.t())) ::
+          {:ok,
+           list(
+             {:ok, {String.t(), binary()}}
+             | {:error, {:kid_exists | :invalid_identifier, String.t()}}
+           )}
+  def gen_recipient_keypairs(_kids), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Derives a recipient keypair from a 32-byte seed and stores it like `gen_recipient_keypair/1`.
+
+  The seed keys a ChaCha20 stream that replaces the OS random number generator
+  during key generation, so the same seed always yields the same keypair.
+  This is meant for reproducible test vectors and for keys derived from a
+  master secret. The seed is as sensitive as the private key itself.
+
+  There is no sender counterpart: the Dilithium implementation used for sender
+  keys takes its randomness from the OS and cannot be seeded.
+
+  ## Parameters
+
+    * `kid` - Key identifier string for the recipient
+    * `seed` - Exactly 32 bytes of secret seed material
+
+  ## Returns
+
+    * `{:ok, {kid, public_key}}` on success where public_key is CBOR-encoded
+    * `{:error, :bad_seed_length}` if `seed` is not 32 bytes long
+
+  ## Examples
+
+      iex> seed = :binary.copy(<<7>>, 32)
+      iex> {:ok, {"seeded", pub1}} = ExTholosPq.gen_recipient_keypair_from_seed("seeded", seed)
+      iex> {:ok, {"seeded", pub2}} =
+      ...>   ExTholosPq.gen_recipient_keypair_from_seed("seeded", seed, overwrite: true)
+      iex> pub1 == pub2
+      true
+      iex> ExTholosPq.gen_recipient_keypair_from_seed("seeded", "short")
+      {:error, :bad_seed_length}
+
+  """
+  @spec gen_recipient_keypair_from_seed(String.t(), binary()) ::
+          {:ok, {String.t(), binary()}} | {:error, :bad_seed_length | error()}
+  def gen_recipient_keypair_from_seed(_kid, _seed), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Derives a recipient keypair from a 32-byte seed with options.
+
+  Works like `gen_recipient_keypair_from_seed/2` and takes the options of
+  `gen_recipient_keypair/2`. The same seed gives unrelated keypairs at
+  different security levels.
+  """
+  @spec gen_recipient_keypair_from_seed(String.t(), binary(), keyword()) ::
+          {:ok, {String.t(), binary()}} | {:error, :bad_seed_length | error()}
+  def gen_recipient_keypair_from_seed(_kid, _seed, _opts),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Generates a new recipient keypair and returns the private key as well.
+
+  Like `gen_recipient_keypair/1`, the keypair is stored internally in the NIF.
+  The CBOR-encoded private key is also returned so it can be persisted outside
+  the node, e.g. in an encrypted vault, and used with `decrypt_with_key/4`.
+
+  ## Parameters
+
+    * `kid` - Key identifier string for the recipient
+
+  ## Returns
+
+    * `{:ok, {kid, public_key, private_key}}` on success where both keys are CBOR-encoded
+    * `{:error, reason}` on failure
+
+  ## Examples
+
+      iex> {:ok, {kid, pub_key, priv_key}} = ExTholosPq.gen_recipient_keypair_exported("recipient1")
+      iex> is_binary(kid) and is_binary(pub_key) and is_binary(priv_key)
+      true
+
+  """
+  @spec gen_recipient_keypair_exported(String.t()) ::
+          {:ok, {String.t(), binary(), binary()}} | {:error, error()}
+  def gen_recipient_keypair_exported(_kid), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Generates a new recipient keypair with options and returns the private key as well.
+
+  Works like `gen_recipient_keypair_exported/1` and takes the options of
+  `gen_recipient_keypair/2`.
+  """
+  @spec gen_recipient_keypair_exported(String.t(), keyword()) ::
+          {:ok, {String.t(), binary(), binary()}} | {:error, error()}
+  def gen_recipient_keypair_exported(_kid, _opts), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Converts a CBOR-encoded recipient public key to its JSON form.
+
+  The JSON is an object with the `"kid"` and the ML-KEM public key as unpadded
+  base64url in `"pk_kyber"`, the same as `gen_recipient_keypair/2` returns with
+  `format: :json`.
+
+  ## Returns
+
+    * `{:ok, json}` on success
+    * `{:error, {:deserialize_recipient, detail}}` if `pub_key` is not a recipient public key
+
+  ## Examples
+
+      iex> {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("json-doc")
+      iex> {:ok, json} = ExTholosPq.recipient_pub_to_json(pub)
+      iex> ExTholosPq.recipient_pub_from_json(json) == {:ok, pub}
+      true
+
+  """
+  @spec recipient_pub_to_json(binary()) :: {:ok, String.t()} | {:error, error()}
+  def recipient_pub_to_json(_pub_key), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Converts the JSON form of a recipient public key back to the CBOR encoding
+  taken by `encrypt/3` and the other encrypt functions.
+
+  ## Returns
+
+    * `{:ok, pub_key}` on success
+    * `{:error, {:deserialize_recipient, detail}}` for invalid JSON
+
+  """
+  @spec recipient_pub_from_json(String.t()) :: {:ok, binary()} | {:error, error()}
+  def recipient_pub_from_json(_json), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Converts a CBOR-encoded recipient public key to its raw form, for storing
+  keys without CBOR overhead, e.g. in a fixed-size database column.
+
+  The raw form is, with no framing around it:
+
+    * 2 bytes - the length `n` of the ML-KEM public key, big-endian
+    * `n` bytes - the ML-KEM public key, as in the CBOR form, so a hybrid key
+      keeps its X25519 part
+    * the rest - the kid as UTF-8
+
+  An ML-KEM-1024 key with a 5-byte kid takes 1575 bytes. As the kid runs to
+  the end of the input, a column wider than the key must keep its length.
+
+  ## Returns
+
+    * `{:ok, raw}` on success
+    * `{:error, {:deserialize_recipient, detail}}` if `pub_key` is not a recipient public key
+
+  ## Examples
+
+      iex> {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, <<1568::16, _pk::binary-size(1568), "Alice">> = raw} =
+      ...>   ExTholosPq.recipient_pub_to_raw(pub)
+      iex> ExTholosPq.recipient_pub_from_raw(raw) == {:ok, pub}
+      true
+
+  """
+  @spec recipient_pub_to_raw(binary()) :: {:ok, binary()} | {:error, error()}
+  def recipient_pub_to_raw(_pub_key), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Converts the raw form of a recipient public key from `recipient_pub_to_raw/1`
+  back to the CBOR encoding taken by `encrypt/3` and the other encrypt
+  functions.
+
+  ## Returns
+
+    * `{:ok, pub_key}` on success
+    * `{:error, {:deserialize_recipient, detail}}` if `raw` is shorter than the
+      length it gives or the kid is not UTF-8
+    * `{:error, {:invalid_identifier, reason}}` if the kid is not a valid one
+
+  """
+  @spec recipient_pub_from_raw(binary()) :: {:ok, binary()} | {:error, error()}
+  def recipient_pub_from_raw(_raw), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Re-encodes a CBOR recipient public key as canonical CBOR.
+
+  Canonical CBOR has definite lengths, shortest-form integers and map keys
+  sorted shortest first, then bytewise, so the same key always has the same
+  bytes, e.g. for storing it where it is compared byte for byte. Every keygen
+  function and `recipient_pub_from_json/1` already return this encoding; use
+  this for keys that came from elsewhere, whose map order or length encoding
+  may differ.
+
+  ## Returns
+
+    * `{:ok, pub_key}` with the canonical encoding
+    * `{:error, {:deserialize_recipient, detail}}` if `pub_key` is not a recipient public key
+
+  ## Examples
+
+      iex> {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("canonical-doc")
+      iex> ExTholosPq.recipient_pub_to_canonical_cbor(pub)
+      {:ok, pub}
+
+  """
+  @spec recipient_pub_to_canonical_cbor(binary()) :: {:ok, binary()} | {:error, error()}
+  def recipient_pub_to_canonical_cbor(_pub_key), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Checks that a recipient public key can be encrypted to.
+
+  The ML-KEM key must have the length of one of the security levels and pass
+  the FIPS 203 modulus check, i.e. every coefficient it encodes must be
+  reduced. Every encrypt function runs the same check on all recipients
+  before doing any KEM work and returns `{:error, :invalid_recipient_key}` if
+  one fails, except `encrypt_partial/3`, which skips the key and reports it
+  among the failed indices.
+
+  ## Returns
+
+    * `{:ok, :valid}` if the key can be used
+    * `{:error, :invalid_recipient_key}` if it cannot
+    * `{:error, {:deserialize_recipient, detail}}` if `pub_key` is not a recipient public key
+
+  ## Examples
+
+      iex> {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("validate-doc")
+      iex> ExTholosPq.validate_recipient_pub(pub)
+      {:ok, :valid}
+
+  """
+  @spec validate_recipient_pub(binary()) :: {:ok, :valid} | {:error, error()}
+  def validate_recipient_pub(_pub_key), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Returns a fingerprint of a recipient public key for comparing keys out of
+  band, e.g. reading it out over the phone.
+
+  The fingerprint is the SHA-256, in lowercase hex, of the kid and ML-KEM key
+  re-encoded canonically, so every CBOR encoding of the same key, whatever its
+  map order, gives the same fingerprint. It is domain-separated from
+  `sender_pub_fingerprint/1`.
+
+  ## Returns
+
+    * `{:ok, fingerprint}` with 64 hex characters
+    * `{:error, {:deserialize_recipient, detail}}` if `pub_key` is not a recipient public key
+
+  ## Examples
+
+      iex> {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("fingerprint-doc")
+      iex> {:ok, fingerprint} = ExTholosPq.recipient_pub_fingerprint(pub)
+      iex> String.length(fingerprint)
+      64
+
+  """
+  @spec recipient_pub_fingerprint(binary()) :: {:ok, String.t()} | {:error, error()}
+  def recipient_pub_fingerprint(_pub_key), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Returns a fingerprint of a sender public key, covering its sid and
+  Dilithium key; see `recipient_pub_fingerprint/1`.
+
+  ## Returns
+
+    * `{:ok, fingerprint}` with 64 hex characters
+    * `{:error, {:deserialize_sender, detail}}` if `pub_key` is not a sender public key
+
+  """
+  @spec sender_pub_fingerprint(binary()) :: {:ok, String.t()} | {:error, error()}
+  def sender_pub_fingerprint(_pub_key), do: :erlang.nif_error(:nif_not_loaded)
+
   @doc """
   Generates a new sender keypair for signing encrypted messages.
 
   The keypair is stored internally in the NIF and referenced by the sender identifier.
+  As with `gen_recipient_keypair/1`, a keypair already stored under `sid` is
+  only replaced with `overwrite: true`, see `gen_sender_keypair/2`.
 
   ## Parameters
 
@@ -89,6 +572,9 @@ defmodule ExTholosPq do
   ## Returns
 
     * `{:ok, {sid, public_key}}` on success where public_key is CBOR-encoded
+    * `{:error, {:sid_exists, sid}}` if a keypair is already stored under `sid`
+    * `{:error, {:invalid_identifier, reason}}` if `sid` is empty, longer than
+      256 bytes or contains a NUL byte
     * `{:error, reason}` on failure
 
   ## Examples
@@ -98,9 +584,149 @@ defmodule ExTholosPq do
       true
 
   """
-  @spec gen_sender_keypair(String.t()) :: {:ok, {String.t(), binary()}} | {:error, String.t()}
+  @spec gen_sender_keypair(String.t()) :: {:ok, {String.t(), binary()}} | {:error, error()}
   def gen_sender_keypair(_sid), do: :erlang.nif_error(:nif_not_loaded)
 
+  @doc """
+  Generates a new sender keypair with options.
+
+  Works like `gen_sender_keypair/1` and takes the options of
+  `gen_recipient_keypair/2`; `:security_level` picks Dilithium-2 (`:level1`),
+  Dilithium-3 (`:level3`, the default) or Dilithium-5 (`:level5`), and
+  `overwrite: true` replaces a keypair already stored under `sid`. Sender
+  public keys are always CBOR, so `:format` is rejected.
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("level5-doc")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("level5-doc-sender", security_level: :level5)
+      iex> {:ok, ct} = ExTholosPq.encrypt("secret", sid, [pub])
+      iex> ExTholosPq.decrypt(ct, kid, [sender_pub])
+      {:ok, "secret"}
+
+  """
+  @spec gen_sender_keypair(String.t(), keyword()) ::
+          {:ok, {String.t(), binary()}} | {:error, error()}
+  def gen_sender_keypair(_sid, _opts), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Generates a new sender keypair and returns its secret key as well.
+
+  Like `gen_sender_keypair/1`, the keypair is stored internally in the NIF.
+  The CBOR-encoded keypair, secret key included, is also returned, encoded as
+  by `export_sender_keypair/1`, so the sender can be persisted outside the
+  node and loaded on others with `load_sender_keypair/3` or used with
+  `encrypt_with_keys/3`. Treat it like any other secret key.
+
+  ## Parameters
+
+    * `sid` - Sender identifier string
+
+  ## Returns
+
+    * `{:ok, {sid, public_key, keypair}}` on success where both are CBOR-encoded
+    * `{:error, {:sid_exists, sid}}` if a keypair is already stored under `sid`
+    * `{:error, reason}` on failure
+
+  ## Examples
+
+      iex> {:ok, {sid, pub, keypair}} = ExTholosPq.gen_sender_keypair_exported("ExportedAtKeygen")
+      iex> :ok = ExTholosPq.delete_sender_key(sid)
+      iex> ExTholosPq.load_sender_keypair(sid, pub, keypair)
+      :ok
+
+  """
+  @spec gen_sender_keypair_exported(String.t()) ::
+          {:ok, {String.t(), binary(), binary()}} | {:error, error()}
+  def gen_sender_keypair_exported(_sid), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Generates a new sender keypair with options and returns its secret key as well.
+
+  Works like `gen_sender_keypair_exported/1` and takes the options of
+  `gen_sender_keypair/2`.
+  """
+  @spec gen_sender_keypair_exported(String.t(), keyword()) ::
+          {:ok, {String.t(), binary(), binary()}} | {:error, error()}
+  def gen_sender_keypair_exported(_sid, _opts), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Generates a new recipient keypair owned by a resource reference.
+
+  Unlike `gen_recipient_keypair/1`, the keypair is not stored in the NIF's
+  global key store. It lives as long as some process holds the returned
+  reference and is reclaimed by the garbage collector afterwards, so a key can
+  be scoped to a single process. The reference can be passed to `decrypt/3` in
+  place of a key identifier.
+
+  ## Parameters
+
+    * `kid` - Key identifier string for the recipient
+
+  ## Returns
+
+    * `{:ok, {key_ref, public_key}}` on success where public_key is CBOR-encoded
+    * `{:error, reason}` on failure
+
+  ## Examples
+
+      iex> {:ok, {key_ref, pub_key}} = ExTholosPq.gen_recipient_key_resource("recipient1")
+      iex> is_reference(key_ref) and is_binary(pub_key)
+      true
+
+  """
+  @spec gen_recipient_key_resource(String.t()) ::
+          {:ok, {reference(), binary()}} | {:error, error()}
+  def gen_recipient_key_resource(_kid), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Generates a new recipient keypair with options, owned by a resource reference.
+
+  Works like `gen_recipient_key_resource/1` and takes the options of
+  `gen_recipient_keypair/2`.
+  """
+  @spec gen_recipient_key_resource(String.t(), keyword()) ::
+          {:ok, {reference(), binary()}} | {:error, error()}
+  def gen_recipient_key_resource(_kid, _opts), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Generates a new sender keypair owned by a resource reference.
+
+  Unlike `gen_sender_keypair/1`, the keypair is not stored in the NIF's global
+  key store and is reclaimed by the garbage collector once no process holds the
+  returned reference. The reference can be passed to `encrypt/3` in place of a
+  sender identifier.
+
+  ## Parameters
+
+    * `sid` - Sender identifier string
+
+  ## Returns
+
+    * `{:ok, {key_ref, public_key}}` on success where public_key is CBOR-encoded
+    * `{:error, reason}` on failure
+
+  ## Examples
+
+      iex> {:ok, {key_ref, pub_key}} = ExTholosPq.gen_sender_key_resource("sender1")
+      iex> is_reference(key_ref) and is_binary(pub_key)
+      true
+
+  """
+  @spec gen_sender_key_resource(String.t()) ::
+          {:ok, {reference(), binary()}} | {:error, error()}
+  def gen_sender_key_resource(_sid), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Generates a new sender keypair with options, owned by a resource reference.
+
+  Works like `gen_sender_key_resource/1` and takes the options of
+  `gen_sender_keypair/2`.
+  """
+  @spec gen_sender_key_resource(String.t(), keyword()) ::
+          {:ok, {reference(), binary()}} | {:error, error()}
+  def gen_sender_key_resource(_sid, _opts), do: :erlang.nif_error(:nif_not_loaded)
+
   @doc """
   Encrypts a message for multiple recipients with sender authentication.
 
@@ -110,13 +736,17 @@ defmodule ExTholosPq do
   ## Parameters
 
     * `message` - The message to encrypt (binary or string)
-    * `sender_id` - The sender's identifier (from `gen_sender_keypair/1`)
+    * `sender_id` - The sender's identifier (from `gen_sender_keypair/1`), or a
+      key reference from `gen_sender_key_resource/1`
     * `recipient_pub_keys` - List of recipient public keys (CBOR-encoded)
 
   ## Returns
 
     * `{:ok, ciphertext}` on success
-    * `{:error, reason}` on failure
+    * `{:error, :no_recipients}` if `recipient_pub_keys` is empty
+    * `{:error, {:param_mismatch, expected, got}}` if the recipient keys are of
+      different security levels
+    * `{:error, reason}` on other failures
 
   ## Examples
 
@@ -127,36 +757,3216 @@ defmodule ExTholosPq do
       true
 
   """
-  @spec encrypt(binary(), String.t(), list(binary())) ::
-          {:ok, binary()} | {:error, String.t()}
+  @spec encrypt(binary(), String.t() | reference(), list(binary())) ::
+          {:ok, binary()} | {:error, error()}
   def encrypt(_message, _sender_id, _recipient_pub_keys), do: :erlang.nif_error(:nif_not_loaded)
 
   @doc """
-  Decrypts a message for a specific recipient.
+  Encrypts a message for multiple recipients with options.
 
-  ## Parameters
+  Works like `encrypt/3`. `opts` is a keyword list, or a binary as shorthand
+  for `aad: binary`.
 
-    * `ciphertext` - The encrypted message
-    * `kid` - The recipient's key identifier (must have been generated with `gen_recipient_keypair/1`)
-    * `allowed_sender_pub_keys` - List of allowed sender public keys for verification (CBOR-encoded)
+  ## Options
 
-  ## Returns
+    * `:aad` - Associated data to authenticate together with the message. It is
+      neither encrypted nor stored in the ciphertext; use it to bind a message to
+      its context, e.g. a tenant id and message type. The recipient must present
+      the same bytes to `decrypt/4`.
+    * `:max_recipients` - Maximum number of recipients (default `256`). Each
+      recipient costs a Kyber encapsulation, so longer lists are rejected before
+      any key is decoded. `encrypt/3` and `encrypt_with_keys/3` always apply the
+      default.
+    * `:compression` - `:none` (default) or `:zstd`. With `:zstd` the message is
+      compressed before encryption and the header records it, so every decrypt
+      function inflates it again transparently. A message that does not get
+      smaller is stored uncompressed. Decryption refuses, as a malformed wire, a
+      message that inflates past 1 GiB. Requires the NIF to be built with the
+      `zstd` cargo feature, see below. Compressed ciphertexts cannot be read by
+      other tholos-pq implementations, which reject them as badly signed.
+    * `:embed_sender_key` - When `true`, the sender's public key is embedded in
+      the header (default `false`), adding about 2 KB. This lets recipients
+      decrypt without knowing the sender up front, by passing an empty
+      allow-list to `decrypt_with_sender/3`. Like compressed ones, such
+      ciphertexts cannot be read by other tholos-pq implementations.
+    * `:encoding` - `:raw` (default) or `:base64url`. With `:base64url` the
+      ciphertext is returned as unpadded base64url text, ready for JSON or
+      headers, without a second pass over it in Elixir. Pass the same option to
+      `decrypt/4` to read it back.
+    * `:hybrid` - When `true`, every recipient is also encapsulated to with
+      X25519 (default `false`), see the Hybrid Keys section. All recipient keys
+      must be hybrid keys.
+    * `:key_commitment` - When `true`, a SHA-256 commitment to the content key
+      is stored in the ciphertext (default `false`), adding 32 bytes. The
+      payload cipher does not commit to its key, so without it a sender could
+      give each recipient a different content key under which the same
+      ciphertext opens to a different plaintext. With it, every recipient
+      checks its content key against the commitment and fails with
+      `{:error, :key_commitment_failed}` on a mismatch, so all of them read the
+      same plaintext. Such ciphertexts cannot be read by other tholos-pq
+      implementations.
+    * `:ttl_seconds` - How long the ciphertext stays valid after it was made.
+      It is recorded in the signed header, next to the creation timestamp,
+      so it cannot be changed without breaking the signature, and is enforced
+      by `decrypt/4` against its `:now`. Every other decrypt function refuses
+      such ciphertexts with `{:error, :now_required}`, having no time to check
+      them against. Such ciphertexts cannot be read by other tholos-pq
+      implementations.
+    * `:message_id` - A non-empty string recorded as the message id in the
+      signed header, instead of a random UUID. Either way the id can be read
+      with `wire_message_id/1`, e.g. to drop replayed ciphertexts.
+    * `:metadata` - Up to 1024 bytes of public metadata, e.g. a content type or
+      a routing key, stored in the clear in the signed header. Anyone can read
+      it with `wire_metadata/1` without a key, and a changed value makes
+      decryption fail. An empty binary records nothing. Such ciphertexts
+      cannot be read by other tholos-pq implementations.
+    * `:aead` - The cipher sealing the message, `:xchacha20poly1305` (default)
+      or `:aes256gcm`, e.g. where AES is mandated or hardware accelerated. The
+      header records the choice and every decrypt function follows it, so
+      recipients need no option of their own. Content keys are wrapped with
+      XChaCha20-Poly1305 either way. AES-256-GCM ciphertexts cannot be read by
+      other tholos-pq implementations.
+    * `:pad_to` - A block size in bytes. The message is padded to a multiple of
+      it before encryption, so all messages that pad to the same size give
+      ciphertexts of the same size, whatever their own length. There is always
+      at least one byte of padding, so a message that is a multiple already
+      gains a whole block. The padding is encrypted and authenticated with the
+      message and stripped by every decrypt function; the header records the
+      block size. A padded message may be at most 1 GiB. Such ciphertexts
+      cannot be read by other tholos-pq implementations.
+    * `:per_recipient_aad` - A list of binaries, one per recipient in the order
+      of `recipient_pub_keys`. Each recipient's envelope is bound to its own
+      entry, e.g. its routing token, on top of `:aad`, so the recipient must
+      present that entry to `decrypt/4` as `:recipient_aad` and cannot decrypt
+      with another recipient's. The entries are not stored in the ciphertext.
+      Recipients cannot be added to such a ciphertext with `add_recipient/4`.
+      Such ciphertexts cannot be read by other tholos-pq implementations.
 
-    * `{:ok, plaintext}` on success
-    * `{:error, reason}` on failure
+  Compression enables length-based attacks such as CRIME when a message mixes
+  secrets with data an attacker controls, since the ciphertext size then leaks
+  how well the two compress together. Only use it for payloads where that
+  cannot happen. With `:pad_to` the compressed message is padded, which hides
+  its exact size but not which block count it compresses to.
 
-  ## Examples
+  The `zstd` feature is enabled in the application config:
 
-      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
-      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
-      iex> {:ok, ct} = ExTholosPq.encrypt("secret", sid, [pub])
-      iex> {:ok, plain} = ExTholosPq.decrypt(ct, kid, [sender_pub])
-      iex> plain == "secret"
+      config :ex_tholos_pq, ExTholosPq, features: ["zstd"]
+
+  ## Parameters
+
+    * `message` - The message to encrypt (binary or string)
+    * `sender_id` - The sender's identifier, or a key reference from `gen_sender_key_resource/1`
+    * `recipient_pub_keys` - List of recipient public keys (CBOR-encoded)
+    * `opts` - Keyword list of options, or associated data (binary)
+
+  ## Returns
+
+    * `{:ok, ciphertext}` on success
+    * `{:error, :no_recipients}` if `recipient_pub_keys` is empty
+    * `{:error, {:too_many_recipients, count}}` if there are more than `:max_recipients` recipients
+    * `{:error, :hybrid_mismatch}` with `hybrid: true` if a recipient key is not hybrid
+    * `{:error, {:invalid_option, name}}` for an unknown option or an unsupported value
+    * `{:error, :plaintext_too_large_for_padding}` if the padded message would be over 1 GiB
+    * `{:error, {:invalid_option, "per_recipient_aad"}}` if it has not one entry per recipient
+    * `{:error, reason}` on other failures
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("secret", sid, [pub], "tenant-1:invoice")
+      iex> ExTholosPq.decrypt(ct, kid, [sender_pub], "tenant-1:invoice")
+      {:ok, "secret"}
+      iex> ExTholosPq.encrypt("secret", sid, [pub, pub], max_recipients: 1)
+      {:error, {:too_many_recipients, 2}}
+
+  """
+  @spec encrypt(binary(), String.t() | reference(), list(binary()), binary() | keyword()) ::
+          {:ok, binary()} | {:error, error()}
+  def encrypt(_message, _sender_id, _recipient_pub_keys, _opts),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Encrypts a message for multiple recipients without a sender.
+
+  Nothing is signed and no sender key is needed, for uses like anonymous drop
+  boxes where recipients must not learn who wrote. The header is flagged as
+  anonymous, and since every envelope and the payload are bound to the header,
+  the flag cannot be added to a signed ciphertext, nor a signature stripped
+  from one to make it pass as anonymous.
+
+  Only `decrypt_with_sender/3` reads anonymous ciphertexts, returning
+  `{:ok, {plaintext, :anonymous}}` whatever the allow-list. Every other
+  decrypt function rejects them with `{:error, :signature_invalid}`,
+  so code that relies on a verified sender never gets one. Anyone holding a
+  recipient's public key can write to it this way, so treat the plaintext
+  accordingly. Anonymous ciphertexts cannot be read by other tholos-pq
+  implementations.
+
+  ## Parameters
+
+    * `message` - The message to encrypt (binary or string)
+    * `recipient_pub_keys` - List of recipient public keys (CBOR-encoded)
+
+  ## Returns
+
+    * `{:ok, ciphertext}` on success
+    * `{:error, reason}` on failure
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("DropBox")
+      iex> {:ok, ct} = ExTholosPq.encrypt_anonymous("tip", [pub])
+      iex> ExTholosPq.decrypt_with_sender(ct, kid, [])
+      {:ok, {"tip", :anonymous}}
+
+  """
+  @spec encrypt_anonymous(binary(), list(binary())) :: {:ok, binary()} | {:error, error()}
+  def encrypt_anonymous(_message, _recipient_pub_keys), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Encrypts a message like `encrypt/3` and reports how long each phase took.
+
+  The durations are measured with a monotonic clock around the Kyber
+  encapsulations (`:kem_us`), the payload encryption and key wrapping
+  (`:aead_us`) and the Dilithium signature (`:sign_us`), in microseconds
+  rounded up. They are meant for `:telemetry` measurements; `encrypt/3` reads
+  no clock, so keep using it where the timings are not needed.
+
+  ## Returns
+
+    * `{:ok, {ciphertext, %{kem_us: integer, aead_us: integer, sign_us: integer}}}` on success
+    * `{:error, reason}` on failure, as for `encrypt/3`
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("TimedAlice")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("TimedSender")
+      iex> {:ok, {ct, %{kem_us: _, aead_us: _, sign_us: _}}} =
+      ...>   ExTholosPq.encrypt_timed("secret", sid, [pub])
+      iex> ExTholosPq.decrypt(ct, kid, [sender_pub])
+      {:ok, "secret"}
+
+  """
+  @spec encrypt_timed(binary(), String.t() | reference(), list(binary())) ::
+          {:ok,
+           {binary(),
+            %{kem_us: non_neg_integer(), aead_us: non_neg_integer(), sign_us: non_neg_integer()}}}
+          | {:error, error()}
+  def encrypt_timed(_message, _sender_id, _recipient_pub_keys),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Exports a stored sender keypair, including its Dilithium secret key.
+
+  The result can be handed to `encrypt_with_keys/3` on any node, which makes it
+  possible to generate a sender key once and encrypt wherever it is needed.
+  Treat the exported binary like any other secret key material.
+
+  ## Parameters
+
+    * `sid` - The sender's identifier (from `gen_sender_keypair/1`)
+
+  ## Returns
+
+    * `{:ok, keypair}` on success where keypair is CBOR-encoded
+    * `{:error, reason}` on failure
+
+  ## Examples
+
+      iex> {:ok, {sid, _pub}} = ExTholosPq.gen_sender_keypair("ExportedSender")
+      iex> {:ok, keypair} = ExTholosPq.export_sender_keypair(sid)
+      iex> is_binary(keypair)
+      true
+
+  """
+  @spec export_sender_keypair(String.t()) :: {:ok, binary()} | {:error, error()}
+  def export_sender_keypair(_sid), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Encrypts a message using a sender keypair supplied by the caller.
+
+  Behaves like `encrypt/3`, except the sender keypair is deserialized from the
+  given CBOR instead of being looked up in the NIF's key store, so encryption
+  does not have to happen on the node that generated the key.
+
+  ## Parameters
+
+    * `message` - The message to encrypt (binary or string)
+    * `sender_keypair` - CBOR-encoded sender keypair (from `export_sender_keypair/1`)
+    * `recipient_pub_keys` - List of recipient public keys (CBOR-encoded)
+
+  ## Returns
+
+    * `{:ok, ciphertext}` on success
+    * `{:error, reason}` on failure
+
+  ## Examples
+
+      iex> {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, _pub}} = ExTholosPq.gen_sender_keypair("PortableSender")
+      iex> {:ok, keypair} = ExTholosPq.export_sender_keypair(sid)
+      iex> {:ok, ct} = ExTholosPq.encrypt_with_keys("secret", keypair, [pub])
+      iex> is_binary(ct)
+      true
+
+  """
+  @spec encrypt_with_keys(binary(), binary(), list(binary())) ::
+          {:ok, binary()} | {:error, error()}
+  def encrypt_with_keys(_message, _sender_keypair, _recipient_pub_keys),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Decrypts a message for a specific recipient.
+
+  A `kid` from the key store stands for every version of it kept by
+  `rotate_recipient_key/1`: the version the message was encrypted to is picked
+  from its envelopes, so messages encrypted before a rotation still decrypt.
+
+  The sender's signature covers the header, the envelopes and the payload, and
+  it is checked first: a ciphertext from a sender that is not allowed, or whose
+  signature does not verify, is rejected with `{:error, :signature_invalid}`
+  before any envelope is decapsulated or anything is decrypted.
+
+  Every field of the ciphertext is signed: each header field, flags and
+  metadata included, the payload and its nonce, every envelope and the key
+  commitment. Associated data is never sent and is bound by the AEAD instead.
+  Only the version byte in front is outside the signature, and a ciphertext
+  without it reads the same.
+
+  The envelope for `kid` is looked up in constant time over all envelopes, and
+  a ciphertext without one still costs a KEM decapsulation before
+  `:no_envelope_for_recipient` is returned, so the time taken does not tell which
+  envelope, if any, belongs to the recipient.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+    * `kid` - The recipient's key identifier (must have been generated with `gen_recipient_keypair/1`),
+      or a key reference from `gen_recipient_key_resource/1`
+    * `allowed_sender_pub_keys` - List of allowed sender public keys for verification (CBOR-encoded).
+      An empty list rejects every signed ciphertext; only `decrypt_with_sender/3`
+      accepts an embedded sender key
+
+  ## Returns
+
+    * `{:ok, plaintext}` on success
+    * `{:error, reason}` on failure
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("secret", sid, [pub])
+      iex> {:ok, plain} = ExTholosPq.decrypt(ct, kid, [sender_pub])
+      iex> plain == "secret"
       true
 
   """
-  @spec decrypt(binary(), String.t(), list(binary())) ::
-          {:ok, binary()} | {:error, String.t()}
+  @spec decrypt(binary(), String.t() | reference(), list(binary())) ::
+          {:ok, binary()} | {:error, error()}
   def decrypt(_ciphertext, _kid, _allowed_sender_pub_keys),
     do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Decrypts a message with options, e.g. one that was bound to associated data
+  with `encrypt/4`.
+
+  Works like `decrypt/3`. `opts` is a keyword list, or a binary as shorthand
+  for `aad: binary`.
+
+  ## Options
+
+    * `:aad` - The associated data given to `encrypt/4`
+    * `:encoding` - `:raw` (default) or `:base64url` for a ciphertext encrypted
+      with `encoding: :base64url`. Padded base64url is accepted as well.
+    * `:now` - The current time in Unix seconds, e.g. `System.os_time(:second)`.
+      When given, a ciphertext older than its `:ttl_seconds` is rejected with
+      `{:error, :expired}`. The NIF reads no clock of its own, so without
+      `:now` a ciphertext that has a `:ttl_seconds` is rejected with
+      `{:error, :now_required}` rather than accepted however old it is; one
+      without is decrypted as usual.
+    * `:max_age_seconds` - Rejects ciphertexts created more than this many
+      seconds before `:now` with `{:error, :expired}`, whatever their own
+      time to live. Requires `:now`.
+    * `:max_recipients` - The most recipients the ciphertext may declare, 256 by
+      default. A ciphertext declaring more is rejected with
+      `{:error, {:malformed_wire, detail}}` before any of it is decoded.
+    * `:recipient_aad` - This recipient's entry of the `:per_recipient_aad`
+      given to `encrypt/4`. It is required for such a ciphertext and refused
+      for any other, both with `{:error, :aad_mismatch}`.
+
+  The age is taken from the creation timestamp in the signed header, so it
+  is only checked once the signature verifies, but before the recipient key
+  is used: an expired ciphertext is never decrypted and does not count
+  towards `recipient_key_usage/1`. A timestamp ahead of `:now` counts as
+  age 0.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+    * `kid` - The recipient's key identifier, or a key reference from `gen_recipient_key_resource/1`
+    * `allowed_sender_pub_keys` - List of allowed sender public keys for verification (CBOR-encoded)
+    * `opts` - Keyword list of options, or associated data (binary)
+
+  ## Returns
+
+    * `{:ok, plaintext}` on success
+    * `{:error, :aad_mismatch}` if `:aad` or `:recipient_aad` differs from the associated data used to encrypt
+    * `{:error, :expired}` if the ciphertext is older than its time to live or `:max_age_seconds`
+    * `{:error, :now_required}` if the ciphertext has a time to live and `:now` is not given
+    * `{:error, {:malformed_wire, "base64url"}}` if the ciphertext is not valid base64url
+    * `{:error, {:invalid_option, name}}` for an unknown option or an unsupported value
+    * `{:error, reason}` on other failures
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("secret", sid, [pub], "tenant-1")
+      iex> ExTholosPq.decrypt(ct, kid, [sender_pub], "tenant-2")
+      {:error, :aad_mismatch}
+
+  """
+  @spec decrypt(binary(), String.t() | reference(), list(binary()), binary() | keyword()) ::
+          {:ok, binary()} | {:error, error()}
+  def decrypt(_ciphertext, _kid, _allowed_sender_pub_keys, _opts),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Decrypts a message like `decrypt/3`, sorting a failure into a category for
+  alerting.
+
+  The error is `{:error, {category, reason}}`, where `reason` is what
+  `decrypt/3` returns in `{:error, reason}` and `category` is one of:
+
+    * `:authz` - the sender is not in the allow-list, the allow-list is empty,
+      or the ciphertext is anonymous
+      (`:signature_invalid` without a key to check the signature against)
+    * `:integrity` - the ciphertext was altered or forged: its signature does
+      not verify under the allowed key for its sender, or its key commitment
+      does not match (`:key_commitment_failed`)
+    * `:format` - the ciphertext or an allowed sender key cannot be parsed,
+      the ciphertext uses an unsupported wire version, or it binds its
+      envelopes to recipient AADs, which only `decrypt/4` takes
+      (`:aad_mismatch`)
+    * `:key` - the recipient key is missing or the wrong one:
+      `:recipient_not_found`, `:no_envelope_for_recipient`,
+      `:auth_tag_mismatch`, `:security_level_mismatch` or `:hybrid_mismatch`. A
+      ciphertext bound to associated data with `encrypt/4` fails with
+      `:auth_tag_mismatch` here too.
+
+  The categories are stable, so dashboards can group by them without
+  matching on `reason`. As the sender's name is only known from the
+  ciphertext, one altered to name a sender outside the allow-list is `:authz`.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+    * `kid` - The recipient's key identifier, or a key reference from `gen_recipient_key_resource/1`
+    * `allowed_sender_pub_keys` - List of allowed sender public keys for verification (CBOR-encoded)
+
+  ## Returns
+
+    * `{:ok, plaintext}` on success
+    * `{:error, {category, reason}}` on failure
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, {_sid, other_pub}} = ExTholosPq.gen_sender_keypair("Other")
+      iex> {:ok, ct} = ExTholosPq.encrypt("secret", sid, [pub])
+      iex> {:error, {:authz, :signature_invalid}} = ExTholosPq.decrypt_classified(ct, kid, [other_pub])
+      iex> ExTholosPq.decrypt_classified(ct, "Bob", [other_pub])
+      {:error, {:key, {:recipient_not_found, "Bob"}}}
+
+  """
+  @spec decrypt_classified(binary(), String.t() | reference(), list(binary())) ::
+          {:ok, binary()} | {:error, {:authz | :integrity | :format | :key, error()}}
+  def decrypt_classified(_ciphertext, _kid, _allowed_sender_pub_keys),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Decrypts a message and returns the sid of the sender that signed it.
+
+  Works like `decrypt/3`. The returned sid is the entry of
+  `allowed_sender_pub_keys` whose Dilithium key verified the signature, so
+  unlike `wire_sender_unverified/1` it can be used for audit logging.
+
+  ## Open mode
+
+  With an empty `allowed_sender_pub_keys` any sender is accepted, provided the
+  ciphertext was encrypted with `embed_sender_key: true`. The signature is then
+  verified against the key embedded in the ciphertext, which is returned as
+  well, so that the caller can decide afterwards whether to trust it. This only
+  proves that the ciphertext was signed by whoever holds that key, not who that
+  is: anyone can generate a keypair under any sid. Compare the returned key with
+  one obtained out of band before relying on the sid. A ciphertext without an
+  embedded key fails with `{:error, :signature_invalid}` in open mode.
+
+  Open mode is specific to this function, which hands the key back. Every
+  other function taking an allow-list, `decrypt/3` and `validate_wire/2`
+  included, rejects all signed ciphertexts when it is empty, so that an
+  allow-list that happens to come out empty never lets forged mail through.
+
+  ## Anonymous ciphertexts
+
+  A ciphertext from `encrypt_anonymous/2` has no sender to check, so it is
+  accepted whatever `allowed_sender_pub_keys` holds and `:anonymous` is
+  returned in place of the sid. Match on it wherever only known senders are
+  acceptable.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+    * `kid` - The recipient's key identifier, or a key reference from `gen_recipient_key_resource/1`
+    * `allowed_sender_pub_keys` - List of allowed sender public keys for verification (CBOR-encoded)
+
+  ## Returns
+
+    * `{:ok, {plaintext, sender_sid}}` on success
+    * `{:ok, {plaintext, sender_sid, sender_pub_key}}` on success in open mode
+    * `{:ok, {plaintext, :anonymous}}` for an anonymous ciphertext
+    * `{:error, reason}` on failure
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("AuditedSender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("secret", sid, [pub])
+      iex> ExTholosPq.decrypt_with_sender(ct, kid, [sender_pub])
+      {:ok, {"secret", "AuditedSender"}}
+      iex> {:ok, ct} = ExTholosPq.encrypt("secret", sid, [pub], embed_sender_key: true)
+      iex> {:ok, {"secret", "AuditedSender", ^sender_pub}} = ExTholosPq.decrypt_with_sender(ct, kid, [])
+
+  """
+  @spec decrypt_with_sender(binary(), String.t() | reference(), list(binary())) ::
+          {:ok, {binary(), String.t() | :anonymous} | {binary(), String.t(), binary()}}
+          | {:error, error()}
+  def decrypt_with_sender(_ciphertext, _kid, _allowed_sender_pub_keys),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Decrypts a batch of messages addressed to one recipient.
+
+  Works like calling `decrypt/3` on each ciphertext, but the allow-list is
+  deserialized and the key store locked once for the whole batch, which makes
+  draining an inbox cheaper. A ciphertext that fails to decrypt does not fail
+  the batch: each one gets its own result, in the order given.
+
+  ## Parameters
+
+    * `ciphertexts` - The encrypted messages
+    * `kid` - The recipient's key identifier, or a key reference from `gen_recipient_key_resource/1`
+    * `allowed_sender_pub_keys` - List of allowed sender public keys for verification (CBOR-encoded)
+
+  ## Returns
+
+    * `{:ok, results}` where each result is `{:ok, plaintext}` or `{:error, reason}`
+    * `{:error, {:invalid_identifier, reason}}` if `kid` is not a valid kid
+    * `{:error, reason}` if the allow-list cannot be deserialized
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("InboxOwner")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("InboxSender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("hello", sid, [pub])
+      iex> {:ok, [{:ok, "hello"}, {:error, _}]} =
+      ...>   ExTholosPq.decrypt_many([ct, "garbage"], kid, [sender_pub])
+
+  """
+  @spec decrypt_many(list(binary()), String.t() | reference(), list(binary())) ::
+          {:ok, list({:ok, binary()} | {:error, error()})} | {:error, error()}
+  def decrypt_many(_ciphertexts, _kid, _allowed_sender_pub_keys),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Decodes a list of sender public keys once into an allow-list for
+  `decrypt_with_allowlist_ref/3`.
+
+  The list form taken by `decrypt/3` is deserialized on every call and
+  searched in order; the allow-list built here is kept in the NIF, keyed by
+  sid, so large allow-lists cost one hash lookup per message. A sid listed
+  twice keeps its first key, as in the list form, and an empty list accepts
+  no sender, as `decrypt/3` does with `[]`.
+
+  ## Parameters
+
+    * `sender_pub_keys` - List of allowed sender public keys (CBOR-encoded)
+
+  ## Returns
+
+    * `{:ok, allowlist}` with a reference to the allow-list
+    * `{:error, reason}` if a public key cannot be deserialized
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("AllowlistOwner")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("AllowlistSender")
+      iex> {:ok, allowlist} = ExTholosPq.build_allowlist([sender_pub])
+      iex> {:ok, ct} = ExTholosPq.encrypt("hello", sid, [pub])
+      iex> ExTholosPq.decrypt_with_allowlist_ref(ct, kid, allowlist)
+      {:ok, "hello"}
+
+  """
+  @spec build_allowlist(list(binary())) :: {:ok, reference()} | {:error, error()}
+  def build_allowlist(_sender_pub_keys), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Decrypts a message like `decrypt/3`, against an allow-list from `build_allowlist/1`.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+    * `kid` - The recipient's key identifier, or a key reference from `gen_recipient_key_resource/1`
+    * `allowlist` - An allow-list from `build_allowlist/1`
+
+  ## Returns
+
+    * `{:ok, plaintext}` on success
+    * `{:error, reason}` on failure, as for `decrypt/3`
+
+  """
+  @spec decrypt_with_allowlist_ref(binary(), String.t() | reference(), reference()) ::
+          {:ok, binary()} | {:error, error()}
+  def decrypt_with_allowlist_ref(_ciphertext, _kid, _allowlist),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Decrypts a message like `decrypt/3` with a key reference from
+  `gen_recipient_key_resource/1`.
+
+  `decrypt/3` takes such a reference too, but also accepts a kid and looks it up
+  in the global key store. This only takes a reference, so it never touches the
+  store or its lock, and raises `ArgumentError` for anything else.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+    * `key_ref` - A key reference from `gen_recipient_key_resource/1`
+    * `allowed_sender_pub_keys` - List of allowed sender public keys (CBOR-encoded)
+
+  ## Returns
+
+    * `{:ok, plaintext}` on success
+    * `{:error, reason}` on failure, as for `decrypt/3`
+
+  ## Examples
+
+      iex> {:ok, {key_ref, pub}} = ExTholosPq.gen_recipient_key_resource("ResourceDoc")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("ResourceDocSender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("hello", sid, [pub])
+      iex> ExTholosPq.decrypt_with_resource(ct, key_ref, [sender_pub])
+      {:ok, "hello"}
+
+  """
+  @spec decrypt_with_resource(binary(), reference(), list(binary())) ::
+          {:ok, binary()} | {:error, error()}
+  def decrypt_with_resource(_ciphertext, _key_ref, _allowed_sender_pub_keys),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Generates a recipient keypair and a sender keypair under the same id and
+  stores both, for `encrypt_self/2` and `decrypt_self/2`.
+
+  Works like calling `gen_recipient_keypair/1` and `gen_sender_keypair/1` with
+  `id`, in one call. Neither keypair is stored if either store already holds
+  one under `id`.
+
+  ## Parameters
+
+    * `id` - The id of both keypairs
+
+  ## Returns
+
+    * `{:ok, {id, recipient_public_key, sender_public_key}}` on success, both
+      CBOR-encoded
+    * `{:error, {:kid_exists, id}}` or `{:error, {:sid_exists, id}}` if a
+      keypair is already stored under `id`
+    * `{:error, {:invalid_identifier, reason}}` if `id` is not a valid kid
+    * `{:error, reason}` on other failures
+
+  ## Examples
+
+      iex> {:ok, {"Journal", recipient_pub, sender_pub}} = ExTholosPq.gen_self_keypair("Journal")
+      iex> is_binary(recipient_pub) and is_binary(sender_pub)
+      true
+
+  """
+  @spec gen_self_keypair(String.t()) ::
+          {:ok, {String.t(), binary(), binary()}} | {:error, error()}
+  def gen_self_keypair(_id), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Encrypts a message to oneself, e.g. a note to be read back later.
+
+  `key_id` names both a sender keypair and a recipient keypair, generated
+  under the same id with `gen_self_keypair/1`, or with `gen_sender_keypair/1`
+  and `gen_recipient_keypair/1`. The message is signed with the first and
+  encrypted for the second, so no public key has to be passed around. Read it
+  back with `decrypt_self/2`.
+
+  ## Parameters
+
+    * `message` - The message to encrypt (binary or string)
+    * `key_id` - The id of both keypairs
+
+  ## Returns
+
+    * `{:ok, ciphertext}` on success
+    * `{:error, {:invalid_identifier, reason}}` if `key_id` is not a valid id
+    * `{:error, {:recipient_not_found, key_id}}` or
+      `{:error, {:sender_not_found, key_id}}` if either keypair is missing
+    * `{:error, reason}` on other failures
+
+  ## Examples
+
+      iex> {:ok, _} = ExTholosPq.gen_self_keypair("Notes")
+      iex> {:ok, ct} = ExTholosPq.encrypt_self("remember the milk", "Notes")
+      iex> ExTholosPq.decrypt_self(ct, "Notes")
+      {:ok, "remember the milk"}
+
+  """
+  @spec encrypt_self(binary(), String.t()) :: {:ok, binary()} | {:error, error()}
+  def encrypt_self(_message, _key_id), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Decrypts a message from `encrypt_self/2`.
+
+  Works like `decrypt/3` with the recipient keypair stored under `key_id`,
+  and with the sender keypair stored under the same id as the only allowed
+  sender, so a ciphertext signed by anyone else fails with
+  `{:error, :signature_invalid}`.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+    * `key_id` - The id of both keypairs
+
+  ## Returns
+
+    * `{:ok, plaintext}` on success
+    * `{:error, {:invalid_identifier, reason}}` if `key_id` is not a valid id
+    * `{:error, {:sender_not_found, key_id}}` or
+      `{:error, {:recipient_not_found, key_id}}` if either keypair is missing
+    * `{:error, reason}` on other failures
+
+  ## Examples
+
+      iex> {:ok, _} = ExTholosPq.gen_self_keypair("Diary")
+      iex> {:ok, ct} = ExTholosPq.encrypt_self("dear diary", "Diary")
+      iex> ExTholosPq.decrypt_self(ct, "Diary")
+      {:ok, "dear diary"}
+
+  """
+  @spec decrypt_self(binary(), String.t()) :: {:ok, binary()} | {:error, error()}
+  def decrypt_self(_ciphertext, _key_id), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Decrypts a message addressed to any one of several stored recipient keys.
+
+  For a process holding many kids that does not know which one a ciphertext
+  is for. The kids are matched against the recipient envelopes of the
+  ciphertext, as listed by `wire_recipients/1`, so no decapsulation is spent
+  on a key that cannot open it. The first kid in `kids` with an envelope is
+  used, any stored version of it included, and otherwise it works like
+  `decrypt_with_sender/3` with a non-empty allow-list.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+    * `kids` - The identifiers of the stored recipient keys to try, in order
+    * `allowed_sender_pub_keys` - List of allowed sender public keys (CBOR-encoded)
+
+  ## Returns
+
+    * `{:ok, {plaintext, matched_kid, sender_sid}}` on success
+    * `{:error, :no_matching_key}` if the ciphertext has no envelope for any
+      of `kids` that is in the key store
+    * `{:error, :malformed_wire}` if the recipient envelopes cannot be read
+    * `{:error, reason}` on other failures
+
+  ## Examples
+
+      iex> {:ok, {_, pub_a}} = ExTholosPq.gen_recipient_keypair("AnyA")
+      iex> {:ok, {_, _pub_b}} = ExTholosPq.gen_recipient_keypair("AnyB")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("AnySender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("for A", sid, [pub_a])
+      iex> ExTholosPq.decrypt_any(ct, ["AnyB", "AnyA"], [sender_pub])
+      {:ok, {"for A", "AnyA", "AnySender"}}
+      iex> ExTholosPq.decrypt_any(ct, ["AnyB"], [sender_pub])
+      {:error, :no_matching_key}
+
+  """
+  @spec decrypt_any(binary(), list(String.t()), list(binary())) ::
+          {:ok, {binary(), String.t(), String.t()}} | {:error, :no_matching_key | error()}
+  def decrypt_any(_ciphertext, _kids, _allowed_sender_pub_keys),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Decrypts a message and returns a digest of the plaintext along with it.
+
+  Works like `decrypt/3`. The plaintext is hashed in Rust while it is still at
+  hand, so deduplication or integrity logging does not need a second pass
+  over it in Elixir. `algorithm` is `:sha256` (the default) or `:blake3`; both
+  give a 32-byte digest.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+    * `kid` - The recipient's key identifier, or a key reference from `gen_recipient_key_resource/1`
+    * `allowed_sender_pub_keys` - List of allowed sender public keys for verification (CBOR-encoded)
+    * `algorithm` - `:sha256` or `:blake3`
+
+  ## Returns
+
+    * `{:ok, {plaintext, digest}}` on success
+    * `{:error, {:invalid_option, "digest"}}` for an unknown algorithm
+    * `{:error, reason}` on other failures
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("abc", sid, [pub])
+      iex> {:ok, {"abc", digest}} = ExTholosPq.decrypt_with_digest(ct, kid, [sender_pub])
+      iex> digest == :crypto.hash(:sha256, "abc")
+      true
+
+  """
+  @spec decrypt_with_digest(binary(), String.t() | reference(), list(binary())) ::
+          {:ok, {binary(), binary()}} | {:error, error()}
+  def decrypt_with_digest(_ciphertext, _kid, _allowed_sender_pub_keys),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Decrypts a message and returns a digest of the plaintext made with
+  `algorithm`.
+
+  See `decrypt_with_digest/3`.
+  """
+  @spec decrypt_with_digest(
+          binary(),
+          String.t() | reference(),
+          list(binary()),
+          :sha256 | :blake3
+        ) :: {:ok, {binary(), binary()}} | {:error, error()}
+  def decrypt_with_digest(_ciphertext, _kid, _allowed_sender_pub_keys, _algorithm),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Exports a stored recipient private key.
+
+  The result can be kept in an external secrets store and passed to
+  `decrypt_with_key/4` later, on any node. Treat the exported binary like any
+  other secret key material.
+
+  ## Parameters
+
+    * `kid` - The recipient's key identifier (from `gen_recipient_keypair/1`)
+
+  ## Returns
+
+    * `{:ok, private_key}` on success where private_key is CBOR-encoded
+    * `{:error, reason}` on failure
+
+  ## Examples
+
+      iex> {:ok, {kid, _pub}} = ExTholosPq.gen_recipient_keypair("ExportedRecipient")
+      iex> {:ok, private_key} = ExTholosPq.export_recipient_key(kid)
+      iex> is_binary(private_key)
+      true
+
+  """
+  @spec export_recipient_key(String.t()) :: {:ok, binary()} | {:error, error()}
+  def export_recipient_key(_kid), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Rederives the public key of a stored recipient key from its private key.
+
+  Useful when only the private key was kept, for instance an export loaded
+  back with `import_recipient_key_encrypted/3` or `import_store/2`: the public
+  key to publish does not have to be stored separately. The result is the
+  CBOR key `gen_recipient_keypair/1` returned for it, byte for byte.
+
+  ## Parameters
+
+    * `kid` - The recipient's key identifier
+
+  ## Returns
+
+    * `{:ok, public_key}` on success where public_key is CBOR-encoded
+    * `{:error, {:recipient_not_found, kid}}` if no key is stored under `kid`
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("DerivedPub")
+      iex> ExTholosPq.recipient_pub_from_priv(kid) == {:ok, pub}
+      true
+
+  """
+  @spec recipient_pub_from_priv(String.t()) :: {:ok, binary()} | {:error, error()}
+  def recipient_pub_from_priv(_kid), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Returns the public key of a stored sender keypair.
+
+  The sender counterpart of `recipient_pub_from_priv/1`. The result is the
+  key `gen_sender_keypair/1` returned for it, byte for byte.
+
+  ## Parameters
+
+    * `sid` - The sender's identifier
+
+  ## Returns
+
+    * `{:ok, public_key}` on success where public_key is CBOR-encoded
+    * `{:error, {:sender_not_found, sid}}` if no keypair is stored under `sid`
+
+  ## Examples
+
+      iex> {:ok, {sid, pub}} = ExTholosPq.gen_sender_keypair("DerivedSenderPub")
+      iex> ExTholosPq.sender_pub_from_priv(sid) == {:ok, pub}
+      true
+
+  """
+  @spec sender_pub_from_priv(String.t()) :: {:ok, binary()} | {:error, error()}
+  def sender_pub_from_priv(_sid), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Decrypts a message using a recipient private key supplied by the caller.
+
+  Behaves like `decrypt/3`, except the private key is deserialized from the
+  given CBOR instead of being looked up in the NIF's key store.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+    * `kid` - The recipient's key identifier, as used when encrypting
+    * `recipient_priv` - CBOR-encoded recipient private key (from `export_recipient_key/1`)
+    * `allowed_sender_pub_keys` - List of allowed sender public keys for verification (CBOR-encoded)
+
+  ## Returns
+
+    * `{:ok, plaintext}` on success
+    * `{:error, {:invalid_private_key, detail}}` if the private key cannot be deserialized
+    * `{:error, :no_envelope_for_recipient}` if the message was not encrypted for `kid`
+    * `{:error, {kind, detail}}` on other failures, see the Errors section above
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("VaultRecipient")
+      iex> {:ok, private_key} = ExTholosPq.export_recipient_key(kid)
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("secret", sid, [pub])
+      iex> ExTholosPq.decrypt_with_key(ct, kid, private_key, [sender_pub])
+      {:ok, "secret"}
+
+  """
+  @spec decrypt_with_key(binary(), String.t(), binary(), list(binary())) ::
+          {:ok, binary()} | {:error, error()}
+  def decrypt_with_key(_ciphertext, _kid, _recipient_priv, _allowed_sender_pub_keys),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Wraps a 32-byte content key for multiple recipients, without encrypting any
+  payload.
+
+  This is the key-wrapping half of `encrypt/3` on its own, for callers that
+  encrypt the bulk data themselves, e.g. with a hardware AEAD, and only want
+  the post-quantum KEM to distribute the key. Every recipient gets the same
+  Kyber envelope as in a ciphertext, bound to the sender's sid and the list of
+  recipients. Unlike a ciphertext, the wrapped key is not signed: anyone with
+  the recipients' public keys can wrap a key under any sid, so the payload
+  layer must authenticate what the key protects.
+
+  ## Parameters
+
+    * `sender_id` - The sender's identifier, or a key reference from `gen_sender_key_resource/1`
+    * `content_key` - The 32-byte symmetric key to wrap
+    * `recipient_pub_keys` - List of recipient public keys (CBOR-encoded)
+
+  ## Returns
+
+    * `{:ok, wrapped_key}` on success
+    * `{:error, :bad_content_key_length}` if `content_key` is not 32 bytes
+    * `{:error, reason}` on other failures, as for `encrypt/3`
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("KeyWrapAlice")
+      iex> {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("KeyWrapSender")
+      iex> content_key = :crypto.strong_rand_bytes(32)
+      iex> {:ok, wrapped} = ExTholosPq.wrap_content_key(sid, content_key, [pub])
+      iex> {:ok, priv} = ExTholosPq.export_recipient_key(kid)
+      iex> {:ok, ^content_key} = ExTholosPq.unwrap_content_key(wrapped, kid, priv)
+
+  """
+  @spec wrap_content_key(String.t() | reference(), binary(), list(binary())) ::
+          {:ok, binary()} | {:error, :bad_content_key_length | error()}
+  def wrap_content_key(_sender_id, _content_key, _recipient_pub_keys),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Recovers a content key wrapped by `wrap_content_key/3`.
+
+  ## Parameters
+
+    * `wrapped_key` - The result of `wrap_content_key/3`
+    * `kid` - The recipient's key identifier
+    * `recipient_priv` - The recipient's private key (CBOR-encoded), as from `export_recipient_key/1`
+
+  ## Returns
+
+    * `{:ok, content_key}` on success
+    * `{:error, :no_envelope_for_recipient}` if the key was not wrapped for `kid`
+    * `{:error, :auth_tag_mismatch}` if the envelope does not open under `recipient_priv`
+    * `{:error, reason}` on other failures
+
+  """
+  @spec unwrap_content_key(binary(), String.t(), binary()) ::
+          {:ok, binary()} | {:error, error()}
+  def unwrap_content_key(_wrapped_key, _kid, _recipient_priv),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Encrypts a message like `encrypt/3`, then seals the whole ciphertext under a
+  pre-shared key.
+
+  This is an outer transport layer for running next to a legacy system that
+  already shares symmetric keys, not a replacement for the post-quantum
+  encryption: the ciphertext inside is signed and encrypted for its
+  recipients as usual, and anyone holding the PSK only gets that ciphertext.
+  The layer key is derived from the PSK with HKDF-SHA256 under a fresh salt,
+  and the ciphertext sealed under it with XChaCha20-Poly1305. Since the PSK is
+  used as key material and not stretched like a password, it must be at least
+  16 bytes of random data, e.g. an AES key.
+
+  ## Parameters
+
+    * `sender_id` - The sender's identifier, or a key reference from `gen_sender_key_resource/1`
+    * `message` - The message to encrypt
+    * `recipient_pub_keys` - List of recipient public keys (CBOR-encoded)
+    * `psk` - The pre-shared key, at least 16 bytes
+
+  ## Returns
+
+    * `{:ok, wrapped}` on success, which only `decrypt_psk_wrapped/4` opens
+    * `{:error, :bad_psk_length}` if `psk` is shorter than 16 bytes
+    * `{:error, reason}` on other failures, as for `encrypt/3`
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> psk = :crypto.strong_rand_bytes(32)
+      iex> {:ok, wrapped} = ExTholosPq.encrypt_psk_wrapped(sid, "secret", [pub], psk)
+      iex> ExTholosPq.decrypt_psk_wrapped(wrapped, kid, [sender_pub], psk)
+      {:ok, "secret"}
+
+  """
+  @spec encrypt_psk_wrapped(String.t() | reference(), binary(), list(binary()), binary()) ::
+          {:ok, binary()} | {:error, error()}
+  def encrypt_psk_wrapped(_sender_id, _message, _recipient_pub_keys, _psk),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Opens the pre-shared key layer of `encrypt_psk_wrapped/4`, then decrypts the
+  ciphertext inside like `decrypt/3`.
+
+  ## Parameters
+
+    * `wrapped` - The output of `encrypt_psk_wrapped/4`
+    * `kid` - The recipient's key identifier, or a key reference from `gen_recipient_key_resource/1`
+    * `allowed_sender_pub_keys` - List of allowed sender public keys for verification (CBOR-encoded)
+    * `psk` - The pre-shared key it was wrapped under
+
+  ## Returns
+
+    * `{:ok, plaintext}` on success
+    * `{:error, :bad_psk}` if the PSK is wrong or the wrapped ciphertext was altered
+    * `{:error, :bad_psk_length}` if `psk` is shorter than 16 bytes
+    * `{:error, {:malformed_wire, detail}}` if `wrapped` is not a wrapped ciphertext
+    * `{:error, reason}` on other failures, as for `decrypt/3`
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, wrapped} = ExTholosPq.encrypt_psk_wrapped(sid, "secret", [pub], <<1::256>>)
+      iex> ExTholosPq.decrypt_psk_wrapped(wrapped, kid, [sender_pub], <<2::256>>)
+      {:error, :bad_psk}
+
+  """
+  @spec decrypt_psk_wrapped(binary(), String.t() | reference(), list(binary()), binary()) ::
+          {:ok, binary()} | {:error, error()}
+  def decrypt_psk_wrapped(_wrapped, _kid, _allowed_sender_pub_keys, _psk),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Removes a recipient keypair from the NIF's key store.
+
+  The private key material is zeroized as it is dropped. Messages encrypted for
+  `kid` can no longer be decrypted with `decrypt/3` afterwards.
+
+  ## Parameters
+
+    * `kid` - The recipient's key identifier
+
+  ## Returns
+
+    * `:ok` if the key was removed
+    * `{:error, :not_found}` if no key is stored under `kid`
+
+  ## Examples
+
+      iex> {:ok, {kid, _pub}} = ExTholosPq.gen_recipient_keypair("ShortLivedRecipient")
+      iex> ExTholosPq.delete_recipient_key(kid)
+      :ok
+      iex> ExTholosPq.delete_recipient_key(kid)
+      {:error, :not_found}
+
+  """
+  @spec delete_recipient_key(String.t()) :: :ok | {:error, :not_found}
+  def delete_recipient_key(_kid), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Removes a sender keypair from the NIF's key store.
+
+  ## Parameters
+
+    * `sid` - The sender's identifier
+
+  ## Returns
+
+    * `:ok` if the key was removed
+    * `{:error, :not_found}` if no key is stored under `sid`
+
+  ## Examples
+
+      iex> {:ok, {sid, _pub}} = ExTholosPq.gen_sender_keypair("ShortLivedSender")
+      iex> ExTholosPq.delete_sender_key(sid)
+      :ok
+
+  """
+  @spec delete_sender_key(String.t()) :: :ok | {:error, :not_found}
+  def delete_sender_key(_sid), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Lists the identifiers of all recipient keypairs in the NIF's key store.
+
+  Only identifiers are returned, never key material.
+
+  ## Returns
+
+    * `{:ok, kids}` with the identifiers sorted
+
+  ## Examples
+
+      iex> {:ok, {kid, _pub}} = ExTholosPq.gen_recipient_keypair("ListedRecipient")
+      iex> {:ok, kids} = ExTholosPq.list_recipient_kids()
+      iex> kid in kids
+      true
+
+  """
+  @spec list_recipient_kids() :: {:ok, [String.t()]}
+  def list_recipient_kids, do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Lists the identifiers of all sender keypairs in the NIF's key store.
+
+  Only identifiers are returned, never key material.
+
+  ## Returns
+
+    * `{:ok, sids}` with the identifiers sorted
+
+  ## Examples
+
+      iex> {:ok, {sid, _pub}} = ExTholosPq.gen_sender_keypair("ListedSender")
+      iex> {:ok, sids} = ExTholosPq.list_sender_sids()
+      iex> sid in sids
+      true
+
+  """
+  @spec list_sender_sids() :: {:ok, [String.t()]}
+  def list_sender_sids, do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Checks whether the NIF's key store holds a recipient keypair for `kid`.
+
+  A cheap check before `decrypt/3`, instead of matching on
+  `{:error, {:recipient_not_found, kid}}`. It only takes a read lock, so it
+  runs alongside encryption and decryption. Another process can still delete
+  the key between this check and the call that uses it.
+
+  ## Parameters
+
+    * `kid` - The recipient's key identifier
+
+  ## Returns
+
+    * `true` if a keypair is stored under `kid`, `false` otherwise
+
+  ## Examples
+
+      iex> {:ok, {kid, _pub}} = ExTholosPq.gen_recipient_keypair("HeldRecipient")
+      iex> ExTholosPq.has_recipient_key(kid)
+      true
+      iex> ExTholosPq.has_recipient_key("NeverGenerated")
+      false
+
+  """
+  @spec has_recipient_key(String.t()) :: boolean()
+  def has_recipient_key(_kid), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Checks whether the NIF's key store holds a sender keypair for `sid`.
+
+  Works like `has_recipient_key/1`, for the sender store.
+
+  ## Parameters
+
+    * `sid` - The sender's identifier
+
+  ## Returns
+
+    * `true` if a keypair is stored under `sid`, `false` otherwise
+
+  ## Examples
+
+      iex> {:ok, {sid, _pub}} = ExTholosPq.gen_sender_keypair("HeldSender")
+      iex> ExTholosPq.has_sender_key(sid)
+      true
+      iex> ExTholosPq.has_sender_key("NeverGenerated")
+      false
+
+  """
+  @spec has_sender_key(String.t()) :: boolean()
+  def has_sender_key(_sid), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Returns how many messages a stored recipient key has decrypted, for
+  auditing.
+
+  Every successful decryption with the kid counts, whichever decrypt function
+  made it, including each message of `decrypt_many/3`. Decryptions with a key
+  reference from `gen_recipient_key_resource/1` and streaming decryption do
+  not. The count is kept next to the key in memory and starts from zero again
+  when the key is deleted or replaced, e.g. with `overwrite: true` or by
+  `import_store/2`. After `rotate_recipient_key/1` a message counts against the
+  version that decrypted it, e.g. `"Alice#v2"`.
+
+  ## Parameters
+
+    * `kid` - The recipient's key identifier
+
+  ## Returns
+
+    * `{:ok, count}`
+    * `{:error, {:recipient_not_found, kid}}` if no key is stored under `kid`
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("AuditedRecipient")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("AuditedSender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("secret", sid, [pub])
+      iex> {:ok, "secret"} = ExTholosPq.decrypt(ct, kid, [sender_pub])
+      iex> ExTholosPq.recipient_key_usage(kid)
+      {:ok, 1}
+
+  """
+  @spec recipient_key_usage(String.t()) :: {:ok, non_neg_integer()} | {:error, error()}
+  def recipient_key_usage(_kid), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Upgrades the recipient key stored under `kid` to the current key format.
+
+  Exported private keys record the version of their format, so a key exported
+  by an older release and loaded with `load_recipient_keypair/3`,
+  `import_recipient_key_encrypted/3` or `import_store/2` keeps working and is
+  recognised as old. Migrating rewrites it in place into the current
+  representation, and later exports of it are in the current format. Keys
+  exported before the version was recorded count as version 0, which only
+  differs from the current version 1 in not saying so. A key exported by a
+  newer release in a format this one does not know is refused when loaded.
+
+  ## Parameters
+
+    * `kid` - The recipient's key identifier
+
+  ## Returns
+
+    * `{:ok, :migrated}` if the key was upgraded
+    * `{:ok, :already_current}` if it was in the current format already
+    * `{:error, {:recipient_not_found, kid}}` if no key is stored under `kid`
+
+  ## Examples
+
+      iex> {:ok, {kid, _pub}} = ExTholosPq.gen_recipient_keypair("CurrentFormat")
+      iex> ExTholosPq.migrate_recipient_key(kid)
+      {:ok, :already_current}
+
+  """
+  @spec migrate_recipient_key(String.t()) ::
+          {:ok, :migrated | :already_current} | {:error, error()}
+  def migrate_recipient_key(_kid), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Removes every recipient and sender keypair from the NIF's key store.
+
+  Each secret key is zeroized as it is removed, as with
+  `delete_recipient_key/1` and `delete_sender_key/1`. Key references from
+  `gen_recipient_key_resource/1` and `gen_sender_key_resource/1` are not in the
+  store and stay usable. Use it to reset state between test runs or before
+  reloading keys on a long-running node.
+
+  ## Returns
+
+    * `:ok`
+
+  ## Examples
+
+      iex> {:ok, _} = ExTholosPq.gen_recipient_keypair("Cleared")
+      iex> ExTholosPq.clear_all_keys()
+      :ok
+      iex> ExTholosPq.list_recipient_kids()
+      {:ok, []}
+
+  """
+  @spec clear_all_keys() :: :ok
+  def clear_all_keys, do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Removes every stored keypair that has expired by `now`.
+
+  A keypair expires when it was generated with the `:ttl_seconds` option of
+  `gen_recipient_keypair/2` or another keygen function and that many seconds
+  have passed, i.e. when its expiry is at or before `now`. Both stores are
+  swept in one pass under their locks, and each secret key is zeroized as it
+  is removed, as with `clear_all_keys/0`. Keypairs without a time to live are
+  kept. Call it periodically, e.g. from a timer, on nodes that create
+  ephemeral keys, so the stores do not grow without bound.
+
+  ## Parameters
+
+    * `now` - The current time in Unix seconds, e.g. `System.os_time(:second)`
+
+  ## Returns
+
+    * `{:ok, {recipients_removed, senders_removed}}`
+
+  ## Examples
+
+      iex> {:ok, _} = ExTholosPq.gen_recipient_keypair("Ephemeral", ttl_seconds: 60)
+      iex> {:ok, _} = ExTholosPq.gen_recipient_keypair("Lasting")
+      iex> ExTholosPq.sweep_expired(System.os_time(:second) + 3600)
+      {:ok, {1, 0}}
+      iex> ExTholosPq.list_recipient_kids()
+      {:ok, ["Lasting"]}
+
+  """
+  @spec sweep_expired(non_neg_integer()) ::
+          {:ok, {non_neg_integer(), non_neg_integer()}}
+  def sweep_expired(_now), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Reports how many keypairs the NIF's key store holds, for capacity monitoring.
+
+  `:approx_bytes` estimates the memory held by the stored keys, their
+  identifiers and public keys. The overhead of the maps themselves is not
+  counted.
+
+  ## Returns
+
+    * `{:ok, %{recipients: count, senders: count, approx_bytes: bytes}}`
+
+  ## Examples
+
+      iex> {:ok, _} = ExTholosPq.gen_sender_keypair("Counted")
+      iex> {:ok, %{senders: senders, approx_bytes: bytes}} = ExTholosPq.key_store_stats()
+      iex> senders > 0 and bytes > 0
+      true
+
+  """
+  @spec key_store_stats() ::
+          {:ok,
+           %{
+             recipients: non_neg_integer(),
+             senders: non_neg_integer(),
+             approx_bytes: non_neg_integer()
+           }}
+  def key_store_stats, do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Sends events from the NIF to `pid`, for observing it in production.
+
+  Every event arrives as a `{:tholos_event, event, metadata}` message:
+
+    * `:key_loaded` with `%{kid: kid}` or `%{sid: sid}` for every key stored
+      by `load_recipient_keypair/3`, `load_sender_keypair/3`,
+      `import_recipient_key_encrypted/3` or `import_store/2`
+    * `:signature_invalid` with `%{kid: kid, reason: reason}` when a wire
+      being decrypted is not signed by an allowed sender
+    * `:decrypt_failed` with `%{kid: kid, reason: reason}` when a wire fails
+      to decrypt for any other reason
+
+  `reason` is what the decrypt function returned in `{:error, reason}`. A
+  decrypt failure is only reported once the wire has been parsed and the
+  recipient key found; a wire that is not one at all fails before that.
+  There is one target for the whole node, and setting another replaces it.
+  Sending is best effort: a target that has exited misses its events.
+
+  ## Parameters
+
+    * `pid` - The process to send events to, or `nil` to stop sending them
+
+  ## Returns
+
+    * `:ok`
+
+  ## Examples
+
+      iex> ExTholosPq.set_log_target(self())
+      :ok
+      iex> {:ok, {sid, pub}} = ExTholosPq.gen_sender_keypair("Logged")
+      iex> {:ok, keypair} = ExTholosPq.export_sender_keypair(sid)
+      iex> :ok = ExTholosPq.load_sender_keypair(sid, pub, keypair)
+      iex> receive do
+      ...>   {:tholos_event, :key_loaded, metadata} -> metadata
+      ...> end
+      %{sid: "Logged"}
+      iex> ExTholosPq.set_log_target(nil)
+      :ok
+
+  """
+  @spec set_log_target(pid() | nil) :: :ok
+  def set_log_target(_pid), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Sets an escrow recipient that can decrypt every message encrypted from now on.
+
+  Every encrypt function, `encrypt_anonymous/2`, `encrypt_batch/3`,
+  `encrypt_partial/3`, `encrypt_timed/3`, `encrypt_with_keys/3` and the
+  streaming `encrypt_init/2` and `encrypt_file/4` included, appends an
+  envelope for the escrow recipient to every ciphertext, after those of the
+  recipients the caller gave, and names its kid in the signed header, so
+  `wire_recipients/1` lists it as `{:escrow, kid}`. A caller that already
+  encrypts for the escrow kid gets no second envelope. With
+  `:per_recipient_aad` the escrow envelope is bound to an empty recipient
+  AAD. The escrow key counts towards `:max_recipients` and must be of the
+  same security level as the other recipients, and hybrid when `hybrid: true`
+  is given. There is one escrow recipient for the whole node, and setting
+  another replaces it. Ciphertexts made before keep the envelopes they have,
+  and so do those `encrypt_cached_for/2` makes from a reference: they get the
+  escrow recipient set when `encrypt_cached_init/2` sealed the message.
+
+  ## Parameters
+
+    * `pub_cbor` - The escrow recipient's public key (CBOR-encoded)
+
+  ## Returns
+
+    * `:ok` on success
+    * `{:error, {:deserialize_recipient, detail}}` if the key cannot be decoded
+    * `{:error, :invalid_recipient_key}` if it is no valid ML-KEM key
+
+  ## Examples
+
+      iex> {:ok, {_kid, escrow_pub}} = ExTholosPq.gen_recipient_keypair("Escrow")
+      iex> ExTholosPq.set_escrow_recipient(escrow_pub)
+      :ok
+      iex> {:ok, {_kid, alice_pub}} = ExTholosPq.gen_recipient_keypair("EscrowAlice")
+      iex> {:ok, {sid, _pub}} = ExTholosPq.gen_sender_keypair("EscrowSender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("audited", sid, [alice_pub])
+      iex> ExTholosPq.wire_recipients(ct)
+      {:ok, ["EscrowAlice", {:escrow, "Escrow"}]}
+      iex> ExTholosPq.clear_escrow_recipient()
+      :ok
+
+  """
+  @spec set_escrow_recipient(binary()) :: :ok | {:error, error()}
+  def set_escrow_recipient(_pub_cbor), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Clears the escrow recipient set by `set_escrow_recipient/1`.
+
+  Ciphertexts encrypted from now on get no escrow envelope; those made before
+  keep theirs.
+
+  ## Returns
+
+    * `:ok`, also if no escrow recipient was set
+
+  ## Examples
+
+      iex> ExTholosPq.clear_escrow_recipient()
+      :ok
+      iex> ExTholosPq.escrow_recipient()
+      {:ok, :none}
+
+  """
+  @spec clear_escrow_recipient() :: :ok
+  def clear_escrow_recipient, do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Returns the kid of the escrow recipient set by `set_escrow_recipient/1`.
+
+  ## Returns
+
+    * `{:ok, kid}` if an escrow recipient is set
+    * `{:ok, :none}` otherwise
+
+  ## Examples
+
+      iex> {:ok, {_kid, escrow_pub}} = ExTholosPq.gen_recipient_keypair("Escrow")
+      iex> :ok = ExTholosPq.set_escrow_recipient(escrow_pub)
+      iex> ExTholosPq.escrow_recipient()
+      {:ok, "Escrow"}
+      iex> ExTholosPq.clear_escrow_recipient()
+      :ok
+
+  """
+  @spec escrow_recipient() :: {:ok, String.t() | :none}
+  def escrow_recipient, do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Lists the recipient kids a ciphertext was encrypted for.
+
+  The kids are read from the per-recipient envelopes without decrypting
+  anything, so no private key is needed. The signature is not checked either:
+  treat the list as a routing hint, not as proof of who can read the message.
+  The envelope of an escrow recipient, see `set_escrow_recipient/1`, is listed
+  as `{:escrow, kid}`.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+
+  ## Returns
+
+    * `{:ok, kids}` in the order the envelopes appear in the ciphertext
+    * `{:error, :malformed_wire}` if the ciphertext cannot be parsed
+    * `{:error, {:unsupported_wire_version, version}}` for a wire format this build cannot read
+
+  ## Examples
+
+      iex> {:ok, {alice, alice_pub}} = ExTholosPq.gen_recipient_keypair("WireAlice")
+      iex> {:ok, {bob, bob_pub}} = ExTholosPq.gen_recipient_keypair("WireBob")
+      iex> {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("WireSender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("hello", sid, [alice_pub, bob_pub])
+      iex> ExTholosPq.wire_recipients(ct) == {:ok, [alice, bob]}
+      true
+
+  """
+  @spec wire_recipients(binary()) ::
+          {:ok, [String.t() | {:escrow, String.t()}]} | {:error, :malformed_wire | error()}
+  def wire_recipients(_ciphertext), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Returns the sender sid a ciphertext claims to come from, without verifying it.
+
+  The sid is read from the header, or from the ciphertext's signer once it has
+  been re-signed with `resign_wire/3`. The signature is **not** checked, so a
+  forged ciphertext can claim any sender. The value is only trustworthy once
+  `decrypt/3` has succeeded with that sender in the allow-list. Use it to drop
+  messages from unknown senders cheaply before attempting decryption, never for
+  authorization or audit.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+
+  ## Returns
+
+    * `{:ok, sid}` with the claimed sender sid
+    * `{:error, :malformed_wire}` if the ciphertext cannot be parsed
+    * `{:error, {:unsupported_wire_version, version}}` for a wire format this build cannot read
+
+  ## Examples
+
+      iex> {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("ClaimedSender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("hello", sid, [pub])
+      iex> ExTholosPq.wire_sender_unverified(ct)
+      {:ok, "ClaimedSender"}
+
+  """
+  @spec wire_sender_unverified(binary()) ::
+          {:ok, String.t()} | {:error, :malformed_wire | error()}
+  def wire_sender_unverified(_ciphertext), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Returns the message id of a ciphertext, without decrypting it.
+
+  Every ciphertext carries an id in its header, a random UUID unless one was
+  given with the `:message_id` option of `encrypt/4`. The header is signed and
+  bound to every envelope and the payload, so a ciphertext whose id was
+  changed fails to decrypt, which lets a recipient keep seen ids in a replay
+  cache. Like `wire_sender_unverified/1` the signature is not checked here,
+  so only record an id once `decrypt/3` has succeeded on its ciphertext.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+
+  ## Returns
+
+    * `{:ok, message_id}` with the id from the header
+    * `{:error, :malformed_wire}` if the ciphertext cannot be parsed
+    * `{:error, {:unsupported_wire_version, version}}` for a wire format this build cannot read
+
+  ## Examples
+
+      iex> {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("IdSender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("hello", sid, [pub], message_id: "order-42")
+      iex> ExTholosPq.wire_message_id(ct)
+      {:ok, "order-42"}
+
+  """
+  @spec wire_message_id(binary()) :: {:ok, String.t()} | {:error, :malformed_wire | error()}
+  def wire_message_id(_ciphertext), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Returns the public metadata of a ciphertext, without decrypting it.
+
+  The metadata given with the `:metadata` option of `encrypt/4` sits in the
+  clear in the header, so intermediaries holding no key can route or label a
+  ciphertext by it. It is covered by the signature, so a ciphertext whose
+  metadata was changed fails to decrypt, but like `wire_message_id/1` nothing
+  is verified here: only trust the value once `decrypt/3` has succeeded on its
+  ciphertext.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+
+  ## Returns
+
+    * `{:ok, metadata}`, an empty binary for a ciphertext without metadata
+    * `{:error, :malformed_wire}` if the ciphertext cannot be parsed
+    * `{:error, {:unsupported_wire_version, version}}` for a wire format this build cannot read
+
+  ## Examples
+
+      iex> {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("MetadataSender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("hello", sid, [pub], metadata: "text/plain")
+      iex> ExTholosPq.wire_metadata(ct)
+      {:ok, "text/plain"}
+
+  """
+  @spec wire_metadata(binary()) :: {:ok, binary()} | {:error, :malformed_wire | error()}
+  def wire_metadata(_ciphertext), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Returns the wire format version of a ciphertext.
+
+  Ciphertexts from `encrypt/3` and friends start with a version byte, currently
+  `1`, ahead of the CBOR bundle. Bundles written by tholos-pq directly, or by
+  releases before the version byte, have none and report version `0`; they
+  still decrypt. Decrypting a version this build does not know returns
+  `{:error, {:unsupported_wire_version, version}}`, and this function reports
+  such versions too, for diagnostics.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+
+  ## Returns
+
+    * `{:ok, version}` on success
+    * `{:error, :malformed_wire}` if the ciphertext does not start like a wire
+
+  ## Examples
+
+      iex> {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("hello", sid, [pub])
+      iex> ExTholosPq.wire_version(ct)
+      {:ok, 1}
+
+  """
+  @spec wire_version(binary()) :: {:ok, non_neg_integer()} | {:error, :malformed_wire}
+  def wire_version(_ciphertext), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Prefixes a ciphertext with its length, for sending several over one socket.
+
+  The length is a 4-byte big-endian integer, so a reader can split
+  concatenated ciphertexts again with `unframe/1`. Framing adds no integrity
+  of its own; each ciphertext is still authenticated when it is decrypted.
+
+  ## Returns
+
+    * `{:ok, framed}` on success
+    * `{:error, :frame_too_large}` if `ciphertext` is 4 GiB or longer
+
+  ## Examples
+
+      iex> ExTholosPq.frame("wire")
+      {:ok, <<0, 0, 0, 4, "wire">>}
+
+  """
+  @spec frame(binary()) :: {:ok, binary()} | {:error, :frame_too_large}
+  def frame(_ciphertext), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Splits the first frame written by `frame/1` off `buffer`.
+
+  Returns the ciphertext and whatever follows it, both as sub-binaries of
+  `buffer`, so nothing is copied. Until the whole frame has arrived the buffer
+  is left for the caller to append to. The length prefix is not checked
+  against any limit, so a reader should cap how much it buffers.
+
+  ## Returns
+
+    * `{:ok, {ciphertext, rest}}` on success
+    * `{:error, :incomplete}` if `buffer` does not hold a whole frame yet
+
+  ## Examples
+
+      iex> ExTholosPq.unframe(<<0, 0, 0, 4, "wire", 0, 0>>)
+      {:ok, {"wire", <<0, 0>>}}
+      iex> ExTholosPq.unframe(<<0, 0, 0, 4, "wi">>)
+      {:error, :incomplete}
+
+  """
+  @spec unframe(binary()) :: {:ok, {binary(), binary()}} | {:error, :incomplete}
+  def unframe(_buffer), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Signs a message with a sender key, without encrypting it.
+
+  Useful for payloads that must be authenticated but not hidden, such as
+  protocol handshakes. The signature is domain separated from the signatures
+  on encrypted messages, so it cannot be replayed as one.
+
+  ## Parameters
+
+    * `sender_id` - The sender's identifier, or a key reference from `gen_sender_key_resource/1`
+    * `message` - The message to sign (binary or string)
+
+  ## Returns
+
+    * `{:ok, signature}` with the CBOR-encoded signature on success
+    * `{:error, reason}` on failure
+
+  ## Examples
+
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Signer")
+      iex> {:ok, signature} = ExTholosPq.sign(sid, "hello")
+      iex> ExTholosPq.verify(sender_pub, "hello", signature)
+      {:ok, :valid}
+
+  """
+  @spec sign(String.t() | reference(), binary()) :: {:ok, binary()} | {:error, error()}
+  def sign(_sender_id, _message), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Verifies a signature made by `sign/2`.
+
+  Only the signer's public key is needed, not a key in the NIF's key store, so
+  verification can run on nodes that hold no secret keys at all.
+
+  ## Parameters
+
+    * `sender_pub_key` - The signer's CBOR-encoded public key
+    * `message` - The signed message
+    * `signature` - The CBOR-encoded signature from `sign/2`
+
+  ## Returns
+
+    * `{:ok, :valid}` if the signature is valid for this sender and message
+    * `{:ok, :invalid}` otherwise, including for a signature made by another sender
+    * `{:error, :malformed_signature}` if `signature` is not structurally a signature
+    * `{:error, reason}` if the public key cannot be decoded
+
+  A signature from `sign_final/1` is checked here too, against the SHA-512 of
+  `message`, so a message signed in pieces can be verified in one go.
+
+  ## Examples
+
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Signer")
+      iex> {:ok, signature} = ExTholosPq.sign(sid, "hello")
+      iex> ExTholosPq.verify(sender_pub, "hello", signature)
+      {:ok, :valid}
+      iex> ExTholosPq.verify(sender_pub, "goodbye", signature)
+      {:ok, :invalid}
+
+  """
+  @spec verify(binary(), binary(), binary()) ::
+          {:ok, :valid | :invalid} | {:error, :malformed_signature | error()}
+  def verify(_sender_pub_key, _message, _signature), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Starts signing a message fed in pieces, for messages too large to hold in memory.
+
+  Each piece passed to `sign_update/2` is hashed with SHA-512, and
+  `sign_final/1` signs the digest. The signature is marked as prehashed and
+  domain separated from those made by `sign/2`, so one never passes as the
+  other; `verify/3` and `verify_final/2` both accept it. The sender keypair is
+  copied into the stream.
+
+  ## Parameters
+
+    * `sender_id` - The sender's identifier, or a key reference from `gen_sender_key_resource/1`
+
+  ## Returns
+
+    * `{:ok, stream}` with a reference to the signing state
+    * `{:error, reason}` if the sender is unknown
+
+  ## Examples
+
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("StreamSigner")
+      iex> {:ok, signer} = ExTholosPq.sign_init(sid)
+      iex> :ok = ExTholosPq.sign_update(signer, "hello ")
+      iex> :ok = ExTholosPq.sign_update(signer, "world")
+      iex> {:ok, signature} = ExTholosPq.sign_final(signer)
+      iex> ExTholosPq.verify(sender_pub, "hello world", signature)
+      {:ok, :valid}
+
+  """
+  @spec sign_init(String.t() | reference()) :: {:ok, reference()} | {:error, error()}
+  def sign_init(_sender_id), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Feeds the next piece of a message to a stream started with `sign_init/1`.
+
+  ## Returns
+
+    * `:ok` on success
+    * `{:error, :stream_closed}` if the stream was finished earlier
+
+  """
+  @spec sign_update(reference(), binary()) :: :ok | {:error, :stream_closed}
+  def sign_update(_stream, _chunk), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Signs everything fed to the stream and closes it.
+
+  ## Returns
+
+    * `{:ok, signature}` with the CBOR-encoded prehashed signature
+    * `{:error, :stream_closed}` if the stream was finished earlier
+
+  """
+  @spec sign_final(reference()) :: {:ok, binary()} | {:error, :stream_closed | error()}
+  def sign_final(_stream), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Starts verifying a message fed in pieces against a signature from `sign_final/1`.
+
+  ## Parameters
+
+    * `sender_pub_key` - The signer's CBOR-encoded public key
+
+  ## Returns
+
+    * `{:ok, stream}` with a reference to the verification state
+    * `{:error, reason}` if the public key cannot be decoded
+
+  ## Examples
+
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("StreamVerified")
+      iex> {:ok, signer} = ExTholosPq.sign_init(sid)
+      iex> :ok = ExTholosPq.sign_update(signer, "hello")
+      iex> {:ok, signature} = ExTholosPq.sign_final(signer)
+      iex> {:ok, verifier} = ExTholosPq.verify_init(sender_pub)
+      iex> :ok = ExTholosPq.verify_update(verifier, "hel")
+      iex> :ok = ExTholosPq.verify_update(verifier, "lo")
+      iex> ExTholosPq.verify_final(verifier, signature)
+      {:ok, :valid}
+
+  """
+  @spec verify_init(binary()) :: {:ok, reference()} | {:error, error()}
+  def verify_init(_sender_pub_key), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Feeds the next piece of a message to a stream started with `verify_init/1`.
+
+  ## Returns
+
+    * `:ok` on success
+    * `{:error, :stream_closed}` if the stream was finished earlier
+
+  """
+  @spec verify_update(reference(), binary()) :: :ok | {:error, :stream_closed}
+  def verify_update(_stream, _chunk), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Checks a signature against everything fed to the stream and closes it.
+
+  ## Returns
+
+    * `{:ok, :valid}` if the signature is valid for this sender and message
+    * `{:ok, :invalid}` otherwise
+    * `{:error, :not_prehashed}` for a signature made by `sign/2`, which only
+      `verify/3` can check
+    * `{:error, :malformed_signature}` if `signature` is not structurally a signature
+    * `{:error, :stream_closed}` if the stream was finished earlier
+
+  """
+  @spec verify_final(reference(), binary()) ::
+          {:ok, :valid | :invalid}
+          | {:error, :not_prehashed | :malformed_signature | :stream_closed}
+  def verify_final(_stream, _signature), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Starts a streaming encryption for multiple recipients.
+
+  For messages too large to hold in memory: the Kyber encapsulations happen
+  here, once, and the message is then fed through `encrypt_update/2` in pieces
+  of any size and closed with `encrypt_final/1`. The concatenation of every
+  returned binary, in order, is the ciphertext.
+
+  The output is its own format, read by `decrypt_init/2`, not by `decrypt/3`.
+  The sender keypair is copied into the stream, so deleting it from the key
+  store does not affect a stream already started.
+
+  ## Parameters
+
+    * `sender_id` - The sender's identifier, or a key reference from `gen_sender_key_resource/1`
+    * `recipient_pub_keys` - List of recipient public keys (CBOR-encoded)
+
+  ## Returns
+
+    * `{:ok, stream}` with a reference to the stream state
+    * `{:error, reason}` on failure
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, enc} = ExTholosPq.encrypt_init(sid, [pub])
+      iex> {:ok, part1} = ExTholosPq.encrypt_update(enc, "hello ")
+      iex> {:ok, part2} = ExTholosPq.encrypt_update(enc, "world")
+      iex> {:ok, tail} = ExTholosPq.encrypt_final(enc)
+      iex> {:ok, dec} = ExTholosPq.decrypt_init(kid, [sender_pub])
+      iex> {:ok, plain} = ExTholosPq.decrypt_update(dec, part1 <> part2 <> tail)
+      iex> {:ok, plain_tail} = ExTholosPq.decrypt_final(dec)
+      iex> plain <> plain_tail
+      "hello world"
+
+  """
+  @spec encrypt_init(String.t() | reference(), list(binary())) ::
+          {:ok, reference()} | {:error, error()}
+  def encrypt_init(_sender_id, _recipient_pub_keys), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Encrypts the next piece of a stream started with `encrypt_init/2`.
+
+  Plaintext is sealed in 64 KiB frames, so the result holds only the frames
+  completed so far and is often empty for small pieces.
+
+  ## Returns
+
+    * `{:ok, ciphertext_chunk}` on success
+    * `{:error, :stream_closed}` if the stream was finished or failed earlier
+    * `{:error, reason}` on failure, which also closes the stream
+
+  """
+  @spec encrypt_update(reference(), binary()) :: {:ok, binary()} | {:error, error()}
+  def encrypt_update(_stream, _chunk), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Finishes a streaming encryption and closes the stream.
+
+  ## Returns
+
+    * `{:ok, tail}` with the last frame and the sender's signature over the stream
+    * `{:error, :stream_closed}` if the stream was finished or failed earlier
+    * `{:error, reason}` on failure
+
+  """
+  @spec encrypt_final(reference()) :: {:ok, binary()} | {:error, error()}
+  def encrypt_final(_stream), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Starts a streaming decryption of a ciphertext produced by `encrypt_init/2`.
+
+  The recipient's private key is copied into the stream. Ciphertext can be fed
+  to `decrypt_update/2` in pieces of any size, regardless of how it was split
+  when it was encrypted.
+
+  ## Parameters
+
+    * `kid` - The recipient's key identifier, or a key reference from `gen_recipient_key_resource/1`
+    * `allowed_sender_pub_keys` - List of allowed sender public keys for verification (CBOR-encoded)
+
+  ## Returns
+
+    * `{:ok, stream}` with a reference to the stream state
+    * `{:error, reason}` on failure
+
+  """
+  @spec decrypt_init(String.t() | reference(), list(binary())) ::
+          {:ok, reference()} | {:error, error()}
+  def decrypt_init(_kid, _allowed_sender_pub_keys), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Decrypts the next piece of a stream started with `decrypt_init/2`.
+
+  Every frame is authenticated before its plaintext is returned, but the
+  sender's signature over the whole stream is only checked by
+  `decrypt_final/1`. Until that succeeds, treat the plaintext as unverified:
+  buffer it, or be ready to discard it.
+
+  ## Returns
+
+    * `{:ok, plaintext_chunk}` on success; may be empty while a frame fills up
+    * `{:error, :stream_closed}` if the stream was finished or failed earlier
+    * `{:error, reason}` on failure, which also closes the stream
+
+  """
+  @spec decrypt_update(reference(), binary()) :: {:ok, binary()} | {:error, error()}
+  def decrypt_update(_stream, _chunk), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Finishes a streaming decryption and closes the stream.
+
+  Decrypts the last frame and verifies the sender's signature over the whole
+  stream, which also detects a stream that was cut short: the frame held back
+  as the last one then fails to decrypt.
+
+  ## Returns
+
+    * `{:ok, tail}` with the remaining plaintext
+    * `{:error, :signature_invalid}` if the stream was not signed by the sender
+    * `{:error, :auth_tag_mismatch}` if the stream is truncated or was altered
+    * `{:error, {:malformed_wire, detail}}` if too little of the stream arrived to hold a frame
+    * `{:error, :stream_closed}` if the stream was finished or failed earlier
+    * `{:error, reason}` on other failures
+
+  """
+  @spec decrypt_final(reference()) :: {:ok, binary()} | {:error, error()}
+  def decrypt_final(_stream), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Returns the size of the preamble at the start of a stream ciphertext.
+
+  The preamble holds the signed header and the recipient envelopes, and the
+  first frame starts right after it. Its size is read from the first 9 bytes of
+  the stream, so that exactly the preamble can be fed to `decrypt_update/2`
+  before reading single chunks with `decrypt_chunk/3`.
+
+  ## Returns
+
+    * `{:ok, size}` on success
+    * `{:error, {:malformed_wire, detail}}` if `head` is shorter than 9 bytes or
+      does not start a stream
+
+  """
+  @spec stream_preamble_size(binary()) :: {:ok, pos_integer()} | {:error, error()}
+  def stream_preamble_size(_head), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Returns the byte range of chunk `index` in a stream ciphertext of
+  `stream_size` bytes.
+
+  The preamble must have been fed to `decrypt_update/2` first, see
+  `stream_preamble_size/1`. Every chunk but the last holds 64 KiB of plaintext,
+  so the range is computed from the chunk size in the signed header; the last
+  chunk's range ends before the sender's signature.
+
+  ## Returns
+
+    * `{:ok, {offset, length}}` on success
+    * `{:error, :chunk_out_of_range}` if the stream has no chunk `index`
+    * `{:error, :preamble_pending}` if the preamble has not been fed yet
+    * `{:error, :stream_closed}` if the stream was finished or failed earlier
+    * `{:error, reason}` on other failures
+
+  """
+  @spec stream_chunk_range(reference(), non_neg_integer(), non_neg_integer()) ::
+          {:ok, {non_neg_integer(), pos_integer()}} | {:error, error()}
+  def stream_chunk_range(_stream, _index, _stream_size), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Decrypts chunk `index` of a stream ciphertext on its own.
+
+  `frame` is the range of the ciphertext given by `stream_chunk_range/3`, so a
+  byte range of a large blob can be decrypted without reading or decrypting
+  the chunks in front of it. The stream is not advanced, and a failure does not
+  close it, so any number of chunks can be read in any order.
+
+  Each chunk is authenticated under the content key and its index, so it
+  cannot be altered or read at another position, but the sender's signature
+  over the whole stream is only checked by reading all of it with
+  `decrypt_update/2` and `decrypt_final/1`, and neither is a stream that was
+  cut short detected. Any recipient knows the content key, so treat chunks as
+  unverified unless every recipient is trusted. Chunk boundaries are visible in
+  the ciphertext and every chunk has a fixed size, so whoever stores the stream
+  can tell which part of the plaintext a read touches.
+
+  ## Returns
+
+    * `{:ok, plaintext_chunk}` on success
+    * `{:error, :auth_tag_mismatch}` if the chunk was altered or belongs
+      at another index
+    * `{:error, :preamble_pending}` if the preamble has not been fed yet
+    * `{:error, :stream_closed}` if the stream was finished or failed earlier
+    * `{:error, reason}` on other failures
+
+  """
+  @spec decrypt_chunk(reference(), non_neg_integer(), binary()) ::
+          {:ok, binary()} | {:error, error()}
+  def decrypt_chunk(_stream, _index, _frame), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Encrypts the file at `input_path` into `output_path`.
+
+  The file is read and written in Rust through buffered IO, so its contents
+  never pass through the BEAM and it can be far larger than memory. The output
+  is a stream ciphertext, as from `encrypt_init/2`, and can be decrypted with
+  `decrypt_file/4` or the streaming functions. An existing `output_path` is
+  overwritten.
+
+  ## Parameters
+
+    * `input_path` - Path of the file to encrypt
+    * `output_path` - Path to write the ciphertext to
+    * `sender_id` - The sender's identifier, or a key reference from `gen_sender_key_resource/1`
+    * `recipient_pub_keys` - List of recipient public keys (CBOR-encoded)
+
+  ## Returns
+
+    * `:ok` on success
+    * `{:error, {:io, detail}}` if a file cannot be read or written
+    * `{:error, reason}` on other failures
+
+  """
+  @spec encrypt_file(String.t(), String.t(), String.t() | reference(), list(binary())) ::
+          :ok | {:error, error()}
+  def encrypt_file(_input_path, _output_path, _sender_id, _recipient_pub_keys),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Decrypts the stream ciphertext in `input_path` into `output_path`.
+
+  The plaintext is written to `output_path <> ".partial"` and only renamed to
+  `output_path` once the sender's signature over the whole stream has been
+  verified. On any failure the partial file is removed, so `output_path` never
+  holds unverified plaintext.
+
+  ## Parameters
+
+    * `input_path` - Path of the ciphertext to decrypt
+    * `output_path` - Path to write the plaintext to
+    * `kid` - The recipient's key identifier, or a key reference from `gen_recipient_key_resource/1`
+    * `allowed_sender_pub_keys` - List of allowed sender public keys for verification (CBOR-encoded)
+
+  ## Returns
+
+    * `:ok` on success
+    * `{:error, {:io, detail}}` if a file cannot be read or written
+    * `{:error, reason}` on other failures, as for `decrypt_final/1`
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> dir = Path.join(System.tmp_dir!(), "ex_tholos_pq_doctest")
+      iex> File.mkdir_p!(dir)
+      iex> File.write!(Path.join(dir, "plain"), "hello file")
+      iex> ExTholosPq.encrypt_file(Path.join(dir, "plain"), Path.join(dir, "sealed"), sid, [pub])
+      :ok
+      iex> ExTholosPq.decrypt_file(Path.join(dir, "sealed"), Path.join(dir, "opened"), kid, [sender_pub])
+      :ok
+      iex> File.read!(Path.join(dir, "opened"))
+      "hello file"
+
+  """
+  @spec decrypt_file(String.t(), String.t(), String.t() | reference(), list(binary())) ::
+          :ok | {:error, error()}
+  def decrypt_file(_input_path, _output_path, _kid, _allowed_sender_pub_keys),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Exports a stored recipient private key encrypted under a password.
+
+  A key is derived from the password with Argon2id (64 MiB, 3 passes) and the
+  private key is sealed under it with XChaCha20-Poly1305. The blob records the
+  KDF parameters, salt and nonce, so `import_recipient_key_encrypted/3` needs
+  only the blob and the password. Use this to keep recipient keys on disk.
+
+  ## Parameters
+
+    * `kid` - Key identifier of a stored recipient keypair
+    * `password` - The password to protect the key with
+
+  ## Returns
+
+    * `{:ok, encrypted_blob}` on success
+    * `{:error, {:recipient_not_found, kid}}` if no key is stored under `kid`
+
+  """
+  @spec export_recipient_key_encrypted(String.t(), binary()) ::
+          {:ok, binary()} | {:error, error()}
+  def export_recipient_key_encrypted(_kid, _password), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Loads a recipient private key exported by `export_recipient_key_encrypted/2`
+  into the key store under `kid`, replacing any key stored there.
+
+  ## Parameters
+
+    * `kid` - Key identifier to store the key under
+    * `encrypted_blob` - The blob from `export_recipient_key_encrypted/2`
+    * `password` - The password it was exported with
+
+  ## Returns
+
+    * `{:ok, {kid, public_key}}` on success where public_key is CBOR-encoded
+    * `{:error, :bad_password}` if the password is wrong or the blob was altered
+    * `{:error, {:invalid_private_key, detail}}` if the blob cannot be parsed
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, blob} = ExTholosPq.export_recipient_key_encrypted(kid, "correct horse")
+      iex> ExTholosPq.import_recipient_key_encrypted(kid, blob, "wrong")
+      {:error, :bad_password}
+      iex> {:ok, {^kid, imported}} = ExTholosPq.import_recipient_key_encrypted(kid, blob, "correct horse")
+      iex> imported == pub
+      true
+
+  """
+  @spec import_recipient_key_encrypted(String.t(), binary(), binary()) ::
+          {:ok, {String.t(), binary()}} | {:error, :bad_password | error()}
+  def import_recipient_key_encrypted(_kid, _encrypted_blob, _password),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Loads a recipient keypair kept outside the NIF into the key store under
+  `kid`, replacing any keypair stored there.
+
+  Use it to seed the store at boot from keys persisted elsewhere. The halves
+  are checked to belong together before anything is stored: the private key
+  must embed the public key and decapsulate a test encapsulation to it.
+
+  ## Parameters
+
+    * `kid` - Key identifier to store the keypair under
+    * `public_key` - The CBOR-encoded public key, whose kid must be `kid`
+    * `private_key` - The CBOR-encoded private key from `export_recipient_key/1`
+
+  ## Returns
+
+    * `:ok` on success
+    * `{:error, :key_mismatch}` if the private key is not that of the public key
+    * `{:error, {:kid_mismatch, kid, key_kid}}` if the public key names another kid
+    * `{:error, reason}` if either half cannot be deserialized
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Persisted")
+      iex> {:ok, priv} = ExTholosPq.export_recipient_key(kid)
+      iex> :ok = ExTholosPq.delete_recipient_key(kid)
+      iex> ExTholosPq.load_recipient_keypair(kid, pub, priv)
+      :ok
+
+  """
+  @spec load_recipient_keypair(String.t(), binary(), binary()) ::
+          :ok | {:error, :key_mismatch | {:kid_mismatch, String.t(), String.t()} | error()}
+  def load_recipient_keypair(_kid, _public_key, _private_key),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Loads a sender keypair kept outside the NIF into the key store under `sid`,
+  replacing any keypair stored there.
+
+  The twin of `load_recipient_keypair/3`. The keypair must hold the public key
+  and sign a test message that verifies under it.
+
+  ## Parameters
+
+    * `sid` - Sender identifier to store the keypair under
+    * `public_key` - The CBOR-encoded public key, whose sid must be `sid`
+    * `keypair` - The CBOR-encoded keypair from `export_sender_keypair/1`, whose sid must be `sid`
+
+  ## Returns
+
+    * `:ok` on success
+    * `{:error, :key_mismatch}` if the keypair is not that of the public key
+    * `{:error, {:sid_mismatch, sid, key_sid}}` if the public key or the keypair names another sid
+    * `{:error, reason}` if either half cannot be deserialized
+
+  ## Examples
+
+      iex> {:ok, {sid, pub}} = ExTholosPq.gen_sender_keypair("PersistedSender")
+      iex> {:ok, keypair} = ExTholosPq.export_sender_keypair(sid)
+      iex> :ok = ExTholosPq.delete_sender_key(sid)
+      iex> ExTholosPq.load_sender_keypair(sid, pub, keypair)
+      :ok
+
+  """
+  @spec load_sender_keypair(String.t(), binary(), binary()) ::
+          :ok | {:error, :key_mismatch | {:sid_mismatch, String.t(), String.t()} | error()}
+  def load_sender_keypair(_sid, _public_key, _keypair),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Exports every keypair in the key store, private keys included, as one blob
+  encrypted under a password.
+
+  Meant for dumping a node's keys at shutdown and loading them with
+  `import_store/2` at boot. The blob is protected the same way as
+  `export_recipient_key_encrypted/2`. Keys held by resources are not part of
+  the store and are not exported.
+
+  ## Parameters
+
+    * `password` - The password to protect the store with
+
+  ## Returns
+
+    * `{:ok, encrypted_blob}` on success
+    * `{:error, reason}` on failure
+
+  """
+  @spec export_store(binary()) :: {:ok, binary()} | {:error, error()}
+  def export_store(_password), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Loads every keypair from an `export_store/1` blob into the key store.
+
+  Keys already stored under the same kid or sid are replaced; other stored keys
+  are kept. The blob is checked completely before anything is stored, so a
+  failed import leaves the store unchanged.
+
+  ## Parameters
+
+    * `encrypted_blob` - The blob from `export_store/1`
+    * `password` - The password it was exported with
+
+  ## Returns
+
+    * `{:ok, {recipient_count, sender_count}}` with the number of keys loaded
+    * `{:error, :bad_password}` if the password is wrong or the blob was altered
+    * `{:error, :corrupt_store}` if the blob is truncated or cannot be parsed
+    * `{:error, {:invalid_identifier, reason}}` if a kid or sid in the blob is
+      not a valid one
+
+  ## Examples
+
+      iex> {:ok, {kid, _pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, blob} = ExTholosPq.export_store("correct horse")
+      iex> :ok = ExTholosPq.delete_recipient_key(kid)
+      iex> {:ok, {_recipients, _senders}} = ExTholosPq.import_store(blob, "correct horse")
+      iex> {:ok, kids} = ExTholosPq.list_recipient_kids()
+      iex> kid in kids
+      true
+
+  """
+  @spec import_store(binary(), binary()) ::
+          {:ok, {non_neg_integer(), non_neg_integer()}}
+          | {:error, :bad_password | :corrupt_store | error()}
+  def import_store(_encrypted_blob, _password), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Encrypts many messages for the same recipients, performing the Kyber
+  encapsulation to each recipient once for the whole batch.
+
+  Each message becomes an ordinary ciphertext, decrypted on its own with
+  `decrypt/3`, with its own header, content key, nonces and signature. Only
+  the KEM step is shared: every ciphertext in the batch carries the same KEM
+  ciphertext per recipient, and the key that wraps each content key is derived
+  from the one shared secret behind it together with that message's header.
+
+  ## Security
+
+  Sharing the KEM context across messages has consequences that one
+  `encrypt/3` call per message does not:
+
+    * Ciphertexts of one batch are linkable: anyone who sees them can tell
+      they were produced together, from the identical KEM ciphertexts.
+    * The shared secret per recipient protects the whole batch. Anyone who
+      learns it, e.g. from the memory of a process that decrypted one of the
+      messages, can unwrap the content key of every message in the batch for
+      that recipient, not just the one.
+
+  Use it for many messages to the same group at the same time, where neither
+  matters; keep it out of places where individual messages must stay
+  unlinkable or separately compromised.
+
+  ## Parameters
+
+    * `sender_id` - The sender's identifier, or a key reference from `gen_sender_key_resource/1`
+    * `messages` - List of messages to encrypt
+    * `recipient_pub_keys` - List of recipient public keys (CBOR-encoded)
+
+  ## Returns
+
+    * `{:ok, ciphertexts}` on success, in the order of `messages`
+    * `{:error, reason}` on failure
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, [ct1, ct2]} = ExTholosPq.encrypt_batch(sid, ["first", "second"], [pub])
+      iex> ExTholosPq.decrypt(ct2, kid, [sender_pub])
+      {:ok, "second"}
+      iex> ExTholosPq.decrypt(ct1, kid, [sender_pub])
+      {:ok, "first"}
+
+  """
+  @spec encrypt_batch(String.t() | reference(), list(binary()), list(binary())) ::
+          {:ok, list(binary())} | {:error, error()}
+  def encrypt_batch(_sender_id, _messages, _recipient_pub_keys),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Encrypts a message once for recipient sets chosen later with
+  `encrypt_cached_for/2`.
+
+  The message is sealed under a fresh content key, which the returned
+  reference holds until the BEAM garbage collector drops it, along with a copy
+  of the sender keypair. Each `encrypt_cached_for/2` call then only wraps that
+  key for its recipients and signs, so sending the same large message to
+  changing subsets of recipients costs one payload encryption in total.
+
+  ## Security
+
+  Every ciphertext made from one reference carries the same payload, message
+  id and timestamp, so they can be told apart only by their recipients and
+  are linkable. The header, which the payload is bound to, is fixed before
+  any recipient is known, so it names none; `wire_recipients/1` still lists
+  the recipients of each ciphertext. A recipient of one ciphertext holds the
+  content key of all of them, so leaving a recipient out of a later set does
+  not keep the message from it. No escrow envelope is added.
+
+  ## Parameters
+
+    * `sender_id` - The sender's identifier, or a key reference from `gen_sender_key_resource/1`
+    * `message` - The message to encrypt
+
+  ## Returns
+
+    * `{:ok, ctx}` with a reference to the sealed message
+    * `{:error, reason}` if the sender is unknown
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, ctx} = ExTholosPq.encrypt_cached_init(sid, "announcement")
+      iex> {:ok, ct} = ExTholosPq.encrypt_cached_for(ctx, [pub])
+      iex> ExTholosPq.decrypt(ct, kid, [sender_pub])
+      {:ok, "announcement"}
+
+  """
+  @spec encrypt_cached_init(String.t() | reference(), binary()) ::
+          {:ok, reference()} | {:error, error()}
+  def encrypt_cached_init(_sender_id, _message), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Makes a ciphertext of a message sealed by `encrypt_cached_init/2` for
+  `recipient_pub_keys`, without encrypting the message again.
+
+  The content key is wrapped for each recipient and the result signed by the
+  sender given to `encrypt_cached_init/2`. The reference can be used any
+  number of times, from any process.
+
+  ## Parameters
+
+    * `ctx` - A reference from `encrypt_cached_init/2`
+    * `recipient_pub_keys` - List of recipient public keys (CBOR-encoded)
+
+  ## Returns
+
+    * `{:ok, ciphertext}` on success
+    * `{:error, :no_recipients}` if `recipient_pub_keys` is empty
+    * `{:error, reason}` on other failures
+
+  ## Examples
+
+      iex> {:ok, {kid_a, pub_a}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {kid_b, pub_b}} = ExTholosPq.gen_recipient_keypair("Bob")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, ctx} = ExTholosPq.encrypt_cached_init(sid, "announcement")
+      iex> {:ok, ct} = ExTholosPq.encrypt_cached_for(ctx, [pub_b])
+      iex> ExTholosPq.decrypt(ct, kid_b, [sender_pub])
+      {:ok, "announcement"}
+      iex> {:error, :no_envelope_for_recipient} = ExTholosPq.decrypt(ct, kid_a, [sender_pub])
+      iex> {:ok, ct} = ExTholosPq.encrypt_cached_for(ctx, [pub_a, pub_b])
+      iex> ExTholosPq.decrypt(ct, kid_a, [sender_pub])
+      {:ok, "announcement"}
+
+  """
+  @spec encrypt_cached_for(reference(), list(binary())) :: {:ok, binary()} | {:error, error()}
+  def encrypt_cached_for(_ctx, _recipient_pub_keys), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Grants a new recipient access to an existing ciphertext without re-encrypting
+  its payload.
+
+  The content key is unwrapped with the private key of `kid`, an existing
+  recipient of the ciphertext held in the key store, wrapped again for the new
+  recipient in an added envelope, and the result is re-signed by the sender.
+  Only the sender that signed the ciphertext can do this, so that the new
+  ciphertext stays authenticated under the same sender.
+
+  The header is not changed, because the payload and every envelope are bound
+  to it. Its list of recipients therefore keeps naming the original
+  recipients; `wire_recipients/1` lists every envelope, including added ones.
+  Ciphertexts encrypted with associated data need it passed to
+  `add_recipient/5`, and those with `:per_recipient_aad` are refused; both
+  with `{:error, :aad_mismatch}`.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+    * `sender_id` - The sender that signed it, or a key reference from `gen_sender_key_resource/1`
+    * `new_recipient_pub_key` - Public key of the recipient to add (CBOR-encoded)
+    * `kid` - An existing recipient's key identifier, or a key reference from `gen_recipient_key_resource/1`
+
+  ## Returns
+
+    * `{:ok, new_ciphertext}` on success
+    * `{:error, {:sender_not_found, sid}}` if the sender is not in the key store
+    * `{:error, :signature_invalid}` if the ciphertext was not signed by the sender
+    * `{:error, :no_envelope_for_recipient}` if `kid` is not a recipient of the ciphertext
+    * `{:error, :recipient_already_present}` if the new recipient already has access
+    * `{:error, :aad_mismatch}` if the ciphertext was encrypted with associated data
+    * `{:error, reason}` on other failures
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {new_kid, new_pub}} = ExTholosPq.gen_recipient_keypair("Bob")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("shared later", sid, [pub])
+      iex> {:ok, ct} = ExTholosPq.add_recipient(ct, sid, new_pub, kid)
+      iex> ExTholosPq.decrypt(ct, new_kid, [sender_pub])
+      {:ok, "shared later"}
+
+  """
+  @spec add_recipient(binary(), String.t() | reference(), binary(), String.t() | reference()) ::
+          {:ok, binary()} | {:error, :recipient_already_present | :aad_mismatch | error()}
+  def add_recipient(_ciphertext, _sender_id, _new_recipient_pub_key, _kid),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Grants a new recipient access to an existing ciphertext with options, e.g.
+  one that was bound to associated data with `encrypt/4`.
+
+  Works like `add_recipient/4`. The envelopes are bound to the associated
+  data as the payload is, so the new envelope is bound to it as well.
+
+  ## Options
+
+    * `:aad` - The associated data given to `encrypt/4`
+
+  ## Returns
+
+    * `{:ok, new_ciphertext}` on success
+    * `{:error, :aad_mismatch}` if `:aad` differs from the associated data used to encrypt
+    * `{:error, {:invalid_option, name}}` for an unknown option
+    * `{:error, reason}` as for `add_recipient/4`
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {new_kid, new_pub}} = ExTholosPq.gen_recipient_keypair("Bob")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("bound", sid, [pub], aad: "tenant-42")
+      iex> {:ok, ct} = ExTholosPq.add_recipient(ct, sid, new_pub, kid, aad: "tenant-42")
+      iex> ExTholosPq.decrypt(ct, new_kid, [sender_pub], "tenant-42")
+      {:ok, "bound"}
+
+  """
+  @spec add_recipient(
+          binary(),
+          String.t() | reference(),
+          binary(),
+          String.t() | reference(),
+          keyword()
+        ) :: {:ok, binary()} | {:error, :recipient_already_present | :aad_mismatch | error()}
+  def add_recipient(_ciphertext, _sender_id, _new_recipient_pub_key, _kid, _opts),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Removes a recipient's access to a ciphertext without re-encrypting its
+  payload.
+
+  The envelope of `kid` is dropped and the result is re-signed by the sender,
+  which must be the sender that signed the ciphertext. As with
+  `add_recipient/4` the header is not changed.
+
+  This does **not** provide forward secrecy: the content key stays the same,
+  so a removed recipient who kept the old ciphertext, or the content key
+  recovered from it, can still read the payload. Only the returned ciphertext
+  stops carrying an envelope for `kid`. To revoke access for real, encrypt the
+  plaintext again for the remaining recipients.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+    * `sender_id` - The sender that signed it, or a key reference from `gen_sender_key_resource/1`
+    * `kid` - Key identifier of the recipient to remove
+
+  ## Returns
+
+    * `{:ok, new_ciphertext}` on success
+    * `{:error, {:sender_not_found, sid}}` if the sender is not in the key store
+    * `{:error, :signature_invalid}` if the ciphertext was not signed by the sender
+    * `{:error, :recipient_not_present}` if `kid` is not a recipient of the ciphertext
+    * `{:error, reason}` on other failures
+
+  ## Examples
+
+      iex> {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {bob_kid, bob_pub}} = ExTholosPq.gen_recipient_keypair("Bob")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("not for Bob", sid, [pub, bob_pub])
+      iex> {:ok, ct} = ExTholosPq.remove_recipient(ct, sid, "Bob")
+      iex> {:error, :no_envelope_for_recipient} = ExTholosPq.decrypt(ct, bob_kid, [sender_pub])
+
+  """
+  @spec remove_recipient(binary(), String.t() | reference(), String.t()) ::
+          {:ok, binary()} | {:error, :recipient_not_present | error()}
+  def remove_recipient(_ciphertext, _sender_id, _kid), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Hands a recipient's access to a ciphertext over to a new key, e.g. one made
+  by `rotate_recipient_key/1`, without re-encrypting its payload.
+
+  The content key is unwrapped with the private key of `old_kid`, which must be
+  in the key store, wrapped again for the new key in an envelope that takes the
+  place of the old one, and the result is re-signed by the sender, which must be
+  the sender that signed the ciphertext. Once every stored ciphertext is
+  rewrapped, the old key can be deleted. As with `add_recipient/4` the header
+  is not changed and keeps naming `old_kid`, ciphertexts encrypted with
+  associated data need `rewrap_for_recipient/5`, and those with
+  `:per_recipient_aad` are refused with `{:error, :aad_mismatch}`.
+
+  As with `remove_recipient/3`, the content key stays the same, so the old key
+  still opens the original ciphertext and any copy of it.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+    * `old_kid` - The key identifier of the recipient's old key, or a key reference from `gen_recipient_key_resource/1`
+    * `new_recipient_pub_key` - Public key of the new key (CBOR-encoded)
+    * `sender_id` - The sender that signed it, or a key reference from `gen_sender_key_resource/1`
+
+  ## Returns
+
+    * `{:ok, new_ciphertext}` on success
+    * `{:error, {:recipient_not_found, kid}}` if `old_kid` is not in the key store
+    * `{:error, {:sender_not_found, sid}}` if the sender is not in the key store
+    * `{:error, :signature_invalid}` if the ciphertext was not signed by the sender
+    * `{:error, :no_envelope_for_recipient}` if `old_kid` is not a recipient of the ciphertext
+    * `{:error, :recipient_already_present}` if another envelope already has the new key's kid
+    * `{:error, :aad_mismatch}` if the ciphertext was encrypted with associated data
+    * `{:error, reason}` on other failures
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("kept across rotation", sid, [pub])
+      iex> {:ok, {2, new_pub}} = ExTholosPq.rotate_recipient_key(kid)
+      iex> {:ok, ct} = ExTholosPq.rewrap_for_recipient(ct, kid, new_pub, sid)
+      iex> ExTholosPq.wire_recipients(ct)
+      {:ok, ["Alice#v2"]}
+      iex> ExTholosPq.decrypt(ct, "Alice#v2", [sender_pub])
+      {:ok, "kept across rotation"}
+
+  """
+  @spec rewrap_for_recipient(
+          binary(),
+          String.t() | reference(),
+          binary(),
+          String.t() | reference()
+        ) :: {:ok, binary()} | {:error, :recipient_already_present | :aad_mismatch | error()}
+  def rewrap_for_recipient(_ciphertext, _old_kid, _new_recipient_pub_key, _sender_id),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Hands a recipient's access to a ciphertext over to a new key with options,
+  e.g. for one that was bound to associated data with `encrypt/4`.
+
+  Works like `rewrap_for_recipient/4`, with the options of `add_recipient/5`.
+
+  ## Returns
+
+    * `{:ok, new_ciphertext}` on success
+    * `{:error, :aad_mismatch}` if `:aad` differs from the associated data used to encrypt
+    * `{:error, {:invalid_option, name}}` for an unknown option
+    * `{:error, reason}` as for `rewrap_for_recipient/4`
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("bound", sid, [pub], aad: "tenant-42")
+      iex> {:ok, {2, new_pub}} = ExTholosPq.rotate_recipient_key(kid)
+      iex> {:ok, ct} = ExTholosPq.rewrap_for_recipient(ct, kid, new_pub, sid, aad: "tenant-42")
+      iex> ExTholosPq.decrypt(ct, "Alice#v2", [sender_pub], "tenant-42")
+      {:ok, "bound"}
+
+  """
+  @spec rewrap_for_recipient(
+          binary(),
+          String.t() | reference(),
+          binary(),
+          String.t() | reference(),
+          keyword()
+        ) :: {:ok, binary()} | {:error, :recipient_already_present | :aad_mismatch | error()}
+  def rewrap_for_recipient(_ciphertext, _old_kid, _new_recipient_pub_key, _sender_id, _opts),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Generates a new version of a recipient keypair in the NIF's key store.
+
+  The earlier versions are kept, so messages already encrypted to them still
+  decrypt with `decrypt/3` under the same `kid`. The first version is the one
+  stored under `kid` itself; version `n` after it is stored as `"kid#vn"`,
+  which is also the kid in its public key and in `list_recipient_kids/0`.
+  Delete an old version with `delete_recipient_key/1` once nothing encrypted to
+  it is in flight.
+
+  ## Parameters
+
+    * `kid` - The recipient's key identifier
+
+  ## Returns
+
+    * `{:ok, {new_version, public_key_cbor}}` on success
+    * `{:error, {:recipient_not_found, kid}}` if no version of `kid` is stored
+
+  ## Examples
+
+      iex> {:ok, {kid, _pub}} = ExTholosPq.gen_recipient_keypair("RotatedRecipient")
+      iex> {:ok, {2, _new_pub}} = ExTholosPq.rotate_recipient_key(kid)
+      iex> ExTholosPq.list_key_versions(kid)
+      {:ok, [1, 2]}
+
+  """
+  @spec rotate_recipient_key(String.t()) :: {:ok, {pos_integer(), binary()}} | {:error, error()}
+  def rotate_recipient_key(_kid), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Lists the versions of a recipient kid in the NIF's key store.
+
+  ## Parameters
+
+    * `kid` - The recipient's key identifier
+
+  ## Returns
+
+    * `{:ok, versions}` sorted, empty if no version of `kid` is stored
+
+  ## Examples
+
+      iex> {:ok, {kid, _pub}} = ExTholosPq.gen_recipient_keypair("VersionedRecipient")
+      iex> ExTholosPq.list_key_versions(kid)
+      {:ok, [1]}
+
+  """
+  @spec list_key_versions(String.t()) :: {:ok, [pos_integer()]}
+  def list_key_versions(_kid), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Checks that a ciphertext was signed by an allowed sender, without decrypting
+  it.
+
+  Only the Dilithium signature is verified: no envelope is decapsulated and the
+  payload is not decrypted, so no recipient key is needed. This suits gateways
+  and fan-out nodes that only decide whether to pass a message on. The
+  signature covers the whole ciphertext, header, envelopes and payload, so any
+  change to it is caught here. An empty `allowed_sender_pub_keys` allows no
+  sender, as in `decrypt/3`.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+    * `allowed_sender_pub_keys` - List of allowed sender public keys for verification (CBOR-encoded)
+
+  ## Returns
+
+    * `{:ok, {:valid, sender_sid}}` if an allowed sender signed it
+    * `{:ok, :invalid_signature}` if the sender is not allowed or the signature does not verify
+    * `{:error, {:malformed_wire, detail}}` if the ciphertext cannot be parsed
+    * `{:error, reason}` on other failures
+
+  ## Examples
+
+      iex> {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("GatewaySender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("secret", sid, [pub])
+      iex> ExTholosPq.validate_wire(ct, [sender_pub])
+      {:ok, {:valid, "GatewaySender"}}
+
+  """
+  @spec validate_wire(binary(), list(binary())) ::
+          {:ok, {:valid, String.t()} | :invalid_signature} | {:error, error()}
+  def validate_wire(_ciphertext, _allowed_sender_pub_keys), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Checks that a ciphertext is authentic and decrypts for a recipient, without
+  returning the plaintext.
+
+  Works like `decrypt/3`, but the plaintext never leaves the NIF: it is
+  zeroized once decrypted and only its length is returned. This suits
+  pipelines that must confirm a message is readable without bringing its
+  contents into BEAM memory. Unlike `validate_wire/2`, it also proves that the
+  ciphertext was encrypted for `kid` and that the payload passes
+  authentication under its key, and it fails with the same errors as
+  `decrypt/3`.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+    * `kid` - The recipient's key identifier, or a key reference from `gen_recipient_key_resource/1`
+    * `allowed_sender_pub_keys` - List of allowed sender public keys for verification (CBOR-encoded)
+
+  ## Returns
+
+    * `{:ok, {:valid, sender_sid, plaintext_len}}` on success
+    * `{:error, reason}` on failure
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("DryRunSender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("secret", sid, [pub])
+      iex> ExTholosPq.decrypt_verify_only(ct, kid, [sender_pub])
+      {:ok, {:valid, "DryRunSender", 6}}
+
+  """
+  @spec decrypt_verify_only(binary(), String.t() | reference(), list(binary())) ::
+          {:ok, {:valid, String.t(), non_neg_integer()}} | {:error, error()}
+  def decrypt_verify_only(_ciphertext, _kid, _allowed_sender_pub_keys),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Returns an upper bound on the size of the ciphertext `encrypt/3` would
+  produce, without encrypting anything.
+
+  The bound is computed from the fixed sizes of the Kyber ciphertexts, the
+  Dilithium signature and the AEAD nonces and tags, for keys of the default
+  security levels. Sender and recipient
+  identifiers are assumed to be at most 64 bytes long; with identifiers of
+  exactly that length the bound is within a few bytes of the real size.
+  Associated data is not stored in the ciphertext and does not count.
+
+  ## Parameters
+
+    * `plaintext_len` - Length of the message in bytes
+    * `recipient_count` - Number of recipients
+    * `opts` - Keyword list of options
+
+  ## Options
+
+    * `:max_id_length` - Longest sender or recipient identifier in bytes (default `64`)
+    * `:compression` - As for `encrypt/4`. Compression only shrinks the payload,
+      so the bound stays that of the uncompressed message plus the header field
+    * `:embed_sender_key` - As for `encrypt/4`
+    * `:security_level` - Level of the sender and recipient keys, as for
+      `gen_recipient_keypair/2`; by default those of default keys
+
+  ## Returns
+
+    * `{:ok, bytes}`
+    * `{:error, {:invalid_option, name}}` for an unknown option or an unsupported value
+
+  ## Examples
+
+      iex> {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("secret", sid, [pub])
+      iex> {:ok, bytes} = ExTholosPq.estimate_wire_size(6, 1)
+      iex> byte_size(ct) <= bytes
+      true
+
+  """
+  @spec estimate_wire_size(non_neg_integer(), non_neg_integer()) :: {:ok, pos_integer()}
+  def estimate_wire_size(_plaintext_len, _recipient_count),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Returns an upper bound on the size of the ciphertext `encrypt/4` would
+  produce with the given options.
+
+  See `estimate_wire_size/2`.
+  """
+  @spec estimate_wire_size(non_neg_integer(), non_neg_integer(), keyword()) ::
+          {:ok, pos_integer()} | {:error, error()}
+  def estimate_wire_size(_plaintext_len, _recipient_count, _opts),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Returns the algorithms and formats this build uses, e.g. for tagging
+  messages in telemetry.
+
+  `:kem` and `:sig` name the parameter sets of keys generated without a
+  `:security_level`, `:wire_version` is the format version `encrypt/3` writes
+  (see `wire_version/1`) and `:tholos_pq_version` is the version of the
+  underlying `tholos_pq` crate.
+
+  ## Examples
+
+      iex> {:ok, info} = ExTholosPq.crypto_info()
+      iex> info.kem
+      "ML-KEM-1024"
+      iex> info.sig
+      "Dilithium3"
+
+  """
+  @spec crypto_info() ::
+          {:ok,
+           %{
+             kem: String.t(),
+             sig: String.t(),
+             wire_version: non_neg_integer(),
+             tholos_pq_version: String.t()
+           }}
+  def crypto_info, do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Describes what this build supports, for agreeing on a suite with a peer.
+
+  Each list is ordered strongest first. Exchange the map with the peer when a
+  connection is set up and pass what it sent to `choose_suite/1`.
+
+  ## Returns
+
+    * `{:ok, %{wire_versions: versions, aeads: aeads, security_levels: levels}}`
+
+  ## Examples
+
+      iex> {:ok, capabilities} = ExTholosPq.capabilities()
+      iex> capabilities.aeads
+      [:xchacha20poly1305, :aes256gcm]
+      iex> capabilities.security_levels
+      [:level5, :level3, :level1]
+
+  """
+  @spec capabilities() ::
+          {:ok,
+           %{
+             wire_versions: [non_neg_integer()],
+             aeads: [atom()],
+             security_levels: [atom()]
+           }}
+  def capabilities, do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Picks the strongest suite both this build and a peer support.
+
+  For each of the wire version, the payload AEAD and the security level, the
+  first entry of this build's `capabilities/0` list that the peer lists too is
+  chosen; the order of the peer's lists does not matter. Pass the `:aead` and
+  `:security_level` on to `encrypt/4` and keygen. Entries this build does not
+  know are ignored, so a newer peer can advertise more.
+
+  ## Parameters
+
+    * `peer_capabilities` - The peer's `capabilities/0` map
+
+  ## Returns
+
+    * `{:ok, %{wire_version: version, aead: aead, security_level: level}}`
+    * `{:error, :no_common_suite}` if any of the three has nothing in common
+
+  ## Examples
+
+      iex> ExTholosPq.choose_suite(%{
+      ...>   wire_versions: [1],
+      ...>   aeads: [:aes256gcm],
+      ...>   security_levels: [:level1, :level3]
+      ...> })
+      {:ok, %{wire_version: 1, aead: :aes256gcm, security_level: :level3}}
+
+  """
+  @spec choose_suite(%{
+          wire_versions: [non_neg_integer()],
+          aeads: [atom()],
+          security_levels: [atom()]
+        }) ::
+          {:ok, %{wire_version: non_neg_integer(), aead: atom(), security_level: atom()}}
+          | {:error, :no_common_suite}
+  def choose_suite(_peer_capabilities), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Runs known-answer tests against the compiled Kyber and Dilithium primitives.
+
+  Meant as a boot-time assertion that this build computes correctly on this
+  platform, catching miscompiles and broken SIMD backends. Kyber keygen and
+  encapsulation from fixed seeds and Dilithium signing with a fixed key are
+  checked against outputs recorded from a known-good build, followed by an
+  `encrypt/3` and `decrypt/3` round trip with fresh keys. Takes a few
+  milliseconds and touches no stored keys.
+
+  ## Returns
+
+    * `:ok`
+    * `{:error, {:kat_failed, check}}` naming the first failed check, one of
+      `:keygen`, `:encapsulation`, `:signing` or `:round_trip`
+
+  ## Examples
+
+      iex> ExTholosPq.self_test()
+      :ok
+
+  """
+  @spec self_test() ::
+          :ok | {:error, {:kat_failed, :keygen | :encapsulation | :signing | :round_trip}}
+  def self_test, do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Measures how fast this node encrypts or decrypts, for capacity planning.
+
+  The operation runs `iterations` times in a loop on a dirty scheduler, with
+  fresh keys at the default security levels, a random payload of
+  `payload_size` bytes and `recipients` recipients, so BEAM overhead does not
+  skew the numbers. A decrypt is by one of the recipients. Generating the
+  keys, and for `:decrypt` the one ciphertext decrypted over and over, is not
+  timed. No stored keys are touched.
+
+  `:ops_per_sec` is the iteration count over the time of the whole loop;
+  `:p50_us` and `:p99_us` are latency percentiles of single iterations, in
+  microseconds. Each iteration includes the Dilithium signature or its
+  verification, which dominates for small payloads.
+
+  ## Parameters
+
+    * `op` - `:encrypt` or `:decrypt`
+    * `payload_size` - Bytes of plaintext, at most 64 MiB
+    * `recipients` - Number of recipients, from 1 to 256
+    * `iterations` - Number of timed runs, from 1 to 1,000,000
+
+  So that one call cannot hold a dirty scheduler for long, a run is also
+  limited in its total work: every iteration counts as its payload plus
+  256 KiB for the signature and for each recipient, and the iterations
+  together may count up to 16 GiB, e.g. 254 iterations of a 64 MiB payload or
+  32,768 of an empty one for one recipient.
+
+  ## Returns
+
+    * `{:ok, %{ops_per_sec: float, p50_us: integer, p99_us: integer}}`
+    * `{:error, {:invalid_option, name}}` if an argument is out of range, with
+      `"iterations"` also for a run over the work limit
+    * `{:error, {:encrypt_failed, detail}}` if an encryption fails, or the
+      error `decrypt/3` would give if a decryption does
+
+  ## Examples
+
+      iex> {:ok, report} = ExTholosPq.benchmark(:decrypt, 64, 1, 3)
+      iex> report.ops_per_sec > 0 and report.p50_us <= report.p99_us
+      true
+
+  """
+  @spec benchmark(:encrypt | :decrypt, non_neg_integer(), pos_integer(), pos_integer()) ::
+          {:ok, %{ops_per_sec: float(), p50_us: non_neg_integer(), p99_us: non_neg_integer()}}
+          | {:error, error()}
+  def benchmark(_op, _payload_size, _recipients, _iterations),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Re-signs a ciphertext under another sender, e.g. after the original sender's
+  key was compromised.
+
+  The signature is verified with the key stored for `old_sid` and replaced by
+  one made with the key stored for `new_sid`. The payload, its content key and
+  the recipient envelopes are not touched, so no recipient key is needed.
+
+  The header keeps naming the original sender, because the envelopes and the
+  payload are bound to it; the ciphertext names `new_sid` as its signer
+  instead. From then on it verifies against `new_sid`'s public key, and
+  `decrypt_with_sender/3` and `wire_sender_unverified/1` report `new_sid`. If
+  the original ciphertext embedded its sender's key (`embed_sender_key: true`),
+  `new_sid`'s key is embedded too. Re-signed ciphertexts cannot be read by
+  other tholos-pq implementations.
+
+  ## Parameters
+
+    * `ciphertext` - The encrypted message
+    * `old_sid` - The sender that signed it
+    * `new_sid` - The sender to sign it under instead
+
+  ## Returns
+
+    * `{:ok, new_ciphertext}` on success
+    * `{:error, {:invalid_identifier, reason}}` if either sid is not a valid one
+    * `{:error, {:sender_not_found, sid}}` if either sender is not in the key store
+    * `{:error, :signature_invalid}` if the ciphertext was not signed by `old_sid`
+    * `{:error, reason}` on other failures
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {old_sid, _old_pub}} = ExTholosPq.gen_sender_keypair("CompromisedSender")
+      iex> {:ok, {new_sid, new_pub}} = ExTholosPq.gen_sender_keypair("ReplacementSender")
+      iex> {:ok, ct} = ExTholosPq.encrypt("secret", old_sid, [pub])
+      iex> {:ok, ct} = ExTholosPq.resign_wire(ct, old_sid, new_sid)
+      iex> ExTholosPq.decrypt_with_sender(ct, kid, [new_pub])
+      {:ok, {"secret", "ReplacementSender"}}
+
+  """
+  @spec resign_wire(binary(), String.t(), String.t()) :: {:ok, binary()} | {:error, error()}
+  def resign_wire(_ciphertext, _old_sid, _new_sid), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Encrypts a message for recipients given as a map of kid to public key.
+
+  Works like `encrypt/3`, but every public key must carry the kid it is keyed
+  by. That catches a public key loaded for the wrong recipient before anything
+  is encrypted to it. Recipients are encrypted for in kid order.
+
+  ## Parameters
+
+    * `message` - The message to encrypt (binary or string)
+    * `sender_id` - The sender's identifier, or a key reference from `gen_sender_key_resource/1`
+    * `recipient_pub_keys` - Map of recipient kid to public key (CBOR-encoded)
+
+  ## Returns
+
+    * `{:ok, ciphertext}` on success
+    * `{:error, {:kid_mismatch, map_kid, key_kid}}` if the public key under `map_kid` is for `key_kid`
+    * `{:error, reason}` on other failures
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, ct} = ExTholosPq.encrypt_to_map("secret", sid, %{"Alice" => pub})
+      iex> ExTholosPq.decrypt(ct, kid, [sender_pub])
+      {:ok, "secret"}
+
+  """
+  @spec encrypt_to_map(binary(), String.t() | reference(), %{String.t() => binary()}) ::
+          {:ok, binary()} | {:error, {:kid_mismatch, String.t(), String.t()} | error()}
+  def encrypt_to_map(_message, _sender_id, _recipient_pub_keys),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Encrypts a message for every recipient whose public key can be used,
+  skipping the rest instead of failing the whole call.
+
+  A public key is skipped if it cannot be decoded, is not a valid Kyber key,
+  or is of another security level than the first key that has one, since a
+  ciphertext is for keys of one level only. With an escrow recipient set, see
+  `set_escrow_recipient/1`, its level is the one kept instead. The ciphertext
+  only has envelopes for the others. Their positions in
+  `recipient_pub_keys` are returned so that bulk senders can report or retry
+  them. If none of the keys can be used, or the list is empty, there is nobody
+  to encrypt for and an error is returned instead.
+
+  ## Parameters
+
+    * `message` - The message to encrypt (binary or string)
+    * `sender_id` - The sender's identifier, or a key reference from `gen_sender_key_resource/1`
+    * `recipient_pub_keys` - List of recipient public keys (CBOR-encoded)
+
+  ## Returns
+
+    * `{:ok, {ciphertext, failed}}` with the zero-based indices of the skipped keys
+    * `{:error, :no_valid_recipients}` if no key can be used
+    * `{:error, {:encrypt_failed, detail}}` if the escrow recipient cannot be
+      used; `failed` only ever holds indices into `recipient_pub_keys`
+    * `{:error, {:too_many_recipients, count}}` if there are more than 256 keys
+    * `{:error, reason}` on other failures
+
+  ## Examples
+
+      iex> {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      iex> {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+      iex> {:ok, {ct, [1]}} = ExTholosPq.encrypt_partial("secret", sid, [pub, "not a key"])
+      iex> ExTholosPq.decrypt(ct, kid, [sender_pub])
+      {:ok, "secret"}
+
+  """
+  @spec encrypt_partial(binary(), String.t() | reference(), list(binary())) ::
+          {:ok, {binary(), [non_neg_integer()]}} | {:error, :no_valid_recipients | error()}
+  def encrypt_partial(_message, _sender_id, _recipient_pub_keys),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  # Test hook, only implemented when the NIF is built with the `test-hooks`
+  # cargo feature (see config/test.exs)
+  @doc false
+  def sender_key_wiped_on_drop, do: :erlang.nif_error(:nif_not_loaded)
+
+  # Test hook, only implemented with the `test-hooks` cargo feature. Counts the
+  # KEM decapsulations and AEAD decryptions run since the NIF was loaded.
+  @doc false
+  def decrypt_operations, do: :erlang.nif_error(:nif_not_loaded)
+
+  # Seeded encrypt for byte-exact test vectors, only implemented with the
+  # `deterministic-rng` cargo feature, which release builds refuse. All
+  # randomness comes from the 32-byte seed and the header timestamp is 0.
+  @doc false
+  def encrypt_deterministic(_seed, _sender_id, _message, _recipient_pub_keys),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  # Wire test vectors as JSON, the contents of test/fixtures/test_vectors.json.
+  # Only implemented with the `test-vectors` cargo feature, see the README.
+  @doc false
+  def export_test_vectors, do: :erlang.nif_error(:nif_not_loaded)
+
+  # Test hooks, only implemented with the `test-hooks` cargo feature. Panic
+  # where a crypto primitive would, or where one would on an encryption stream.
+  @doc false
+  def crypto_panic, do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc false
+  def stream_panic(_stream), do: :erlang.nif_error(:nif_not_loaded)
+
+  # Test hooks, only implemented with the `test-hooks` cargo feature. Panic
+  # while holding the key store locks, or the lock of an encryption stream.
+  @doc false
+  def poison_key_stores, do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc false
+  def poison_stream(_stream), do: :erlang.nif_error(:nif_not_loaded)
+
+  # Test hook, only implemented with the `test-hooks` cargo feature. Drops the
+  # signature of a ciphertext and, with `anonymous`, flags its header anonymous.
+  @doc false
+  def strip_signature(_ciphertext, _anonymous), do: :erlang.nif_error(:nif_not_loaded)
+
+  # Test hook, only implemented with the `test-hooks` cargo feature. The key
+  # format of the recipient key stored under `kid`.
+  @doc false
+  def recipient_key_format(_kid), do: :erlang.nif_error(:nif_not_loaded)
+
+  # Test hook, only implemented with the `test-hooks` cargo feature. An
+  # `export_store/1` blob with a new keypair under each id, which is not checked.
+  @doc false
+  def export_store_as(_kid, _sid, _password), do: :erlang.nif_error(:nif_not_loaded)
+
+  # Test hook, only implemented with the `test-hooks` cargo feature. Changes the
+  # field of a ciphertext named as in its CBOR and keeps the old signature.
+  @doc false
+  def tamper_wire(_ciphertext, _field), do: :erlang.nif_error(:nif_not_loaded)
+
+  # Test hook, only implemented with the `test-hooks` cargo feature. Signs a
+  # ciphertext whose key commitment does not match its content key.
+  @doc false
+  def encrypt_wrong_key_commitment(_message, _sender_id, _recipient_pub_keys),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  # Test hook, only implemented with the `test-hooks` cargo feature. Allocates
+  # a binary of `size` bytes through the same path as every NIF result.
+  @doc false
+  def alloc_binary_of(_size), do: :erlang.nif_error(:nif_not_loaded)
+
+  # Test hooks, only implemented with the `test-hooks` cargo feature. Re-encode
+  # a public key with serde_cbor, as releases before ciborium wrote it.
+  @doc false
+  def serde_cbor_recipient_pub(_pub_key), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc false
+  def serde_cbor_sender_pub(_pub_key), do: :erlang.nif_error(:nif_not_loaded)
+
+  # Test hook, see bench/output_binary.exs
+  @doc false
+  def output_binary(_size, _copy), do: :erlang.nif_error(:nif_not_loaded)
 end
@@ -0,0 +1,25 @@
+//! Records the resolved `tholos-pq` version for `crypto_info`, which
+//! `CARGO_PKG_VERSION` cannot give since that is this crate's own version.
+
+use std::{env, fs, path::Path};
+
+fn main() {
+    let lock = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock.display());
+
+    let version = fs::read_to_string(&lock)
+        .ok()
+        .and_then(|lock| tholos_pq_version(&lock))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=THOLOS_PQ_VERSION={version}");
+}
+
+/// The version of the `tholos-pq` package in a Cargo.lock.
+fn tholos_pq_version(lock: &str) -> Option<String> {
+    let mut lines = lock
+        .lines()
+        .skip_while(|line| *line != "name = \"tholos-pq\"");
+    lines.next()?;
+    let version = lines.next()?.strip_prefix("version = \"")?;
+    Some(version.trim_end_matches('"').to_string())
+}
@@ -0,0 +1,134 @@
+//! Timing encryption and decryption on the machine the NIF runs on, run by
+//! `benchmark`.
+//!
+//! Each run generates fresh keys at the default levels and a random payload,
+//! then times every iteration of the operation on its own. Keygen and the one
+//! encryption a decrypt run needs up front are left out of the numbers.
+
+use crate::crypto::{self, Compression};
+use crate::keys::{self, SenderKey};
+use crate::level::Level;
+use crate::wire;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::time::{Duration, Instant};
+use tholos_pq::TholosError;
+
+/// Largest payload a run may encrypt.
+pub const MAX_PAYLOAD_SIZE: usize = 64 * 1024 * 1024;
+
+/// Most iterations of a run.
+pub const MAX_ITERATIONS: usize = 1_000_000;
+
+/// What an iteration is charged on top of its payload, once for the signature
+/// and once per recipient for the KEM: about the bytes of payload that take as
+/// long to seal.
+const ITERATION_OVERHEAD: u64 = 256 * 1024;
+
+/// Most work a run may do, see `work`: seconds of a dirty scheduler, not hours.
+pub const MAX_WORK: u64 = 16 * 1024 * 1024 * 1024;
+
+/// The work of a run, in bytes of payload: every iteration is charged its
+/// payload and `ITERATION_OVERHEAD` for the signature and each recipient.
+/// `None` if that does not fit a `u64`.
+pub fn work(payload_size: usize, recipients: usize, iterations: usize) -> Option<u64> {
+    let overhead = (recipients as u64 + 1).checked_mul(ITERATION_OVERHEAD)?;
+    (payload_size as u64)
+        .checked_add(overhead)?
+        .checked_mul(iterations as u64)
+}
+
+/// The operation to time.
+#[derive(Clone, Copy)]
+pub enum Op {
+    Encrypt,
+    Decrypt,
+}
+
+/// Why a run failed: the operation that failed and its error. The encryption
+/// a decrypt run does up front counts as `Encrypt`.
+pub struct Failed(pub Op, pub TholosError);
+
+/// Throughput and latency of a run.
+pub struct Report {
+    /// Iterations over the wall time of the whole loop.
+    pub ops_per_sec: f64,
+    pub p50: Duration,
+    pub p99: Duration,
+}
+
+/// Time `iterations` runs of `op` on a `payload_size`-byte payload for
+/// `recipients` recipients. Decryption is by the first recipient.
+pub fn run(
+    op: Op,
+    payload_size: usize,
+    recipients: usize,
+    iterations: usize,
+) -> Result<Report, Failed> {
+    let kids: Vec<_> = (0..recipients).map(|i| format!("bench-{}", i)).collect();
+    let kids: Vec<_> = kids.iter().map(String::as_str).collect();
+    let keypairs = keys::recipient_keypairs(&kids);
+    let pubs: Vec<_> = keypairs
+        .iter()
+        .map(|(pub_key, _)| pub_key.clone())
+        .collect();
+    let sender = SenderKey::generate("bench", Level::SENDER_DEFAULT);
+    let allowed = [(sender.sid.clone(), sender.public().pk_dilithium)];
+
+    let mut payload = vec![0u8; payload_size];
+    OsRng.fill_bytes(&mut payload);
+    let encrypt = || {
+        crypto::encrypt(
+            &payload,
+            &sender,
+            &pubs,
+            &[],
+            Compression::None,
+            false,
+            false,
+            false,
+            &crypto::HeaderFields::default(),
+        )
+    };
+    let failed = |op| move |e| Failed(op, e);
+    let wire = encrypt().map_err(failed(Op::Encrypt))?;
+    let body = wire::body(&wire)
+        .map_err(|_| Failed(Op::Encrypt, TholosError::Malformed("wire version")))?;
+    let (pub_key, priv_key) = &keypairs[0];
+
+    let mut timings = Vec::with_capacity(iterations);
+    let start = Instant::now();
+    for _ in 0..iterations {
+        let iteration = Instant::now();
+        match op {
+            Op::Encrypt => drop(encrypt().map_err(failed(op))?),
+            Op::Decrypt => drop(
+                crypto::decrypt(
+                    body,
+                    &pub_key.kid,
+                    priv_key,
+                    allowed.as_slice(),
+                    crypto::Aad::default(),
+                    None,
+                    crypto::Accept::default(),
+                )
+                .map_err(failed(op))?,
+            ),
+        }
+        timings.push(iteration.elapsed());
+    }
+    let total = start.elapsed();
+
+    timings.sort_unstable();
+    Ok(Report {
+        ops_per_sec: iterations as f64 / total.as_secs_f64(),
+        p50: percentile(&timings, 50),
+        p99: percentile(&timings, 99),
+    })
+}
+
+/// The nearest-rank `p`th percentile of the sorted, non-empty `timings`.
+fn percentile(timings: &[Duration], p: usize) -> Duration {
+    let rank = (timings.len() * p).div_ceil(100);
+    timings[rank.max(1) - 1]
+}
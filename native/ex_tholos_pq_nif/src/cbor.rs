@@ -0,0 +1,104 @@
+//! CBOR encoding and decoding on ciborium.
+//!
+//! `serde_cbor` is unmaintained, so everything the NIF encodes or decodes goes
+//! through here instead of it or the `tholos_pq` helpers built on it. The bytes
+//! do not change: ciborium also writes structs as maps in field order, with
+//! definite lengths and shortest-form integers, and decoding skips tags and
+//! rejects trailing bytes as `serde_cbor::from_slice` did. Wires, keys and
+//! blobs written before the switch therefore decode and re-encode identically.
+//!
+//! `tholos_pq` still depends on `serde_cbor` itself; only its types are used
+//! here, so nothing runs through it any more once that dependency goes too.
+
+use ciborium::Value;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::fmt;
+use tholos_pq::TholosError;
+
+/// CBOR self-describe tag that `tholos_pq::to_cbor_canonical` starts every
+/// encoding with, and so every tholos-pq bundle.
+pub const SELF_DESCRIBE_TAG: [u8; 3] = [0xd9, 0xd9, 0xf7];
+
+/// A value that could not be encoded, or bytes that are no CBOR of the type
+/// asked for.
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<Error> for TholosError {
+    fn from(e: Error) -> Self {
+        TholosError::Ser(e.0)
+    }
+}
+
+/// The CBOR of `value`.
+pub fn to_vec(value: &(impl Serialize + ?Sized)) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+    write(value, &mut buf)?;
+    Ok(buf)
+}
+
+/// The self-describe tag followed by the CBOR of `value`, the encoding of
+/// `tholos_pq::to_cbor_canonical`.
+pub fn to_vec_tagged(value: &(impl Serialize + ?Sized)) -> Result<Vec<u8>, Error> {
+    let mut buf = SELF_DESCRIBE_TAG.to_vec();
+    write(value, &mut buf)?;
+    Ok(buf)
+}
+
+/// Append the CBOR of `value` to `buf`.
+pub fn write(value: &(impl Serialize + ?Sized), buf: &mut Vec<u8>) -> Result<(), Error> {
+    ciborium::into_writer(value, buf).map_err(|e| Error(e.to_string()))
+}
+
+/// Canonical CBOR of `value`, as in RFC 7049 section 3.9: every length is
+/// definite, integers take their shortest form and map keys are sorted
+/// shortest first, then bytewise. The same logical value always encodes to
+/// the same bytes, whatever order its fields were declared or decoded in.
+pub fn canonical(value: &(impl Serialize + ?Sized)) -> Result<Vec<u8>, Error> {
+    let mut value = Value::serialized(value).map_err(|e| Error(e.to_string()))?;
+    sort_maps(&mut value)?;
+    to_vec(&value)
+}
+
+/// Sort the entries of every map in `value` by the encoding of their key.
+fn sort_maps(value: &mut Value) -> Result<(), Error> {
+    match value {
+        Value::Map(entries) => {
+            let mut keyed = Vec::with_capacity(entries.len());
+            for (mut k, mut v) in entries.drain(..) {
+                sort_maps(&mut k)?;
+                sort_maps(&mut v)?;
+                keyed.push((to_vec(&k)?, k, v));
+            }
+            keyed.sort_by(|(a, ..), (b, ..)| canonical_order(a, b));
+            entries.extend(keyed.into_iter().map(|(_, k, v)| (k, v)));
+        }
+        Value::Array(items) => items.iter_mut().try_for_each(sort_maps)?,
+        Value::Tag(_, inner) => sort_maps(inner)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn canonical_order(a: &[u8], b: &[u8]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+/// Decode a `T` from the whole of `bytes`, skipping tags; trailing bytes are
+/// an error.
+pub fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    let mut reader = bytes;
+    let value = ciborium::from_reader(&mut reader).map_err(|e| Error(e.to_string()))?;
+    if !reader.is_empty() {
+        return Err(Error("trailing data".to_string()));
+    }
+    Ok(value)
+}
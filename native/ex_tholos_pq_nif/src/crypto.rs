@@ -0,0 +1,1701 @@
+//! Encryption and decryption with caller-supplied associated data.
+//!
+//! This follows `tholos_pq::encrypt` and `tholos_pq::decrypt` step for step and
+//! uses the same wire layout, so with empty associated data the output after
+//! its version byte (see `wire`) is an ordinary tholos-pq wire that
+//! `tholos_pq::decrypt` accepts, and `decrypt` reads tholos-pq wires as they are.
+//!
+//! The associated data is appended to the canonical header CBOR wherever
+//! tholos-pq uses the header as AEAD associated data, i.e. for the payload and
+//! for every wrapped CEK. It is never written to the wire: the recipient has to
+//! supply the same bytes again for decryption to succeed.
+//!
+//! The header can additionally name a compression applied to the plaintext
+//! before sealing, or AES-256-GCM in place of XChaCha20-Poly1305 for the
+//! payload. `tholos_pq::Header` has no room for either, so the bundle types
+//! are mirrored here with optional fields that are left out of the CBOR when
+//! unset. A default bundle is therefore byte-for-byte a tholos-pq bundle,
+//! while `tholos_pq::decrypt` rejects any other with a bad signature,
+//! since it drops the field it does not know before verifying.
+//!
+//! Keys of a non-default security level (see `level`) give wires of the same
+//! layout, only with KEM ciphertexts and signatures of another size, which
+//! `tholos_pq` cannot read.
+
+use crate::cbor;
+use crate::hybrid;
+use crate::keys::{RecipientPriv, SenderKey};
+use crate::level::{self, KemSecret};
+use crate::wire;
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use rand::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use subtle::{ConditionallySelectable, ConstantTimeEq};
+use tholos_pq::{RecipientEnvelope, RecipientPub, TholosError, SUITE_V1};
+use time::OffsetDateTime;
+use zeroize::Zeroizing;
+
+/* ---------------- Wire types ---------------- */
+
+/// Detail of the `Malformed` error for a CEK that does not match the key
+/// commitment of its wire.
+pub const KEY_COMMITMENT: &str = "key commitment";
+/// Detail of the error for an AAD given for a recipient whose envelope is not
+/// bound to one, or the other way round.
+pub const RECIPIENT_AAD: &str = "recipient aad";
+/// Detail of the error for a wire older than its time to live or the age the
+/// caller accepts.
+pub const EXPIRED: &str = "expired";
+/// Detail of the error for a wire with a time to live decrypted without the
+/// current time to check it against.
+pub const NOW_REQUIRED: &str = "now required";
+
+const KEY_COMMITMENT_CONTEXT: &[u8] = b"ex_tholos_pq/key-commitment/v1\0";
+
+/// Compression applied to the plaintext before it is sealed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Zstd,
+}
+
+impl Compression {
+    fn is_none(&self) -> bool {
+        *self == Compression::None
+    }
+}
+
+/// AEAD the payload is sealed with. CEKs are wrapped with XChaCha20-Poly1305
+/// whichever it is.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadAead {
+    #[default]
+    XChaCha20Poly1305,
+    Aes256Gcm,
+}
+
+impl PayloadAead {
+    fn is_default(&self) -> bool {
+        *self == PayloadAead::XChaCha20Poly1305
+    }
+
+    fn nonce_len(self) -> usize {
+        match self {
+            PayloadAead::XChaCha20Poly1305 => 24,
+            PayloadAead::Aes256Gcm => 12,
+        }
+    }
+
+    /// Seal `pt` under a fresh nonce drawn from `rng`. Returns the nonce and
+    /// the ciphertext.
+    fn seal(
+        self,
+        rng: &mut impl RngCore,
+        key: &[u8; 32],
+        aad: &[u8],
+        pt: &[u8],
+    ) -> Result<(Vec<u8>, Vec<u8>), TholosError> {
+        let mut nonce = vec![0u8; self.nonce_len()];
+        rng.fill_bytes(&mut nonce);
+        let ct = match self {
+            PayloadAead::XChaCha20Poly1305 => XChaCha20Poly1305::new(key.into())
+                .encrypt(XNonce::from_slice(&nonce), Payload { msg: pt, aad }),
+            PayloadAead::Aes256Gcm => Aes256Gcm::new(key.into())
+                .encrypt(aes_gcm::Nonce::from_slice(&nonce), Payload { msg: pt, aad }),
+        }
+        .map_err(|_| TholosError::Aead)?;
+        Ok((nonce, ct))
+    }
+
+    fn open(
+        self,
+        key: &[u8; 32],
+        nonce: &[u8],
+        aad: &[u8],
+        ct: &[u8],
+    ) -> Result<Vec<u8>, TholosError> {
+        if nonce.len() != self.nonce_len() {
+            return Err(TholosError::Malformed("pay nonce"));
+        }
+        count_open();
+        match self {
+            PayloadAead::XChaCha20Poly1305 => XChaCha20Poly1305::new(key.into())
+                .decrypt(XNonce::from_slice(nonce), Payload { msg: ct, aad }),
+            PayloadAead::Aes256Gcm => Aes256Gcm::new(key.into())
+                .decrypt(aes_gcm::Nonce::from_slice(nonce), Payload { msg: ct, aad }),
+        }
+        .map_err(|_| TholosError::Aead)
+    }
+}
+
+/// `tholos_pq::Header` plus the compression flag, the payload AEAD, the hybrid
+/// flag (see `hybrid`), the anonymous flag, a time to live, public metadata,
+/// a padding block size, the per-recipient AAD flag, the escrow recipient and,
+/// optionally, the sender's public key. All are left out when unused, so
+/// such headers stay readable by `tholos_pq` itself.
+#[derive(Clone, Serialize, Deserialize)]
+struct Header {
+    v: u32,
+    suite: String,
+    sender: String,
+    recipients: Vec<String>,
+    msg_id: String,
+    timestamp_unix: u64,
+    #[serde(default, skip_serializing_if = "Compression::is_none")]
+    compression: Compression,
+    #[serde(default, skip_serializing_if = "PayloadAead::is_default")]
+    aead: PayloadAead,
+    /// Whether every envelope is a hybrid ML-KEM and X25519 encapsulation.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    hybrid: bool,
+    /// Whether the wire is unsigned, see `encrypt_anonymous`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    anonymous: bool,
+    /// How long after `timestamp_unix` the wire may still be opened.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl_seconds: Option<u64>,
+    /// Caller data sent in the clear, see `wire::metadata`.
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "serde_bytes")]
+    metadata: Option<Vec<u8>>,
+    /// Block size the sealed plaintext is padded to a multiple of, see `pad`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pad_to: Option<u32>,
+    /// Whether every envelope is bound to an AAD of its recipient's own, see
+    /// `wrap_aad`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    per_recipient_aad: bool,
+    /// Kid of the recipient that is the node's escrow key rather than one the
+    /// caller chose, see `wire::recipients`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    escrow: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "serde_bytes")]
+    sender_pk: Option<Vec<u8>>,
+}
+
+/// `tholos_pq::BundleUnsigned` with the extended header.
+///
+/// A wire re-signed by another sender names it in `signer`, and embeds its key
+/// in `signer_pk` if the header embeds the original sender's. They sit outside
+/// the header because the envelopes and the payload are bound to it, but are
+/// still covered by the signature. Both are left out of wires never re-signed.
+///
+/// XChaCha20-Poly1305 does not commit to its key: a sender can craft a payload
+/// that opens under two CEKs to two plaintexts and wrap a different one for
+/// each recipient. `key_commitment`, a SHA-256 of the CEK, rules that out, as
+/// every recipient checks the CEK it unwrapped against it before opening the
+/// payload. It is only written when asked for and left out otherwise.
+#[derive(Serialize, Deserialize)]
+struct BundleUnsigned {
+    header: Header,
+    #[serde(with = "serde_bytes")]
+    pay_nonce: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    ciphertext: Vec<u8>,
+    recipients: Vec<RecipientEnvelope>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "serde_bytes")]
+    signer_pk: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "serde_bytes")]
+    key_commitment: Option<Vec<u8>>,
+}
+
+impl BundleUnsigned {
+    /// The sid of the sender whose signature the wire carries.
+    fn signer(&self) -> &str {
+        self.signer.as_deref().unwrap_or(&self.header.sender)
+    }
+
+    /// That sender's embedded Dilithium key, if there is one.
+    fn signer_pk(&self) -> Option<&Vec<u8>> {
+        match self.signer {
+            Some(_) => self.signer_pk.as_ref(),
+            None => self.header.sender_pk.as_ref(),
+        }
+    }
+}
+
+/// `tholos_pq::BundleSigned` with the extended header.
+#[derive(Serialize, Deserialize)]
+struct BundleSigned {
+    inner: BundleUnsigned,
+    #[serde(with = "serde_bytes")]
+    sig_dilithium: Vec<u8>,
+}
+
+/* ---------------- Padding ---------------- */
+
+/// Longest a plaintext may be once padded.
+pub const MAX_PADDED_LEN: usize = 1 << 30;
+
+/// Length of `len` bytes padded to a multiple of `block`, or `None` if that is
+/// over `MAX_PADDED_LEN`. There is always at least one byte of padding, so a
+/// length that is a multiple already gains a whole block.
+pub fn padded_len(len: usize, block: u32) -> Option<usize> {
+    let padded = (len / block as usize + 1).checked_mul(block as usize)?;
+    (padded <= MAX_PADDED_LEN).then_some(padded)
+}
+
+/// Pad `sealed` to a multiple of `block` with a 0x80 byte followed by zeros.
+/// Unlike PKCS#7 the padding does not encode its own length, so any block size
+/// works, and it is removed just as unambiguously.
+fn pad(sealed: &[u8], block: u32) -> Result<Vec<u8>, TholosError> {
+    let len = padded_len(sealed.len(), block).ok_or(TholosError::Malformed("padding"))?;
+    let mut padded = Vec::with_capacity(len);
+    padded.extend_from_slice(sealed);
+    padded.push(0x80);
+    padded.resize(len, 0);
+    Ok(padded)
+}
+
+/// Strip the padding `pad` added. Only called on an opened payload, which the
+/// sender authenticated, but every padding byte is checked all the same.
+fn unpad(mut padded: Vec<u8>, block: u32) -> Result<Vec<u8>, TholosError> {
+    let block = block as usize;
+    if block == 0 || !padded.len().is_multiple_of(block) {
+        return Err(TholosError::Malformed("padding"));
+    }
+    match padded.iter().rposition(|&b| b != 0) {
+        Some(end) if padded[end] == 0x80 && padded.len() - end <= block => {
+            padded.truncate(end);
+            Ok(padded)
+        }
+        _ => Err(TholosError::Malformed("padding")),
+    }
+}
+
+/* ---------------- Compression ---------------- */
+
+/// Longest a compressed message may inflate to. A few bytes of zstd can
+/// describe gigabytes of output, so decompression stops here rather than at
+/// the end of the frame.
+#[cfg(feature = "zstd")]
+pub const MAX_DECOMPRESSED_LEN: usize = MAX_PADDED_LEN;
+
+/// Compress `plaintext` if that makes it smaller. Returns what to seal and the
+/// compression actually applied, so incompressible input is stored as is.
+#[cfg(feature = "zstd")]
+fn compress(plaintext: &[u8], compression: Compression) -> (Cow<'_, [u8]>, Compression) {
+    if compression == Compression::Zstd {
+        if let Ok(compressed) = zstd::bulk::compress(plaintext, zstd::DEFAULT_COMPRESSION_LEVEL) {
+            if compressed.len() < plaintext.len() {
+                return (Cow::Owned(compressed), Compression::Zstd);
+            }
+        }
+    }
+    (Cow::Borrowed(plaintext), Compression::None)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn compress(plaintext: &[u8], _compression: Compression) -> (Cow<'_, [u8]>, Compression) {
+    (Cow::Borrowed(plaintext), Compression::None)
+}
+
+fn decompress(sealed: Vec<u8>, compression: Compression) -> Result<Vec<u8>, TholosError> {
+    match compression {
+        Compression::None => Ok(sealed),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => {
+            use std::io::Read;
+
+            let mut plaintext = Vec::new();
+            zstd::stream::Decoder::new(sealed.as_slice())
+                .and_then(|decoder| {
+                    decoder
+                        .take(MAX_DECOMPRESSED_LEN as u64 + 1)
+                        .read_to_end(&mut plaintext)
+                })
+                .map_err(|e| TholosError::Ser(e.to_string()))?;
+            if plaintext.len() > MAX_DECOMPRESSED_LEN {
+                return Err(TholosError::Malformed("decompressed length"));
+            }
+            Ok(plaintext)
+        }
+        #[cfg(not(feature = "zstd"))]
+        Compression::Zstd => Err(TholosError::Ser(
+            "zstd compressed message, but zstd support is not compiled in".to_string(),
+        )),
+    }
+}
+
+/* ---------------- Symmetric helpers ---------------- */
+
+pub fn hkdf32(shared: &[u8], kid: &str, header_cbor: &[u8]) -> [u8; 32] {
+    // Domain separation with recipient kid + canonical header CBOR as info
+    let hk = Hkdf::<Sha256>::new(Some(kid.as_bytes()), shared);
+    let mut okm = [0u8; 32];
+    // A 32-byte output is far below the HKDF-SHA256 limit of 8160 bytes
+    #[allow(clippy::expect_used)]
+    hk.expand(header_cbor, &mut okm)
+        .expect("HKDF expand failed - this should never happen with 32-byte output");
+    okm
+}
+
+pub fn aead_enc(
+    key: &[u8; 32],
+    nonce24: &[u8; 24],
+    aad: &[u8],
+    pt: &[u8],
+) -> Result<Vec<u8>, TholosError> {
+    XChaCha20Poly1305::new(key.into())
+        .encrypt(&XNonce::from(*nonce24), Payload { msg: pt, aad })
+        .map_err(|_| TholosError::Aead)
+}
+
+/// KEM decapsulations and AEAD decryptions run so far, for the test suite to
+/// check that none of them happens before a signature verifies.
+#[cfg(feature = "test-hooks")]
+pub static OPENS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+/// Count a decapsulation or an AEAD decryption in `OPENS`.
+fn count_open() {
+    #[cfg(feature = "test-hooks")]
+    OPENS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+}
+
+pub fn aead_dec(
+    key: &[u8; 32],
+    nonce24: &[u8; 24],
+    aad: &[u8],
+    ct: &[u8],
+) -> Result<Vec<u8>, TholosError> {
+    count_open();
+    XChaCha20Poly1305::new(key.into())
+        .decrypt(&XNonce::from(*nonce24), Payload { msg: ct, aad })
+        .map_err(|_| TholosError::Aead)
+}
+
+/// A KEM encapsulation to one recipient: the ciphertext for the envelope and
+/// the shared secret it carries.
+pub struct Encapsulated {
+    kem_ct: Vec<u8>,
+    shared: Zeroizing<[u8; 32]>,
+    hybrid: bool,
+}
+
+/// Encapsulate a fresh shared secret to `recipient`'s ML-KEM key, at the
+/// level of that key. If `hybrid`, the key must be a hybrid one and the
+/// encapsulation is to its X25519 key as well; otherwise only the ML-KEM part
+/// of a hybrid key is used.
+pub fn encapsulate(
+    rng: &mut (impl RngCore + CryptoRng),
+    recipient: &RecipientPub,
+    hybrid: bool,
+) -> Result<Encapsulated, TholosError> {
+    let (kem_ct, shared) = if hybrid {
+        hybrid::encapsulate(rng, &recipient.pk_kyber)?
+    } else {
+        level::encapsulate(rng, hybrid::kem_pk(&recipient.pk_kyber))?
+    };
+    Ok(Encapsulated {
+        kem_ct,
+        shared,
+        hybrid,
+    })
+}
+
+/// Recover the shared secret of an envelope's `kem_ct` with `my`, the inverse of
+/// `encapsulate`. A hybrid envelope needs a hybrid key.
+fn decapsulate(
+    my: &RecipientPriv,
+    kem_ct: &[u8],
+    hybrid: bool,
+) -> Result<Zeroizing<[u8; 32]>, TholosError> {
+    count_open();
+    if !hybrid {
+        return my.sk_kyber.decapsulate(kem_ct);
+    }
+    let x25519 = my
+        .x25519
+        .as_ref()
+        .ok_or(TholosError::Malformed(hybrid::MISMATCH))?;
+    hybrid::decapsulate(&my.sk_kyber, x25519, kem_ct)
+}
+
+/// Recipient sets at least this large are encapsulated to in parallel.
+#[cfg(feature = "parallel")]
+const PARALLEL_MIN_RECIPIENTS: usize = 8;
+
+/// Encapsulate to every recipient from the OS RNG, in recipient order.
+#[cfg(not(feature = "parallel"))]
+fn encapsulate_all(
+    recipients: &[RecipientPub],
+    hybrid: bool,
+) -> Result<Vec<Encapsulated>, TholosError> {
+    recipients
+        .iter()
+        .map(|r| encapsulate(&mut OsRng, r, hybrid))
+        .collect()
+}
+
+/// Encapsulate to every recipient from the OS RNG, in recipient order, spread
+/// over the rayon pool once there are enough recipients to pay for it. Each
+/// encapsulation is independent, and collecting an indexed parallel iterator
+/// keeps the order, so the envelopes come out as they would serially.
+#[cfg(feature = "parallel")]
+fn encapsulate_all(
+    recipients: &[RecipientPub],
+    hybrid: bool,
+) -> Result<Vec<Encapsulated>, TholosError> {
+    use rayon::prelude::*;
+
+    if recipients.len() < PARALLEL_MIN_RECIPIENTS {
+        return recipients
+            .iter()
+            .map(|r| encapsulate(&mut OsRng, r, hybrid))
+            .collect();
+    }
+    recipients
+        .par_iter()
+        .map(|r| encapsulate(&mut OsRng, r, hybrid))
+        .collect()
+}
+
+/// Seal `cek` for one recipient under a KEK derived from an encapsulation and
+/// the header.
+fn wrap_cek_with(
+    rng: &mut (impl RngCore + CryptoRng),
+    cek: &[u8; 32],
+    recipient: &RecipientPub,
+    encapsulated: &Encapsulated,
+    header_cbor: &[u8],
+    aead_aad: &[u8],
+) -> Result<RecipientEnvelope, TholosError> {
+    let kek = hkdf32(encapsulated.shared.as_slice(), &recipient.kid, header_cbor);
+
+    let mut wrap_nonce = [0u8; 24];
+    rng.fill_bytes(&mut wrap_nonce);
+    let wrapped_cek = aead_enc(&kek, &wrap_nonce, aead_aad, cek)?;
+
+    Ok(RecipientEnvelope {
+        kid: recipient.kid.clone(),
+        kem_ct: encapsulated.kem_ct.clone(),
+        wrap_nonce: wrap_nonce.to_vec(),
+        wrapped_cek,
+    })
+}
+
+/// Wrap `cek` for one recipient: encapsulate to its ML-KEM key, and its X25519
+/// key if `hybrid`, derive a KEK from the shared secret and the header, and
+/// seal the CEK under it.
+pub fn wrap_cek(
+    rng: &mut (impl RngCore + CryptoRng),
+    cek: &[u8; 32],
+    recipient: &RecipientPub,
+    header_cbor: &[u8],
+    aead_aad: &[u8],
+    hybrid: bool,
+) -> Result<RecipientEnvelope, TholosError> {
+    let encapsulated = encapsulate(rng, recipient, hybrid)?;
+    wrap_cek_with(rng, cek, recipient, &encapsulated, header_cbor, aead_aad)
+}
+
+/// The envelope for `my_kid` among `envelopes`, found in time that depends only
+/// on the number of envelopes and not on which of them, if any, matches.
+///
+/// Every kid is compared in constant time and none is skipped, and when there
+/// is no match `my_sk` still decapsulates a dummy ciphertext, so a missing
+/// envelope costs the same KEM work as a present one. Only the lengths of kids
+/// that differ in length from `my_kid` show.
+pub fn find_envelope<'a>(
+    envelopes: &'a [RecipientEnvelope],
+    my_kid: &str,
+    my_sk: &KemSecret,
+) -> Result<&'a RecipientEnvelope, TholosError> {
+    let mut found = subtle::Choice::from(0);
+    let mut index = 0u64;
+    for (i, env) in envelopes.iter().enumerate() {
+        let matches = env.kid.as_bytes().ct_eq(my_kid.as_bytes()) & !found;
+        index.conditional_assign(&(i as u64), matches);
+        found |= matches;
+    }
+
+    if bool::from(found) {
+        Ok(&envelopes[index as usize])
+    } else {
+        let dummy = vec![0u8; my_sk.level().kem_ct_len()];
+        count_open();
+        std::hint::black_box(my_sk.decapsulate(&dummy)).ok();
+        Err(TholosError::MissingEnvelope(my_kid.to_string()))
+    }
+}
+
+/// Recover the CEK from `env`, the inverse of `wrap_cek`. An envelope made for
+/// a key of another level than `my` fails with `level::MISMATCH`, a hybrid one
+/// for a key that is not hybrid with `hybrid::MISMATCH`.
+pub fn unwrap_cek(
+    env: &RecipientEnvelope,
+    my_kid: &str,
+    my: &RecipientPriv,
+    header_cbor: &[u8],
+    aead_aad: &[u8],
+    hybrid: bool,
+) -> Result<[u8; 32], TholosError> {
+    // ML-KEM decapsulate → KEK
+    let wrap_nonce: [u8; 24] = env
+        .wrap_nonce
+        .as_slice()
+        .try_into()
+        .map_err(|_| TholosError::Malformed("wrap nonce"))?;
+    let shared = decapsulate(my, &env.kem_ct, hybrid)?;
+
+    let kek = hkdf32(shared.as_slice(), my_kid, header_cbor);
+
+    // Unwrap CEK
+    aead_dec(&kek, &wrap_nonce, aead_aad, &env.wrapped_cek)?
+        .as_slice()
+        .try_into()
+        .map_err(|_| TholosError::Malformed("cek length"))
+}
+
+/// Header fields a caller may set on `encrypt`, all unset by default.
+#[derive(Default)]
+pub struct HeaderFields {
+    /// See `Header::ttl_seconds`.
+    pub ttl_seconds: Option<u64>,
+    /// Message id to use instead of a random UUID.
+    pub message_id: Option<String>,
+    /// See `Header::metadata`.
+    pub metadata: Option<Vec<u8>>,
+    /// AEAD to seal the payload with.
+    pub aead: PayloadAead,
+    /// See `Header::pad_to`.
+    pub pad_to: Option<u32>,
+    /// AAD to bind each recipient's envelope to, in recipient order.
+    pub recipient_aads: Option<Vec<Vec<u8>>>,
+    /// See `Header::escrow`.
+    pub escrow: Option<String>,
+}
+
+/// `recipients` with `escrow` appended, unless one of them already has its kid,
+/// which `HeaderFields::escrow` names either way.
+pub fn with_escrow<'a>(
+    recipients: &'a [RecipientPub],
+    escrow: Option<&RecipientPub>,
+) -> Cow<'a, [RecipientPub]> {
+    match escrow {
+        Some(escrow) if !recipients.iter().any(|r| r.kid == escrow.kid) => {
+            Cow::Owned([recipients, std::slice::from_ref(escrow)].concat())
+        }
+        _ => Cow::Borrowed(recipients),
+    }
+}
+
+/// Associated data to decrypt with: the message's own, which every AEAD
+/// operation is bound to, and the recipient's, which only its envelope is.
+#[derive(Clone, Copy, Default)]
+pub struct Aad<'a> {
+    pub message: &'a [u8],
+    pub recipient: Option<&'a [u8]>,
+}
+
+/// Associated data of a CEK wrap. Without a recipient AAD it is that of the
+/// payload; with one it is the header, the length of the message AAD, the
+/// message AAD and the recipient AAD, so no two pairs of AADs give the same
+/// bytes.
+fn wrap_aad<'a>(
+    header_cbor: &[u8],
+    aead_aad: &'a [u8],
+    aad: &[u8],
+    recipient_aad: Option<&[u8]>,
+) -> Cow<'a, [u8]> {
+    match recipient_aad {
+        Some(recipient_aad) => Cow::Owned(
+            [
+                header_cbor,
+                &(aad.len() as u64).to_be_bytes(),
+                aad,
+                recipient_aad,
+            ]
+            .concat(),
+        ),
+        None => Cow::Borrowed(aead_aad),
+    }
+}
+
+/// Encrypt `plaintext` for `recipients`, binding `aad` into every AEAD operation.
+#[allow(clippy::too_many_arguments)]
+pub fn encrypt(
+    plaintext: &[u8],
+    sender: &SenderKey,
+    recipients: &[RecipientPub],
+    aad: &[u8],
+    compression: Compression,
+    embed_sender_key: bool,
+    hybrid: bool,
+    key_commitment: bool,
+    fields: &HeaderFields,
+) -> Result<Vec<u8>, TholosError> {
+    let mut rng = OsRng;
+    let encapsulations = encapsulate_all(recipients, hybrid)?;
+
+    seal(
+        &mut rng,
+        now(),
+        plaintext,
+        sender,
+        recipients,
+        &encapsulations,
+        aad,
+        compression,
+        embed_sender_key,
+        key_commitment,
+        fields,
+    )
+}
+
+/// How long each phase of `encrypt_timed` took.
+pub struct Timings {
+    /// ML-KEM encapsulation to every recipient.
+    pub kem: Duration,
+    /// Encrypting the payload and wrapping the CEK for every recipient.
+    pub aead: Duration,
+    /// Signing the bundle and encoding the wire.
+    pub sign: Duration,
+}
+
+/// `encrypt` without associated data or options other than `fields`, timing
+/// each phase. Kept apart from `encrypt` so that the uninstrumented path reads
+/// no clock.
+pub fn encrypt_timed(
+    plaintext: &[u8],
+    sender: &SenderKey,
+    recipients: &[RecipientPub],
+    fields: &HeaderFields,
+) -> Result<(Vec<u8>, Timings), TholosError> {
+    let start = Instant::now();
+    let encapsulations = encapsulate_all(recipients, false)?;
+    let kem = start.elapsed();
+
+    let start = Instant::now();
+    let inner = seal_unsigned(
+        &mut OsRng,
+        now(),
+        plaintext,
+        Some(sender),
+        recipients,
+        &encapsulations,
+        &[],
+        Compression::None,
+        false,
+        false,
+        fields,
+    )?;
+    let aead = start.elapsed();
+
+    let start = Instant::now();
+    let wire = sign(inner, sender)?;
+    let sign = start.elapsed();
+
+    Ok((wire, Timings { kem, aead, sign }))
+}
+
+/// `encrypt` without associated data or options, with every random value drawn
+/// from a ChaCha20 stream keyed by `seed` and the header timestamp set to 0, so
+/// the same inputs always give the same wire. Anyone knowing the seed can
+/// decrypt the wire, hence the feature is refused in optimized builds.
+#[cfg(feature = "deterministic-rng")]
+pub fn encrypt_deterministic(
+    seed: [u8; 32],
+    plaintext: &[u8],
+    sender: &SenderKey,
+    recipients: &[RecipientPub],
+) -> Result<Vec<u8>, TholosError> {
+    use rand_chacha::rand_core::SeedableRng;
+
+    let mut rng = rand_chacha::ChaCha20Rng::from_seed(seed);
+    let encapsulations = recipients
+        .iter()
+        .map(|r| encapsulate(&mut rng, r, false))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    seal(
+        &mut rng,
+        0,
+        plaintext,
+        sender,
+        recipients,
+        &encapsulations,
+        &[],
+        Compression::None,
+        false,
+        false,
+        &HeaderFields::default(),
+    )
+}
+
+/// Encrypt `plaintext` for `recipients` without a sender: the header names
+/// none, is flagged anonymous and the wire carries an empty signature.
+///
+/// The flag is part of the header, which every KEK derivation and AEAD
+/// operation is bound to, so it cannot be set on a signed wire to pass it off
+/// as anonymous, and a signed wire stripped of its signature still fails to
+/// verify.
+pub fn encrypt_anonymous(
+    plaintext: &[u8],
+    recipients: &[RecipientPub],
+    fields: &HeaderFields,
+) -> Result<Vec<u8>, TholosError> {
+    let encapsulations = encapsulate_all(recipients, false)?;
+    let inner = seal_unsigned(
+        &mut OsRng,
+        now(),
+        plaintext,
+        None,
+        recipients,
+        &encapsulations,
+        &[],
+        Compression::None,
+        false,
+        false,
+        fields,
+    )?;
+
+    to_wire(&BundleSigned {
+        inner,
+        sig_dilithium: Vec::new(),
+    })
+}
+
+/// Drop the signature of a wire and optionally flag its header anonymous, as
+/// an attacker passing a signed wire off as anonymous might, for testing that
+/// `decrypt` rejects it.
+#[cfg(feature = "test-hooks")]
+pub fn strip_signature(wire_cbor: &[u8], anonymous: bool) -> Result<Vec<u8>, TholosError> {
+    let mut bundle: BundleSigned = cbor::from_slice(wire_cbor)?;
+    bundle.sig_dilithium.clear();
+    bundle.inner.header.anonymous = anonymous;
+    to_wire(&bundle)
+}
+
+/// Change one field of a wire, named as in its CBOR, and keep its signature,
+/// for testing that the signature covers every field `decrypt` reads. Envelope
+/// fields are changed in the first envelope. Returns `None` for a name that is
+/// not a field.
+#[cfg(feature = "test-hooks")]
+pub fn tamper(wire_cbor: &[u8], field: &str) -> Result<Option<Vec<u8>>, TholosError> {
+    fn flip(bytes: &mut Vec<u8>) {
+        match bytes.first_mut() {
+            Some(b) => *b ^= 1,
+            None => bytes.push(0),
+        }
+    }
+
+    let mut bundle: BundleSigned = cbor::from_slice(wire_cbor)?;
+    // Destructured in full, so a field added to either struct fails to compile
+    // until it is listed here too
+    let BundleUnsigned {
+        header,
+        pay_nonce,
+        ciphertext,
+        recipients,
+        signer,
+        signer_pk,
+        key_commitment,
+    } = &mut bundle.inner;
+    let Header {
+        v,
+        suite,
+        sender,
+        recipients: kids,
+        msg_id,
+        timestamp_unix,
+        compression,
+        aead,
+        hybrid,
+        anonymous,
+        ttl_seconds,
+        metadata,
+        pad_to,
+        per_recipient_aad,
+        escrow,
+        sender_pk,
+    } = header;
+    let envelope = recipients
+        .first_mut()
+        .ok_or(TholosError::Malformed("no envelope"))?;
+
+    match field {
+        "v" => *v += 1,
+        "suite" => suite.push('!'),
+        "sender" => sender.push('!'),
+        "recipients" => kids.push("extra".to_string()),
+        "msg_id" => msg_id.push('!'),
+        "timestamp_unix" => *timestamp_unix += 1,
+        "compression" => {
+            *compression = match compression {
+                Compression::None => Compression::Zstd,
+                Compression::Zstd => Compression::None,
+            }
+        }
+        "aead" => {
+            *aead = match aead {
+                PayloadAead::XChaCha20Poly1305 => PayloadAead::Aes256Gcm,
+                PayloadAead::Aes256Gcm => PayloadAead::XChaCha20Poly1305,
+            }
+        }
+        "hybrid" => *hybrid = !*hybrid,
+        "anonymous" => *anonymous = !*anonymous,
+        "ttl_seconds" => *ttl_seconds = Some(ttl_seconds.map_or(3600, |ttl| ttl + 1)),
+        "metadata" => flip(metadata.get_or_insert_with(Vec::new)),
+        "pad_to" => *pad_to = Some(pad_to.map_or(16, |block| block * 2)),
+        "per_recipient_aad" => *per_recipient_aad = !*per_recipient_aad,
+        "escrow" => escrow.get_or_insert_with(String::new).push('!'),
+        "sender_pk" => flip(sender_pk.get_or_insert_with(Vec::new)),
+        "pay_nonce" => flip(pay_nonce),
+        "ciphertext" => flip(ciphertext),
+        "kid" => envelope.kid.push('!'),
+        "kem_ct" => flip(&mut envelope.kem_ct),
+        "wrap_nonce" => flip(&mut envelope.wrap_nonce),
+        "wrapped_cek" => flip(&mut envelope.wrapped_cek),
+        "signer" => signer.get_or_insert_with(String::new).push('!'),
+        "signer_pk" => flip(signer_pk.get_or_insert_with(Vec::new)),
+        "key_commitment" => flip(key_commitment.get_or_insert_with(Vec::new)),
+        _ => return Ok(None),
+    }
+    to_wire(&bundle).map(Some)
+}
+
+/// `encrypt` with a key commitment to another CEK than the one wrapped, for
+/// testing that `decrypt` rejects it.
+#[cfg(feature = "test-hooks")]
+pub fn encrypt_wrong_key_commitment(
+    plaintext: &[u8],
+    sender: &SenderKey,
+    recipients: &[RecipientPub],
+) -> Result<Vec<u8>, TholosError> {
+    let encapsulations = encapsulate_all(recipients, false)?;
+    let mut inner = seal_unsigned(
+        &mut OsRng,
+        now(),
+        plaintext,
+        Some(sender),
+        recipients,
+        &encapsulations,
+        &[],
+        Compression::None,
+        false,
+        false,
+        &HeaderFields::default(),
+    )?;
+    inner.key_commitment = Some(commit_to_key(&[0u8; 32]));
+    sign(inner, sender)
+}
+
+/// The current time as the header timestamp.
+pub fn now() -> u64 {
+    OffsetDateTime::now_utc().unix_timestamp() as u64
+}
+
+/// Encrypt every message in `messages` for `recipients`, encapsulating to each
+/// recipient once for the whole batch.
+///
+/// Every wire reuses the same KEM ciphertext and shared secret per recipient.
+/// Each still gets its own header, CEK and nonces, and the KEK is derived from
+/// the shared secret together with that header, so each wire decrypts on its
+/// own with `decrypt`.
+pub fn encrypt_batch(
+    messages: &[&[u8]],
+    sender: &SenderKey,
+    recipients: &[RecipientPub],
+    fields: &HeaderFields,
+) -> Result<Vec<Vec<u8>>, TholosError> {
+    let mut rng = OsRng;
+    let encapsulations = encapsulate_all(recipients, false)?;
+
+    messages
+        .iter()
+        .map(|m| {
+            seal(
+                &mut rng,
+                now(),
+                m,
+                sender,
+                recipients,
+                &encapsulations,
+                &[],
+                Compression::None,
+                false,
+                false,
+                fields,
+            )
+        })
+        .collect()
+}
+
+/// A wire encrypted for only some of the recipients asked for.
+pub struct Partial {
+    pub wire: Vec<u8>,
+    /// Indices of the recipients that were skipped.
+    pub failed: Vec<usize>,
+}
+
+/// Encrypt `plaintext` for those of `recipients` whose ML-KEM key can be
+/// encapsulated to, skipping the rest along with `None` entries, keys the
+/// caller could not decode at all. Returns `None` if no recipient is left to
+/// encrypt for.
+pub fn encrypt_partial(
+    plaintext: &[u8],
+    sender: &SenderKey,
+    recipients: &[Option<RecipientPub>],
+    fields: &HeaderFields,
+) -> Result<Option<Partial>, TholosError> {
+    let mut rng = OsRng;
+    let mut usable = Vec::with_capacity(recipients.len());
+    let mut encapsulations = Vec::with_capacity(recipients.len());
+    let mut failed = Vec::new();
+    for (i, recipient) in recipients.iter().enumerate() {
+        match recipient
+            .as_ref()
+            .map(|r| (r, encapsulate(&mut rng, r, false)))
+        {
+            Some((r, Ok(e))) => {
+                usable.push(r.clone());
+                encapsulations.push(e);
+            }
+            _ => failed.push(i),
+        }
+    }
+    if usable.is_empty() {
+        return Ok(None);
+    }
+
+    let wire = seal(
+        &mut rng,
+        now(),
+        plaintext,
+        sender,
+        &usable,
+        &encapsulations,
+        &[],
+        Compression::None,
+        false,
+        false,
+        fields,
+    )?;
+    Ok(Some(Partial { wire, failed }))
+}
+
+/// A payload sealed once by `encrypt_cached_init`, with the CEK kept so that
+/// `encrypt_cached_for` can make a wire of it for any recipient set.
+pub struct Cached {
+    sender: SenderKey,
+    /// The escrow recipient every wire made from it gets, see `with_escrow`.
+    escrow: Option<RecipientPub>,
+    payload: SealedPayload,
+}
+
+impl Cached {
+    pub fn escrow(&self) -> Option<&RecipientPub> {
+        self.escrow.as_ref()
+    }
+}
+
+/// Compress, pad and seal `plaintext` for recipients chosen later, signed by
+/// `sender` once they are.
+///
+/// The header is fixed here and every envelope and the payload are bound to
+/// it, so it names no recipients, as `add_recipient` leaves the header of an
+/// extended wire naming only the recipients at encryption time. Every wire
+/// made from it carries the same message id, timestamp and ciphertext, and an
+/// envelope for `escrow`, which the header names.
+pub fn encrypt_cached_init(
+    plaintext: &[u8],
+    sender: &SenderKey,
+    escrow: Option<RecipientPub>,
+) -> Result<Cached, TholosError> {
+    let payload = seal_payload(
+        &mut OsRng,
+        now(),
+        plaintext,
+        Some(sender),
+        &[],
+        false,
+        &[],
+        Compression::None,
+        false,
+        &HeaderFields {
+            escrow: escrow.as_ref().map(|escrow| escrow.kid.clone()),
+            ..HeaderFields::default()
+        },
+    )?;
+    Ok(Cached {
+        sender: sender.clone(),
+        escrow,
+        payload,
+    })
+}
+
+/// Wrap the CEK of `cached` for `recipients` and its escrow recipient and sign
+/// the result, without touching the payload.
+pub fn encrypt_cached_for(
+    cached: &Cached,
+    recipients: &[RecipientPub],
+) -> Result<Vec<u8>, TholosError> {
+    let payload = &cached.payload;
+    let recipients = with_escrow(recipients, cached.escrow());
+    let encapsulations = encapsulate_all(&recipients, false)?;
+    let envs = recipients
+        .iter()
+        .zip(&encapsulations)
+        .map(|(r, e)| {
+            wrap_cek_with(
+                &mut OsRng,
+                &payload.cek,
+                r,
+                e,
+                &payload.header_cbor,
+                &payload.aead_aad,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    sign(
+        BundleUnsigned {
+            header: payload.header.clone(),
+            pay_nonce: payload.pay_nonce.clone(),
+            ciphertext: payload.ciphertext.clone(),
+            recipients: envs,
+            signer: None,
+            signer_pk: None,
+            key_commitment: None,
+        },
+        &cached.sender,
+    )
+}
+
+/// Build and sign one wire stamped `timestamp_unix`, wrapping its CEK under the
+/// given encapsulations, one per recipient. The CEK, nonces and message id are
+/// drawn from `rng`.
+#[allow(clippy::too_many_arguments)]
+fn seal(
+    rng: &mut (impl RngCore + CryptoRng),
+    timestamp_unix: u64,
+    plaintext: &[u8],
+    sender: &SenderKey,
+    recipients: &[RecipientPub],
+    encapsulations: &[Encapsulated],
+    aad: &[u8],
+    compression: Compression,
+    embed_sender_key: bool,
+    key_commitment: bool,
+    fields: &HeaderFields,
+) -> Result<Vec<u8>, TholosError> {
+    let inner = seal_unsigned(
+        rng,
+        timestamp_unix,
+        plaintext,
+        Some(sender),
+        recipients,
+        encapsulations,
+        aad,
+        compression,
+        embed_sender_key,
+        key_commitment,
+        fields,
+    )?;
+    sign(inner, sender)
+}
+
+/// The bundle `seal` signs: header, payload and envelopes. Without a sender the
+/// header names none and is flagged anonymous.
+#[allow(clippy::too_many_arguments)]
+fn seal_unsigned(
+    rng: &mut (impl RngCore + CryptoRng),
+    timestamp_unix: u64,
+    plaintext: &[u8],
+    sender: Option<&SenderKey>,
+    recipients: &[RecipientPub],
+    encapsulations: &[Encapsulated],
+    aad: &[u8],
+    compression: Compression,
+    embed_sender_key: bool,
+    key_commitment: bool,
+    fields: &HeaderFields,
+) -> Result<BundleUnsigned, TholosError> {
+    let payload = seal_payload(
+        rng,
+        timestamp_unix,
+        plaintext,
+        sender,
+        recipients,
+        encapsulations.first().is_some_and(|e| e.hybrid),
+        aad,
+        compression,
+        embed_sender_key,
+        fields,
+    )?;
+
+    // Envelopes (one per recipient)
+    let recipient_aads = match &fields.recipient_aads {
+        Some(aads) if aads.len() != recipients.len() => {
+            return Err(TholosError::Malformed(RECIPIENT_AAD));
+        }
+        Some(aads) => aads.iter().map(|aad| Some(aad.as_slice())).collect(),
+        None => vec![None; recipients.len()],
+    };
+    let envs = recipients
+        .iter()
+        .zip(encapsulations)
+        .zip(recipient_aads)
+        .map(|((r, e), recipient_aad)| {
+            let wrap_aad = wrap_aad(&payload.header_cbor, &payload.aead_aad, aad, recipient_aad);
+            wrap_cek_with(rng, &payload.cek, r, e, &payload.header_cbor, &wrap_aad)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(BundleUnsigned {
+        key_commitment: key_commitment.then(|| commit_to_key(&payload.cek)),
+        header: payload.header,
+        pay_nonce: payload.pay_nonce,
+        ciphertext: payload.ciphertext,
+        recipients: envs,
+        signer: None,
+        signer_pk: None,
+    })
+}
+
+/// A header and the payload sealed under a fresh CEK bound to it, before any
+/// envelope is made.
+struct SealedPayload {
+    header: Header,
+    header_cbor: Vec<u8>,
+    /// The header CBOR followed by the message AAD.
+    aead_aad: Vec<u8>,
+    cek: Zeroizing<[u8; 32]>,
+    pay_nonce: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+/// Build the header naming `recipients` and seal `plaintext` under a CEK drawn
+/// from `rng`, the part of `seal_unsigned` that does not depend on any
+/// recipient's key.
+#[allow(clippy::too_many_arguments)]
+fn seal_payload(
+    rng: &mut (impl RngCore + CryptoRng),
+    timestamp_unix: u64,
+    plaintext: &[u8],
+    sender: Option<&SenderKey>,
+    recipients: &[RecipientPub],
+    hybrid: bool,
+    aad: &[u8],
+    compression: Compression,
+    embed_sender_key: bool,
+    fields: &HeaderFields,
+) -> Result<SealedPayload, TholosError> {
+    let (sealed, compression) = compress(plaintext, compression);
+    // Padded after compression, so the length of the compressed bytes is hidden
+    let sealed = match fields.pad_to {
+        Some(block) => Cow::Owned(pad(&sealed, block)?),
+        None => sealed,
+    };
+
+    // Header
+    let header = Header {
+        v: 1,
+        suite: SUITE_V1.to_string(),
+        sender: sender.map(|s| s.sid.clone()).unwrap_or_default(),
+        recipients: recipients.iter().map(|r| r.kid.clone()).collect(),
+        msg_id: fields.message_id.clone().unwrap_or_else(|| message_id(rng)),
+        timestamp_unix,
+        compression,
+        aead: fields.aead,
+        hybrid,
+        anonymous: sender.is_none(),
+        ttl_seconds: fields.ttl_seconds,
+        metadata: fields.metadata.clone(),
+        pad_to: fields.pad_to,
+        per_recipient_aad: fields.recipient_aads.is_some(),
+        escrow: fields.escrow.clone(),
+        sender_pk: sender
+            .filter(|_| embed_sender_key)
+            .map(|s| s.dilithium.public_bytes()),
+    };
+    let header_cbor = cbor::to_vec_tagged(&header)?;
+    let aead_aad = [header_cbor.as_slice(), aad].concat();
+
+    // CEK
+    let mut cek = Zeroizing::new([0u8; 32]);
+    rng.fill_bytes(cek.as_mut_slice());
+
+    // Payload AEAD
+    let (pay_nonce, ciphertext) = header.aead.seal(rng, &cek, &aead_aad, &sealed)?;
+
+    Ok(SealedPayload {
+        header,
+        header_cbor,
+        aead_aad,
+        cek,
+        pay_nonce,
+        ciphertext,
+    })
+}
+
+/// The key commitment of `cek`, see `BundleUnsigned`.
+fn commit_to_key(cek: &[u8; 32]) -> Vec<u8> {
+    Sha256::new()
+        .chain_update(KEY_COMMITMENT_CONTEXT)
+        .chain_update(cek)
+        .finalize()
+        .to_vec()
+}
+
+/// Check the key commitment of a wire, if it has one, against the unwrapped `cek`.
+fn check_key_commitment(inner: &BundleUnsigned, cek: &[u8; 32]) -> Result<(), TholosError> {
+    match &inner.key_commitment {
+        Some(commitment) if !bool::from(commitment.ct_eq(&commit_to_key(cek))) => {
+            Err(TholosError::Malformed(KEY_COMMITMENT))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// A random version 4 UUID, as `uuid::Uuid::new_v4` makes from the OS RNG.
+fn message_id(rng: &mut impl RngCore) -> String {
+    let mut bytes = [0u8; 16];
+    rng.fill_bytes(&mut bytes);
+    uuid::Builder::from_random_bytes(bytes)
+        .into_uuid()
+        .to_string()
+}
+
+/// Sign the canonical CBOR of `inner` and encode the signed bundle as a wire.
+fn sign(inner: BundleUnsigned, sender: &SenderKey) -> Result<Vec<u8>, TholosError> {
+    let inner_cbor = cbor::to_vec_tagged(&inner)?;
+    let sig_dilithium = sender.dilithium.sign(&inner_cbor);
+
+    to_wire(&BundleSigned {
+        inner,
+        sig_dilithium,
+    })
+}
+
+/// Check the signature of `bundle` against the Dilithium public key `pk`.
+fn verify(bundle: &BundleSigned, pk: &[u8]) -> Result<(), TholosError> {
+    let inner_cbor = cbor::to_vec_tagged(&bundle.inner)?;
+    match level::verify(pk, &inner_cbor, &bundle.sig_dilithium) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(TholosError::BadSignature),
+        Err(level::VerifyError::PublicKey) => Err(TholosError::Malformed("dilithium pk")),
+        Err(level::VerifyError::Signature) => Err(TholosError::Malformed("signature")),
+        Err(level::VerifyError::Mismatch) => Err(TholosError::Malformed(level::MISMATCH)),
+    }
+}
+
+/// The version byte followed by the canonical CBOR of `bundle`, serialized in
+/// place so the ciphertext is not copied again to make room for the prefix.
+fn to_wire(bundle: &BundleSigned) -> Result<Vec<u8>, TholosError> {
+    let mut buf = vec![wire::VERSION];
+    buf.extend_from_slice(&cbor::SELF_DESCRIBE_TAG);
+    cbor::write(bundle, &mut buf)?;
+    Ok(buf)
+}
+
+/// A successfully decrypted message.
+pub struct Decrypted {
+    /// The decrypted payload.
+    pub plaintext: Vec<u8>,
+    /// The sid whose Dilithium key verified the signature, empty for an
+    /// anonymous wire.
+    pub sender: String,
+    /// That Dilithium public key, empty for an anonymous wire.
+    pub sender_pk: Vec<u8>,
+    /// Whether the wire is anonymous, see `encrypt_anonymous`.
+    pub anonymous: bool,
+}
+
+/// When a wire counts as expired: at `now`, once older than its time to live
+/// or than `max_age_seconds`.
+#[derive(Clone, Copy)]
+pub struct Expiry {
+    /// The current time, in Unix seconds.
+    pub now: u64,
+    /// The oldest wire the caller accepts, whatever its time to live.
+    pub max_age_seconds: Option<u64>,
+}
+
+impl Expiry {
+    /// Whether a wire with `header` has expired. A timestamp ahead of `now`
+    /// counts as age 0.
+    fn passed(&self, header: &Header) -> bool {
+        let age = self.now.saturating_sub(header.timestamp_unix);
+        header
+            .ttl_seconds
+            .into_iter()
+            .chain(self.max_age_seconds)
+            .any(|limit| age > limit)
+    }
+}
+
+/// The senders whose wires are accepted, looked up by sid.
+///
+/// A list is scanned for the first entry with the sid; a map built once from
+/// many keys turns that into a hash lookup.
+pub trait AllowedSenders {
+    /// Whether no sender is listed.
+    fn is_empty(&self) -> bool;
+
+    /// The Dilithium public key listed for `sid`.
+    fn get(&self, sid: &str) -> Option<&Vec<u8>>;
+}
+
+impl AllowedSenders for [(String, Vec<u8>)] {
+    fn is_empty(&self) -> bool {
+        <[_]>::is_empty(self)
+    }
+
+    fn get(&self, sid: &str) -> Option<&Vec<u8>> {
+        self.iter().find(|(s, _)| s == sid).map(|(_, pk)| pk)
+    }
+}
+
+impl AllowedSenders for HashMap<String, Vec<u8>> {
+    fn is_empty(&self) -> bool {
+        HashMap::is_empty(self)
+    }
+
+    fn get(&self, sid: &str) -> Option<&Vec<u8>> {
+        HashMap::get(self, sid)
+    }
+}
+
+/// Verify the signature of `bundle` against the allow-list entry for the sid of
+/// its signer, returning the Dilithium key that verified it. The signer is the
+/// sender in the header unless the wire was re-signed.
+///
+/// With `embedded_key`, an empty allow-list accepts any sender instead,
+/// verifying against the key embedded in the wire; without one there is
+/// nothing to verify against and the wire is rejected. Otherwise an empty
+/// allow-list rejects every wire.
+fn verify_sender<'a>(
+    bundle: &'a BundleSigned,
+    allowed_senders: &'a (impl AllowedSenders + ?Sized),
+    embedded_key: bool,
+) -> Result<&'a Vec<u8>, TholosError> {
+    let Some(pk_bytes) = signer_key(bundle, allowed_senders, embedded_key) else {
+        return Err(TholosError::BadSignature);
+    };
+    verify(bundle, pk_bytes)?;
+    Ok(pk_bytes)
+}
+
+/// The Dilithium key `verify_sender` checks the signature of `bundle` against.
+fn signer_key<'a>(
+    bundle: &'a BundleSigned,
+    allowed_senders: &'a (impl AllowedSenders + ?Sized),
+    embedded_key: bool,
+) -> Option<&'a Vec<u8>> {
+    if embedded_key && allowed_senders.is_empty() {
+        bundle.inner.signer_pk()
+    } else {
+        allowed_senders.get(bundle.inner.signer())
+    }
+}
+
+/// Whether `decrypt` has a key to check the signature of the bundle CBOR of a
+/// signed wire against, so that a `BadSignature` from it means the signature
+/// does not verify rather than that the sender is not allowed. False for an
+/// anonymous wire and for one that does not parse.
+pub fn signer_known(wire_cbor: &[u8], allowed_senders: &(impl AllowedSenders + ?Sized)) -> bool {
+    match cbor::from_slice::<BundleSigned>(wire_cbor) {
+        Ok(bundle) => {
+            !bundle.inner.header.anonymous && signer_key(&bundle, allowed_senders, false).is_some()
+        }
+        Err(_) => false,
+    }
+}
+
+/// Which wires `decrypt` accepts besides those signed by an allowed sender.
+/// The default accepts none.
+#[derive(Clone, Copy, Default)]
+pub struct Accept {
+    /// Anonymous wires, which are not signed at all.
+    pub anonymous: bool,
+    /// With an empty allow-list, wires signed by the sender key embedded in
+    /// them, which proves nothing about who the sender is.
+    pub embedded_key: bool,
+}
+
+/// Check that the bundle CBOR of a wire was signed by an allowed sender,
+/// without touching any envelope or the payload, and return the sender's sid.
+pub fn validate(
+    wire_cbor: &[u8],
+    allowed_senders: &(impl AllowedSenders + ?Sized),
+) -> Result<String, TholosError> {
+    let bundle: BundleSigned = cbor::from_slice(wire_cbor)?;
+    verify_sender(&bundle, allowed_senders, false)?;
+    Ok(bundle.inner.signer().to_string())
+}
+
+/// Decrypt the bundle CBOR of a wire (see `wire::body`) as `my_kid`, requiring
+/// the same `aad` that was used to encrypt it. A recipient AAD must be given
+/// exactly when the wire binds envelopes to one, or `RECIPIENT_AAD` is
+/// returned. Senders are checked as `verify_sender` describes.
+///
+/// An anonymous wire is only accepted with `accept.anonymous`, regardless of
+/// the allow-list, and fails with `BadSignature` otherwise, so that callers
+/// relying on a verified sender never see one. Likewise a wire is only
+/// verified against its embedded sender key with `accept.embedded_key`.
+///
+/// The signature covers the header, the payload and every envelope, and it is
+/// verified before anything else happens: no envelope is decapsulated and
+/// nothing is decrypted for a wire whose sender is not allowed or whose
+/// signature does not verify, so attacker-controlled ciphertext only ever
+/// reaches the KEM and the AEAD from an allowed sender.
+///
+/// With `expiry`, a wire that has expired fails with `EXPIRED` as soon as its
+/// signature verifies, before its envelope is opened. Without it, a wire with
+/// a time to live fails with `NOW_REQUIRED` at the same point, since there is
+/// no telling whether it is stale.
+pub fn decrypt(
+    wire_cbor: &[u8],
+    my_kid: &str,
+    my: &RecipientPriv,
+    allowed_senders: &(impl AllowedSenders + ?Sized),
+    aad: Aad,
+    expiry: Option<Expiry>,
+    accept: Accept,
+) -> Result<Decrypted, TholosError> {
+    let bundle: BundleSigned = cbor::from_slice(wire_cbor)?;
+    let anonymous = bundle.inner.header.anonymous;
+
+    // Verify sender + signature
+    let pk_bytes = if anonymous {
+        if !accept.anonymous {
+            return Err(TholosError::BadSignature);
+        }
+        Vec::new()
+    } else {
+        verify_sender(&bundle, allowed_senders, accept.embedded_key)?.clone()
+    };
+
+    // The signed timestamp is checked before any key is used on the wire
+    let header = &bundle.inner.header;
+    match expiry {
+        Some(expiry) if expiry.passed(header) => return Err(TholosError::Malformed(EXPIRED)),
+        None if header.ttl_seconds.is_some() => {
+            return Err(TholosError::Malformed(NOW_REQUIRED));
+        }
+        _ => {}
+    }
+
+    // Find my envelope
+    let env = find_envelope(&bundle.inner.recipients, my_kid, &my.sk_kyber)?;
+
+    if header.per_recipient_aad != aad.recipient.is_some() {
+        return Err(TholosError::Malformed(RECIPIENT_AAD));
+    }
+    let header_cbor = cbor::to_vec_tagged(header)?;
+    let aead_aad = [header_cbor.as_slice(), aad.message].concat();
+    let wrap_aad = wrap_aad(&header_cbor, &aead_aad, aad.message, aad.recipient);
+    let cek = unwrap_cek(env, my_kid, my, &header_cbor, &wrap_aad, header.hybrid)?;
+    check_key_commitment(&bundle.inner, &cek)?;
+
+    // Decrypt payload
+    let sealed = header.aead.open(
+        &cek,
+        &bundle.inner.pay_nonce,
+        &aead_aad,
+        &bundle.inner.ciphertext,
+    )?;
+    let sealed = match header.pad_to {
+        Some(block) => unpad(sealed, block)?,
+        None => sealed,
+    };
+    let plaintext = decompress(sealed, bundle.inner.header.compression)?;
+
+    Ok(Decrypted {
+        plaintext,
+        sender: bundle.inner.signer().to_string(),
+        sender_pk: pk_bytes,
+        anonymous,
+    })
+}
+
+/* ---------------- Editing recipients ---------------- */
+
+/// Why the recipients of a wire could not be changed.
+pub enum EditError {
+    Tholos(TholosError),
+    /// The recipient to add already has an envelope.
+    AlreadyPresent,
+    /// The recipient to remove has no envelope.
+    NotPresent,
+    /// The envelope to unwrap does not open under the associated data given,
+    /// which differs from the one the wire was encrypted with.
+    AadMismatch,
+}
+
+impl From<TholosError> for EditError {
+    fn from(e: TholosError) -> Self {
+        EditError::Tholos(e)
+    }
+}
+
+/// Parse a wire and check that `sender` signed it, since editing re-signs it
+/// under that sender's key.
+fn open_own(wire_cbor: &[u8], sender: &SenderKey) -> Result<BundleSigned, TholosError> {
+    let bundle: BundleSigned = cbor::from_slice(wire_cbor)?;
+    if bundle.inner.header.anonymous || bundle.inner.signer() != sender.sid {
+        return Err(TholosError::BadSignature);
+    }
+    verify(&bundle, &sender.dilithium.public_bytes())?;
+    Ok(bundle)
+}
+
+/// Give `recipient` access to a wire signed by `sender`, without touching the
+/// payload: the CEK is unwrapped with `my` and wrapped again for the new
+/// recipient in an envelope appended to the wire, which is then re-signed.
+///
+/// The header, which every KEK derivation and AEAD operation is bound to, is
+/// left as it is, so the existing envelopes and the payload stay valid. Its
+/// recipient list therefore keeps naming the recipients at encryption time.
+///
+/// `aad` is the associated data the wire was encrypted with, which the CEK
+/// wraps are bound to as the payload is.
+pub fn add_recipient(
+    wire_cbor: &[u8],
+    sender: &SenderKey,
+    my_kid: &str,
+    my: &RecipientPriv,
+    recipient: &RecipientPub,
+    aad: &[u8],
+) -> Result<Vec<u8>, EditError> {
+    let mut inner = open_own(wire_cbor, sender)?.inner;
+    if inner.recipients.iter().any(|e| e.kid == recipient.kid) {
+        return Err(EditError::AlreadyPresent);
+    }
+
+    let new_env = rewrap_cek(&inner, my_kid, my, recipient, aad)?;
+    inner.recipients.push(new_env);
+
+    Ok(sign(inner, sender)?)
+}
+
+/// Hand the access of `old_kid` to a wire signed by `sender` over to
+/// `recipient`, typically a rotated key of the same recipient: the CEK is
+/// unwrapped with `my`, the key of `old_kid`, and wrapped again for `recipient`
+/// in an envelope that takes the place of the old one, and the wire is
+/// re-signed.
+///
+/// As with `add_recipient` the header is left as it is, so it keeps naming
+/// `old_kid`. `recipient` may have the kid of the old key, whose envelope is
+/// then replaced, but no other kid the wire already has an envelope for.
+/// `aad` is as for `add_recipient`.
+pub fn rewrap_recipient(
+    wire_cbor: &[u8],
+    sender: &SenderKey,
+    old_kid: &str,
+    my: &RecipientPriv,
+    recipient: &RecipientPub,
+    aad: &[u8],
+) -> Result<Vec<u8>, EditError> {
+    let mut inner = open_own(wire_cbor, sender)?.inner;
+    if recipient.kid != old_kid && inner.recipients.iter().any(|e| e.kid == recipient.kid) {
+        return Err(EditError::AlreadyPresent);
+    }
+
+    let new_env = rewrap_cek(&inner, old_kid, my, recipient, aad)?;
+    for env in &mut inner.recipients {
+        if env.kid == old_kid {
+            *env = new_env;
+            break;
+        }
+    }
+
+    Ok(sign(inner, sender)?)
+}
+
+/// A new envelope for `recipient` carrying the CEK of `inner`, unwrapped from
+/// the envelope of `my_kid` with `my` under the message AAD `aad`.
+fn rewrap_cek(
+    inner: &BundleUnsigned,
+    my_kid: &str,
+    my: &RecipientPriv,
+    recipient: &RecipientPub,
+    aad: &[u8],
+) -> Result<RecipientEnvelope, EditError> {
+    // Envelopes bound to recipient AADs need the AAD of the recipient unwrapping
+    if inner.header.per_recipient_aad {
+        return Err(TholosError::Malformed(RECIPIENT_AAD).into());
+    }
+    let env = find_envelope(&inner.recipients, my_kid, &my.sk_kyber)?;
+
+    // A hybrid wire only gets hybrid envelopes, so the new recipient's key must be one
+    let hybrid = inner.header.hybrid;
+    let header_cbor = cbor::to_vec_tagged(&inner.header).map_err(TholosError::from)?;
+    let aead_aad = [header_cbor.as_slice(), aad].concat();
+    let wrap_aad = wrap_aad(&header_cbor, &aead_aad, aad, None);
+    // The wire is the sender's own and verified, so a wrap that does not open
+    // under the stored key was bound to other associated data
+    let cek =
+        unwrap_cek(env, my_kid, my, &header_cbor, &wrap_aad, hybrid).map_err(|e| match e {
+            TholosError::Aead => EditError::AadMismatch,
+            e => EditError::Tholos(e),
+        })?;
+    let cek = Zeroizing::new(cek);
+    Ok(wrap_cek(
+        &mut OsRng,
+        &cek,
+        recipient,
+        &header_cbor,
+        &wrap_aad,
+        hybrid,
+    )?)
+}
+
+/// Drop the envelope of `kid` from a wire signed by `sender` and re-sign it.
+///
+/// As with `add_recipient` the header is left as it is. The content key is
+/// unchanged, so whoever kept the old wire or its content key can still read
+/// the payload; only the new wire stops carrying an envelope for `kid`.
+pub fn remove_recipient(
+    wire_cbor: &[u8],
+    sender: &SenderKey,
+    kid: &str,
+) -> Result<Vec<u8>, EditError> {
+    let mut inner = open_own(wire_cbor, sender)?.inner;
+    let before = inner.recipients.len();
+    inner.recipients.retain(|e| e.kid != kid);
+    if inner.recipients.len() == before {
+        return Err(EditError::NotPresent);
+    }
+
+    Ok(sign(inner, sender)?)
+}
+
+/* ---------------- Re-signing ---------------- */
+
+/// Re-sign a wire signed by `old` under `new`, leaving the header, the
+/// envelopes and the payload as they are.
+///
+/// The header keeps naming the original sender, since everything else is bound
+/// to it; the wire names `new` as its signer instead. Re-signing back to the
+/// sender in the header drops that again.
+pub fn resign(wire_cbor: &[u8], old: &SenderKey, new: &SenderKey) -> Result<Vec<u8>, TholosError> {
+    let mut inner = open_own(wire_cbor, old)?.inner;
+    if new.sid == inner.header.sender {
+        inner.signer = None;
+        inner.signer_pk = None;
+    } else {
+        inner.signer_pk = inner
+            .header
+            .sender_pk
+            .is_some()
+            .then(|| new.dilithium.public_bytes());
+        inner.signer = Some(new.sid.clone());
+    }
+
+    sign(inner, new)
+}
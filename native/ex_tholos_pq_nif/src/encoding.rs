@@ -0,0 +1,39 @@
+//! Text encodings of a whole wire, for transports that only carry strings.
+//!
+//! The encoding wraps the finished wire, version byte included, so it is not
+//! part of the format: decoding gives back exactly the bytes `encrypt` would
+//! have returned raw. Base64url is written unpadded, as in JWS and JWE, and
+//! read with or without padding.
+
+use base64ct::{Base64UrlUnpadded, Encoding as _};
+use std::borrow::Cow;
+
+/// How a wire is handed to and taken from Elixir.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Raw,
+    Base64Url,
+}
+
+impl Encoding {
+    pub fn encode(self, wire: Vec<u8>) -> Vec<u8> {
+        match self {
+            Encoding::Raw => wire,
+            Encoding::Base64Url => Base64UrlUnpadded::encode_string(&wire).into_bytes(),
+        }
+    }
+
+    /// The raw wire, or `None` if `text` is not valid in this encoding.
+    pub fn decode(self, text: &[u8]) -> Option<Cow<'_, [u8]>> {
+        match self {
+            Encoding::Raw => Some(Cow::Borrowed(text)),
+            Encoding::Base64Url => {
+                let unpadded = text.strip_suffix(b"==").or_else(|| text.strip_suffix(b"="));
+                Base64UrlUnpadded::decode_vec(std::str::from_utf8(unpadded.unwrap_or(text)).ok()?)
+                    .ok()
+                    .map(Cow::Owned)
+            }
+        }
+    }
+}
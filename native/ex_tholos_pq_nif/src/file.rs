@@ -0,0 +1,117 @@
+//! Streaming encryption between files, so large payloads never pass through
+//! BEAM binaries.
+//!
+//! Decrypted output is written next to its destination under a `.partial`
+//! suffix and only renamed into place once the stream has been verified, so a
+//! failed decryption never leaves unauthenticated plaintext at the output path.
+
+use crate::stream::{Decryptor, Encryptor};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use tholos_pq::TholosError;
+
+/// Bytes read from the input per step.
+const READ_SIZE: usize = 1024 * 1024;
+
+pub enum FileError {
+    /// A filesystem operation failed; the detail names the path.
+    Io(String),
+    /// The stream itself failed to encrypt or decrypt.
+    Crypto(TholosError),
+}
+
+impl From<TholosError> for FileError {
+    fn from(e: TholosError) -> Self {
+        FileError::Crypto(e)
+    }
+}
+
+fn io_error(path: &Path) -> impl FnOnce(io::Error) -> FileError + '_ {
+    move |e| FileError::Io(format!("{}: {}", path.display(), e))
+}
+
+/// The half of a stream that `pump` drives.
+trait Transform {
+    fn update(&mut self, input: &[u8]) -> Result<Vec<u8>, TholosError>;
+    fn finish(&mut self) -> Result<Vec<u8>, TholosError>;
+}
+
+impl Transform for Encryptor {
+    fn update(&mut self, input: &[u8]) -> Result<Vec<u8>, TholosError> {
+        Encryptor::update(self, input)
+    }
+
+    fn finish(&mut self) -> Result<Vec<u8>, TholosError> {
+        Encryptor::finish(self)
+    }
+}
+
+impl Transform for Decryptor {
+    fn update(&mut self, input: &[u8]) -> Result<Vec<u8>, TholosError> {
+        Decryptor::update(self, input)
+    }
+
+    fn finish(&mut self) -> Result<Vec<u8>, TholosError> {
+        Decryptor::finish(self)
+    }
+}
+
+/// Read `input` through `transform` and write everything it produces to `output`.
+fn pump(input: &Path, output: &Path, transform: &mut impl Transform) -> Result<(), FileError> {
+    let mut reader = File::open(input).map_err(io_error(input))?;
+    let mut writer = BufWriter::new(File::create(output).map_err(io_error(output))?);
+
+    let mut buf = vec![0u8; READ_SIZE];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(io_error(input)(e)),
+        };
+        writer
+            .write_all(&transform.update(&buf[..n])?)
+            .map_err(io_error(output))?;
+    }
+    writer
+        .write_all(&transform.finish()?)
+        .map_err(io_error(output))?;
+
+    let file = writer
+        .into_inner()
+        .map_err(|e| io_error(output)(e.into_error()))?;
+    file.sync_all().map_err(io_error(output))
+}
+
+/// Encrypt the file at `input` into `output`.
+pub fn encrypt_file(
+    input: &Path,
+    output: &Path,
+    mut encryptor: Encryptor,
+) -> Result<(), FileError> {
+    pump(input, output, &mut encryptor)
+}
+
+/// Decrypt the file at `input` into `output`, which only appears once the
+/// whole stream has been verified.
+pub fn decrypt_file(
+    input: &Path,
+    output: &Path,
+    mut decryptor: Decryptor,
+) -> Result<(), FileError> {
+    let partial = partial_path(output);
+    let result = pump(input, &partial, &mut decryptor)
+        .and_then(|()| fs::rename(&partial, output).map_err(io_error(output)));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result
+}
+
+fn partial_path(output: &Path) -> PathBuf {
+    let mut name = output.as_os_str().to_owned();
+    name.push(".partial");
+    PathBuf::from(name)
+}
@@ -0,0 +1,26 @@
+//! Length-prefixed framing, for sending several wires over one byte stream.
+//!
+//! A frame is the length of the wire as a 4-byte big-endian integer followed
+//! by the wire itself. The framing is only a transport helper: it knows
+//! nothing about what it carries and adds no integrity of its own, which the
+//! wire already has.
+
+/// Length of the big-endian length in front of every frame.
+pub const PREFIX_LEN: usize = 4;
+
+/// `wire` with its length in front, or `None` if it is too long to frame.
+pub fn frame(wire: &[u8]) -> Option<Vec<u8>> {
+    let len = u32::try_from(wire.len()).ok()?;
+    let mut framed = Vec::with_capacity(PREFIX_LEN + wire.len());
+    framed.extend_from_slice(&len.to_be_bytes());
+    framed.extend_from_slice(wire);
+    Some(framed)
+}
+
+/// The length of the wire in the first frame of `buffer`, or `None` until the
+/// whole frame has arrived.
+pub fn first_frame_len(buffer: &[u8]) -> Option<usize> {
+    let prefix: [u8; PREFIX_LEN] = buffer.get(..PREFIX_LEN)?.try_into().ok()?;
+    let len = u32::from_be_bytes(prefix) as usize;
+    (buffer.len() - PREFIX_LEN >= len).then_some(len)
+}
@@ -0,0 +1,122 @@
+//! Hybrid recipient keys: ML-KEM together with X25519.
+//!
+//! A hybrid key is an ML-KEM key of some level plus an X25519 key, so that a
+//! message stays confidential as long as either of the two holds. Its public
+//! half is still a `tholos_pq::RecipientPub`: `pk_kyber` carries the ML-KEM
+//! encapsulation key followed by the 32-byte X25519 public key. No level's
+//! ML-KEM key is 32 bytes longer than another's, so the length alone tells the
+//! two kinds apart, and everything that only needs the ML-KEM key takes
+//! `kem_pk` of it and treats a hybrid key like any other.
+//!
+//! A hybrid encapsulation performs both: an ML-KEM encapsulation and an
+//! ephemeral X25519 key agreement. Its ciphertext is the ML-KEM ciphertext
+//! followed by the ephemeral public key, and its shared secret is HKDF-SHA256
+//! over both shared secrets, with the ciphertext and the recipient's X25519
+//! key as info, so neither can be swapped out on its own. Whether a wire used
+//! hybrid encapsulations is recorded in its header, see `crypto::Header`.
+
+use crate::level::{self, KemSecret, Level};
+use hkdf::Hkdf;
+use rand::{CryptoRng, RngCore};
+use sha2::Sha256;
+use tholos_pq::TholosError;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+/// Detail of the `Malformed` error for a hybrid wire opened with a key that
+/// is not hybrid, or a hybrid encryption to such a key.
+pub const MISMATCH: &str = "hybrid mismatch";
+
+/// Length of an X25519 public key and of an ephemeral key in a ciphertext.
+pub const X25519_LEN: usize = 32;
+
+const DOMAIN: &[u8] = b"ex_tholos_pq hybrid v1";
+
+/// Split a recipient public key into its ML-KEM key and, for a hybrid key,
+/// its X25519 key.
+pub fn split_pk(pk: &[u8]) -> (&[u8], Option<[u8; X25519_LEN]>) {
+    if Level::of_kem_pk(pk.len()).is_none() && pk.len() > X25519_LEN {
+        let (kem_pk, x25519_pk) = pk.split_at(pk.len() - X25519_LEN);
+        if Level::of_kem_pk(kem_pk.len()).is_some() {
+            return (kem_pk, x25519_pk.try_into().ok());
+        }
+    }
+    (pk, None)
+}
+
+/// The ML-KEM key of a recipient public key, hybrid or not.
+pub fn kem_pk(pk: &[u8]) -> &[u8] {
+    split_pk(pk).0
+}
+
+/// The public key of a hybrid keypair made of `kem_pk` and `x25519`.
+pub fn public_key(kem_pk: &[u8], x25519: &StaticSecret) -> Vec<u8> {
+    [kem_pk, PublicKey::from(x25519).as_bytes()].concat()
+}
+
+/// Encapsulate to the hybrid public key `pk`. Returns the ciphertext and the
+/// combined shared secret, or `MISMATCH` if `pk` is no hybrid key.
+pub fn encapsulate(
+    rng: &mut (impl RngCore + CryptoRng),
+    pk: &[u8],
+) -> Result<(Vec<u8>, Zeroizing<[u8; 32]>), TholosError> {
+    let (kem_pk, Some(x25519_pk)) = split_pk(pk) else {
+        return Err(TholosError::Malformed(MISMATCH));
+    };
+    let (kem_ct, kem_shared) = level::encapsulate(rng, kem_pk)?;
+
+    let ephemeral = EphemeralSecret::random_from_rng(&mut *rng);
+    let ephemeral_pk = PublicKey::from(&ephemeral);
+    let dh_shared = ephemeral.diffie_hellman(&PublicKey::from(x25519_pk));
+    // A low-order recipient key would make the X25519 secret a known value
+    if !dh_shared.was_contributory() {
+        return Err(TholosError::Malformed("x25519 pk"));
+    }
+
+    let ct = [kem_ct.as_slice(), ephemeral_pk.as_bytes()].concat();
+    let shared = combine(&kem_shared, dh_shared.as_bytes(), &ct, &x25519_pk);
+    Ok((ct, shared))
+}
+
+/// Recover the combined shared secret from a hybrid ciphertext `ct` with the
+/// ML-KEM key `kem_sk` and the X25519 key `x25519`.
+pub fn decapsulate(
+    kem_sk: &KemSecret,
+    x25519: &StaticSecret,
+    ct: &[u8],
+) -> Result<Zeroizing<[u8; 32]>, TholosError> {
+    if ct.len() < X25519_LEN {
+        return Err(TholosError::Malformed("kem ct"));
+    }
+    let (kem_ct, ephemeral_pk) = ct.split_at(ct.len() - X25519_LEN);
+    let ephemeral_pk: [u8; X25519_LEN] = ephemeral_pk
+        .try_into()
+        .map_err(|_| TholosError::Malformed("kem ct"))?;
+
+    let kem_shared = kem_sk.decapsulate(kem_ct)?;
+    let dh_shared = x25519.diffie_hellman(&PublicKey::from(ephemeral_pk));
+    let x25519_pk = PublicKey::from(x25519);
+    Ok(combine(
+        &kem_shared,
+        dh_shared.as_bytes(),
+        ct,
+        x25519_pk.as_bytes(),
+    ))
+}
+
+fn combine(
+    kem_shared: &[u8; 32],
+    dh_shared: &[u8; 32],
+    ct: &[u8],
+    x25519_pk: &[u8; X25519_LEN],
+) -> Zeroizing<[u8; 32]> {
+    let ikm = Zeroizing::new([kem_shared.as_slice(), dh_shared.as_slice()].concat());
+    let info = [DOMAIN, ct, x25519_pk].concat();
+    let mut shared = Zeroizing::new([0u8; 32]);
+    // A 32-byte output is far below the HKDF-SHA256 limit of 8160 bytes
+    #[allow(clippy::expect_used)]
+    Hkdf::<Sha256>::new(None, &ikm)
+        .expand(&info, shared.as_mut_slice())
+        .expect("HKDF expand failed - this should never happen with 32-byte output");
+    shared
+}
@@ -0,0 +1,546 @@
+//! Secret key material: zeroizing holders and serializable forms.
+//!
+//! `tholos_pq` only derives `Serialize` for public keys. Secret keys leave the
+//! NIF through these wrappers instead, so serializing a secret always means a
+//! caller explicitly asked for it to be exported or handed it in.
+//!
+//! Every secret held here is wiped when it is dropped. ML-KEM decapsulation
+//! keys do that themselves (ml-kem's `zeroize` feature); Dilithium secret keys
+//! are plain `Copy` byte arrays, so `SenderKey` wipes them instead.
+//!
+//! Keys of every security level (see `level`) are held by the same types;
+//! their public halves are the `tholos_pq` ones, whose key bytes are of
+//! whatever length the level has.
+//!
+//! Recipient keys can also be derived from a seed. Sender keys cannot: the
+//! Dilithium implementation draws its randomness from the OS internally and
+//! offers no way to supply it.
+//!
+//! Recipient public keys are CBOR everywhere they are taken, but can also be
+//! handed out as JSON for clients that cannot parse CBOR, or as the raw bytes
+//! of `recipient_pub_to_raw` for columns that store them as they are.
+//!
+//! Recipient private keys carry the version of their representation, so keys
+//! exported by an older release can be recognised and upgraded with `migrate`
+//! once they are loaded.
+//!
+//! A keypair loaded from halves stored apart is checked to belong together by
+//! using it, see `recipient_pair_matches` and `sender_pair_matches`.
+//!
+//! Public keys of both kinds have a fingerprint for comparing them out of
+//! band. It is taken over the key as re-encoded here rather than the bytes
+//! handed in, so any CBOR encoding of the same key gives the same fingerprint.
+//!
+//! Kids and sids key the stores and are written into the header of every wire
+//! they take part in, so `check_id` keeps them non-empty, short and free of
+//! NUL bytes. New kids are also kept from ending like a later version of
+//! another kid, see `check_new_kid`.
+
+use crate::cbor;
+use crate::hybrid;
+use crate::level::{self, KemSecret, Level, SigKeys};
+use base64ct::{Base64UrlUnpadded, Encoding as _};
+use rand::rngs::OsRng;
+use rand_chacha::rand_core::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use subtle::ConstantTimeEq;
+use x25519_dalek::StaticSecret;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// A sender keypair whose Dilithium secret key is wiped when it is dropped.
+///
+/// Because the secret key is `Copy`, moves may leave stray copies on the stack
+/// that nothing can reach to clear; the copy kept for the lifetime of the key,
+/// in the store or a resource, is the one this guarantees to wipe. A clone is
+/// a separate copy, wiped independently of the original.
+#[derive(Clone)]
+pub struct SenderKey {
+    /// Sender identifier.
+    pub sid: String,
+    pub dilithium: SigKeys,
+    /// Unix time from which `sweep_expired` removes the key from the store.
+    pub expires_at: Option<u64>,
+}
+
+impl SenderKey {
+    /// A fresh keypair for `sid` at `level`.
+    pub fn generate(sid: &str, level: Level) -> Self {
+        SenderKey {
+            sid: sid.to_string(),
+            dilithium: SigKeys::generate(level),
+            expires_at: None,
+        }
+    }
+
+    /// The public half, as `tholos_pq::sender_pub` returns it.
+    pub fn public(&self) -> tholos_pq::SenderPub {
+        tholos_pq::SenderPub {
+            sid: self.sid.clone(),
+            pk_dilithium: self.dilithium.public_bytes(),
+        }
+    }
+}
+
+impl Drop for SenderKey {
+    fn drop(&mut self) {
+        self.dilithium.wipe();
+    }
+}
+
+/// Drop a fresh `SenderKey` in place, then read its secret key bytes back.
+///
+/// The key lives in a `MaybeUninit` slot so its memory stays allocated after
+/// the drop and can be inspected without reading freed memory.
+#[cfg(feature = "test-hooks")]
+pub fn sender_key_wiped_on_drop() -> bool {
+    let mut slot =
+        std::mem::MaybeUninit::new(SenderKey::generate("wipe-check", Level::SENDER_DEFAULT));
+    let key = slot.as_mut_ptr();
+    // SAFETY: `key` points at an initialized `SenderKey` that is dropped exactly
+    // once; afterwards only the secret key bytes are read, which lie inside the
+    // slot and which the drop has just overwritten, and the slot outlives every
+    // read
+    unsafe {
+        let (sk, len) = (*key).dilithium.secret_key_region();
+        std::ptr::drop_in_place(key);
+        (0..len).all(|i| std::ptr::read_volatile(sk.add(i)) == 0)
+    }
+}
+
+/// A recipient private key, the ML-KEM decapsulation key of its level and,
+/// for a hybrid key, an X25519 key (see `hybrid`), which x25519-dalek zeroizes
+/// when it is dropped.
+#[derive(Clone)]
+pub struct RecipientPriv {
+    /// Recipient identifier.
+    pub kid: String,
+    pub sk_kyber: KemSecret,
+    pub x25519: Option<StaticSecret>,
+    pub uses: Uses,
+    /// Unix time from which `sweep_expired` removes the key from the store.
+    pub expires_at: Option<u64>,
+    /// Version of the representation, `KEY_FORMAT` unless loaded from an
+    /// older export and not migrated since.
+    pub format: u8,
+}
+
+/// Version of the representation of recipient private keys in memory and in
+/// their CBOR form. Version 0 is every key exported before the version was
+/// recorded; it is represented exactly like version 1, so migrating it only
+/// records the version.
+pub const KEY_FORMAT: u8 = 1;
+
+/// Upgrade `priv_key` to `KEY_FORMAT` in place. Returns whether anything
+/// changed.
+pub fn migrate(priv_key: &mut RecipientPriv) -> bool {
+    if priv_key.format == KEY_FORMAT {
+        return false;
+    }
+    // Every older version is read into the current representation already;
+    // a version whose layout differs would be converted here
+    priv_key.format = KEY_FORMAT;
+    true
+}
+
+/// How many messages a key has decrypted, for auditing. It is atomic so that
+/// decryptions only need the shared lock on the store to record a use. A copy
+/// of a key starts again from zero.
+#[derive(Default)]
+pub struct Uses(AtomicU64);
+
+impl Uses {
+    pub fn record(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Clone for Uses {
+    fn clone(&self) -> Self {
+        Uses::default()
+    }
+}
+
+fn recipient_keypair_with(
+    kid: &str,
+    level: Level,
+    hybrid: bool,
+    rng: &mut (impl rand::RngCore + rand::CryptoRng),
+) -> (tholos_pq::RecipientPub, RecipientPriv) {
+    let (sk, pk) = KemSecret::generate(level, rng);
+    let x25519 = hybrid.then(|| StaticSecret::random_from_rng(&mut *rng));
+    let priv_key = RecipientPriv {
+        kid: kid.to_string(),
+        sk_kyber: sk,
+        x25519,
+        uses: Uses::default(),
+        expires_at: None,
+        format: KEY_FORMAT,
+    };
+    let pk_kyber = match &priv_key.x25519 {
+        Some(x25519) => hybrid::public_key(&pk, x25519),
+        None => pk,
+    };
+    (
+        tholos_pq::RecipientPub {
+            kid: kid.to_string(),
+            pk_kyber,
+        },
+        priv_key,
+    )
+}
+
+/// Generate a recipient keypair at `level`, as `tholos_pq::gen_recipient_keypair`
+/// does for level 5, with an X25519 key as well if `hybrid`.
+pub fn recipient_keypair(
+    kid: &str,
+    level: Level,
+    hybrid: bool,
+) -> (tholos_pq::RecipientPub, RecipientPriv) {
+    recipient_keypair_with(kid, level, hybrid, &mut OsRng)
+}
+
+/// Batches at least this large are generated in parallel.
+#[cfg(feature = "parallel")]
+const PARALLEL_MIN_KEYPAIRS: usize = 16;
+
+/// Generate a recipient keypair at the default level for every kid, in order.
+#[cfg(not(feature = "parallel"))]
+pub fn recipient_keypairs(kids: &[&str]) -> Vec<(tholos_pq::RecipientPub, RecipientPriv)> {
+    kids.iter()
+        .map(|kid| recipient_keypair(kid, Level::RECIPIENT_DEFAULT, false))
+        .collect()
+}
+
+/// Generate a recipient keypair at the default level for every kid, in order,
+/// spread over the rayon pool once the batch is large enough to pay for it.
+#[cfg(feature = "parallel")]
+pub fn recipient_keypairs(kids: &[&str]) -> Vec<(tholos_pq::RecipientPub, RecipientPriv)> {
+    use rayon::prelude::*;
+
+    if kids.len() < PARALLEL_MIN_KEYPAIRS {
+        return kids
+            .iter()
+            .map(|kid| recipient_keypair(kid, Level::RECIPIENT_DEFAULT, false))
+            .collect();
+    }
+    kids.par_iter()
+        .map(|kid| recipient_keypair(kid, Level::RECIPIENT_DEFAULT, false))
+        .collect()
+}
+
+/// Derive a recipient keypair from `seed`, exactly as `recipient_keypair`
+/// generates one but with a ChaCha20 stream keyed by the seed in place of the OS RNG.
+/// The ML-KEM key is drawn first, so a hybrid key and a plain one derived from
+/// the same seed share it.
+pub fn recipient_keypair_from_seed(
+    kid: &str,
+    seed: [u8; 32],
+    level: Level,
+    hybrid: bool,
+) -> (tholos_pq::RecipientPub, RecipientPriv) {
+    recipient_keypair_with(kid, level, hybrid, &mut ChaCha20Rng::from_seed(seed))
+}
+
+/// CBOR form of a full sender keypair, including the Dilithium secret key.
+#[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct SenderKeypairCbor {
+    /// Sender identifier.
+    pub sid: String,
+    /// Dilithium public key bytes; their length gives the level.
+    #[serde(with = "serde_bytes")]
+    pub pk_dilithium: Vec<u8>,
+    /// Dilithium secret key bytes.
+    #[serde(with = "serde_bytes")]
+    pub sk_dilithium: Vec<u8>,
+}
+
+impl SenderKeypairCbor {
+    pub fn from_keypair(keypair: &SenderKey) -> Self {
+        SenderKeypairCbor {
+            sid: keypair.sid.clone(),
+            pk_dilithium: keypair.dilithium.public_bytes(),
+            sk_dilithium: std::mem::take(&mut *keypair.dilithium.secret_bytes()),
+        }
+    }
+
+    pub fn into_keypair(mut self) -> Result<SenderKey, String> {
+        let dilithium = SigKeys::from_bytes(&self.pk_dilithium, &self.sk_dilithium)?;
+        Ok(SenderKey {
+            sid: std::mem::take(&mut self.sid),
+            dilithium,
+            expires_at: None,
+        })
+    }
+}
+
+/// CBOR form of a recipient private key, i.e. the ML-KEM decapsulation key.
+#[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct RecipientPrivCbor {
+    /// Recipient identifier.
+    pub kid: String,
+    /// ML-KEM decapsulation key bytes; their length gives the level.
+    #[serde(with = "serde_bytes")]
+    pub sk_kyber: Vec<u8>,
+    /// X25519 secret key bytes of a hybrid key, left out otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "serde_bytes")]
+    pub sk_x25519: Option<Vec<u8>>,
+    /// Version of the representation, see `KEY_FORMAT`; 0 when left out.
+    #[serde(default)]
+    pub format: u8,
+}
+
+impl RecipientPrivCbor {
+    pub fn from_priv(priv_key: &RecipientPriv) -> Self {
+        RecipientPrivCbor {
+            kid: priv_key.kid.clone(),
+            sk_kyber: std::mem::take(&mut *priv_key.sk_kyber.to_bytes()),
+            sk_x25519: priv_key
+                .x25519
+                .as_ref()
+                .map(|x25519| x25519.to_bytes().to_vec()),
+            format: priv_key.format,
+        }
+    }
+
+    pub fn into_priv(mut self) -> Result<RecipientPriv, String> {
+        // A newer release may lay the key out differently, so it is not guessed at
+        if self.format > KEY_FORMAT {
+            return Err(format!("unsupported key format {}", self.format));
+        }
+        let x25519 = self
+            .sk_x25519
+            .as_deref()
+            .map(|bytes| {
+                <[u8; 32]>::try_from(bytes)
+                    .map(StaticSecret::from)
+                    .map_err(|_| format!("x25519 secret key: unexpected length {}", bytes.len()))
+            })
+            .transpose()?;
+        Ok(RecipientPriv {
+            sk_kyber: KemSecret::from_bytes(&self.sk_kyber)?,
+            kid: std::mem::take(&mut self.kid),
+            x25519,
+            uses: Uses::default(),
+            expires_at: None,
+            format: self.format,
+        })
+    }
+}
+
+/// The public half of a recipient private key, as `recipient_keypair` returns
+/// it alongside the private key.
+pub fn recipient_pub(priv_key: &RecipientPriv) -> tholos_pq::RecipientPub {
+    let kem_pk = priv_key.sk_kyber.public_bytes();
+    tholos_pq::RecipientPub {
+        kid: priv_key.kid.clone(),
+        pk_kyber: match &priv_key.x25519 {
+            Some(x25519) => hybrid::public_key(&kem_pk, x25519),
+            None => kem_pk,
+        },
+    }
+}
+
+/// Whether `priv_key` is the decapsulation key for `pub_key`: it embeds that
+/// encapsulation key, and decapsulating a fresh encapsulation to `pub_key`
+/// recovers the same shared secret.
+pub fn recipient_pair_matches(pub_key: &tholos_pq::RecipientPub, priv_key: &RecipientPriv) -> bool {
+    if recipient_pub(priv_key).pk_kyber != pub_key.pk_kyber {
+        return false;
+    }
+    let kem_pk = hybrid::kem_pk(&pub_key.pk_kyber);
+    let Ok((kem_ct, shared)) = level::encapsulate(&mut OsRng, kem_pk) else {
+        return false;
+    };
+    priv_key
+        .sk_kyber
+        .decapsulate(&kem_ct)
+        .is_ok_and(|decapsulated| bool::from(decapsulated.ct_eq(&*shared)))
+}
+
+/// Whether `keypair` is the keypair of `pub_key`: it holds that public key,
+/// and a signature made with its secret key verifies under it.
+pub fn sender_pair_matches(pub_key: &tholos_pq::SenderPub, keypair: &SenderKey) -> bool {
+    const PROBE: &[u8] = b"ex_tholos_pq keypair check";
+
+    if keypair.dilithium.public_bytes() != pub_key.pk_dilithium {
+        return false;
+    }
+    let signature = keypair.dilithium.sign(PROBE);
+    matches!(
+        level::verify(&pub_key.pk_dilithium, PROBE, &signature),
+        Ok(true)
+    )
+}
+
+/// Serialization of a public key handed to Elixir.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyFormat {
+    #[default]
+    Cbor,
+    Json,
+}
+
+/// JSON form of a `tholos_pq::RecipientPub`. JSON has no byte strings, so the
+/// key is unpadded base64url text.
+#[derive(Serialize, Deserialize)]
+struct RecipientPubJson {
+    kid: String,
+    pk_kyber: String,
+}
+
+/// Serialize a recipient public key in `format`.
+pub fn encode_recipient_pub(
+    pub_key: &tholos_pq::RecipientPub,
+    format: KeyFormat,
+) -> Result<Vec<u8>, String> {
+    match format {
+        KeyFormat::Cbor => cbor::canonical(pub_key).map_err(|e| e.to_string()),
+        KeyFormat::Json => serde_json::to_vec(&RecipientPubJson {
+            kid: pub_key.kid.clone(),
+            pk_kyber: Base64UrlUnpadded::encode_string(&pub_key.pk_kyber),
+        })
+        .map_err(|e| e.to_string()),
+    }
+}
+
+/// Deserialize the JSON form of a recipient public key.
+pub fn recipient_pub_from_json(json: &[u8]) -> Result<tholos_pq::RecipientPub, String> {
+    let parsed: RecipientPubJson = serde_json::from_slice(json).map_err(|e| e.to_string())?;
+    let pk_kyber = Base64UrlUnpadded::decode_vec(&parsed.pk_kyber)
+        .map_err(|_| "pk_kyber is not base64url".to_string())?;
+    Ok(tholos_pq::RecipientPub {
+        kid: parsed.kid,
+        pk_kyber,
+    })
+}
+
+/// Raw form of a recipient public key: the length of the ML-KEM key in bytes as
+/// a big-endian `u16`, the key bytes, then the kid as UTF-8 up to the end. The
+/// key is as in the CBOR form, so a hybrid one keeps its X25519 part.
+pub fn recipient_pub_to_raw(pub_key: &tholos_pq::RecipientPub) -> Result<Vec<u8>, String> {
+    let len = u16::try_from(pub_key.pk_kyber.len())
+        .map_err(|_| "pk_kyber is longer than 65535 bytes".to_string())?;
+    let mut raw = Vec::with_capacity(2 + pub_key.pk_kyber.len() + pub_key.kid.len());
+    raw.extend_from_slice(&len.to_be_bytes());
+    raw.extend_from_slice(&pub_key.pk_kyber);
+    raw.extend_from_slice(pub_key.kid.as_bytes());
+    Ok(raw)
+}
+
+/// Deserialize the raw form of a recipient public key, see `recipient_pub_to_raw`.
+pub fn recipient_pub_from_raw(raw: &[u8]) -> Result<tholos_pq::RecipientPub, String> {
+    let (len, rest) = raw
+        .split_first_chunk::<2>()
+        .ok_or_else(|| "shorter than the length prefix".to_string())?;
+    let len = u16::from_be_bytes(*len) as usize;
+    if rest.len() < len {
+        return Err("shorter than the pk_kyber length it gives".to_string());
+    }
+    let (pk_kyber, kid) = rest.split_at(len);
+    let kid = std::str::from_utf8(kid).map_err(|_| "kid is not UTF-8".to_string())?;
+    Ok(tholos_pq::RecipientPub {
+        kid: kid.to_string(),
+        pk_kyber: pk_kyber.to_vec(),
+    })
+}
+
+/// Domain separation for fingerprints, so a recipient and a sender key never
+/// share one even if their encodings coincided.
+const RECIPIENT_FINGERPRINT_DOMAIN: &[u8] = b"ex_tholos_pq recipient fingerprint v1";
+const SENDER_FINGERPRINT_DOMAIN: &[u8] = b"ex_tholos_pq sender fingerprint v1";
+
+/// Lowercase hex SHA-256 of `domain` followed by the self-describe tag and
+/// the canonical CBOR of `key`. The tag is there because fingerprints were
+/// first taken over `tholos_pq::to_cbor_canonical`, which starts with it.
+fn fingerprint(domain: &[u8], key: &impl Serialize) -> Result<String, tholos_pq::TholosError> {
+    let digest = Sha256::new()
+        .chain_update(domain)
+        .chain_update(cbor::SELF_DESCRIBE_TAG)
+        .chain_update(cbor::canonical(key)?)
+        .finalize();
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// The fingerprint of a recipient public key, covering its kid and ML-KEM key.
+pub fn recipient_fingerprint(
+    pub_key: &tholos_pq::RecipientPub,
+) -> Result<String, tholos_pq::TholosError> {
+    fingerprint(RECIPIENT_FINGERPRINT_DOMAIN, pub_key)
+}
+
+/// The fingerprint of a sender public key, covering its sid and Dilithium key.
+pub fn sender_fingerprint(
+    pub_key: &tholos_pq::SenderPub,
+) -> Result<String, tholos_pq::TholosError> {
+    fingerprint(SENDER_FINGERPRINT_DOMAIN, pub_key)
+}
+
+/// Longest kid or sid accepted, in bytes.
+pub const MAX_ID_LEN: usize = 256;
+
+/// Check that `id` can be used as a kid or sid, returning why not otherwise.
+pub fn check_id(id: &str) -> Result<(), &'static str> {
+    if id.is_empty() {
+        Err("empty")
+    } else if id.len() > MAX_ID_LEN {
+        Err("longer than 256 bytes")
+    } else if id.contains('\0') {
+        Err("contains a NUL byte")
+    } else {
+        Ok(())
+    }
+}
+
+/// Check that `id` can name a new recipient keypair: as `check_id`, and not
+/// ending in `#v<digits>`. That suffix names the later versions of a kid (see
+/// `versioned_kid`), so a keypair stored as `alice#v2` would be taken for a
+/// version of `alice` and opened when decrypting as `alice`.
+pub fn check_new_kid(id: &str) -> Result<(), &'static str> {
+    check_id(id)?;
+    let versioned = id
+        .rsplit_once("#v")
+        .is_some_and(|(_, v)| !v.is_empty() && v.bytes().all(|b| b.is_ascii_digit()));
+    if versioned {
+        Err("ends in a key version suffix")
+    } else {
+        Ok(())
+    }
+}
+
+/// Check that `id` can be stored as a recipient kid along with `kids`: as
+/// `check_new_kid`, except that a version of one of `kids` is allowed, as a
+/// store export of a rotated kid holds.
+pub fn check_imported_kid(id: &str, kids: &HashSet<&str>) -> Result<(), &'static str> {
+    match id.rsplit_once("#v") {
+        Some((kid, _)) if kids.contains(kid) && kid_version(id, kid).is_some() => check_id(id),
+        _ => check_new_kid(id),
+    }
+}
+
+/// The kid that version `version` of `kid` is stored under: the first version
+/// is `kid` itself and every later one `kid#v<version>`.
+pub fn versioned_kid(kid: &str, version: u32) -> String {
+    if version <= 1 {
+        kid.to_string()
+    } else {
+        format!("{}#v{}", kid, version)
+    }
+}
+
+/// Which version of `kid` the stored kid `stored` is, if it is one at all.
+pub fn kid_version(stored: &str, kid: &str) -> Option<u32> {
+    if stored == kid {
+        return Some(1);
+    }
+    let version: u32 = stored.strip_prefix(kid)?.strip_prefix("#v")?.parse().ok()?;
+    // Only the spelling `versioned_kid` produces, so `kid#v02` is not version 2
+    (version >= 2 && versioned_kid(kid, version) == stored).then_some(version)
+}
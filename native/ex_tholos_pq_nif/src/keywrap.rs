@@ -0,0 +1,83 @@
+//! Wrapping a caller's 32-byte content key for recipients, without a payload.
+//!
+//! This is the KEM layer of `crypto::encrypt` on its own: every recipient gets
+//! the same envelope as in a wire, under a KEK derived from its encapsulation
+//! and a header naming the sender and the recipients. The header stands in for
+//! the wire header and carries its own suite, so an envelope from a wrapped key
+//! never opens as one from a wire or the other way round.
+//!
+//! Nothing is signed. An envelope only opens under the header it was made
+//! with, but anyone holding the recipients' public keys can wrap a key of
+//! their choosing under any sender sid, so the caller's own payload layer has
+//! to authenticate what the content key protects.
+
+use crate::cbor;
+use crate::crypto;
+use crate::keys::{RecipientPriv, SenderKey};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use tholos_pq::{RecipientEnvelope, RecipientPub, TholosError};
+use zeroize::Zeroizing;
+
+const SUITE: &str = "ex_tholos_pq keywrap v1";
+
+/// What every envelope's KEK is bound to.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    suite: String,
+    sender: String,
+    recipients: Vec<String>,
+}
+
+/// CBOR form of a wrapped content key.
+#[derive(Serialize, Deserialize)]
+struct WrappedKey {
+    header: Header,
+    recipients: Vec<RecipientEnvelope>,
+}
+
+/// Wrap `content_key` for every one of `recipients`, in recipient order.
+pub fn wrap(
+    content_key: &[u8; 32],
+    sender: &SenderKey,
+    recipients: &[RecipientPub],
+) -> Result<Vec<u8>, TholosError> {
+    let header = Header {
+        suite: SUITE.to_string(),
+        sender: sender.sid.clone(),
+        recipients: recipients.iter().map(|r| r.kid.clone()).collect(),
+    };
+    let header_cbor = cbor::to_vec_tagged(&header)?;
+
+    let recipients = recipients
+        .iter()
+        .map(|r| {
+            crypto::wrap_cek(
+                &mut OsRng,
+                content_key,
+                r,
+                &header_cbor,
+                &header_cbor,
+                false,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(cbor::to_vec_tagged(&WrappedKey { header, recipients })?)
+}
+
+/// Recover the content key wrapped for `my_kid`, the inverse of `wrap`.
+pub fn unwrap(
+    wrapped: &[u8],
+    my_kid: &str,
+    my_priv: &RecipientPriv,
+) -> Result<Zeroizing<[u8; 32]>, TholosError> {
+    let wrapped: WrappedKey = cbor::from_slice(wrapped)?;
+    if wrapped.header.suite != SUITE {
+        return Err(TholosError::Malformed("keywrap suite"));
+    }
+    let header_cbor = cbor::to_vec_tagged(&wrapped.header)?;
+
+    let env = crypto::find_envelope(&wrapped.recipients, my_kid, &my_priv.sk_kyber)?;
+    crypto::unwrap_cek(env, my_kid, my_priv, &header_cbor, &header_cbor, false).map(Zeroizing::new)
+}
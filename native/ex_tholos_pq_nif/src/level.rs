@@ -0,0 +1,456 @@
+//! Security levels: which ML-KEM and Dilithium parameter set a key belongs to.
+//!
+//! `tholos_pq` only knows ML-KEM-1024 recipients and Dilithium-3 senders. The
+//! keys here may use any of the three NIST categories instead:
+//!
+//! | level    | recipient (KEM) | sender (signature) |
+//! |----------|-----------------|--------------------|
+//! | `level1` | ML-KEM-512      | Dilithium-2        |
+//! | `level3` | ML-KEM-768      | Dilithium-3        |
+//! | `level5` | ML-KEM-1024     | Dilithium-5        |
+//!
+//! Recipient keys default to level 5 and sender keys to level 3, the parameter
+//! sets `tholos_pq` uses, so wires between default keys stay tholos-pq wires.
+//!
+//! No level is written anywhere: every parameter set has its own public key,
+//! ciphertext and signature size, so the level of a key or wire is read off
+//! the length of its bytes. That is also how a KEM ciphertext made for another
+//! level than the key trying to decapsulate it is told apart from a corrupt
+//! one.
+
+use ml_kem::kem::{Decapsulate, Encapsulate};
+use ml_kem::{Ciphertext, EncodedSizeUser, KemCore, MlKem1024, MlKem512, MlKem768};
+use pqcrypto_dilithium::{dilithium2, dilithium3, dilithium5};
+use rand::{CryptoRng, RngCore};
+use tholos_pq::TholosError;
+use zeroize::{Zeroize, Zeroizing};
+
+/// `TholosError::Malformed` detail for a key and a wire of different levels.
+pub const MISMATCH: &str = "security level mismatch";
+
+/// A NIST security category.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    L1,
+    L3,
+    L5,
+}
+
+/// Byte lengths that identify an ML-KEM parameter set, by level.
+const KEM_PK_LEN: [(Level, usize); 3] = [(Level::L1, 800), (Level::L3, 1184), (Level::L5, 1568)];
+const KEM_SK_LEN: [(Level, usize); 3] = [(Level::L1, 1632), (Level::L3, 2400), (Level::L5, 3168)];
+const KEM_CT_LEN: [(Level, usize); 3] = [(Level::L1, 768), (Level::L3, 1088), (Level::L5, 1568)];
+
+fn level_of(table: &[(Level, usize); 3], len: usize) -> Option<Level> {
+    table
+        .iter()
+        .find(|(_, l)| *l == len)
+        .map(|(level, _)| *level)
+}
+
+fn length_of(table: &[(Level, usize); 3], level: Level) -> usize {
+    table
+        .iter()
+        .find(|(l, _)| *l == level)
+        .map_or(0, |(_, len)| *len)
+}
+
+impl Level {
+    /// The level a recipient key defaults to.
+    pub const RECIPIENT_DEFAULT: Level = Level::L5;
+    /// The level a sender key defaults to.
+    pub const SENDER_DEFAULT: Level = Level::L3;
+
+    /// The level of an ML-KEM encapsulation key of `len` bytes.
+    pub fn of_kem_pk(len: usize) -> Option<Level> {
+        level_of(&KEM_PK_LEN, len)
+    }
+
+    /// The level of an ML-KEM ciphertext of `len` bytes.
+    pub fn of_kem_ct(len: usize) -> Option<Level> {
+        level_of(&KEM_CT_LEN, len)
+    }
+
+    /// The level of a Dilithium public key of `len` bytes.
+    pub fn of_sig_pk(len: usize) -> Option<Level> {
+        [Level::L1, Level::L3, Level::L5]
+            .into_iter()
+            .find(|level| level.sig_pk_len() == len)
+    }
+
+    /// Name of the ML-KEM parameter set at this level.
+    pub fn kem_name(self) -> &'static str {
+        match self {
+            Level::L1 => "ML-KEM-512",
+            Level::L3 => "ML-KEM-768",
+            Level::L5 => "ML-KEM-1024",
+        }
+    }
+
+    /// Name of the Dilithium parameter set at this level.
+    pub fn sig_name(self) -> &'static str {
+        match self {
+            Level::L1 => "Dilithium2",
+            Level::L3 => "Dilithium3",
+            Level::L5 => "Dilithium5",
+        }
+    }
+
+    /// Length of an ML-KEM ciphertext at this level.
+    pub fn kem_ct_len(self) -> usize {
+        length_of(&KEM_CT_LEN, self)
+    }
+
+    /// Length of a Dilithium public key at this level.
+    pub fn sig_pk_len(self) -> usize {
+        match self {
+            Level::L1 => dilithium2::public_key_bytes(),
+            Level::L3 => dilithium3::public_key_bytes(),
+            Level::L5 => dilithium5::public_key_bytes(),
+        }
+    }
+
+    /// Length of a Dilithium detached signature at this level.
+    pub fn sig_len(self) -> usize {
+        match self {
+            Level::L1 => dilithium2::signature_bytes(),
+            Level::L3 => dilithium3::signature_bytes(),
+            Level::L5 => dilithium5::signature_bytes(),
+        }
+    }
+}
+
+/* ---------------- ML-KEM ---------------- */
+
+/// An ML-KEM decapsulation key of any level. Each of them is zeroized when it
+/// is dropped (ml-kem's `zeroize` feature).
+// Held inline like the `tholos_pq` key it replaces; the variants are within a
+// factor of two of each other
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+pub enum KemSecret {
+    L1(<MlKem512 as KemCore>::DecapsulationKey),
+    L3(<MlKem768 as KemCore>::DecapsulationKey),
+    L5(<MlKem1024 as KemCore>::DecapsulationKey),
+}
+
+/// Decode a fixed-size ML-KEM encoding from `bytes`.
+fn decode<T: EncodedSizeUser>(bytes: &[u8]) -> Option<T> {
+    Some(T::from_bytes(bytes.try_into().ok()?))
+}
+
+fn decapsulate_with<K: KemCore>(
+    sk: &K::DecapsulationKey,
+    kem_ct: &[u8],
+) -> Result<Zeroizing<[u8; 32]>, TholosError> {
+    let kem_ct = Ciphertext::<K>::try_from(kem_ct).map_err(|_| TholosError::Malformed("kem_ct"))?;
+    let shared = sk
+        .decapsulate(&kem_ct)
+        .map_err(|_| TholosError::Malformed("decapsulation"))?;
+    shared
+        .as_slice()
+        .try_into()
+        .map(Zeroizing::new)
+        .map_err(|_| TholosError::Malformed("decapsulation"))
+}
+
+fn encapsulate_with<K: KemCore>(
+    rng: &mut (impl RngCore + CryptoRng),
+    pk_bytes: &[u8],
+) -> Result<(Vec<u8>, Zeroizing<[u8; 32]>), TholosError> {
+    let pk: K::EncapsulationKey = decode(pk_bytes).ok_or(TholosError::Malformed("ml-kem pk"))?;
+    let (kem_ct, shared) = pk
+        .encapsulate(rng)
+        .map_err(|_| TholosError::Malformed("encapsulation"))?;
+    let shared = shared
+        .as_slice()
+        .try_into()
+        .map_err(|_| TholosError::Malformed("encapsulation"))?;
+    Ok((kem_ct.as_slice().to_vec(), Zeroizing::new(shared)))
+}
+
+impl KemSecret {
+    /// A fresh keypair at `level`, returned with its encapsulation key bytes.
+    pub fn generate(level: Level, rng: &mut (impl RngCore + CryptoRng)) -> (Self, Vec<u8>) {
+        match level {
+            Level::L1 => {
+                let (sk, pk) = MlKem512::generate(rng);
+                (KemSecret::L1(sk), pk.as_bytes().to_vec())
+            }
+            Level::L3 => {
+                let (sk, pk) = MlKem768::generate(rng);
+                (KemSecret::L3(sk), pk.as_bytes().to_vec())
+            }
+            Level::L5 => {
+                let (sk, pk) = MlKem1024::generate(rng);
+                (KemSecret::L5(sk), pk.as_bytes().to_vec())
+            }
+        }
+    }
+
+    /// Decode a decapsulation key, taking its level from its length.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let sk = match level_of(&KEM_SK_LEN, bytes.len()) {
+            Some(Level::L1) => decode(bytes).map(KemSecret::L1),
+            Some(Level::L3) => decode(bytes).map(KemSecret::L3),
+            Some(Level::L5) => decode(bytes).map(KemSecret::L5),
+            None => None,
+        };
+        sk.ok_or_else(|| {
+            format!(
+                "ml-kem decapsulation key: unexpected length {}",
+                bytes.len()
+            )
+        })
+    }
+
+    pub fn level(&self) -> Level {
+        match self {
+            KemSecret::L1(_) => Level::L1,
+            KemSecret::L3(_) => Level::L3,
+            KemSecret::L5(_) => Level::L5,
+        }
+    }
+
+    /// The decapsulation key bytes.
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        Zeroizing::new(match self {
+            KemSecret::L1(sk) => sk.as_bytes().to_vec(),
+            KemSecret::L3(sk) => sk.as_bytes().to_vec(),
+            KemSecret::L5(sk) => sk.as_bytes().to_vec(),
+        })
+    }
+
+    /// The matching encapsulation key bytes.
+    pub fn public_bytes(&self) -> Vec<u8> {
+        match self {
+            KemSecret::L1(sk) => sk.encapsulation_key().as_bytes().to_vec(),
+            KemSecret::L3(sk) => sk.encapsulation_key().as_bytes().to_vec(),
+            KemSecret::L5(sk) => sk.encapsulation_key().as_bytes().to_vec(),
+        }
+    }
+
+    /// Recover the shared secret from `kem_ct`. A ciphertext of another level's
+    /// size fails with `MISMATCH`, any other size as a malformed `kem_ct`.
+    pub fn decapsulate(&self, kem_ct: &[u8]) -> Result<Zeroizing<[u8; 32]>, TholosError> {
+        match Level::of_kem_ct(kem_ct.len()) {
+            Some(level) if level != self.level() => {
+                return Err(TholosError::Malformed(MISMATCH));
+            }
+            _ => {}
+        }
+        match self {
+            KemSecret::L1(sk) => decapsulate_with::<MlKem512>(sk, kem_ct),
+            KemSecret::L3(sk) => decapsulate_with::<MlKem768>(sk, kem_ct),
+            KemSecret::L5(sk) => decapsulate_with::<MlKem1024>(sk, kem_ct),
+        }
+    }
+}
+
+/// The ML-KEM modulus q.
+const KEM_Q: u16 = 3329;
+
+/// The level of `pk_bytes` if it is a valid ML-KEM encapsulation key: of the
+/// length of some level, and passing the modulus check of FIPS 203, i.e. every
+/// 12-bit coefficient of the encoded vector before the 32-byte seed is below q.
+/// ml-kem decodes any bytes of the right length without that check.
+pub fn check_kem_pk(pk_bytes: &[u8]) -> Option<Level> {
+    let level = Level::of_kem_pk(pk_bytes.len())?;
+    let (t_hat, _rho) = pk_bytes.split_at(pk_bytes.len() - 32);
+    t_hat
+        .chunks_exact(3)
+        .all(|c| {
+            let first = u16::from(c[0]) | (u16::from(c[1] & 0x0f) << 8);
+            let second = (u16::from(c[1]) >> 4) | (u16::from(c[2]) << 4);
+            first < KEM_Q && second < KEM_Q
+        })
+        .then_some(level)
+}
+
+/// Encapsulate a fresh shared secret to the encapsulation key `pk_bytes`, at
+/// the level its length names. Returns the ciphertext and the shared secret,
+/// or `Malformed` for a key failing `check_kem_pk`.
+pub fn encapsulate(
+    rng: &mut (impl RngCore + CryptoRng),
+    pk_bytes: &[u8],
+) -> Result<(Vec<u8>, Zeroizing<[u8; 32]>), TholosError> {
+    match check_kem_pk(pk_bytes) {
+        Some(Level::L1) => encapsulate_with::<MlKem512>(rng, pk_bytes),
+        Some(Level::L3) => encapsulate_with::<MlKem768>(rng, pk_bytes),
+        Some(Level::L5) => encapsulate_with::<MlKem1024>(rng, pk_bytes),
+        None => Err(TholosError::Malformed("ml-kem pk")),
+    }
+}
+
+/* ---------------- Dilithium ---------------- */
+
+/// A Dilithium keypair of any level.
+///
+/// The pqcrypto key types are plain `Copy` byte arrays that do not wipe
+/// themselves; `wipe` is there for the holder to call when it is dropped.
+// Held inline so that `wipe` overwrites the very bytes a holder owns, which
+// boxing the larger variants would move out of reach of `secret_key_region`
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+pub enum SigKeys {
+    L1(dilithium2::PublicKey, dilithium2::SecretKey),
+    L3(dilithium3::PublicKey, dilithium3::SecretKey),
+    L5(dilithium5::PublicKey, dilithium5::SecretKey),
+}
+
+/// Run `$body` with `$pk` and `$sk` bound to the keys of `$keys`, whatever
+/// their level.
+macro_rules! with_sig_keys {
+    ($keys:expr, $pk:pat, $sk:pat => $body:expr) => {
+        match $keys {
+            SigKeys::L1($pk, $sk) => $body,
+            SigKeys::L3($pk, $sk) => $body,
+            SigKeys::L5($pk, $sk) => $body,
+        }
+    };
+}
+
+/// Run `$body` with `$m` bound to the Dilithium module of `$level`.
+macro_rules! with_sig_level {
+    ($level:expr, $m:ident => $body:expr) => {
+        match $level {
+            Level::L1 => {
+                use pqcrypto_dilithium::dilithium2 as $m;
+                $body
+            }
+            Level::L3 => {
+                use pqcrypto_dilithium::dilithium3 as $m;
+                $body
+            }
+            Level::L5 => {
+                use pqcrypto_dilithium::dilithium5 as $m;
+                $body
+            }
+        }
+    };
+}
+
+impl SigKeys {
+    /// A fresh keypair at `level`, drawn from the OS RNG.
+    pub fn generate(level: Level) -> Self {
+        match level {
+            Level::L1 => {
+                let (pk, sk) = dilithium2::keypair();
+                SigKeys::L1(pk, sk)
+            }
+            Level::L3 => {
+                let (pk, sk) = dilithium3::keypair();
+                SigKeys::L3(pk, sk)
+            }
+            Level::L5 => {
+                let (pk, sk) = dilithium5::keypair();
+                SigKeys::L5(pk, sk)
+            }
+        }
+    }
+
+    /// Decode a keypair, taking its level from the public key length.
+    pub fn from_bytes(pk: &[u8], sk: &[u8]) -> Result<Self, String> {
+        fn keys<P: pqcrypto_traits::sign::PublicKey, S: pqcrypto_traits::sign::SecretKey>(
+            pk: &[u8],
+            sk: &[u8],
+        ) -> Result<(P, S), String> {
+            Ok((
+                P::from_bytes(pk).map_err(|e| format!("dilithium public key: {}", e))?,
+                S::from_bytes(sk).map_err(|e| format!("dilithium secret key: {}", e))?,
+            ))
+        }
+
+        match Level::of_sig_pk(pk.len()) {
+            Some(Level::L1) => keys(pk, sk).map(|(pk, sk)| SigKeys::L1(pk, sk)),
+            Some(Level::L3) => keys(pk, sk).map(|(pk, sk)| SigKeys::L3(pk, sk)),
+            Some(Level::L5) => keys(pk, sk).map(|(pk, sk)| SigKeys::L5(pk, sk)),
+            None => Err(format!(
+                "dilithium public key: unexpected length {}",
+                pk.len()
+            )),
+        }
+    }
+
+    pub fn level(&self) -> Level {
+        match self {
+            SigKeys::L1(..) => Level::L1,
+            SigKeys::L3(..) => Level::L3,
+            SigKeys::L5(..) => Level::L5,
+        }
+    }
+
+    /// The public key bytes.
+    pub fn public_bytes(&self) -> Vec<u8> {
+        use pqcrypto_traits::sign::PublicKey as _;
+        with_sig_keys!(self, pk, _ => pk.as_bytes().to_vec())
+    }
+
+    /// The secret key bytes.
+    pub fn secret_bytes(&self) -> Zeroizing<Vec<u8>> {
+        use pqcrypto_traits::sign::SecretKey as _;
+        with_sig_keys!(self, _, sk => Zeroizing::new(sk.as_bytes().to_vec()))
+    }
+
+    /// A detached signature over `message`.
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        use pqcrypto_traits::sign::DetachedSignature as _;
+        match self {
+            SigKeys::L1(_, sk) => dilithium2::detached_sign(message, sk).as_bytes().to_vec(),
+            SigKeys::L3(_, sk) => dilithium3::detached_sign(message, sk).as_bytes().to_vec(),
+            SigKeys::L5(_, sk) => dilithium5::detached_sign(message, sk).as_bytes().to_vec(),
+        }
+    }
+
+    /// Where the secret key bytes are and how many there are.
+    pub fn secret_key_region(&mut self) -> (*mut u8, usize) {
+        fn region<T>(sk: &mut T) -> (*mut u8, usize) {
+            ((sk as *mut T).cast::<u8>(), std::mem::size_of::<T>())
+        }
+        with_sig_keys!(self, _, sk => region(sk))
+    }
+
+    /// Overwrite the secret key with zeros in place.
+    pub fn wipe(&mut self) {
+        let (sk, len) = self.secret_key_region();
+        // SAFETY: every pqcrypto `SecretKey` is a single-field tuple struct around
+        // `[u8; N]`, with alignment 1 and size N, so all of its `size_of` bytes are
+        // that array and any byte pattern, including all zeros, is a valid key
+        let bytes = unsafe { std::slice::from_raw_parts_mut(sk, len) };
+        bytes.zeroize();
+    }
+}
+
+/// Why a signature could not be checked at all.
+pub enum VerifyError {
+    /// The public key is not a Dilithium public key of any level.
+    PublicKey,
+    /// The signature is not a Dilithium signature of any level.
+    Signature,
+    /// The signature is one of another level than the public key.
+    Mismatch,
+}
+
+/// Check that `signature` is a valid signature over `message` under the
+/// public key `pk`, at the level its length names.
+pub fn verify(pk: &[u8], message: &[u8], signature: &[u8]) -> Result<bool, VerifyError> {
+    use pqcrypto_traits::sign::{DetachedSignature as _, PublicKey as _};
+
+    let level = Level::of_sig_pk(pk.len()).ok_or(VerifyError::PublicKey)?;
+    if signature.len() != level.sig_len() {
+        let other = [Level::L1, Level::L3, Level::L5]
+            .into_iter()
+            .any(|l| l.sig_len() == signature.len());
+        return Err(if other {
+            VerifyError::Mismatch
+        } else {
+            VerifyError::Signature
+        });
+    }
+    with_sig_level!(level, m => {
+        let pk = m::PublicKey::from_bytes(pk).map_err(|_| VerifyError::PublicKey)?;
+        let sig = m::DetachedSignature::from_bytes(signature)
+            .map_err(|_| VerifyError::Signature)?;
+        Ok(m::verify_detached_signature(&sig, message, &pk).is_ok())
+    })
+}
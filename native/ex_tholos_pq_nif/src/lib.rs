@@ -1,147 +1,4006 @@
 use rustler::types::binary::{Binary, OwnedBinary};
-use rustler::{Env, Error, NifResult};
+use rustler::{Encoder, Env, Error, LocalPid, NifResult, ResourceArc, Term};
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use zeroize::Zeroizing;
+
+mod bench;
+mod cbor;
+mod crypto;
+mod encoding;
+mod file;
+mod framing;
+mod hybrid;
+mod keys;
+mod keywrap;
+mod level;
+mod options;
+mod password;
+mod psk;
+mod selftest;
+mod sign;
+mod size;
+mod store;
+mod stream;
+#[cfg(feature = "test-vectors")]
+mod vectors;
+mod wire;
+
+// A seeded encrypt is only for test vectors, so a build with debug assertions
+// off, i.e. a release one, must never carry it
+#[cfg(all(feature = "deterministic-rng", not(debug_assertions)))]
+compile_error!("the deterministic-rng feature must not be enabled in release builds");
 
 mod atoms {
     rustler::atoms! {
         ok,
         error,
+        invalid_private_key,
+        not_found,
+        aad_mismatch,
+        malformed_wire,
+        sender_not_found,
+        recipient_not_found,
+        deserialize_recipient,
+        deserialize_sender,
+        serialization_failed,
+        encrypt_failed,
+        decrypt_failed,
+        auth_tag_mismatch,
+        no_envelope_for_recipient,
+        malformed_signature,
+        valid,
+        invalid,
+        aad,
+        max_recipients,
+        invalid_option,
+        too_many_recipients,
+        stream_closed,
+        preamble_pending,
+        chunk_out_of_range,
+        io,
+        compression,
+        none,
+        zstd,
+        embed_sender_key,
+        sha256,
+        blake3,
+        invalid_signature,
+        max_id_length,
+        key_mismatch,
+        hybrid_mismatch,
+        key_commitment_failed,
+        anonymous,
+        incomplete,
+        not_prehashed,
+        allocation_failed,
+        ttl_seconds,
+        max_age_seconds,
+        now,
+        expired,
+        now_required,
+        message_id,
+        metadata,
+        aead,
+        xchacha20poly1305,
+        aes256gcm,
+        kid_exists,
+        sid_exists,
+        overwrite,
+        frame_too_large,
+        kid_mismatch,
+        sid_mismatch,
+        invalid_identifier,
+        no_matching_key,
+        migrated,
+        already_current,
+        no_valid_recipients,
+        no_recipients,
+        unsupported_wire_version,
+        bad_seed_length,
+        bad_content_key_length,
+        bad_password,
+        bad_psk,
+        bad_psk_length,
+        corrupt_store,
+        recipient_already_present,
+        recipient_not_present,
+        security_level,
+        level1,
+        level3,
+        level5,
+        security_level_mismatch,
+        param_mismatch,
+        kat_failed,
+        encoding,
+        raw,
+        base64url,
+        format,
+        hybrid,
+        key_commitment,
+        cbor,
+        json,
+        invalid_recipient_key,
+        internal_crypto_panic,
+        keygen,
+        encapsulation,
+        signing,
+        round_trip,
+        pad_to,
+        plaintext_too_large_for_padding,
+        no_common_suite,
+        per_recipient_aad,
+        recipient_aad,
+        encrypt,
+        decrypt,
+        tholos_event,
+        key_loaded,
+        escrow,
+        authz,
+        integrity,
+        key,
+        signature_invalid,
+        kid,
+        sid,
+        reason,
     }
 }
 
 // Store keypairs in a global state (this is a simple approach for demo)
 // In production, you'd want better key management
+//
+// encrypt and decrypt only read the stores, so they take shared read locks and
+// run in parallel; only keygen and delete take the write lock
+//
+// A NIF that locks both key stores takes RECIPIENT_KEYS before SENDER_KEYS, so
+// two of them never wait on each other. Every lock is a guard held in a local,
+// so it is given back on every return, whether with an error, through `?` or
+// after catch_panic caught a panic, as it is on success
 lazy_static::lazy_static! {
-    static ref RECIPIENT_KEYS: Mutex<HashMap<String, (tholos_pq::RecipientPub, tholos_pq::RecipientPriv)>> =
-        Mutex::new(HashMap::new());
-    static ref SENDER_KEYS: Mutex<HashMap<String, tholos_pq::SenderKeypair>> =
-        Mutex::new(HashMap::new());
+    static ref RECIPIENT_KEYS: RwLock<HashMap<String, (tholos_pq::RecipientPub, keys::RecipientPriv)>> =
+        RwLock::new(HashMap::new());
+    static ref SENDER_KEYS: RwLock<HashMap<String, keys::SenderKey>> =
+        RwLock::new(HashMap::new());
+    static ref LOG_TARGET: RwLock<Option<LocalPid>> = RwLock::new(None);
+    static ref ESCROW_RECIPIENT: RwLock<Option<tholos_pq::RecipientPub>> = RwLock::new(None);
+}
+
+/// Take a shared lock on a key store, recovering it if it was poisoned
+/// Stores are only changed by single inserts and removes, so a panic while one
+/// was held cannot leave it half updated, and the node keeps its keys usable
+fn read_store<T>(store: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    store.read().unwrap_or_else(|poisoned| {
+        store.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// Take the exclusive lock on a key store, recovering it like `read_store`
+fn write_store<T>(store: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    store.write().unwrap_or_else(|poisoned| {
+        store.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// Lock the state of a stream resource
+/// A stream poisoned by a panic may be halfway through a frame, so it is closed
+/// rather than recovered, and fails with {error, stream_closed} from then on
+fn stream_state<T>(state: &Mutex<Option<T>>) -> MutexGuard<'_, Option<T>> {
+    state.lock().unwrap_or_else(|poisoned| {
+        state.clear_poison();
+        let mut guard = poisoned.into_inner();
+        *guard = None;
+        guard
+    })
+}
+
+/// Run `f` on an open stream under `catch_panic`
+/// A failed call, a panic included, may leave the stream halfway through a
+/// frame, so it closes the stream
+fn update_stream<T, R>(
+    state: &Mutex<Option<T>>,
+    f: impl FnOnce(&mut T) -> NifResult<R>,
+) -> NifResult<R> {
+    let mut state = stream_state(state);
+    let stream = state
+        .as_mut()
+        .ok_or_else(|| Error::Term(Box::new(atoms::stream_closed())))?;
+
+    let result = catch_panic(|| f(stream));
+    if result.is_err() {
+        *state = None;
+    }
+    result
+}
+
+/// A recipient keypair owned by an Elixir term instead of `RECIPIENT_KEYS`.
+///
+/// The keys are dropped by the BEAM garbage collector once no process holds a
+/// reference to the term, which lets a key be scoped to the process using it.
+pub struct RecipientKeyResource {
+    pub_key: tholos_pq::RecipientPub,
+    priv_key: keys::RecipientPriv,
+}
+
+#[rustler::resource_impl]
+impl rustler::Resource for RecipientKeyResource {}
+
+/// A sender keypair owned by an Elixir term instead of `SENDER_KEYS`.
+pub struct SenderKeyResource {
+    keypair: keys::SenderKey,
+}
+
+#[rustler::resource_impl]
+impl rustler::Resource for SenderKeyResource {}
+
+/// A streaming encryption in progress; `None` once finished or failed.
+pub struct EncryptStreamResource(Mutex<Option<stream::Encryptor>>);
+
+#[rustler::resource_impl]
+impl rustler::Resource for EncryptStreamResource {}
+
+/// A streaming decryption in progress; `None` once finished or failed.
+pub struct DecryptStreamResource(Mutex<Option<stream::Decryptor>>);
+
+#[rustler::resource_impl]
+impl rustler::Resource for DecryptStreamResource {}
+
+/// A streaming signature in progress; `None` once finished.
+pub struct SignStreamResource(Mutex<Option<sign::Signer>>);
+
+#[rustler::resource_impl]
+impl rustler::Resource for SignStreamResource {}
+
+/// A streaming verification in progress; `None` once finished.
+pub struct VerifyStreamResource(Mutex<Option<sign::Verifier>>);
+
+#[rustler::resource_impl]
+impl rustler::Resource for VerifyStreamResource {}
+
+/// A payload sealed once, for wires to any recipient set.
+pub struct CachedContentResource(crypto::Cached);
+
+#[rustler::resource_impl]
+impl rustler::Resource for CachedContentResource {}
+
+/// Allowed sender public keys decoded once, keyed by sid.
+pub struct AllowlistResource(HashMap<String, Vec<u8>>);
+
+#[rustler::resource_impl]
+impl rustler::Resource for AllowlistResource {}
+
+/// Output bytes owned by the binary term that points into them.
+struct OutputBuffer(Vec<u8>);
+
+#[rustler::resource_impl]
+impl rustler::Resource for OutputBuffer {}
+
+/// Build the {error, {kind, detail}} returned for a failure
+/// `detail` is human-readable and meant for logs; callers match on `kind`
+fn error_term(kind: rustler::Atom, detail: impl ToString) -> Error {
+    Error::Term(Box::new((kind, detail.to_string())))
+}
+
+/// Send {tholos_event, event, metadata} to the process from set_log_target, if
+/// there is one
+/// A target that has exited just misses the event
+fn emit_event(env: Env, event: rustler::Atom, metadata: &[(rustler::Atom, Term)]) {
+    let Some(pid) = *read_store(&LOG_TARGET) else {
+        return;
+    };
+    if let Ok(metadata) = Term::map_from_pairs(env, metadata) {
+        let _ = env.send(&pid, (atoms::tholos_event(), event, metadata));
+    }
+}
+
+/// Map a failed decryption to its error kind
+/// A CEK not matching the key commitment is returned as {error, key_commitment_failed},
+/// and a bad signature, a missing envelope and a failed AEAD tag as
+/// {error, signature_invalid}, {error, no_envelope_for_recipient} and
+/// {error, auth_tag_mismatch}, without detail
+fn decrypt_error(e: tholos_pq::TholosError) -> Error {
+    let kind = match e {
+        tholos_pq::TholosError::Malformed(detail) if detail == crypto::KEY_COMMITMENT => {
+            return Error::Term(Box::new(atoms::key_commitment_failed()));
+        }
+        tholos_pq::TholosError::Malformed(detail) if detail == crypto::RECIPIENT_AAD => {
+            return Error::Term(Box::new(atoms::aad_mismatch()));
+        }
+        tholos_pq::TholosError::Malformed(detail) if detail == crypto::EXPIRED => {
+            return Error::Term(Box::new(atoms::expired()));
+        }
+        tholos_pq::TholosError::Malformed(detail) if detail == crypto::NOW_REQUIRED => {
+            return Error::Term(Box::new(atoms::now_required()));
+        }
+        tholos_pq::TholosError::BadSignature => {
+            return Error::Term(Box::new(atoms::signature_invalid()));
+        }
+        tholos_pq::TholosError::MissingEnvelope(_) => {
+            return Error::Term(Box::new(atoms::no_envelope_for_recipient()));
+        }
+        tholos_pq::TholosError::Aead => {
+            return Error::Term(Box::new(atoms::auth_tag_mismatch()));
+        }
+        tholos_pq::TholosError::Malformed(detail) if detail == level::MISMATCH => {
+            atoms::security_level_mismatch()
+        }
+        tholos_pq::TholosError::Malformed(detail) if detail == hybrid::MISMATCH => {
+            atoms::hybrid_mismatch()
+        }
+        tholos_pq::TholosError::Malformed(_) | tholos_pq::TholosError::Ser(_) => {
+            atoms::malformed_wire()
+        }
+    };
+    error_term(kind, e)
+}
+
+/// Allocate an Erlang binary of `size` bytes
+/// Returns {error, {allocation_failed, size}} if the VM cannot allocate it
+fn alloc_binary(size: usize) -> NifResult<OwnedBinary> {
+    OwnedBinary::new(size).ok_or_else(|| Error::Term(Box::new((atoms::allocation_failed(), size))))
+}
+
+/// Copy bytes produced on the Rust side into a new Erlang binary
+/// An `OwnedBinary` dropped before it is released, as on an early return, is freed
+/// by its own `Drop`, so no error path leaks one or hands it to the BEAM
+fn to_binary<'a>(env: Env<'a>, bytes: &[u8]) -> NifResult<Binary<'a>> {
+    let mut bin = alloc_binary(bytes.len())?;
+    bin.as_mut_slice().copy_from_slice(bytes);
+    Ok(bin.release(env))
+}
+
+/// Hand a buffer produced on the Rust side to the BEAM without copying it.
+/// The result is a resource binary over `bytes`, freed once the binary is garbage
+/// collected; used for ciphertexts and plaintexts, which can be large
+fn into_binary(env: Env, bytes: Vec<u8>) -> Binary {
+    ResourceArc::new(OutputBuffer(bytes)).make_binary(env, |buffer| &buffer.0)
+}
+
+/// Strip the version byte from a wire, see `wire::body`
+/// Returns {error, {unsupported_wire_version, n}} for versions this build cannot read
+fn wire_body(wire: &[u8]) -> NifResult<&[u8]> {
+    checked_wire_body(wire, options::DEFAULT_MAX_RECIPIENTS)
+}
+
+/// Like `wire_body`, with at most `max_recipients` recipients in the wire
+/// Every body is checked before it is parsed, so a wire declaring lengths its
+/// bytes cannot hold, or too many recipients, fails without allocating for them
+/// Returns {error, {malformed_wire, detail}}
+fn checked_wire_body(wire: &[u8], max_recipients: usize) -> NifResult<&[u8]> {
+    let body = wire::body(wire)
+        .map_err(|v| Error::Term(Box::new((atoms::unsupported_wire_version(), v))))?;
+    wire::check(body, max_recipients).map_err(|e| match e {
+        wire::Malformed::Structure(detail) => error_term(atoms::malformed_wire(), detail),
+        wire::Malformed::TooManyRecipients(count) => error_term(
+            atoms::malformed_wire(),
+            format!(
+                "{} recipients, more than the {} allowed",
+                count, max_recipients
+            ),
+        ),
+    })?;
+    Ok(body)
+}
+
+/// The body of a wire for the read-only views, which list every recipient
+/// however many there are, so only its structure is checked
+/// Returns {error, malformed_wire} or {error, {unsupported_wire_version, n}}
+fn view_body(wire: &[u8]) -> NifResult<&[u8]> {
+    let body = wire::body(wire)
+        .map_err(|v| Error::Term(Box::new((atoms::unsupported_wire_version(), v))))?;
+    wire::check(body, usize::MAX).map_err(|_| Error::Term(Box::new(atoms::malformed_wire())))?;
+    Ok(body)
+}
+
+/// Reject an empty recipient list, whose wire nobody could open, and one
+/// longer than `max` before any key is decoded or encapsulated to, since every
+/// recipient costs a Kyber encapsulation
+/// Returns {error, no_recipients} or {error, {too_many_recipients, count}}
+fn check_recipient_count(count: usize, max: usize) -> NifResult<()> {
+    if count == 0 {
+        return Err(Error::Term(Box::new(atoms::no_recipients())));
+    }
+    if count > max {
+        return Err(Error::Term(Box::new((atoms::too_many_recipients(), count))));
+    }
+    Ok(())
+}
+
+fn level_atom(level: level::Level) -> rustler::Atom {
+    match level {
+        level::Level::L1 => atoms::level1(),
+        level::Level::L3 => atoms::level3(),
+        level::Level::L5 => atoms::level5(),
+    }
+}
+
+/// Reject recipients whose public key is not a valid ML-KEM encapsulation key,
+/// see `level::check_kem_pk`, before any of them is encapsulated to
+/// Returns {error, invalid_recipient_key}
+fn check_recipient_keys<'r>(
+    recipients: impl IntoIterator<Item = &'r tholos_pq::RecipientPub>,
+) -> NifResult<()> {
+    if recipients
+        .into_iter()
+        .all(|r| level::check_kem_pk(hybrid::kem_pk(&r.pk_kyber)).is_some())
+    {
+        Ok(())
+    } else {
+        Err(Error::Term(Box::new(atoms::invalid_recipient_key())))
+    }
+}
+
+/// Reject recipients whose keys are not all of the security level of the first
+/// one; keys of no level at all are left for encapsulation to report
+/// Returns {error, {param_mismatch, expected, got}}
+fn check_recipient_levels<'r>(
+    recipients: impl IntoIterator<Item = &'r tholos_pq::RecipientPub>,
+) -> NifResult<()> {
+    let mut levels = recipients
+        .into_iter()
+        .filter_map(|r| level::Level::of_kem_pk(hybrid::kem_pk(&r.pk_kyber).len()));
+    let Some(expected) = levels.next() else {
+        return Ok(());
+    };
+    match levels.find(|&level| level != expected) {
+        Some(got) => Err(Error::Term(Box::new((
+            atoms::param_mismatch(),
+            level_atom(expected),
+            level_atom(got),
+        )))),
+        None => Ok(()),
+    }
+}
+
+/// Reject a hybrid encryption to recipients that are not all hybrid keys
+/// Returns {error, hybrid_mismatch}
+fn check_recipient_hybrid<'r>(
+    recipients: impl IntoIterator<Item = &'r tholos_pq::RecipientPub>,
+    hybrid: bool,
+) -> NifResult<()> {
+    if hybrid
+        && recipients
+            .into_iter()
+            .any(|r| hybrid::split_pk(&r.pk_kyber).1.is_none())
+    {
+        Err(Error::Term(Box::new(atoms::hybrid_mismatch())))
+    } else {
+        Ok(())
+    }
+}
+
+/// Run a call into the crypto primitives, returning a panic inside them as
+/// {error, internal_crypto_panic} so that one bad input only fails its own call
+/// Rustler would raise nif_panicked in the calling process instead
+fn catch_panic<T>(f: impl FnOnce() -> NifResult<T>) -> NifResult<T> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f))
+        .unwrap_or_else(|_| Err(Error::Term(Box::new(atoms::internal_crypto_panic()))))
+}
+
+/// Deserialize a list of CBOR-encoded recipient public keys
+/// A kid that check_identifier rejects fails as an invalid identifier
+fn decode_recipient_pubs(recipient_pub_keys: &[Binary]) -> NifResult<Vec<tholos_pq::RecipientPub>> {
+    let recipients = recipient_pub_keys
+        .iter()
+        .map(|b| cbor::from_slice(b.as_slice()))
+        .collect::<Result<Vec<tholos_pq::RecipientPub>, _>>()
+        .map_err(|e| error_term(atoms::deserialize_recipient(), e))?;
+    for recipient in &recipients {
+        check_identifier(&recipient.kid)?;
+    }
+    Ok(recipients)
+}
+
+/// Deserialize a list of CBOR-encoded sender public keys into a `(sid, pk)` allow-list
+fn decode_allowed_senders(allowed_sender_pub_keys: &[Binary]) -> NifResult<Vec<(String, Vec<u8>)>> {
+    let mut allowed = Vec::with_capacity(allowed_sender_pub_keys.len());
+    for pub_key_bytes in allowed_sender_pub_keys {
+        let sender_pub: tholos_pq::SenderPub = cbor::from_slice(pub_key_bytes.as_slice())
+            .map_err(|e| error_term(atoms::deserialize_sender(), e))?;
+        allowed.push((sender_pub.sid, sender_pub.pk_dilithium));
+    }
+    Ok(allowed)
+}
+
+/// Reject a kid or sid that is empty, longer than `keys::MAX_ID_LEN` bytes or
+/// holds a NUL byte, before it keys a store or goes into a wire header
+/// Returns {error, {invalid_identifier, reason}}
+fn check_identifier(id: &str) -> NifResult<()> {
+    keys::check_id(id).map_err(|reason| error_term(atoms::invalid_identifier(), reason))
+}
+
+/// Reject a kid for a new recipient keypair as check_identifier does, and also one
+/// ending in `#v<digits>`, which would pass for a later version of another kid
+/// Returns {error, {invalid_identifier, reason}}
+fn check_new_kid(kid: &str) -> NifResult<()> {
+    keys::check_new_kid(kid).map_err(|reason| error_term(atoms::invalid_identifier(), reason))
+}
+
+/// Run `f` with the sender keypair named by `sender`, which is either a sid in
+/// `SENDER_KEYS` or a `SenderKeyResource`
+fn with_sender<T>(sender: Term, f: impl FnOnce(&keys::SenderKey) -> NifResult<T>) -> NifResult<T> {
+    if let Ok(resource) = sender.decode::<ResourceArc<SenderKeyResource>>() {
+        return f(&resource.keypair);
+    }
+
+    let sender_id: String = sender.decode()?;
+    check_identifier(&sender_id)?;
+    let sender_keys = read_store(&SENDER_KEYS);
+    let keypair = sender_keys
+        .get(&sender_id)
+        .ok_or_else(|| error_term(atoms::sender_not_found(), &sender_id))?;
+    f(keypair)
+}
+
+/// Run `f` with the kid and private key named by `recipient`, which is either a kid in
+/// `RECIPIENT_KEYS` or a `RecipientKeyResource`
+fn with_recipient<T>(
+    recipient: Term,
+    f: impl FnOnce(&str, &keys::RecipientPriv) -> NifResult<T>,
+) -> NifResult<T> {
+    if let Ok(resource) = recipient.decode::<ResourceArc<RecipientKeyResource>>() {
+        return f(&resource.pub_key.kid, &resource.priv_key);
+    }
+
+    let kid: String = recipient.decode()?;
+    check_identifier(&kid)?;
+    let recipient_keys = read_store(&RECIPIENT_KEYS);
+    let (_, priv_key) = recipient_keys
+        .get(&kid)
+        .ok_or_else(|| error_term(atoms::recipient_not_found(), &kid))?;
+    f(&kid, priv_key)
+}
+
+/// Like `with_recipient`, but a stored kid stands for every version of it: the
+/// version with an envelope in `wire_cbor` is used, so a message encrypted to
+/// any version still decrypts after rotation
+/// A stored key that `f` succeeds with has the use recorded, see `recipient_key_usage`
+fn with_recipient_version<T>(
+    recipient: Term,
+    wire_cbor: &[u8],
+    f: impl FnOnce(&str, &keys::RecipientPriv) -> NifResult<T>,
+) -> NifResult<T> {
+    let Ok(kid) = recipient.decode::<String>() else {
+        return with_recipient(recipient, f);
+    };
+    check_identifier(&kid)?;
+
+    let recipient_keys = read_store(&RECIPIENT_KEYS);
+    let kid = stored_version(&recipient_keys, kid, wire_cbor);
+    let (_, priv_key) = recipient_keys
+        .get(&kid)
+        .ok_or_else(|| error_term(atoms::recipient_not_found(), &kid))?;
+    let result = f(&kid, priv_key)?;
+    priv_key.uses.record();
+    Ok(result)
+}
+
+/// The stored version of `kid` with an envelope in `wire_cbor`, or `kid` itself
+fn stored_version(
+    recipient_keys: &HashMap<String, (tholos_pq::RecipientPub, keys::RecipientPriv)>,
+    kid: String,
+    wire_cbor: &[u8],
+) -> String {
+    // An unreadable wire falls through to decrypting under `kid`, which reports it
+    wire::recipient_kids(wire_cbor)
+        .unwrap_or_default()
+        .into_iter()
+        .find(|e| keys::kid_version(e, &kid).is_some() && recipient_keys.contains_key(e))
+        .unwrap_or(kid)
+}
+
+/// Every stored version of `kid`, sorted
+fn key_versions(
+    recipient_keys: &HashMap<String, (tholos_pq::RecipientPub, keys::RecipientPriv)>,
+    kid: &str,
+) -> Vec<u32> {
+    let mut versions: Vec<u32> = recipient_keys
+        .keys()
+        .filter_map(|stored| keys::kid_version(stored, kid))
+        .collect();
+    versions.sort_unstable();
+    versions
 }
 
 // Initialize the NIF module
+//
+// Every crypto NIF below runs on a dirty CPU scheduler: Kyber encapsulation is done once
+// per recipient and Dilithium signing is not cheap either, so even modest calls
+// blow well past the 1ms budget of a normal scheduler. The `Env` handed to a dirty
+// NIF is still bound to the calling process, so releasing an `OwnedBinary` into it
+// behaves exactly as it does on a normal scheduler.
 rustler::init!("Elixir.ExTholosPq");
 
+/// Serialize a recipient public key, store the keypair under its kid, and hand
+/// back the public key CBOR
+/// A kid already stored is only replaced with `overwrite`, see `KeygenOptions`
+fn store_recipient_keypair(
+    pub_key: tholos_pq::RecipientPub,
+    mut priv_key: keys::RecipientPriv,
+    opts: &options::KeygenOptions,
+) -> NifResult<Vec<u8>> {
+    // Serialize public key
+    let pub_bytes = keys::encode_recipient_pub(&pub_key, opts.format)
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+    priv_key.expires_at = opts.expires_at();
+
+    // Store the keys
+    let mut recipient_keys = write_store(&RECIPIENT_KEYS);
+    if !opts.overwrite && recipient_keys.contains_key(&pub_key.kid) {
+        return Err(error_term(atoms::kid_exists(), &pub_key.kid));
+    }
+    recipient_keys.insert(pub_key.kid.clone(), (pub_key, priv_key));
+
+    Ok(pub_bytes)
+}
+
 /// Generate a new recipient keypair and store it
-/// Returns {ok, {kid, public_key_cbor}}
-#[rustler::nif]
+/// Returns {ok, {kid, public_key_cbor}}, or {error, {kid_exists, kid}} if a
+/// keypair is already stored under kid
+#[rustler::nif(schedule = "DirtyCpu")]
 fn gen_recipient_keypair<'a>(
     env: Env<'a>,
     kid: String,
 ) -> NifResult<(rustler::Atom, (String, Binary<'a>))> {
-    let (pub_key, priv_key) = tholos_pq::gen_recipient_keypair(&kid);
+    check_new_kid(&kid)?;
+    let (pub_key, priv_key) = keys::recipient_keypair(&kid, level::Level::RECIPIENT_DEFAULT, false);
+    let pub_bytes = store_recipient_keypair(pub_key, priv_key, &options::KeygenOptions::default())?;
+    Ok((atoms::ok(), (kid, to_binary(env, &pub_bytes)?)))
+}
+
+/// Generate and store a recipient keypair for every kid in one call
+/// The store is locked once for the whole batch; a kid already stored, or
+/// given earlier in the list, is left as it is, as gen_recipient_keypair does
+/// Returns {ok, results} in kid order, each {ok, {kid, public_key_cbor}},
+/// {error, {kid_exists, kid}} or {error, {invalid_identifier, reason}}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn gen_recipient_keypairs<'a>(
+    env: Env<'a>,
+    kids: Vec<String>,
+) -> NifResult<(rustler::Atom, Vec<Term<'a>>)> {
+    let mut seen = std::collections::HashSet::with_capacity(kids.len());
+    let fresh: Vec<&str> = kids
+        .iter()
+        .filter(|kid| keys::check_new_kid(kid).is_ok() && seen.insert(kid.as_str()))
+        .map(String::as_str)
+        .collect();
+    let mut generated: HashMap<String, _> = keys::recipient_keypairs(&fresh)
+        .into_iter()
+        .map(|(pub_key, priv_key)| (pub_key.kid.clone(), (pub_key, priv_key)))
+        .collect();
+
+    let mut recipient_keys = write_store(&RECIPIENT_KEYS);
+    let results = kids
+        .into_iter()
+        .map(|kid| {
+            let result = check_new_kid(&kid).and_then(|_| match generated.remove(&kid) {
+                Some(keys) if !recipient_keys.contains_key(&kid) => {
+                    let pub_bytes = keys::encode_recipient_pub(&keys.0, keys::KeyFormat::Cbor)
+                        .map_err(|e| error_term(atoms::serialization_failed(), e));
+                    if pub_bytes.is_ok() {
+                        recipient_keys.insert(kid.clone(), keys);
+                    }
+                    pub_bytes
+                }
+                _ => Err(error_term(atoms::kid_exists(), &kid)),
+            });
+            match result.and_then(|pub_bytes| to_binary(env, &pub_bytes)) {
+                Ok(pub_bytes) => (atoms::ok(), (kid, pub_bytes)).encode(env),
+                Err(e) => result_error(env, e),
+            }
+        })
+        .collect();
+    Ok((atoms::ok(), results))
+}
+
+/// Generate a new recipient keypair with options and store it
+/// `opts` is a keyword list; `security_level` picks the ML-KEM parameter set and
+/// `format` whether the public key is returned as CBOR or JSON, and `overwrite`
+/// lets it replace a keypair already stored under kid
+/// Returns {ok, {kid, public_key}}
+#[rustler::nif(name = "gen_recipient_keypair", schedule = "DirtyCpu")]
+fn gen_recipient_keypair_with_opts<'a>(
+    env: Env<'a>,
+    kid: String,
+    opts: Term<'a>,
+) -> NifResult<(rustler::Atom, (String, Binary<'a>))> {
+    check_new_kid(&kid)?;
+    let opts = options::KeygenOptions::decode(opts)?;
+    let (pub_key, priv_key) = keys::recipient_keypair(&kid, opts.recipient_level(), opts.hybrid);
+    let pub_bytes = store_recipient_keypair(pub_key, priv_key, &opts)?;
+    Ok((atoms::ok(), (kid, to_binary(env, &pub_bytes)?)))
+}
+
+/// Derive a recipient keypair from a 32-byte seed and store it
+/// The same kid and seed always give the same keypair
+/// Returns {ok, {kid, public_key_cbor}}, or {error, bad_seed_length}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn gen_recipient_keypair_from_seed<'a>(
+    env: Env<'a>,
+    kid: String,
+    seed: Binary,
+) -> NifResult<(rustler::Atom, (String, Binary<'a>))> {
+    let opts = options::KeygenOptions::default();
+    recipient_keypair_from_seed(env, kid, seed, &opts)
+}
+
+/// Derive a recipient keypair from a 32-byte seed with options and store it
+/// The same kid, seed and security level always give the same keypair
+/// Returns {ok, {kid, public_key_cbor}}, or {error, bad_seed_length}
+#[rustler::nif(name = "gen_recipient_keypair_from_seed", schedule = "DirtyCpu")]
+fn gen_recipient_keypair_from_seed_with_opts<'a>(
+    env: Env<'a>,
+    kid: String,
+    seed: Binary,
+    opts: Term<'a>,
+) -> NifResult<(rustler::Atom, (String, Binary<'a>))> {
+    let opts = options::KeygenOptions::decode(opts)?;
+    recipient_keypair_from_seed(env, kid, seed, &opts)
+}
+
+fn recipient_keypair_from_seed<'a>(
+    env: Env<'a>,
+    kid: String,
+    seed: Binary,
+    opts: &options::KeygenOptions,
+) -> NifResult<(rustler::Atom, (String, Binary<'a>))> {
+    check_new_kid(&kid)?;
+    let seed = Zeroizing::new(
+        <[u8; 32]>::try_from(seed.as_slice())
+            .map_err(|_| Error::Term(Box::new(atoms::bad_seed_length())))?,
+    );
+    let (pub_key, priv_key) =
+        keys::recipient_keypair_from_seed(&kid, *seed, opts.recipient_level(), opts.hybrid);
+    let pub_bytes = store_recipient_keypair(pub_key, priv_key, opts)?;
+    Ok((atoms::ok(), (kid, to_binary(env, &pub_bytes)?)))
+}
+
+/// Generate a new version of a stored recipient keypair, keeping the older ones
+/// Version n > 1 is stored, and named in its public key, as `kid#vn`, at the
+/// security level of the latest version
+/// Returns {ok, {new_version, public_key_cbor}}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn rotate_recipient_key<'a>(
+    env: Env<'a>,
+    kid: String,
+) -> NifResult<(rustler::Atom, (u32, Binary<'a>))> {
+    let mut recipient_keys = write_store(&RECIPIENT_KEYS);
+    let latest = key_versions(&recipient_keys, &kid)
+        .last()
+        .copied()
+        .ok_or_else(|| error_term(atoms::recipient_not_found(), &kid))?;
+    let (_, latest_priv) = &recipient_keys[&keys::versioned_kid(&kid, latest)];
+    let level = latest_priv.sk_kyber.level();
+    let hybrid = latest_priv.x25519.is_some();
+    let versioned = keys::versioned_kid(&kid, latest + 1);
+    check_identifier(&versioned)?;
+    let (pub_key, priv_key) = keys::recipient_keypair(&versioned, level, hybrid);
 
     // Serialize public key to CBOR
-    let pub_bytes = serde_cbor::to_vec(&pub_key)
-        .map_err(|e| Error::Term(Box::new(format!("Serialization failed: {:?}", e))))?;
+    let pub_bytes =
+        cbor::canonical(&pub_key).map_err(|e| error_term(atoms::serialization_failed(), e))?;
 
-    // Store the keys
-    RECIPIENT_KEYS
-        .lock()
-        .unwrap()
-        .insert(kid.clone(), (pub_key, priv_key));
+    recipient_keys.insert(versioned, (pub_key, priv_key));
+
+    Ok((atoms::ok(), (latest + 1, to_binary(env, &pub_bytes)?)))
+}
 
-    let mut pub_bin = OwnedBinary::new(pub_bytes.len()).ok_or(Error::Atom("allocation_failed"))?;
-    pub_bin.as_mut_slice().copy_from_slice(&pub_bytes);
+/// List the stored versions of a recipient kid
+/// Returns {ok, [version]} sorted, empty if the kid is not stored
+#[rustler::nif]
+fn list_key_versions(kid: String) -> (rustler::Atom, Vec<u32>) {
+    (
+        atoms::ok(),
+        key_versions(&read_store(&RECIPIENT_KEYS), &kid),
+    )
+}
 
-    Ok((atoms::ok(), (kid, pub_bin.release(env))))
+/// Generate a new recipient keypair, store it, and also hand back the private key
+/// Returns {ok, {kid, public_key_cbor, private_key_cbor}}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn gen_recipient_keypair_exported<'a>(
+    env: Env<'a>,
+    kid: String,
+) -> NifResult<(rustler::Atom, (String, Binary<'a>, Binary<'a>))> {
+    recipient_keypair_exported(env, kid, &options::KeygenOptions::default())
 }
 
-/// Generate a new sender keypair and store it
-/// Returns {ok, {sid, public_key_cbor}}
+/// Generate a new recipient keypair with options, store it, and also hand back
+/// the private key
+/// Returns {ok, {kid, public_key_cbor, private_key_cbor}}
+#[rustler::nif(name = "gen_recipient_keypair_exported", schedule = "DirtyCpu")]
+fn gen_recipient_keypair_exported_with_opts<'a>(
+    env: Env<'a>,
+    kid: String,
+    opts: Term<'a>,
+) -> NifResult<(rustler::Atom, (String, Binary<'a>, Binary<'a>))> {
+    let opts = options::KeygenOptions::decode(opts)?;
+    recipient_keypair_exported(env, kid, &opts)
+}
+
+fn recipient_keypair_exported<'a>(
+    env: Env<'a>,
+    kid: String,
+    opts: &options::KeygenOptions,
+) -> NifResult<(rustler::Atom, (String, Binary<'a>, Binary<'a>))> {
+    check_new_kid(&kid)?;
+    let (pub_key, priv_key) = keys::recipient_keypair(&kid, opts.recipient_level(), opts.hybrid);
+
+    // Serialize the private half to CBOR; the public half is serialized on storing
+    let priv_bytes = cbor::to_vec(&keys::RecipientPrivCbor::from_priv(&priv_key))
+        .map(Zeroizing::new)
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+    let pub_bytes = store_recipient_keypair(pub_key, priv_key, opts)?;
+
+    Ok((
+        atoms::ok(),
+        (
+            kid,
+            to_binary(env, &pub_bytes)?,
+            to_binary(env, &priv_bytes)?,
+        ),
+    ))
+}
+
+/// Convert a CBOR recipient public key to its JSON form
+/// Returns {ok, public_key_json}
+#[rustler::nif]
+fn recipient_pub_to_json<'a>(
+    env: Env<'a>,
+    pub_key: Binary,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let pub_key = decode_recipient_pubs(&[pub_key])?.remove(0);
+    let json = keys::encode_recipient_pub(&pub_key, keys::KeyFormat::Json)
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+    Ok((atoms::ok(), to_binary(env, &json)?))
+}
+
+/// Convert the JSON form of a recipient public key back to the CBOR encrypt takes
+/// Returns {ok, public_key_cbor}
+#[rustler::nif]
+fn recipient_pub_from_json<'a>(
+    env: Env<'a>,
+    json: Binary,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let pub_key = keys::recipient_pub_from_json(json.as_slice())
+        .map_err(|e| error_term(atoms::deserialize_recipient(), e))?;
+    let cbor = keys::encode_recipient_pub(&pub_key, keys::KeyFormat::Cbor)
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+    Ok((atoms::ok(), to_binary(env, &cbor)?))
+}
+
+/// Convert a CBOR recipient public key to its raw form, see `keys::recipient_pub_to_raw`
+/// Returns {ok, public_key_raw}
+#[rustler::nif]
+fn recipient_pub_to_raw<'a>(
+    env: Env<'a>,
+    pub_key: Binary,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let pub_key = decode_recipient_pubs(&[pub_key])?.remove(0);
+    let raw = keys::recipient_pub_to_raw(&pub_key)
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+    Ok((atoms::ok(), to_binary(env, &raw)?))
+}
+
+/// Convert the raw form of a recipient public key back to the CBOR encrypt takes
+/// Returns {ok, public_key_cbor}
 #[rustler::nif]
+fn recipient_pub_from_raw<'a>(env: Env<'a>, raw: Binary) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let pub_key = keys::recipient_pub_from_raw(raw.as_slice())
+        .map_err(|e| error_term(atoms::deserialize_recipient(), e))?;
+    check_identifier(&pub_key.kid)?;
+    let cbor = keys::encode_recipient_pub(&pub_key, keys::KeyFormat::Cbor)
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+    Ok((atoms::ok(), to_binary(env, &cbor)?))
+}
+
+/// Re-encode a CBOR recipient public key as canonical CBOR, the encoding every
+/// keygen returns
+/// Returns {ok, public_key_cbor}
+#[rustler::nif]
+fn recipient_pub_to_canonical_cbor<'a>(
+    env: Env<'a>,
+    pub_key: Binary,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let pub_key = decode_recipient_pubs(&[pub_key])?.remove(0);
+    let cbor = keys::encode_recipient_pub(&pub_key, keys::KeyFormat::Cbor)
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+    Ok((atoms::ok(), to_binary(env, &cbor)?))
+}
+
+/// Check that a CBOR recipient public key can be encrypted to, as every encrypt does
+/// Returns {ok, valid}, {error, invalid_recipient_key} or
+/// {error, {deserialize_recipient, detail}}
+#[rustler::nif]
+fn validate_recipient_pub(pub_key: Binary) -> NifResult<(rustler::Atom, rustler::Atom)> {
+    let pub_key = decode_recipient_pubs(&[pub_key])?.remove(0);
+    check_recipient_keys([&pub_key])?;
+    Ok((atoms::ok(), atoms::valid()))
+}
+
+/// Fingerprint of a CBOR recipient public key, the same for any encoding of the key
+/// Returns {ok, hex}
+#[rustler::nif]
+fn recipient_pub_fingerprint(pub_key: Binary) -> NifResult<(rustler::Atom, String)> {
+    let pub_key = decode_recipient_pubs(&[pub_key])?.remove(0);
+    let fingerprint = keys::recipient_fingerprint(&pub_key)
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+    Ok((atoms::ok(), fingerprint))
+}
+
+/// Fingerprint of a CBOR sender public key, the same for any encoding of the key
+/// Returns {ok, hex}
+#[rustler::nif]
+fn sender_pub_fingerprint(pub_key: Binary) -> NifResult<(rustler::Atom, String)> {
+    let sender_pub: tholos_pq::SenderPub = cbor::from_slice(pub_key.as_slice())
+        .map_err(|e| error_term(atoms::deserialize_sender(), e))?;
+    let fingerprint = keys::sender_fingerprint(&sender_pub)
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+    Ok((atoms::ok(), fingerprint))
+}
+
+/// Generate a new sender keypair and store it
+/// Returns {ok, {sid, public_key_cbor}}, or {error, {sid_exists, sid}} if a
+/// keypair is already stored under sid
+#[rustler::nif(schedule = "DirtyCpu")]
 fn gen_sender_keypair<'a>(
     env: Env<'a>,
     sid: String,
 ) -> NifResult<(rustler::Atom, (String, Binary<'a>))> {
-    let sender = tholos_pq::gen_sender_keypair(&sid);
-    let sender_pub = tholos_pq::sender_pub(&sender);
+    sender_keypair(env, sid, &options::KeygenOptions::default())
+}
+
+/// Generate a new sender keypair with options and store it
+/// `opts` is a keyword list; `security_level` picks the Dilithium parameter set
+/// and `overwrite` lets it replace a keypair already stored under sid
+/// Returns {ok, {sid, public_key_cbor}}
+#[rustler::nif(name = "gen_sender_keypair", schedule = "DirtyCpu")]
+fn gen_sender_keypair_with_opts<'a>(
+    env: Env<'a>,
+    sid: String,
+    opts: Term<'a>,
+) -> NifResult<(rustler::Atom, (String, Binary<'a>))> {
+    let opts = options::KeygenOptions::decode_for_sender(opts)?;
+    sender_keypair(env, sid, &opts)
+}
+
+fn sender_keypair<'a>(
+    env: Env<'a>,
+    sid: String,
+    opts: &options::KeygenOptions,
+) -> NifResult<(rustler::Atom, (String, Binary<'a>))> {
+    check_identifier(&sid)?;
+    let sender = keys::SenderKey::generate(&sid, opts.sender_level());
+    let pub_bytes = store_sender_keypair(sender, opts)?;
+    Ok((atoms::ok(), (sid, to_binary(env, &pub_bytes)?)))
+}
 
+/// Store a sender keypair under its sid, unless another one is stored there and
+/// `overwrite` is false
+/// Returns the CBOR public key
+fn store_sender_keypair(
+    mut sender: keys::SenderKey,
+    opts: &options::KeygenOptions,
+) -> NifResult<Vec<u8>> {
     // Serialize sender public key to CBOR
-    let pub_bytes = serde_cbor::to_vec(&sender_pub)
-        .map_err(|e| Error::Term(Box::new(format!("Serialization failed: {:?}", e))))?;
+    let pub_bytes = cbor::canonical(&sender.public())
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+    sender.expires_at = opts.expires_at();
 
-    // Store the sender keypair
-    SENDER_KEYS.lock().unwrap().insert(sid.clone(), sender);
+    let mut sender_keys = write_store(&SENDER_KEYS);
+    if !opts.overwrite && sender_keys.contains_key(&sender.sid) {
+        return Err(error_term(atoms::sid_exists(), &sender.sid));
+    }
+    sender_keys.insert(sender.sid.clone(), sender);
 
-    let mut pub_bin = OwnedBinary::new(pub_bytes.len()).ok_or(Error::Atom("allocation_failed"))?;
-    pub_bin.as_mut_slice().copy_from_slice(&pub_bytes);
+    Ok(pub_bytes)
+}
 
-    Ok((atoms::ok(), (sid, pub_bin.release(env))))
+/// Generate a new sender keypair, store it, and also hand back the keypair
+/// with its secret key, as export_sender_keypair encodes it
+/// Returns {ok, {sid, public_key_cbor, keypair_cbor}}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn gen_sender_keypair_exported<'a>(
+    env: Env<'a>,
+    sid: String,
+) -> NifResult<(rustler::Atom, (String, Binary<'a>, Binary<'a>))> {
+    sender_keypair_exported(env, sid, &options::KeygenOptions::default())
 }
 
-/// Encrypt a message for multiple recipients
-/// Returns {ok, ciphertext}
-#[rustler::nif]
-fn encrypt<'a>(
+/// Generate a new sender keypair with options, store it, and also hand back
+/// the keypair with its secret key
+/// Returns {ok, {sid, public_key_cbor, keypair_cbor}}
+#[rustler::nif(name = "gen_sender_keypair_exported", schedule = "DirtyCpu")]
+fn gen_sender_keypair_exported_with_opts<'a>(
     env: Env<'a>,
-    message: Binary,
-    sender_id: String,
-    recipient_pub_keys: Vec<Binary>,
-) -> NifResult<(rustler::Atom, Binary<'a>)> {
-    // Get sender keypair
-    let sender_keys = SENDER_KEYS.lock().unwrap();
-    let sender = sender_keys
-        .get(&sender_id)
-        .ok_or_else(|| Error::Term(Box::new(format!("Sender {} not found", sender_id))))?;
+    sid: String,
+    opts: Term<'a>,
+) -> NifResult<(rustler::Atom, (String, Binary<'a>, Binary<'a>))> {
+    let opts = options::KeygenOptions::decode_for_sender(opts)?;
+    sender_keypair_exported(env, sid, &opts)
+}
 
-    // Deserialize recipient public keys
-    let recipients: Result<Vec<tholos_pq::RecipientPub>, _> = recipient_pub_keys
-        .iter()
-        .map(|b| serde_cbor::from_slice(b.as_slice()))
-        .collect();
-    let recipients = recipients.map_err(|e| {
-        Error::Term(Box::new(format!(
-            "Failed to deserialize recipients: {:?}",
-            e
-        )))
-    })?;
+fn sender_keypair_exported<'a>(
+    env: Env<'a>,
+    sid: String,
+    opts: &options::KeygenOptions,
+) -> NifResult<(rustler::Atom, (String, Binary<'a>, Binary<'a>))> {
+    check_identifier(&sid)?;
+    let sender = keys::SenderKey::generate(&sid, opts.sender_level());
 
-    // Encrypt
-    let wire = tholos_pq::encrypt(message.as_slice(), sender, &recipients)
-        .map_err(|e| Error::Term(Box::new(format!("Encryption failed: {:?}", e))))?;
+    // Serialize the secret half to CBOR; the public half is serialized on storing
+    let keypair_bytes = cbor::to_vec(&keys::SenderKeypairCbor::from_keypair(&sender))
+        .map(Zeroizing::new)
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+    let pub_bytes = store_sender_keypair(sender, opts)?;
 
-    let mut wire_bin = OwnedBinary::new(wire.len()).ok_or(Error::Atom("allocation_failed"))?;
-    wire_bin.as_mut_slice().copy_from_slice(&wire);
+    Ok((
+        atoms::ok(),
+        (
+            sid,
+            to_binary(env, &pub_bytes)?,
+            to_binary(env, &keypair_bytes)?,
+        ),
+    ))
+}
 
-    Ok((atoms::ok(), wire_bin.release(env)))
+/// Generate a new recipient keypair held by a resource instead of the global store
+/// Returns {ok, {resource, public_key_cbor}}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn gen_recipient_key_resource<'a>(
+    env: Env<'a>,
+    kid: String,
+) -> NifResult<(
+    rustler::Atom,
+    (ResourceArc<RecipientKeyResource>, Binary<'a>),
+)> {
+    recipient_key_resource(env, kid, &options::KeygenOptions::default())
 }
 
-/// Decrypt a message for a recipient
-/// Returns {ok, plaintext}
-#[rustler::nif]
-fn decrypt<'a>(
+/// Generate a new recipient keypair with options, held by a resource
+/// Returns {ok, {resource, public_key_cbor}}
+#[rustler::nif(name = "gen_recipient_key_resource", schedule = "DirtyCpu")]
+fn gen_recipient_key_resource_with_opts<'a>(
     env: Env<'a>,
-    wire: Binary,
     kid: String,
-    allowed_sender_pub_keys: Vec<Binary>,
-) -> NifResult<(rustler::Atom, Binary<'a>)> {
-    // Get recipient private key
-    let recipient_keys = RECIPIENT_KEYS.lock().unwrap();
-    let (_, priv_key) = recipient_keys
-        .get(&kid)
-        .ok_or_else(|| Error::Term(Box::new(format!("Recipient {} not found", kid))))?;
+    opts: Term<'a>,
+) -> NifResult<(
+    rustler::Atom,
+    (ResourceArc<RecipientKeyResource>, Binary<'a>),
+)> {
+    let opts = options::KeygenOptions::decode(opts)?;
+    reject_store_options(&opts)?;
+    recipient_key_resource(env, kid, &opts)
+}
 
-    // Deserialize allowed sender public keys and build allowed list
-    let mut allowed = Vec::new();
-    for pub_key_bytes in &allowed_sender_pub_keys {
-        let sender_pub: tholos_pq::SenderPub = serde_cbor::from_slice(pub_key_bytes.as_slice())
-            .map_err(|e| {
-                Error::Term(Box::new(format!(
-                    "Failed to deserialize sender pub key: {:?}",
-                    e
-                )))
-            })?;
-        allowed.push((sender_pub.sid.clone(), sender_pub.pk_dilithium.clone()));
-    }
+fn recipient_key_resource<'a>(
+    env: Env<'a>,
+    kid: String,
+    opts: &options::KeygenOptions,
+) -> NifResult<(
+    rustler::Atom,
+    (ResourceArc<RecipientKeyResource>, Binary<'a>),
+)> {
+    check_new_kid(&kid)?;
+    let (pub_key, priv_key) = keys::recipient_keypair(&kid, opts.recipient_level(), opts.hybrid);
 
-    // Decrypt
-    let plaintext = tholos_pq::decrypt(wire.as_slice(), &kid, &priv_key.sk_kyber, &allowed)
-        .map_err(|e| Error::Term(Box::new(format!("Decryption failed: {:?}", e))))?;
+    // Serialize public key
+    let pub_bytes = keys::encode_recipient_pub(&pub_key, opts.format)
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+
+    let resource = ResourceArc::new(RecipientKeyResource { pub_key, priv_key });
+
+    Ok((atoms::ok(), (resource, to_binary(env, &pub_bytes)?)))
+}
+
+/// Generate a new sender keypair held by a resource instead of the global store
+/// Returns {ok, {resource, public_key_cbor}}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn gen_sender_key_resource<'a>(
+    env: Env<'a>,
+    sid: String,
+) -> NifResult<(rustler::Atom, (ResourceArc<SenderKeyResource>, Binary<'a>))> {
+    sender_key_resource(env, sid, &options::KeygenOptions::default())
+}
+
+/// Generate a new sender keypair with options, held by a resource
+/// Returns {ok, {resource, public_key_cbor}}
+#[rustler::nif(name = "gen_sender_key_resource", schedule = "DirtyCpu")]
+fn gen_sender_key_resource_with_opts<'a>(
+    env: Env<'a>,
+    sid: String,
+    opts: Term<'a>,
+) -> NifResult<(rustler::Atom, (ResourceArc<SenderKeyResource>, Binary<'a>))> {
+    let opts = options::KeygenOptions::decode_for_sender(opts)?;
+    reject_store_options(&opts)?;
+    sender_key_resource(env, sid, &opts)
+}
 
-    let mut plain_bin =
-        OwnedBinary::new(plaintext.len()).ok_or(Error::Atom("allocation_failed"))?;
-    plain_bin.as_mut_slice().copy_from_slice(&plaintext);
+/// A resource is never stored, so there is nothing to overwrite and nothing
+/// for `sweep_expired` to remove
+fn reject_store_options(opts: &options::KeygenOptions) -> NifResult<()> {
+    if opts.overwrite {
+        return Err(error_term(atoms::invalid_option(), "overwrite"));
+    }
+    if opts.ttl_seconds.is_some() {
+        return Err(error_term(atoms::invalid_option(), "ttl_seconds"));
+    }
+    Ok(())
+}
+
+fn sender_key_resource<'a>(
+    env: Env<'a>,
+    sid: String,
+    opts: &options::KeygenOptions,
+) -> NifResult<(rustler::Atom, (ResourceArc<SenderKeyResource>, Binary<'a>))> {
+    check_identifier(&sid)?;
+    let keypair = keys::SenderKey::generate(&sid, opts.sender_level());
+
+    // Serialize sender public key to CBOR
+    let pub_bytes = cbor::canonical(&keypair.public())
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+
+    let resource = ResourceArc::new(SenderKeyResource { keypair });
+
+    Ok((atoms::ok(), (resource, to_binary(env, &pub_bytes)?)))
+}
+
+/// Encrypt a message for multiple recipients
+/// The sender is either a sender id in the global store or a sender key resource
+/// Returns {ok, ciphertext}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn encrypt<'a>(
+    env: Env<'a>,
+    message: Binary,
+    sender: Term<'a>,
+    recipient_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let opts = options::EncryptOptions::default();
+    encrypt_message(env, message, sender, recipient_pub_keys, &opts)
+}
+
+/// Encrypt a message for multiple recipients with options
+/// `opts` is a keyword list, or a binary taken as the associated data
+/// The associated data is not part of the wire and must be supplied again to decrypt
+/// Returns {ok, ciphertext}
+#[rustler::nif(name = "encrypt", schedule = "DirtyCpu")]
+fn encrypt_with_opts<'a>(
+    env: Env<'a>,
+    message: Binary,
+    sender: Term<'a>,
+    recipient_pub_keys: Vec<Binary>,
+    opts: Term<'a>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let opts = options::EncryptOptions::decode(opts)?;
+    encrypt_message(env, message, sender, recipient_pub_keys, &opts)
+}
+
+fn encrypt_message<'a>(
+    env: Env<'a>,
+    message: Binary,
+    sender: Term<'a>,
+    recipient_pub_keys: Vec<Binary>,
+    opts: &options::EncryptOptions,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    check_recipient_count(recipient_pub_keys.len(), opts.max_recipients)?;
+
+    // Deserialize recipient public keys
+    let recipients = decode_recipient_pubs(&recipient_pub_keys)?;
+    encrypt_for(env, message, sender, &recipients, opts)
+}
+
+/// The phase durations `encrypt_timed` reports, in microseconds rounded up so
+/// that a phase that ran is never reported as 0.
+#[derive(rustler::NifMap)]
+struct PhaseTimings {
+    kem_us: u64,
+    sign_us: u64,
+    aead_us: u64,
+}
+
+fn micros(duration: std::time::Duration) -> u64 {
+    duration.as_nanos().div_ceil(1000) as u64
+}
+
+/// Encrypt a message for multiple recipients like `encrypt`, timing the
+/// encapsulation, AEAD and signing phases
+/// Returns {ok, {ciphertext, %{kem_us, sign_us, aead_us}}}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn encrypt_timed<'a>(
+    env: Env<'a>,
+    message: Binary,
+    sender: Term<'a>,
+    recipient_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, (Binary<'a>, PhaseTimings))> {
+    check_recipient_count(recipient_pub_keys.len(), options::DEFAULT_MAX_RECIPIENTS)?;
+
+    let recipients = decode_recipient_pubs(&recipient_pub_keys)?;
+    let (recipients, escrow) = with_escrow(&recipients, options::DEFAULT_MAX_RECIPIENTS)?;
+    let fields = escrow_fields(escrow);
+    let (wire, timings) = with_sender(sender, |sender| {
+        catch_panic(|| {
+            crypto::encrypt_timed(message.as_slice(), sender, &recipients, &fields)
+                .map_err(|e| error_term(atoms::encrypt_failed(), e))
+        })
+    })?;
+
+    let timings = PhaseTimings {
+        kem_us: micros(timings.kem),
+        sign_us: micros(timings.sign),
+        aead_us: micros(timings.aead),
+    };
+    Ok((atoms::ok(), (into_binary(env, wire), timings)))
+}
+
+/// Encrypt a message for recipients keyed by kid
+/// Each public key must carry the kid it is keyed by, which catches a key loaded for
+/// the wrong recipient; recipients are encrypted for in kid order
+/// Returns {ok, ciphertext}, or {error, {kid_mismatch, map_kid, key_kid}}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn encrypt_to_map<'a>(
+    env: Env<'a>,
+    message: Binary,
+    sender: Term<'a>,
+    recipient_pub_keys: HashMap<String, Binary>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let opts = options::EncryptOptions::default();
+    check_recipient_count(recipient_pub_keys.len(), opts.max_recipients)?;
+
+    let mut entries: Vec<_> = recipient_pub_keys.into_iter().collect();
+    entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut recipients = Vec::with_capacity(entries.len());
+    for (kid, pub_key) in entries {
+        let recipient = decode_recipient_pubs(&[pub_key])?.remove(0);
+        if recipient.kid != kid {
+            return Err(Error::Term(Box::new((
+                atoms::kid_mismatch(),
+                kid,
+                recipient.kid,
+            ))));
+        }
+        recipients.push(recipient);
+    }
+    encrypt_for(env, message, sender, &recipients, &opts)
+}
+
+/// Encrypt a message for every recipient whose public key can be used, skipping the rest
+/// A key is skipped if it does not decode, fails the key checks or is of another
+/// security level than the escrow recipient, or than the first key with one
+/// Returns {ok, {ciphertext, failed_indices}}, {error, no_valid_recipients}, or
+/// {error, {encrypt_failed, detail}} if the escrow recipient cannot be used
+#[rustler::nif(schedule = "DirtyCpu")]
+fn encrypt_partial<'a>(
+    env: Env<'a>,
+    message: Binary,
+    sender: Term<'a>,
+    recipient_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, (Binary<'a>, Vec<usize>))> {
+    if recipient_pub_keys.is_empty() {
+        return Err(Error::Term(Box::new(atoms::no_valid_recipients())));
+    }
+    check_recipient_count(recipient_pub_keys.len(), options::DEFAULT_MAX_RECIPIENTS)?;
+
+    let mut recipients: Vec<Option<tholos_pq::RecipientPub>> = recipient_pub_keys
+        .iter()
+        .map(|b| {
+            cbor::from_slice::<tholos_pq::RecipientPub>(b.as_slice())
+                .ok()
+                .filter(|r| keys::check_id(&r.kid).is_ok())
+        })
+        .collect();
+    let escrow = read_store(&ESCROW_RECIPIENT).clone();
+
+    // Keys that cannot share a wire with the escrow recipient, or with the first
+    // key given, are skipped like the ones that do not decode
+    let level_of =
+        |r: &tholos_pq::RecipientPub| level::Level::of_kem_pk(hybrid::kem_pk(&r.pk_kyber).len());
+    let expected = match &escrow {
+        Some(escrow) => level_of(escrow),
+        None => recipients.iter().flatten().find_map(level_of),
+    };
+    for recipient in &mut recipients {
+        if recipient.as_ref().is_some_and(|r| level_of(r) != expected) {
+            *recipient = None;
+        }
+    }
+
+    // As in `with_escrow`, but the escrow recipient comes last so the indices
+    // given stay
+    let given = recipients.len();
+    if let Some(escrow) = &escrow {
+        if !recipients.iter().flatten().any(|r| r.kid == escrow.kid) {
+            recipients.push(Some(escrow.clone()));
+        }
+    }
+    check_recipient_count(recipients.len(), options::DEFAULT_MAX_RECIPIENTS)?;
+    let fields = escrow_fields(escrow.map(|escrow| escrow.kid));
+
+    let partial = with_sender(sender, |sender| {
+        catch_panic(|| {
+            crypto::encrypt_partial(message.as_slice(), sender, &recipients, &fields)
+                .map_err(|e| error_term(atoms::encrypt_failed(), e))
+        })
+    })?
+    .ok_or_else(|| Error::Term(Box::new(atoms::no_valid_recipients())))?;
+    // Every wire has the escrow envelope, and `failed` only the indices given
+    if partial.failed.last() == Some(&given) {
+        return Err(error_term(atoms::encrypt_failed(), "escrow recipient"));
+    }
+    // A wire only the escrow recipient can read has no valid recipient either
+    if partial.failed.len() == given {
+        return Err(Error::Term(Box::new(atoms::no_valid_recipients())));
+    }
+
+    Ok((
+        atoms::ok(),
+        (into_binary(env, partial.wire), partial.failed),
+    ))
+}
+
+/// Encrypt a message for multiple recipients without a sender and without signing it
+/// Returns {ok, ciphertext}, which only decrypt_with_sender accepts
+#[rustler::nif(schedule = "DirtyCpu")]
+fn encrypt_anonymous<'a>(
+    env: Env<'a>,
+    message: Binary,
+    recipient_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    check_recipient_count(recipient_pub_keys.len(), options::DEFAULT_MAX_RECIPIENTS)?;
+    let recipients = decode_recipient_pubs(&recipient_pub_keys)?;
+    let (recipients, escrow) = with_escrow(&recipients, options::DEFAULT_MAX_RECIPIENTS)?;
+
+    let wire = catch_panic(|| {
+        crypto::encrypt_anonymous(message.as_slice(), &recipients, &escrow_fields(escrow))
+            .map_err(|e| error_term(atoms::encrypt_failed(), e))
+    })?;
+
+    Ok((atoms::ok(), into_binary(env, wire)))
+}
+
+/// `recipients` of a new wire with the escrow recipient set by
+/// set_escrow_recipient, see `crypto::with_escrow`, and the escrow kid for the
+/// header, which names it whether or not it was appended
+/// The escrow recipient counts towards `max_recipients` and is checked along
+/// with the others
+fn with_escrow(
+    recipients: &[tholos_pq::RecipientPub],
+    max_recipients: usize,
+) -> NifResult<(Cow<'_, [tholos_pq::RecipientPub]>, Option<String>)> {
+    let escrow = read_store(&ESCROW_RECIPIENT).clone();
+    let recipients = crypto::with_escrow(recipients, escrow.as_ref());
+    check_recipient_count(recipients.len(), max_recipients)?;
+    check_recipient_keys(recipients.iter())?;
+    check_recipient_levels(recipients.iter())?;
+    Ok((recipients, escrow.map(|escrow| escrow.kid)))
+}
+
+/// Header fields naming `escrow`, for the encrypt NIFs that take no options
+fn escrow_fields(escrow: Option<String>) -> crypto::HeaderFields {
+    crypto::HeaderFields {
+        escrow,
+        ..crypto::HeaderFields::default()
+    }
+}
+
+fn encrypt_for<'a>(
+    env: Env<'a>,
+    message: Binary,
+    sender: Term<'a>,
+    recipients: &[tholos_pq::RecipientPub],
+    opts: &options::EncryptOptions,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    if opts
+        .per_recipient_aad
+        .as_ref()
+        .is_some_and(|aads| aads.len() != recipients.len())
+    {
+        return Err(error_term(atoms::invalid_option(), "per_recipient_aad"));
+    }
+    let mut recipient_aads: Option<Vec<Vec<u8>>> = opts
+        .per_recipient_aad
+        .as_ref()
+        .map(|aads| aads.iter().map(|aad| aad.to_vec()).collect());
+
+    // An appended escrow recipient gets its envelope under an empty recipient AAD
+    let given = recipients.len();
+    let (recipients, escrow) = with_escrow(recipients, opts.max_recipients)?;
+    if recipients.len() > given {
+        if let Some(aads) = &mut recipient_aads {
+            aads.push(Vec::new());
+        }
+    }
+    check_recipient_hybrid(recipients.iter(), opts.hybrid)?;
+    if let Some(block) = opts.pad_to {
+        if crypto::padded_len(message.len(), block).is_none() {
+            return Err(Error::Term(Box::new(
+                atoms::plaintext_too_large_for_padding(),
+            )));
+        }
+    }
+    let wire = with_sender(sender, |sender| {
+        catch_panic(|| {
+            crypto::encrypt(
+                message.as_slice(),
+                sender,
+                &recipients,
+                opts.aad(),
+                opts.compression,
+                opts.embed_sender_key,
+                opts.hybrid,
+                opts.key_commitment,
+                &crypto::HeaderFields {
+                    ttl_seconds: opts.ttl_seconds,
+                    message_id: opts.message_id.clone(),
+                    metadata: opts.metadata.clone(),
+                    aead: opts.aead,
+                    pad_to: opts.pad_to,
+                    recipient_aads: recipient_aads.clone(),
+                    escrow: escrow.clone(),
+                },
+            )
+            .map_err(|e| error_term(atoms::encrypt_failed(), e))
+        })
+    })?;
+
+    Ok((atoms::ok(), into_binary(env, opts.encoding.encode(wire))))
+}
+
+/// Encrypt many messages for the same recipients with one KEM encapsulation per
+/// recipient for the whole batch; every wire shares those KEM ciphertexts
+/// Returns {ok, [ciphertext]} in message order
+#[rustler::nif(schedule = "DirtyCpu")]
+fn encrypt_batch<'a>(
+    env: Env<'a>,
+    sender: Term<'a>,
+    messages: Vec<Binary>,
+    recipient_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, Vec<Binary<'a>>)> {
+    check_recipient_count(recipient_pub_keys.len(), options::DEFAULT_MAX_RECIPIENTS)?;
+
+    // Deserialize recipient public keys
+    let recipients = decode_recipient_pubs(&recipient_pub_keys)?;
+    let (recipients, escrow) = with_escrow(&recipients, options::DEFAULT_MAX_RECIPIENTS)?;
+    let fields = escrow_fields(escrow);
+
+    // Encrypt
+    let messages: Vec<&[u8]> = messages.iter().map(|m| m.as_slice()).collect();
+    let wires = with_sender(sender, |sender| {
+        catch_panic(|| {
+            crypto::encrypt_batch(&messages, sender, &recipients, &fields)
+                .map_err(|e| error_term(atoms::encrypt_failed(), e))
+        })
+    })?;
+
+    Ok((
+        atoms::ok(),
+        wires
+            .into_iter()
+            .map(|wire| into_binary(env, wire))
+            .collect(),
+    ))
+}
+
+/// Seal a message once for recipient sets chosen later with encrypt_cached_for,
+/// keeping its content key in the returned reference
+/// The sender keypair and the escrow recipient, if one is set, are copied into
+/// the reference
+/// Returns {ok, ctx}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn encrypt_cached_init(
+    sender: Term,
+    message: Binary,
+) -> NifResult<(rustler::Atom, ResourceArc<CachedContentResource>)> {
+    let escrow = read_store(&ESCROW_RECIPIENT).clone();
+    let cached = with_sender(sender, |sender| {
+        catch_panic(|| {
+            crypto::encrypt_cached_init(message.as_slice(), sender, escrow)
+                .map_err(|e| error_term(atoms::encrypt_failed(), e))
+        })
+    })?;
+
+    Ok((atoms::ok(), ResourceArc::new(CachedContentResource(cached))))
+}
+
+/// Make a wire of a message sealed by encrypt_cached_init for `recipient_pub_keys`,
+/// wrapping its content key for each and signing, without encrypting the message again
+/// Returns {ok, ciphertext}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn encrypt_cached_for<'a>(
+    env: Env<'a>,
+    ctx: ResourceArc<CachedContentResource>,
+    recipient_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    check_recipient_count(recipient_pub_keys.len(), options::DEFAULT_MAX_RECIPIENTS)?;
+
+    let recipients = decode_recipient_pubs(&recipient_pub_keys)?;
+    // The escrow recipient is the one set at encrypt_cached_init
+    let all = crypto::with_escrow(&recipients, ctx.0.escrow());
+    check_recipient_count(all.len(), options::DEFAULT_MAX_RECIPIENTS)?;
+    check_recipient_keys(all.iter())?;
+    check_recipient_levels(all.iter())?;
+
+    let wire = catch_panic(|| {
+        crypto::encrypt_cached_for(&ctx.0, &recipients)
+            .map_err(|e| error_term(atoms::encrypt_failed(), e))
+    })?;
+
+    Ok((atoms::ok(), into_binary(env, wire)))
+}
+
+fn edit_error(e: crypto::EditError) -> Error {
+    match e {
+        crypto::EditError::Tholos(e) => decrypt_error(e),
+        crypto::EditError::AlreadyPresent => {
+            Error::Term(Box::new(atoms::recipient_already_present()))
+        }
+        crypto::EditError::NotPresent => Error::Term(Box::new(atoms::recipient_not_present())),
+        crypto::EditError::AadMismatch => Error::Term(Box::new(atoms::aad_mismatch())),
+    }
+}
+
+/// Grant a new recipient access to a wire without re-encrypting its payload
+/// The wire must be signed by sender, which re-signs it; recipient is an existing
+/// recipient of the wire whose key unwraps the content key for the new envelope
+/// Returns {ok, new_wire}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn add_recipient<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    sender: Term<'a>,
+    new_recipient_pub_key: Binary,
+    recipient: Term<'a>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let opts = options::EditOptions::default();
+    add_recipient_for(env, wire, sender, new_recipient_pub_key, recipient, &opts)
+}
+
+/// Grant a new recipient access to a wire with options
+/// `opts` is a keyword list; `aad` is the associated data the wire was encrypted with
+/// Returns {ok, new_wire}, or {error, aad_mismatch} if the associated data differs
+#[rustler::nif(name = "add_recipient", schedule = "DirtyCpu")]
+fn add_recipient_with_opts<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    sender: Term<'a>,
+    new_recipient_pub_key: Binary,
+    recipient: Term<'a>,
+    opts: Term<'a>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let opts = options::EditOptions::decode(opts)?;
+    add_recipient_for(env, wire, sender, new_recipient_pub_key, recipient, &opts)
+}
+
+fn add_recipient_for<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    sender: Term<'a>,
+    new_recipient_pub_key: Binary,
+    recipient: Term<'a>,
+    opts: &options::EditOptions,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let new_recipient = decode_recipient_pubs(&[new_recipient_pub_key])?.remove(0);
+    check_recipient_keys([&new_recipient])?;
+    let body = wire_body(wire.as_slice())?;
+
+    // Recipient before sender, the order the store locks are always taken in
+    let new_wire = with_recipient(recipient, |kid, priv_key| {
+        with_sender(sender, |sender| {
+            catch_panic(|| {
+                crypto::add_recipient(body, sender, kid, priv_key, &new_recipient, opts.aad())
+                    .map_err(edit_error)
+            })
+        })
+    })?;
+
+    Ok((atoms::ok(), into_binary(env, new_wire)))
+}
+
+/// Hand the access of old_kid to a wire over to a new recipient key, e.g. after
+/// a rotation: the old key unwraps the content key, which is wrapped for the new
+/// one in place of the old envelope, and sender re-signs the wire
+/// Returns {ok, new_wire}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn rewrap_for_recipient<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    old_kid: Term<'a>,
+    new_recipient_pub_key: Binary,
+    sender: Term<'a>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let opts = options::EditOptions::default();
+    rewrap_recipient(env, wire, old_kid, new_recipient_pub_key, sender, &opts)
+}
+
+/// Hand the access of old_kid to a wire over to a new recipient key with options,
+/// which are as for add_recipient
+/// Returns {ok, new_wire}, or {error, aad_mismatch} if the associated data differs
+#[rustler::nif(name = "rewrap_for_recipient", schedule = "DirtyCpu")]
+fn rewrap_for_recipient_with_opts<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    old_kid: Term<'a>,
+    new_recipient_pub_key: Binary,
+    sender: Term<'a>,
+    opts: Term<'a>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let opts = options::EditOptions::decode(opts)?;
+    rewrap_recipient(env, wire, old_kid, new_recipient_pub_key, sender, &opts)
+}
+
+fn rewrap_recipient<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    old_kid: Term<'a>,
+    new_recipient_pub_key: Binary,
+    sender: Term<'a>,
+    opts: &options::EditOptions,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let new_recipient = decode_recipient_pubs(&[new_recipient_pub_key])?.remove(0);
+    check_recipient_keys([&new_recipient])?;
+    let body = wire_body(wire.as_slice())?;
+
+    // Recipient before sender, the order the store locks are always taken in
+    let new_wire = with_recipient(old_kid, |kid, priv_key| {
+        with_sender(sender, |sender| {
+            catch_panic(|| {
+                crypto::rewrap_recipient(body, sender, kid, priv_key, &new_recipient, opts.aad())
+                    .map_err(edit_error)
+            })
+        })
+    })?;
+
+    Ok((atoms::ok(), into_binary(env, new_wire)))
+}
+
+/// Drop a recipient's envelope from a wire and re-sign it under sender
+/// The wire must be signed by sender; the payload is not re-encrypted
+/// Returns {ok, new_wire}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn remove_recipient<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    sender: Term<'a>,
+    kid: String,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let body = wire_body(wire.as_slice())?;
+    let new_wire = with_sender(sender, |sender| {
+        catch_panic(|| crypto::remove_recipient(body, sender, &kid).map_err(edit_error))
+    })?;
+
+    Ok((atoms::ok(), into_binary(env, new_wire)))
+}
+
+/// Re-sign a wire signed by old_sid under new_sid, e.g. once the old key is compromised
+/// Both senders must be in the store; the payload and envelopes are not touched
+/// Returns {ok, new_wire}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn resign_wire<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    old_sid: String,
+    new_sid: String,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    check_identifier(&old_sid)?;
+    check_identifier(&new_sid)?;
+    let body = wire_body(wire.as_slice())?;
+
+    let sender_keys = read_store(&SENDER_KEYS);
+    let sender = |sid: &String| {
+        sender_keys
+            .get(sid)
+            .ok_or_else(|| error_term(atoms::sender_not_found(), sid))
+    };
+    let (old_sender, new_sender) = (sender(&old_sid)?, sender(&new_sid)?);
+    let new_wire =
+        catch_panic(|| crypto::resign(body, old_sender, new_sender).map_err(decrypt_error))?;
+
+    Ok((atoms::ok(), into_binary(env, new_wire)))
+}
+
+/// Export a stored sender keypair, including its secret key
+/// Returns {ok, keypair_cbor}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn export_sender_keypair<'a>(env: Env<'a>, sid: String) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let sender_keys = read_store(&SENDER_KEYS);
+    let sender = sender_keys
+        .get(&sid)
+        .ok_or_else(|| error_term(atoms::sender_not_found(), &sid))?;
+
+    let keypair_bytes = cbor::to_vec(&keys::SenderKeypairCbor::from_keypair(sender))
+        .map(Zeroizing::new)
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+
+    Ok((atoms::ok(), to_binary(env, &keypair_bytes)?))
+}
+
+/// Encrypt a message with a sender keypair supplied by the caller
+/// Nothing is looked up in or added to the global store
+/// Returns {ok, ciphertext}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn encrypt_with_keys<'a>(
+    env: Env<'a>,
+    message: Binary,
+    sender_keypair: Binary,
+    recipient_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    // Deserialize the sender keypair
+    let sender = cbor::from_slice::<keys::SenderKeypairCbor>(sender_keypair.as_slice())
+        .map_err(|e| e.to_string())
+        .and_then(keys::SenderKeypairCbor::into_keypair)
+        .map_err(|e| error_term(atoms::deserialize_sender(), e))?;
+
+    check_recipient_count(recipient_pub_keys.len(), options::DEFAULT_MAX_RECIPIENTS)?;
+
+    // Deserialize recipient public keys
+    let recipients = decode_recipient_pubs(&recipient_pub_keys)?;
+    let (recipients, escrow) = with_escrow(&recipients, options::DEFAULT_MAX_RECIPIENTS)?;
+
+    // Encrypt
+    let wire = catch_panic(|| {
+        crypto::encrypt(
+            message.as_slice(),
+            &sender,
+            &recipients,
+            &[],
+            crypto::Compression::None,
+            false,
+            false,
+            false,
+            &escrow_fields(escrow),
+        )
+        .map_err(|e| error_term(atoms::encrypt_failed(), e))
+    })?;
+
+    Ok((atoms::ok(), into_binary(env, wire)))
+}
+
+/// Wrap a caller's 32-byte content key for multiple recipients, without a payload
+/// The envelopes are those encrypt makes, bound to the sender sid and recipient kids
+/// but not signed, see `keywrap`
+/// Returns {ok, wrapped_key}, or {error, bad_content_key_length}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn wrap_content_key<'a>(
+    env: Env<'a>,
+    sender: Term<'a>,
+    content_key: Binary,
+    recipient_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let content_key = Zeroizing::new(
+        <[u8; 32]>::try_from(content_key.as_slice())
+            .map_err(|_| Error::Term(Box::new(atoms::bad_content_key_length())))?,
+    );
+    check_recipient_count(recipient_pub_keys.len(), options::DEFAULT_MAX_RECIPIENTS)?;
+
+    let recipients = decode_recipient_pubs(&recipient_pub_keys)?;
+    check_recipient_keys(&recipients)?;
+    check_recipient_levels(&recipients)?;
+    let wrapped = with_sender(sender, |sender| {
+        catch_panic(|| {
+            keywrap::wrap(&content_key, sender, &recipients)
+                .map_err(|e| error_term(atoms::encrypt_failed(), e))
+        })
+    })?;
+
+    Ok((atoms::ok(), to_binary(env, &wrapped)?))
+}
+
+/// Recover a content key from wrap_content_key with a recipient private key
+/// supplied by the caller
+/// Returns {ok, content_key}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn unwrap_content_key<'a>(
+    env: Env<'a>,
+    wrapped_key: Binary,
+    kid: String,
+    recipient_priv: Binary,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    check_identifier(&kid)?;
+    let priv_key = cbor::from_slice::<keys::RecipientPrivCbor>(recipient_priv.as_slice())
+        .map_err(|e| e.to_string())
+        .and_then(keys::RecipientPrivCbor::into_priv)
+        .map_err(|e| error_term(atoms::invalid_private_key(), e))?;
+
+    let content_key = catch_panic(|| {
+        keywrap::unwrap(wrapped_key.as_slice(), &kid, &priv_key).map_err(decrypt_error)
+    })?;
+
+    Ok((atoms::ok(), to_binary(env, content_key.as_slice())?))
+}
+
+/// Reject a pre-shared key too short to derive the outer layer key from
+/// Returns {error, bad_psk_length}
+fn check_psk(psk: &Binary) -> NifResult<()> {
+    if psk.len() < psk::MIN_PSK_LEN {
+        return Err(Error::Term(Box::new(atoms::bad_psk_length())));
+    }
+    Ok(())
+}
+
+/// Encrypt a message like encrypt, then seal the whole wire under a pre-shared key
+/// The outer layer is only transport, see `psk`
+/// Returns {ok, wrapped}, or {error, bad_psk_length} for a PSK under 16 bytes
+#[rustler::nif(schedule = "DirtyCpu")]
+fn encrypt_psk_wrapped<'a>(
+    env: Env<'a>,
+    sender: Term<'a>,
+    message: Binary,
+    recipient_pub_keys: Vec<Binary>,
+    psk: Binary,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    check_psk(&psk)?;
+    let opts = options::EncryptOptions::default();
+    let (_, wire) = encrypt_message(env, message, sender, recipient_pub_keys, &opts)?;
+    let wrapped = psk::wrap(psk.as_slice(), wire.as_slice())
+        .map_err(|e| error_term(atoms::encrypt_failed(), e))?;
+
+    Ok((atoms::ok(), into_binary(env, wrapped)))
+}
+
+/// Open the pre-shared key layer of encrypt_psk_wrapped, then decrypt the wire
+/// inside like decrypt
+/// Returns {ok, plaintext}, {error, bad_psk} if the PSK does not open it or
+/// {error, bad_psk_length}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn decrypt_psk_wrapped<'a>(
+    env: Env<'a>,
+    wrapped: Binary,
+    recipient: Term<'a>,
+    allowed_sender_pub_keys: Vec<Binary>,
+    psk: Binary,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    check_psk(&psk)?;
+    let wire = psk::unwrap(psk.as_slice(), wrapped.as_slice()).map_err(|e| match e {
+        psk::OpenError::BadPsk => Error::Term(Box::new(atoms::bad_psk())),
+        psk::OpenError::Malformed(detail) => error_term(atoms::malformed_wire(), detail),
+    })?;
+
+    let decrypted = decrypt_message(
+        env,
+        &wire,
+        recipient,
+        allowed_sender_pub_keys,
+        None,
+        None,
+        crypto::Accept::default(),
+        options::DEFAULT_MAX_RECIPIENTS,
+    )?;
+    Ok((atoms::ok(), into_binary(env, decrypted.plaintext)))
+}
+
+/// Decrypt a message for a recipient
+/// The recipient is either a kid in the global store or a recipient key resource
+/// Returns {ok, plaintext}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn decrypt<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    recipient: Term<'a>,
+    allowed_sender_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let decrypted = decrypt_message(
+        env,
+        wire.as_slice(),
+        recipient,
+        allowed_sender_pub_keys,
+        None,
+        None,
+        crypto::Accept::default(),
+        options::DEFAULT_MAX_RECIPIENTS,
+    )?;
+    Ok((atoms::ok(), into_binary(env, decrypted.plaintext)))
+}
+
+/// Decrypt a message like decrypt, sorting a failure into a category for alerting:
+/// authz, integrity, format or key
+/// Returns {ok, plaintext} or {error, {category, reason}}, where reason is what
+/// decrypt returns
+#[rustler::nif(schedule = "DirtyCpu")]
+fn decrypt_classified<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    recipient: Term<'a>,
+    allowed_sender_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let classify = |error: Error, signer_known: &dyn Fn() -> bool| match error {
+        Error::Term(reason) => {
+            let category = failure_category(reason.encode(env), signer_known);
+            Error::Term(Box::new(Classified(category, reason)))
+        }
+        error => error,
+    };
+    let allowed =
+        decode_allowed_senders(&allowed_sender_pub_keys).map_err(|e| classify(e, &|| false))?;
+
+    let decrypted = open_wire(
+        env,
+        wire.as_slice(),
+        recipient,
+        allowed.as_slice(),
+        None,
+        None,
+        crypto::Accept::default(),
+        options::DEFAULT_MAX_RECIPIENTS,
+    )
+    .map_err(|e| {
+        classify(e, &|| {
+            wire_body(wire.as_slice())
+                .is_ok_and(|body| crypto::signer_known(body, allowed.as_slice()))
+        })
+    })?;
+    Ok((atoms::ok(), into_binary(env, decrypted.plaintext)))
+}
+
+/// A failure reason tagged with its category, see `failure_category`
+struct Classified(rustler::Atom, Box<dyn Encoder>);
+
+impl Encoder for Classified {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        (self.0, self.1.encode(env)).encode(env)
+    }
+}
+
+/// The category decrypt_classified reports a failure with `reason` under
+/// A bad signature is authz when there is no key to check it against, because the
+/// sender is not allowed or the wire is anonymous, and integrity when the key
+/// does not verify it; a wire naming a sender outside the allow-list is authz even
+/// if the name itself was altered
+fn failure_category(reason: Term, signer_known: &dyn Fn() -> bool) -> rustler::Atom {
+    let kind = match rustler::types::tuple::get_tuple(reason) {
+        Ok(elements) => elements
+            .first()
+            .and_then(|kind| kind.decode::<rustler::Atom>().ok()),
+        Err(_) => reason.decode::<rustler::Atom>().ok(),
+    };
+    match kind {
+        Some(kind) if kind == atoms::signature_invalid() => {
+            if signer_known() {
+                atoms::integrity()
+            } else {
+                atoms::authz()
+            }
+        }
+        Some(kind) if kind == atoms::key_commitment_failed() => atoms::integrity(),
+        // With the signature verified, an envelope that does not open under the
+        // stored key means the key is the wrong one
+        Some(kind)
+            if kind == atoms::no_envelope_for_recipient()
+                || kind == atoms::recipient_not_found()
+                || kind == atoms::auth_tag_mismatch()
+                || kind == atoms::security_level_mismatch()
+                || kind == atoms::hybrid_mismatch() =>
+        {
+            atoms::key()
+        }
+        _ => atoms::format(),
+    }
+}
+
+/// Decrypt a message for a recipient with options
+/// `opts` is a keyword list, or a binary taken as the associated data used to encrypt it
+/// Returns {ok, plaintext}, or {error, aad_mismatch} if the associated data differs
+/// With `now` given, returns {error, expired} for a wire older than its time to
+/// live or `max_age_seconds`; without it, {error, now_required} for a wire that
+/// has a time to live
+/// `max_recipients` raises or lowers how many recipients the wire may declare
+#[rustler::nif(name = "decrypt", schedule = "DirtyCpu")]
+fn decrypt_with_opts<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    recipient: Term<'a>,
+    allowed_sender_pub_keys: Vec<Binary>,
+    opts: Term<'a>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let opts = options::DecryptOptions::decode(opts)?;
+    let wire = opts
+        .encoding
+        .decode(wire.as_slice())
+        .ok_or_else(|| error_term(atoms::malformed_wire(), "base64url"))?;
+    let expiry = opts.now.map(|now| crypto::Expiry {
+        now,
+        max_age_seconds: opts.max_age_seconds,
+    });
+    let decrypted = decrypt_message(
+        env,
+        &wire,
+        recipient,
+        allowed_sender_pub_keys,
+        opts.aad(),
+        expiry,
+        crypto::Accept::default(),
+        opts.max_recipients,
+    )?;
+    Ok((atoms::ok(), into_binary(env, decrypted.plaintext)))
+}
+
+/// Decrypt a message for a recipient and report which allowed sender signed it
+/// Returns {ok, {plaintext, sender_sid}}
+/// With an empty allow-list any sender whose key is embedded in the wire is accepted,
+/// and {ok, {plaintext, sender_sid, sender_pub_key_cbor}} is returned
+/// An anonymous wire is accepted with any allow-list and gives {ok, {plaintext, anonymous}}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn decrypt_with_sender<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    recipient: Term<'a>,
+    allowed_sender_pub_keys: Vec<Binary>,
+) -> NifResult<Term<'a>> {
+    let open = allowed_sender_pub_keys.is_empty();
+    let decrypted = decrypt_message(
+        env,
+        wire.as_slice(),
+        recipient,
+        allowed_sender_pub_keys,
+        None,
+        None,
+        crypto::Accept {
+            anonymous: true,
+            embedded_key: true,
+        },
+        options::DEFAULT_MAX_RECIPIENTS,
+    )?;
+    let plaintext = into_binary(env, decrypted.plaintext);
+    if decrypted.anonymous {
+        return Ok((atoms::ok(), (plaintext, atoms::anonymous())).encode(env));
+    }
+    if !open {
+        return Ok((atoms::ok(), (plaintext, decrypted.sender)).encode(env));
+    }
+
+    // The caller knew nothing about the sender, so hand back the key that verified it
+    let sender_pub = tholos_pq::SenderPub {
+        sid: decrypted.sender,
+        pk_dilithium: decrypted.sender_pk,
+    };
+    let pub_bytes =
+        cbor::canonical(&sender_pub).map_err(|e| error_term(atoms::serialization_failed(), e))?;
+    Ok((
+        atoms::ok(),
+        (plaintext, sender_pub.sid, to_binary(env, &pub_bytes)?),
+    )
+        .encode(env))
+}
+
+/// Decrypt a message for a recipient but keep the plaintext on the Rust side,
+/// zeroizing it before returning, so only its length reaches the caller
+/// Unlike validate_wire this also checks the wire opens under the recipient key
+/// Returns {ok, {valid, sender_sid, plaintext_len}}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn decrypt_verify_only<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    recipient: Term<'a>,
+    allowed_sender_pub_keys: Vec<Binary>,
+) -> NifResult<Term<'a>> {
+    let decrypted = decrypt_message(
+        env,
+        wire.as_slice(),
+        recipient,
+        allowed_sender_pub_keys,
+        None,
+        None,
+        crypto::Accept::default(),
+        options::DEFAULT_MAX_RECIPIENTS,
+    )?;
+    let plaintext = Zeroizing::new(decrypted.plaintext);
+    Ok((
+        atoms::ok(),
+        (atoms::valid(), decrypted.sender, plaintext.len()),
+    )
+        .encode(env))
+}
+
+type DigestFn = fn(&[u8]) -> Vec<u8>;
+
+/// The hash function named by `algorithm`, `:sha256` or `:blake3`
+fn digest_fn(algorithm: rustler::Atom) -> NifResult<DigestFn> {
+    if algorithm == atoms::sha256() {
+        Ok(|bytes| <sha2::Sha256 as sha2::Digest>::digest(bytes).to_vec())
+    } else if algorithm == atoms::blake3() {
+        Ok(|bytes| blake3::hash(bytes).as_bytes().to_vec())
+    } else {
+        Err(error_term(atoms::invalid_option(), "digest"))
+    }
+}
+
+/// Decrypt a message for a recipient and hash the plaintext with SHA-256
+/// Returns {ok, {plaintext, digest}}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn decrypt_with_digest<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    recipient: Term<'a>,
+    allowed_sender_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, (Binary<'a>, Binary<'a>))> {
+    decrypt_and_digest(
+        env,
+        wire,
+        recipient,
+        allowed_sender_pub_keys,
+        atoms::sha256(),
+    )
+}
+
+/// Decrypt a message for a recipient and hash the plaintext with `algorithm`
+/// Returns {ok, {plaintext, digest}}, or {error, {invalid_option, "digest"}}
+#[rustler::nif(name = "decrypt_with_digest", schedule = "DirtyCpu")]
+fn decrypt_with_digest_algorithm<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    recipient: Term<'a>,
+    allowed_sender_pub_keys: Vec<Binary>,
+    algorithm: rustler::Atom,
+) -> NifResult<(rustler::Atom, (Binary<'a>, Binary<'a>))> {
+    decrypt_and_digest(env, wire, recipient, allowed_sender_pub_keys, algorithm)
+}
+
+fn decrypt_and_digest<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    recipient: Term<'a>,
+    allowed_sender_pub_keys: Vec<Binary>,
+    algorithm: rustler::Atom,
+) -> NifResult<(rustler::Atom, (Binary<'a>, Binary<'a>))> {
+    // Decoded up front so a bad algorithm does not cost a decryption
+    let digest = digest_fn(algorithm)?;
+
+    let decrypted = decrypt_message(
+        env,
+        wire.as_slice(),
+        recipient,
+        allowed_sender_pub_keys,
+        None,
+        None,
+        crypto::Accept::default(),
+        options::DEFAULT_MAX_RECIPIENTS,
+    )?;
+    let digest = digest(&decrypted.plaintext);
+    Ok((
+        atoms::ok(),
+        (
+            into_binary(env, decrypted.plaintext),
+            into_binary(env, digest),
+        ),
+    ))
+}
+
+/// Decrypt a wire for a recipient
+/// Anonymous wires and embedded sender keys are only accepted as `accept` says, see
+/// `crypto::decrypt`
+#[allow(clippy::too_many_arguments)]
+fn decrypt_message(
+    env: Env,
+    wire: &[u8],
+    recipient: Term,
+    allowed_sender_pub_keys: Vec<Binary>,
+    aad: Option<crypto::Aad>,
+    expiry: Option<crypto::Expiry>,
+    accept: crypto::Accept,
+    max_recipients: usize,
+) -> NifResult<crypto::Decrypted> {
+    // Deserialize allowed sender public keys and build allowed list
+    let allowed = decode_allowed_senders(&allowed_sender_pub_keys)?;
+
+    open_wire(
+        env,
+        wire,
+        recipient,
+        allowed.as_slice(),
+        aad,
+        expiry,
+        accept,
+        max_recipients,
+    )
+}
+
+/// Decrypt a wire for a recipient against an already decoded allow-list
+#[allow(clippy::too_many_arguments)]
+fn open_wire(
+    env: Env,
+    wire: &[u8],
+    recipient: Term,
+    allowed: &(impl crypto::AllowedSenders + ?Sized),
+    aad: Option<crypto::Aad>,
+    expiry: Option<crypto::Expiry>,
+    accept: crypto::Accept,
+    max_recipients: usize,
+) -> NifResult<crypto::Decrypted> {
+    let body = checked_wire_body(wire, max_recipients)?;
+    with_recipient_version(recipient, body, |kid, priv_key| {
+        open_body(env, body, kid, priv_key, allowed, aad, expiry, accept)
+    })
+}
+
+/// Decode a list of sender public keys once into an allow-list keyed by sid,
+/// for decrypting many wires against many senders
+/// For a sid listed twice the first key wins, as in the list form
+/// Returns {ok, allowlist}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn build_allowlist(
+    sender_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, ResourceArc<AllowlistResource>)> {
+    let mut senders = HashMap::with_capacity(sender_pub_keys.len());
+    for (sid, pk) in decode_allowed_senders(&sender_pub_keys)? {
+        senders.entry(sid).or_insert(pk);
+    }
+    Ok((atoms::ok(), ResourceArc::new(AllowlistResource(senders))))
+}
+
+/// Decrypt a message for a recipient against an allow-list from build_allowlist
+/// Returns {ok, plaintext}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn decrypt_with_allowlist_ref<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    recipient: Term<'a>,
+    allowlist: ResourceArc<AllowlistResource>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let decrypted = open_wire(
+        env,
+        wire.as_slice(),
+        recipient,
+        &allowlist.0,
+        None,
+        None,
+        crypto::Accept::default(),
+        options::DEFAULT_MAX_RECIPIENTS,
+    )?;
+    Ok((atoms::ok(), into_binary(env, decrypted.plaintext)))
+}
+
+/// Decrypt a message with a recipient key resource, without touching the key store
+/// Returns {ok, plaintext}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn decrypt_with_resource<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    recipient: ResourceArc<RecipientKeyResource>,
+    allowed_sender_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let allowed = decode_allowed_senders(&allowed_sender_pub_keys)?;
+    let body = wire_body(wire.as_slice())?;
+    let decrypted = open_body(
+        env,
+        body,
+        &recipient.pub_key.kid,
+        &recipient.priv_key,
+        allowed.as_slice(),
+        None,
+        None,
+        crypto::Accept::default(),
+    )?;
+    Ok((atoms::ok(), into_binary(env, decrypted.plaintext)))
+}
+
+/// Generate a recipient keypair and a sender keypair under the same id and store
+/// both, for encrypt_self and decrypt_self
+/// Both stores are locked together, so neither half is stored without the other
+/// Returns {ok, {id, recipient_public_key_cbor, sender_public_key_cbor}}, or
+/// {error, {kid_exists, id}} or {error, {sid_exists, id}} if either is already stored
+#[rustler::nif(schedule = "DirtyCpu")]
+fn gen_self_keypair<'a>(
+    env: Env<'a>,
+    id: String,
+) -> NifResult<(rustler::Atom, (String, Binary<'a>, Binary<'a>))> {
+    check_new_kid(&id)?;
+    let (pub_key, priv_key) = keys::recipient_keypair(&id, level::Level::RECIPIENT_DEFAULT, false);
+    let sender = keys::SenderKey::generate(&id, level::Level::SENDER_DEFAULT);
+    let recipient_bytes =
+        cbor::canonical(&pub_key).map_err(|e| error_term(atoms::serialization_failed(), e))?;
+    let sender_bytes = cbor::canonical(&sender.public())
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+
+    {
+        // Both maps are locked in the same order as export_store
+        let mut recipient_keys = write_store(&RECIPIENT_KEYS);
+        let mut sender_keys = write_store(&SENDER_KEYS);
+        if recipient_keys.contains_key(&id) {
+            return Err(error_term(atoms::kid_exists(), &id));
+        }
+        if sender_keys.contains_key(&id) {
+            return Err(error_term(atoms::sid_exists(), &id));
+        }
+        recipient_keys.insert(id.clone(), (pub_key, priv_key));
+        sender_keys.insert(id.clone(), sender);
+    }
+
+    Ok((
+        atoms::ok(),
+        (
+            id,
+            to_binary(env, &recipient_bytes)?,
+            to_binary(env, &sender_bytes)?,
+        ),
+    ))
+}
+
+/// Encrypt a message to oneself: signed with the sender key stored under
+/// `key_id` and encrypted for the recipient key stored under the same id
+/// Returns {ok, ciphertext}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn encrypt_self<'a>(
+    env: Env<'a>,
+    message: Binary,
+    key_id: Term<'a>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let kid: String = key_id.decode()?;
+    check_identifier(&kid)?;
+    let recipient = read_store(&RECIPIENT_KEYS)
+        .get(&kid)
+        .map(|(pub_key, _)| pub_key.clone())
+        .ok_or_else(|| error_term(atoms::recipient_not_found(), &kid))?;
+    let opts = options::EncryptOptions::default();
+    encrypt_for(env, message, key_id, &[recipient], &opts)
+}
+
+/// Decrypt a message from encrypt_self, accepting only the sender key stored
+/// under `key_id` as its signer
+/// Returns {ok, plaintext}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn decrypt_self<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    key_id: Term<'a>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let sid: String = key_id.decode()?;
+    check_identifier(&sid)?;
+    let allowed = read_store(&SENDER_KEYS)
+        .get(&sid)
+        .map(|sender| [(sid.clone(), sender.dilithium.public_bytes())])
+        .ok_or_else(|| error_term(atoms::sender_not_found(), &sid))?;
+    let decrypted = open_wire(
+        env,
+        wire.as_slice(),
+        key_id,
+        allowed.as_slice(),
+        None,
+        None,
+        crypto::Accept::default(),
+        options::DEFAULT_MAX_RECIPIENTS,
+    )?;
+    Ok((atoms::ok(), into_binary(env, decrypted.plaintext)))
+}
+
+/// Decrypt a message for whichever of several stored kids it is addressed to
+/// The kids are matched against the recipient envelopes, any version of a
+/// rotated kid included, and only the first that has one is tried
+/// Returns {ok, {plaintext, matched_kid, sender_sid}} or {error, no_matching_key}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn decrypt_any<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    kids: Vec<String>,
+    allowed_sender_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, (Binary<'a>, String, String))> {
+    for kid in &kids {
+        check_identifier(kid)?;
+    }
+    let body = checked_wire_body(wire.as_slice(), options::DEFAULT_MAX_RECIPIENTS)?;
+    let addressed =
+        wire::recipient_kids(body).map_err(|_| Error::Term(Box::new(atoms::malformed_wire())))?;
+    let allowed = decode_allowed_senders(&allowed_sender_pub_keys)?;
+
+    let recipient_keys = read_store(&RECIPIENT_KEYS);
+    let (kid, (envelope_kid, priv_key)) = kids
+        .into_iter()
+        .find_map(|kid| {
+            let stored = addressed
+                .iter()
+                .filter(|e| keys::kid_version(e, &kid).is_some())
+                .find_map(|e| recipient_keys.get(e).map(|(_, priv_key)| (e, priv_key)))?;
+            Some((kid, stored))
+        })
+        .ok_or_else(|| Error::Term(Box::new(atoms::no_matching_key())))?;
+
+    let decrypted = open_body(
+        env,
+        body,
+        envelope_kid,
+        priv_key,
+        allowed.as_slice(),
+        None,
+        None,
+        crypto::Accept::default(),
+    )?;
+    priv_key.uses.record();
+    Ok((
+        atoms::ok(),
+        (into_binary(env, decrypted.plaintext), kid, decrypted.sender),
+    ))
+}
+
+/// Decrypt a wire body under one recipient key
+/// A failure is reported to the log target as signature_invalid or decrypt_failed,
+/// except for an expired wire, which is not one
+#[allow(clippy::too_many_arguments)]
+fn open_body(
+    env: Env,
+    body: &[u8],
+    kid: &str,
+    priv_key: &keys::RecipientPriv,
+    allowed: &(impl crypto::AllowedSenders + ?Sized),
+    aad: Option<crypto::Aad>,
+    expiry: Option<crypto::Expiry>,
+    accept: crypto::Accept,
+) -> NifResult<crypto::Decrypted> {
+    let decrypted = catch_panic(|| {
+        Ok(crypto::decrypt(
+            body,
+            kid,
+            priv_key,
+            allowed,
+            aad.unwrap_or_default(),
+            expiry,
+            accept,
+        ))
+    })?;
+    decrypted.map_err(|e| {
+        let event = match e {
+            tholos_pq::TholosError::Malformed(detail) if detail == crypto::EXPIRED => {
+                return Error::Term(Box::new(atoms::expired()));
+            }
+            tholos_pq::TholosError::Malformed(detail) if detail == crypto::NOW_REQUIRED => {
+                return Error::Term(Box::new(atoms::now_required()));
+            }
+            tholos_pq::TholosError::BadSignature => atoms::signature_invalid(),
+            _ => atoms::decrypt_failed(),
+        };
+        let error = match e {
+            // With the signature verified, an AEAD failure under the stored key means
+            // the associated data differs from what was used at encryption time
+            tholos_pq::TholosError::Aead if aad.is_some() => {
+                Error::Term(Box::new(atoms::aad_mismatch()))
+            }
+            e => decrypt_error(e),
+        };
+        if let Error::Term(reason) = &error {
+            emit_event(
+                env,
+                event,
+                &[
+                    (atoms::kid(), kid.encode(env)),
+                    (atoms::reason(), reason.encode(env)),
+                ],
+            );
+        }
+        error
+    })
+}
+
+/// Decrypt a batch of messages for one recipient
+/// The allow-list is deserialized and the key store locked once for the whole batch,
+/// and each wire gets its own {ok, plaintext} or {error, reason}
+/// Returns {ok, results} in the order of `wires`
+#[rustler::nif(schedule = "DirtyCpu")]
+fn decrypt_many<'a>(
+    env: Env<'a>,
+    wires: Vec<Binary>,
+    recipient: Term<'a>,
+    allowed_sender_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, Vec<Term<'a>>)> {
+    let allowed = decode_allowed_senders(&allowed_sender_pub_keys)?;
+
+    let results: Vec<NifResult<crypto::Decrypted>> =
+        if let Ok(resource) = recipient.decode::<ResourceArc<RecipientKeyResource>>() {
+            wires
+                .iter()
+                .map(|wire| {
+                    let body = wire_body(wire.as_slice())?;
+                    open_body(
+                        env,
+                        body,
+                        &resource.pub_key.kid,
+                        &resource.priv_key,
+                        allowed.as_slice(),
+                        None,
+                        None,
+                        crypto::Accept::default(),
+                    )
+                })
+                .collect()
+        } else {
+            let kid: String = recipient.decode()?;
+            check_identifier(&kid)?;
+            let recipient_keys = read_store(&RECIPIENT_KEYS);
+            wires
+                .iter()
+                .map(|wire| {
+                    let body = wire_body(wire.as_slice())?;
+                    let kid = stored_version(&recipient_keys, kid.clone(), body);
+                    let (_, priv_key) = recipient_keys
+                        .get(&kid)
+                        .ok_or_else(|| error_term(atoms::recipient_not_found(), &kid))?;
+                    let decrypted = open_body(
+                        env,
+                        body,
+                        &kid,
+                        priv_key,
+                        allowed.as_slice(),
+                        None,
+                        None,
+                        crypto::Accept::default(),
+                    )?;
+                    priv_key.uses.record();
+                    Ok(decrypted)
+                })
+                .collect()
+        };
+
+    let results = results
+        .into_iter()
+        .map(|result| match result {
+            Ok(decrypted) => (atoms::ok(), into_binary(env, decrypted.plaintext)).encode(env),
+            Err(e) => result_error(env, e),
+        })
+        .collect();
+    Ok((atoms::ok(), results))
+}
+
+/// The term `e` would have been returned as from a NIF of its own
+fn result_error(env: Env, e: Error) -> Term {
+    match e {
+        Error::Term(reason) => (atoms::error(), reason.encode(env)).encode(env),
+        Error::Atom(atom) => rustler::Atom::from_str(env, atom)
+            .map(|atom| atom.encode(env))
+            .unwrap_or_else(|_| atoms::error().encode(env)),
+        _ => (atoms::error(), rustler::types::atom::badarg()).encode(env),
+    }
+}
+
+/// Export a stored recipient private key
+/// Returns {ok, private_key_cbor}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn export_recipient_key<'a>(env: Env<'a>, kid: String) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let recipient_keys = read_store(&RECIPIENT_KEYS);
+    let (_, priv_key) = recipient_keys
+        .get(&kid)
+        .ok_or_else(|| error_term(atoms::recipient_not_found(), &kid))?;
+
+    let priv_bytes = cbor::to_vec(&keys::RecipientPrivCbor::from_priv(priv_key))
+        .map(Zeroizing::new)
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+
+    Ok((atoms::ok(), to_binary(env, &priv_bytes)?))
+}
+
+/// The public key of a stored recipient key, rederived from its private key
+/// Returns {ok, public_key_cbor}
+#[rustler::nif]
+fn recipient_pub_from_priv<'a>(
+    env: Env<'a>,
+    kid: String,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let recipient_keys = read_store(&RECIPIENT_KEYS);
+    let (_, priv_key) = recipient_keys
+        .get(&kid)
+        .ok_or_else(|| error_term(atoms::recipient_not_found(), &kid))?;
+
+    let pub_bytes = cbor::canonical(&keys::recipient_pub(priv_key))
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+
+    Ok((atoms::ok(), to_binary(env, &pub_bytes)?))
+}
+
+/// The public key of a stored sender keypair, taken from its signing keys
+/// Returns {ok, public_key_cbor}
+#[rustler::nif]
+fn sender_pub_from_priv<'a>(env: Env<'a>, sid: String) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let sender_keys = read_store(&SENDER_KEYS);
+    let sender = sender_keys
+        .get(&sid)
+        .ok_or_else(|| error_term(atoms::sender_not_found(), &sid))?;
+
+    let pub_bytes = cbor::canonical(&sender.public())
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+
+    Ok((atoms::ok(), to_binary(env, &pub_bytes)?))
+}
+
+/// Export a stored recipient private key sealed under a password
+/// The key is derived with Argon2id; the blob records the parameters and salt
+/// Returns {ok, encrypted_blob}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn export_recipient_key_encrypted<'a>(
+    env: Env<'a>,
+    kid: String,
+    password: Binary,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let priv_bytes = {
+        let recipient_keys = read_store(&RECIPIENT_KEYS);
+        let (_, priv_key) = recipient_keys
+            .get(&kid)
+            .ok_or_else(|| error_term(atoms::recipient_not_found(), &kid))?;
+
+        cbor::to_vec(&keys::RecipientPrivCbor::from_priv(priv_key))
+            .map(Zeroizing::new)
+            .map_err(|e| error_term(atoms::serialization_failed(), e))?
+    };
+
+    // The KDF is slow by design, so it runs without holding the store lock
+    let blob = password::seal(password.as_slice(), &priv_bytes)
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+
+    Ok((atoms::ok(), to_binary(env, &blob)?))
+}
+
+/// Open a blob from export_recipient_key_encrypted and store the key under kid
+/// Returns {ok, {kid, public_key_cbor}}, or {error, bad_password}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn import_recipient_key_encrypted<'a>(
+    env: Env<'a>,
+    kid: String,
+    blob: Binary,
+    password: Binary,
+) -> NifResult<(rustler::Atom, (String, Binary<'a>))> {
+    check_new_kid(&kid)?;
+    let priv_bytes = password::open(password.as_slice(), blob.as_slice()).map_err(|e| match e {
+        password::OpenError::BadPassword => Error::Term(Box::new(atoms::bad_password())),
+        password::OpenError::Malformed(detail) => error_term(atoms::invalid_private_key(), detail),
+    })?;
+
+    let mut priv_key = cbor::from_slice::<keys::RecipientPrivCbor>(&priv_bytes)
+        .map_err(|e| e.to_string())
+        .and_then(keys::RecipientPrivCbor::into_priv)
+        .map_err(|e| error_term(atoms::invalid_private_key(), e))?;
+    priv_key.kid = kid.clone();
+    let pub_key = keys::recipient_pub(&priv_key);
+
+    // Serialize public key to CBOR
+    let pub_bytes =
+        cbor::canonical(&pub_key).map_err(|e| error_term(atoms::serialization_failed(), e))?;
+
+    // Store the keys
+    write_store(&RECIPIENT_KEYS).insert(kid.clone(), (pub_key, priv_key));
+    emit_event(env, atoms::key_loaded(), &[(atoms::kid(), kid.encode(env))]);
+
+    Ok((atoms::ok(), (kid, to_binary(env, &pub_bytes)?)))
+}
+
+/// Store a recipient keypair from its public key and the private key from
+/// export_recipient_key, replacing any keypair stored under kid
+/// The halves are checked to belong together with a test encapsulation
+/// Returns ok, {error, key_mismatch} or {error, {kid_mismatch, kid, key_kid}}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn load_recipient_keypair(
+    env: Env,
+    kid: String,
+    public_key: Binary,
+    private_key: Binary,
+) -> NifResult<rustler::Atom> {
+    check_new_kid(&kid)?;
+    let pub_key = decode_recipient_pubs(&[public_key])?.remove(0);
+    if pub_key.kid != kid {
+        return Err(Error::Term(Box::new((
+            atoms::kid_mismatch(),
+            kid,
+            pub_key.kid,
+        ))));
+    }
+    check_recipient_keys([&pub_key])?;
+
+    let mut priv_key = cbor::from_slice::<keys::RecipientPrivCbor>(private_key.as_slice())
+        .map_err(|e| e.to_string())
+        .and_then(keys::RecipientPrivCbor::into_priv)
+        .map_err(|e| error_term(atoms::invalid_private_key(), e))?;
+    priv_key.kid = kid.clone();
+
+    if !catch_panic(|| Ok(keys::recipient_pair_matches(&pub_key, &priv_key)))? {
+        return Err(Error::Term(Box::new(atoms::key_mismatch())));
+    }
+
+    write_store(&RECIPIENT_KEYS).insert(kid.clone(), (pub_key, priv_key));
+    emit_event(env, atoms::key_loaded(), &[(atoms::kid(), kid.encode(env))]);
+    Ok(atoms::ok())
+}
+
+/// Store a sender keypair from its public key and the keypair from
+/// export_sender_keypair, replacing any keypair stored under sid
+/// The halves are checked to belong together with a test signature, and both
+/// must name sid
+/// Returns ok, {error, key_mismatch} or {error, {sid_mismatch, sid, key_sid}}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn load_sender_keypair(
+    env: Env,
+    sid: String,
+    public_key: Binary,
+    private_key: Binary,
+) -> NifResult<rustler::Atom> {
+    check_identifier(&sid)?;
+    let pub_key: tholos_pq::SenderPub = cbor::from_slice(public_key.as_slice())
+        .map_err(|e| error_term(atoms::deserialize_sender(), e))?;
+    if pub_key.sid != sid {
+        return Err(Error::Term(Box::new((
+            atoms::sid_mismatch(),
+            sid,
+            pub_key.sid,
+        ))));
+    }
+
+    let keypair = cbor::from_slice::<keys::SenderKeypairCbor>(private_key.as_slice())
+        .map_err(|e| e.to_string())
+        .and_then(keys::SenderKeypairCbor::into_keypair)
+        .map_err(|e| error_term(atoms::invalid_private_key(), e))?;
+    if keypair.sid != sid {
+        return Err(Error::Term(Box::new((
+            atoms::sid_mismatch(),
+            sid,
+            keypair.sid.clone(),
+        ))));
+    }
+
+    if !catch_panic(|| Ok(keys::sender_pair_matches(&pub_key, &keypair)))? {
+        return Err(Error::Term(Box::new(atoms::key_mismatch())));
+    }
+
+    write_store(&SENDER_KEYS).insert(sid.clone(), keypair);
+    emit_event(env, atoms::key_loaded(), &[(atoms::sid(), sid.encode(env))]);
+    Ok(atoms::ok())
+}
+
+/// Export every stored recipient and sender keypair, secrets included, as one
+/// blob sealed under a password like export_recipient_key_encrypted
+/// Returns {ok, encrypted_blob}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn export_store<'a>(env: Env<'a>, password: Binary) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let contents = {
+        let recipient_keys = read_store(&RECIPIENT_KEYS);
+        let sender_keys = read_store(&SENDER_KEYS);
+        let recipients: Vec<_> = recipient_keys
+            .values()
+            .map(|(_, priv_key)| keys::RecipientPrivCbor::from_priv(priv_key))
+            .collect();
+        let senders: Vec<_> = sender_keys
+            .values()
+            .map(keys::SenderKeypairCbor::from_keypair)
+            .collect();
+
+        store::encode(&recipients, &senders)
+            .map_err(|e| error_term(atoms::serialization_failed(), e))?
+    };
+
+    // The KDF is slow by design, so it runs without holding the store locks
+    let blob = password::seal(password.as_slice(), &contents)
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+
+    Ok((atoms::ok(), to_binary(env, &blob)?))
+}
+
+/// Load every keypair from an export_store blob into the store
+/// Keys already stored under the same ids are replaced and other keys are kept
+/// Nothing is imported unless the whole blob parses and every kid and sid can be
+/// stored, a kid ending in `#v<digits>` only as a version of another kid in the blob
+/// Returns {ok, {recipient_count, sender_count}}, {error, bad_password},
+/// {error, corrupt_store} or {error, {invalid_identifier, reason}}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn import_store(
+    env: Env,
+    blob: Binary,
+    password: Binary,
+) -> NifResult<(rustler::Atom, (usize, usize))> {
+    let contents = password::open(password.as_slice(), blob.as_slice()).map_err(|e| match e {
+        password::OpenError::BadPassword => Error::Term(Box::new(atoms::bad_password())),
+        password::OpenError::Malformed(_) => Error::Term(Box::new(atoms::corrupt_store())),
+    })?;
+    let (recipients, senders) =
+        store::decode(&contents).map_err(|_| Error::Term(Box::new(atoms::corrupt_store())))?;
+    let counts = (recipients.len(), senders.len());
+    let kids: std::collections::HashSet<&str> = recipients
+        .iter()
+        .map(|priv_key| priv_key.kid.as_str())
+        .collect();
+    for priv_key in &recipients {
+        keys::check_imported_kid(&priv_key.kid, &kids)
+            .map_err(|reason| error_term(atoms::invalid_identifier(), reason))?;
+    }
+    for sender in &senders {
+        check_identifier(&sender.sid)?;
+    }
+
+    // Both maps are locked in the same order as export_store
+    let mut recipient_keys = write_store(&RECIPIENT_KEYS);
+    let mut sender_keys = write_store(&SENDER_KEYS);
+    for priv_key in recipients {
+        emit_event(
+            env,
+            atoms::key_loaded(),
+            &[(atoms::kid(), priv_key.kid.encode(env))],
+        );
+        recipient_keys.insert(
+            priv_key.kid.clone(),
+            (keys::recipient_pub(&priv_key), priv_key),
+        );
+    }
+    for sender in senders {
+        emit_event(
+            env,
+            atoms::key_loaded(),
+            &[(atoms::sid(), sender.sid.encode(env))],
+        );
+        sender_keys.insert(sender.sid.clone(), sender);
+    }
+
+    Ok((atoms::ok(), counts))
+}
+
+/// Decrypt a message with a recipient private key supplied by the caller
+/// Returns {ok, plaintext}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn decrypt_with_key<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    kid: String,
+    recipient_priv: Binary,
+    allowed_sender_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    check_identifier(&kid)?;
+    // Deserialize the recipient private key
+    let priv_key = cbor::from_slice::<keys::RecipientPrivCbor>(recipient_priv.as_slice())
+        .map_err(|e| e.to_string())
+        .and_then(keys::RecipientPrivCbor::into_priv)
+        .map_err(|e| error_term(atoms::invalid_private_key(), e))?;
+
+    // Deserialize allowed sender public keys and build allowed list
+    let allowed = decode_allowed_senders(&allowed_sender_pub_keys)?;
+
+    // Decrypt
+    let body = wire_body(wire.as_slice())?;
+    let decrypted = open_body(
+        env,
+        body,
+        &kid,
+        &priv_key,
+        allowed.as_slice(),
+        None,
+        None,
+        crypto::Accept::default(),
+    )?;
+
+    Ok((atoms::ok(), into_binary(env, decrypted.plaintext)))
+}
+
+/// Remove a recipient keypair from the store
+/// The ML-KEM decapsulation key is zeroized when it is dropped
+/// Returns ok, or {error, not_found} if the kid was never stored
+#[rustler::nif]
+fn delete_recipient_key(kid: String) -> NifResult<rustler::Atom> {
+    match write_store(&RECIPIENT_KEYS).remove(&kid) {
+        Some(keys) => {
+            drop(keys);
+            Ok(atoms::ok())
+        }
+        None => Err(Error::Term(Box::new(atoms::not_found()))),
+    }
+}
+
+/// Remove a sender keypair from the store
+/// The Dilithium secret key is zeroized when it is dropped
+/// Returns ok, or {error, not_found} if the sid was never stored
+#[rustler::nif]
+fn delete_sender_key(sid: String) -> NifResult<rustler::Atom> {
+    match write_store(&SENDER_KEYS).remove(&sid) {
+        Some(keypair) => {
+            drop(keypair);
+            Ok(atoms::ok())
+        }
+        None => Err(Error::Term(Box::new(atoms::not_found()))),
+    }
+}
+
+/// List the kids of all stored recipient keypairs
+/// Returns {ok, [kid]} sorted
+#[rustler::nif]
+fn list_recipient_kids() -> (rustler::Atom, Vec<String>) {
+    let mut kids: Vec<String> = read_store(&RECIPIENT_KEYS).keys().cloned().collect();
+    kids.sort();
+    (atoms::ok(), kids)
+}
+
+/// List the sids of all stored sender keypairs
+/// Returns {ok, [sid]} sorted
+#[rustler::nif]
+fn list_sender_sids() -> (rustler::Atom, Vec<String>) {
+    let mut sids: Vec<String> = read_store(&SENDER_KEYS).keys().cloned().collect();
+    sids.sort();
+    (atoms::ok(), sids)
+}
+
+/// How many messages the recipient key stored under `kid` has decrypted
+/// Counts every successful decrypt with the kid, not with a key reference, and
+/// starts from zero again when the key is deleted or replaced; a rotated kid
+/// counts each version under its own versioned kid
+/// Returns {ok, count}, or {error, {recipient_not_found, kid}}
+#[rustler::nif]
+fn recipient_key_usage(kid: String) -> NifResult<(rustler::Atom, u64)> {
+    let recipient_keys = read_store(&RECIPIENT_KEYS);
+    let (_, priv_key) = recipient_keys
+        .get(&kid)
+        .ok_or_else(|| error_term(atoms::recipient_not_found(), &kid))?;
+    Ok((atoms::ok(), priv_key.uses.count()))
+}
+
+/// Upgrade the recipient key stored under `kid` to the current key format, in
+/// place, e.g. after loading a key exported by an older release
+/// Returns {ok, migrated}, {ok, already_current} or {error, {recipient_not_found, kid}}
+#[rustler::nif]
+fn migrate_recipient_key(kid: String) -> NifResult<(rustler::Atom, rustler::Atom)> {
+    let mut recipient_keys = write_store(&RECIPIENT_KEYS);
+    let (_, priv_key) = recipient_keys
+        .get_mut(&kid)
+        .ok_or_else(|| error_term(atoms::recipient_not_found(), &kid))?;
+    let status = if keys::migrate(priv_key) {
+        atoms::migrated()
+    } else {
+        atoms::already_current()
+    };
+    Ok((atoms::ok(), status))
+}
+
+/// Whether a recipient keypair is stored under `kid`, under a shared lock
+/// Returns true or false
+#[rustler::nif]
+fn has_recipient_key(kid: String) -> bool {
+    read_store(&RECIPIENT_KEYS).contains_key(&kid)
+}
+
+/// Whether a sender keypair is stored under `sid`, under a shared lock
+/// Returns true or false
+#[rustler::nif]
+fn has_sender_key(sid: String) -> bool {
+    read_store(&SENDER_KEYS).contains_key(&sid)
+}
+
+/// Remove every keypair from both stores
+/// Each secret key is zeroized as its entry is dropped, as for `delete_recipient_key`
+/// and `delete_sender_key`
+/// Returns ok
+#[rustler::nif]
+fn clear_all_keys() -> rustler::Atom {
+    // Both maps are locked in the same order as export_store
+    let mut recipient_keys = write_store(&RECIPIENT_KEYS);
+    let mut sender_keys = write_store(&SENDER_KEYS);
+    recipient_keys.clear();
+    sender_keys.clear();
+    atoms::ok()
+}
+
+/// Remove every stored keypair whose expiry, set by the ttl_seconds keygen
+/// option, is at or before `now` (Unix seconds), in one pass under both locks
+/// Each secret key is zeroized as its entry is dropped, as for `clear_all_keys`
+/// Returns {ok, {recipients_removed, senders_removed}}
+#[rustler::nif]
+fn sweep_expired(now: u64) -> (rustler::Atom, (usize, usize)) {
+    let expired = |expires_at: Option<u64>| expires_at.is_some_and(|at| at <= now);
+
+    // Both maps are locked in the same order as export_store
+    let mut recipient_keys = write_store(&RECIPIENT_KEYS);
+    let mut sender_keys = write_store(&SENDER_KEYS);
+    let (recipients, senders) = (recipient_keys.len(), sender_keys.len());
+    recipient_keys.retain(|_, (_, priv_key)| !expired(priv_key.expires_at));
+    sender_keys.retain(|_, sender| !expired(sender.expires_at));
+
+    (
+        atoms::ok(),
+        (
+            recipients - recipient_keys.len(),
+            senders - sender_keys.len(),
+        ),
+    )
+}
+
+/// What `key_store_stats` reports about the stores.
+#[derive(rustler::NifMap)]
+struct KeyStoreStats {
+    recipients: usize,
+    senders: usize,
+    approx_bytes: usize,
+}
+
+/// Count the stored keypairs and estimate the memory they hold: the inline size
+/// of every entry plus the heap bytes of its ids and public key, without the
+/// overhead of the maps themselves
+/// Returns {ok, %{recipients, senders, approx_bytes}}
+#[rustler::nif]
+fn key_store_stats() -> (rustler::Atom, KeyStoreStats) {
+    let recipient_keys = read_store(&RECIPIENT_KEYS);
+    let sender_keys = read_store(&SENDER_KEYS);
+
+    let recipient_bytes: usize = recipient_keys
+        .iter()
+        .map(|(kid, (pub_key, priv_key))| {
+            std::mem::size_of::<(String, (tholos_pq::RecipientPub, keys::RecipientPriv))>()
+                + kid.len()
+                + pub_key.kid.len()
+                + pub_key.pk_kyber.len()
+                + priv_key.kid.len()
+        })
+        .sum();
+    let sender_bytes: usize = sender_keys
+        .iter()
+        .map(|(sid, keypair)| {
+            std::mem::size_of::<(String, keys::SenderKey)>() + sid.len() + keypair.sid.len()
+        })
+        .sum();
+
+    (
+        atoms::ok(),
+        KeyStoreStats {
+            recipients: recipient_keys.len(),
+            senders: sender_keys.len(),
+            approx_bytes: recipient_bytes + sender_bytes,
+        },
+    )
+}
+
+/// Send {tholos_event, event, metadata} messages to pid on notable events from
+/// now on, or to no process with nil
+/// Events are key_loaded with a kid or sid, and decrypt_failed or
+/// signature_invalid with the kid and the error reason
+/// Returns ok
+#[rustler::nif]
+fn set_log_target(pid: Option<LocalPid>) -> rustler::Atom {
+    *write_store(&LOG_TARGET) = pid;
+    atoms::ok()
+}
+
+/// Give every wire from encrypt an envelope for this recipient as well, replacing
+/// any escrow recipient set before
+/// Returns ok, {error, {deserialize_recipient, detail}} or {error, invalid_recipient_key}
+#[rustler::nif]
+fn set_escrow_recipient(pub_cbor: Binary) -> NifResult<rustler::Atom> {
+    let escrow = decode_recipient_pubs(&[pub_cbor])?.remove(0);
+    check_recipient_keys([&escrow])?;
+    *write_store(&ESCROW_RECIPIENT) = Some(escrow);
+    Ok(atoms::ok())
+}
+
+/// Stop adding an escrow envelope to new wires
+/// Returns ok, also if no escrow recipient was set
+#[rustler::nif]
+fn clear_escrow_recipient() -> rustler::Atom {
+    *write_store(&ESCROW_RECIPIENT) = None;
+    atoms::ok()
+}
+
+/// Returns {ok, kid} of the escrow recipient, or {ok, none}
+#[rustler::nif]
+fn escrow_recipient<'a>(env: Env<'a>) -> (rustler::Atom, Term<'a>) {
+    let kid = match &*read_store(&ESCROW_RECIPIENT) {
+        Some(escrow) => escrow.kid.encode(env),
+        None => atoms::none().encode(env),
+    };
+    (atoms::ok(), kid)
+}
+
+/// List the kids a wire was encrypted for, read from its recipient envelopes
+/// The envelope of an escrow recipient is listed as {escrow, kid}
+/// No private key is needed and the signature is not checked
+/// Returns {ok, [kid]} in wire order, {error, malformed_wire} or {error, {unsupported_wire_version, n}}
+#[rustler::nif]
+fn wire_recipients<'a>(env: Env<'a>, wire: Binary) -> NifResult<(rustler::Atom, Vec<Term<'a>>)> {
+    let recipients = wire::recipients(view_body(wire.as_slice())?)
+        .map_err(|_| Error::Term(Box::new(atoms::malformed_wire())))?;
+    Ok((
+        atoms::ok(),
+        recipients
+            .into_iter()
+            .map(|(kid, is_escrow)| {
+                if is_escrow {
+                    (atoms::escrow(), kid).encode(env)
+                } else {
+                    kid.encode(env)
+                }
+            })
+            .collect(),
+    ))
+}
+
+/// Read the sender sid claimed by a wire header without verifying the signature
+/// The sid is UNVERIFIED until decrypt succeeds; use it only to pre-filter
+/// Returns {ok, sid}, {error, malformed_wire} or {error, {unsupported_wire_version, n}}
+#[rustler::nif]
+fn wire_sender_unverified(wire: Binary) -> NifResult<(rustler::Atom, String)> {
+    wire::claimed_sender(view_body(wire.as_slice())?)
+        .map(|sid| (atoms::ok(), sid))
+        .map_err(|_| Error::Term(Box::new(atoms::malformed_wire())))
+}
+
+/// Read the message id from a wire header without decrypting it, for replay caches
+/// The id is only as trustworthy as the wire until decrypt succeeds on it
+/// Returns {ok, message_id}, {error, malformed_wire} or {error, {unsupported_wire_version, n}}
+#[rustler::nif]
+fn wire_message_id(wire: Binary) -> NifResult<(rustler::Atom, String)> {
+    wire::message_id(view_body(wire.as_slice())?)
+        .map(|id| (atoms::ok(), id))
+        .map_err(|_| Error::Term(Box::new(atoms::malformed_wire())))
+}
+
+/// Read the public metadata from a wire header without decrypting it
+/// Like the message id it is only as trustworthy as the wire until decrypt succeeds on it
+/// Returns {ok, metadata}, empty if there is none, {error, malformed_wire} or
+/// {error, {unsupported_wire_version, n}}
+#[rustler::nif]
+fn wire_metadata<'a>(env: Env<'a>, wire: Binary) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    wire::metadata(view_body(wire.as_slice())?)
+        .map(|metadata| (atoms::ok(), into_binary(env, metadata)))
+        .map_err(|_| Error::Term(Box::new(atoms::malformed_wire())))
+}
+
+/// Read the format version of a wire, 0 for wires without a version byte
+/// Returns {ok, version}, also for versions this build cannot decrypt, or {error, malformed_wire}
+#[rustler::nif]
+fn wire_version(wire: Binary) -> NifResult<(rustler::Atom, u8)> {
+    wire::version(wire.as_slice())
+        .map(|v| (atoms::ok(), v))
+        .ok_or_else(|| Error::Term(Box::new(atoms::malformed_wire())))
+}
+
+/// Prefix a wire with its length as a 4-byte big-endian integer, for transports
+/// that concatenate wires
+/// Returns {ok, framed}, or {error, frame_too_large} beyond 4 GiB
+#[rustler::nif(schedule = "DirtyCpu")]
+fn frame<'a>(env: Env<'a>, wire: Binary) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let framed = framing::frame(wire.as_slice())
+        .ok_or_else(|| Error::Term(Box::new(atoms::frame_too_large())))?;
+    Ok((atoms::ok(), into_binary(env, framed)))
+}
+
+/// Split the first frame off a buffer; both parts are sub-binaries of it
+/// Returns {ok, {wire, rest}}, or {error, incomplete} until the whole frame has arrived
+#[rustler::nif]
+fn unframe<'a>(buffer: Binary<'a>) -> NifResult<(rustler::Atom, (Binary<'a>, Binary<'a>))> {
+    let len = framing::first_frame_len(buffer.as_slice())
+        .ok_or_else(|| Error::Term(Box::new(atoms::incomplete())))?;
+    let end = framing::PREFIX_LEN + len;
+    let wire = buffer.make_subbinary(framing::PREFIX_LEN, len)?;
+    let rest = buffer.make_subbinary(end, buffer.len() - end)?;
+    Ok((atoms::ok(), (wire, rest)))
+}
+
+/// Sign a message with a sender key, outside of any encrypted bundle
+/// The sender is either a sender id in the global store or a sender key resource
+/// Returns {ok, signature_cbor}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn sign<'a>(
+    env: Env<'a>,
+    sender: Term<'a>,
+    message: Binary,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let signature = with_sender(sender, |sender| {
+        catch_panic(|| Ok(sign::sign(sender, message.as_slice())))
+    })?;
+
+    let sig_bytes =
+        cbor::to_vec(&signature).map_err(|e| error_term(atoms::serialization_failed(), e))?;
+
+    Ok((atoms::ok(), to_binary(env, &sig_bytes)?))
+}
+
+/// Verify a signature made by sign against a sender public key
+/// Needs no key from the store, so it also works on nodes holding no secret keys
+/// Returns {ok, valid} or {ok, invalid}, or {error, malformed_signature} if the
+/// signature is not structurally a signature
+#[rustler::nif(schedule = "DirtyCpu")]
+fn verify(
+    sender_pub_key: Binary,
+    message: Binary,
+    signature: Binary,
+) -> NifResult<(rustler::Atom, rustler::Atom)> {
+    let sender_pub: tholos_pq::SenderPub = cbor::from_slice(sender_pub_key.as_slice())
+        .map_err(|e| error_term(atoms::deserialize_sender(), e))?;
+    let signature: sign::SignatureCbor = cbor::from_slice(signature.as_slice())
+        .map_err(|_| Error::Term(Box::new(atoms::malformed_signature())))?;
+
+    verify_result(catch_panic(|| {
+        Ok(sign::verify(&sender_pub, message.as_slice(), &signature))
+    })?)
+}
+
+fn verify_result(
+    result: Result<bool, sign::VerifyError>,
+) -> NifResult<(rustler::Atom, rustler::Atom)> {
+    match result {
+        Ok(true) => Ok((atoms::ok(), atoms::valid())),
+        Ok(false) => Ok((atoms::ok(), atoms::invalid())),
+        Err(sign::VerifyError::PublicKey) => Err(error_term(
+            atoms::deserialize_sender(),
+            "not a Dilithium public key",
+        )),
+        Err(sign::VerifyError::Signature) => {
+            Err(Error::Term(Box::new(atoms::malformed_signature())))
+        }
+        Err(sign::VerifyError::NotPrehashed) => Err(Error::Term(Box::new(atoms::not_prehashed()))),
+    }
+}
+
+/// Start signing a message fed in pieces, for messages too large to buffer
+/// The sender keypair is copied into the stream
+/// Returns {ok, stream}
+#[rustler::nif]
+fn sign_init(sender: Term) -> NifResult<(rustler::Atom, ResourceArc<SignStreamResource>)> {
+    let sender = with_sender(sender, |sender| Ok(sender.clone()))?;
+
+    Ok((
+        atoms::ok(),
+        ResourceArc::new(SignStreamResource(Mutex::new(Some(sign::Signer::new(
+            sender,
+        ))))),
+    ))
+}
+
+/// Hash the next piece of a message being signed
+/// Returns ok
+#[rustler::nif(schedule = "DirtyCpu")]
+fn sign_update(stream: ResourceArc<SignStreamResource>, chunk: Binary) -> NifResult<rustler::Atom> {
+    stream_state(&stream.0)
+        .as_mut()
+        .ok_or_else(|| Error::Term(Box::new(atoms::stream_closed())))?
+        .update(chunk.as_slice());
+    Ok(atoms::ok())
+}
+
+/// Sign the SHA-512 of everything fed to the stream, closing it
+/// Returns {ok, signature_cbor} with a prehashed signature
+#[rustler::nif(schedule = "DirtyCpu")]
+fn sign_final<'a>(
+    env: Env<'a>,
+    stream: ResourceArc<SignStreamResource>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let signer = stream_state(&stream.0)
+        .take()
+        .ok_or_else(|| Error::Term(Box::new(atoms::stream_closed())))?;
+
+    let signature = catch_panic(|| Ok(signer.finish()))?;
+    let sig_bytes =
+        cbor::to_vec(&signature).map_err(|e| error_term(atoms::serialization_failed(), e))?;
+
+    Ok((atoms::ok(), to_binary(env, &sig_bytes)?))
+}
+
+/// Start verifying a message fed in pieces against a sender public key
+/// Returns {ok, stream}
+#[rustler::nif]
+fn verify_init(
+    sender_pub_key: Binary,
+) -> NifResult<(rustler::Atom, ResourceArc<VerifyStreamResource>)> {
+    let sender_pub: tholos_pq::SenderPub = cbor::from_slice(sender_pub_key.as_slice())
+        .map_err(|e| error_term(atoms::deserialize_sender(), e))?;
+
+    Ok((
+        atoms::ok(),
+        ResourceArc::new(VerifyStreamResource(Mutex::new(Some(sign::Verifier::new(
+            sender_pub,
+        ))))),
+    ))
+}
+
+/// Hash the next piece of a message being verified
+/// Returns ok
+#[rustler::nif(schedule = "DirtyCpu")]
+fn verify_update(
+    stream: ResourceArc<VerifyStreamResource>,
+    chunk: Binary,
+) -> NifResult<rustler::Atom> {
+    stream_state(&stream.0)
+        .as_mut()
+        .ok_or_else(|| Error::Term(Box::new(atoms::stream_closed())))?
+        .update(chunk.as_slice());
+    Ok(atoms::ok())
+}
+
+/// Verify a prehashed signature over everything fed to the stream, closing it
+/// Returns {ok, valid} or {ok, invalid}, or {error, not_prehashed} for a
+/// signature made by sign rather than sign_final
+#[rustler::nif(schedule = "DirtyCpu")]
+fn verify_final(
+    stream: ResourceArc<VerifyStreamResource>,
+    signature: Binary,
+) -> NifResult<(rustler::Atom, rustler::Atom)> {
+    let signature: sign::SignatureCbor = cbor::from_slice(signature.as_slice())
+        .map_err(|_| Error::Term(Box::new(atoms::malformed_signature())))?;
+    let verifier = stream_state(&stream.0)
+        .take()
+        .ok_or_else(|| Error::Term(Box::new(atoms::stream_closed())))?;
+
+    verify_result(catch_panic(|| Ok(verifier.finish(&signature)))?)
+}
+
+/// Check that a wire was signed by an allowed sender without decapsulating or
+/// decrypting anything, so it needs no recipient key
+/// Returns {ok, {valid, sender_sid}} or {ok, invalid_signature}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn validate_wire<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    allowed_sender_pub_keys: Vec<Binary>,
+) -> NifResult<Term<'a>> {
+    let allowed = decode_allowed_senders(&allowed_sender_pub_keys)?;
+    let body = wire_body(wire.as_slice())?;
+    match catch_panic(|| Ok(crypto::validate(body, allowed.as_slice())))? {
+        Ok(sid) => Ok((atoms::ok(), (atoms::valid(), sid)).encode(env)),
+        Err(tholos_pq::TholosError::BadSignature) => {
+            Ok((atoms::ok(), atoms::invalid_signature()).encode(env))
+        }
+        Err(e) => Err(decrypt_error(e)),
+    }
+}
+
+/// Upper bound on the size of the wire encrypt would produce, without encrypting
+/// Returns {ok, bytes}
+#[rustler::nif]
+fn estimate_wire_size(plaintext_len: u32, recipient_count: u32) -> (rustler::Atom, usize) {
+    wire_size(
+        plaintext_len,
+        recipient_count,
+        &options::EstimateOptions::default(),
+    )
+}
+
+/// Upper bound on the size of the wire encrypt would produce with options
+/// Returns {ok, bytes}
+#[rustler::nif(name = "estimate_wire_size")]
+fn estimate_wire_size_with_opts(
+    plaintext_len: u32,
+    recipient_count: u32,
+    opts: Term,
+) -> NifResult<(rustler::Atom, usize)> {
+    let opts = options::EstimateOptions::decode(opts)?;
+    Ok(wire_size(plaintext_len, recipient_count, &opts))
+}
+
+fn wire_size(
+    plaintext_len: u32,
+    recipient_count: u32,
+    opts: &options::EstimateOptions,
+) -> (rustler::Atom, usize) {
+    let bytes = size::estimate(&size::Estimate {
+        plaintext_len: plaintext_len as usize,
+        recipient_count: recipient_count as usize,
+        max_id_len: opts.max_id_length as usize,
+        compression: opts.compression != crypto::Compression::None,
+        embed_sender_key: opts.embed_sender_key,
+        recipient_level: opts.security_level.recipient_level(),
+        sender_level: opts.security_level.sender_level(),
+    });
+    (atoms::ok(), bytes)
+}
+
+/// What `crypto_info` reports about this build.
+#[derive(rustler::NifMap)]
+struct CryptoInfo {
+    kem: String,
+    sig: String,
+    wire_version: u8,
+    tholos_pq_version: String,
+}
+
+/// The algorithms default keys use, the wire version written and the
+/// `tholos_pq` version built against
+/// Returns {ok, %{kem, sig, wire_version, tholos_pq_version}}
+#[rustler::nif]
+fn crypto_info() -> (rustler::Atom, CryptoInfo) {
+    (
+        atoms::ok(),
+        CryptoInfo {
+            kem: level::Level::RECIPIENT_DEFAULT.kem_name().to_string(),
+            sig: level::Level::SENDER_DEFAULT.sig_name().to_string(),
+            wire_version: wire::VERSION,
+            tholos_pq_version: env!("THOLOS_PQ_VERSION").to_string(),
+        },
+    )
+}
+
+/// Payload AEADs this build can seal and open, strongest first
+const AEADS: [crypto::PayloadAead; 2] = [
+    crypto::PayloadAead::XChaCha20Poly1305,
+    crypto::PayloadAead::Aes256Gcm,
+];
+
+/// Security levels this build has keys for, strongest first
+const SECURITY_LEVELS: [level::Level; 3] = [level::Level::L5, level::Level::L3, level::Level::L1];
+
+fn aead_atom(aead: crypto::PayloadAead) -> rustler::Atom {
+    match aead {
+        crypto::PayloadAead::XChaCha20Poly1305 => atoms::xchacha20poly1305(),
+        crypto::PayloadAead::Aes256Gcm => atoms::aes256gcm(),
+    }
+}
+
+/// What `capabilities` reports, and what `choose_suite` takes from a peer.
+#[derive(rustler::NifMap)]
+struct Capabilities {
+    wire_versions: Vec<u8>,
+    aeads: Vec<rustler::Atom>,
+    security_levels: Vec<rustler::Atom>,
+}
+
+/// What `choose_suite` settles on.
+#[derive(rustler::NifMap)]
+struct Suite {
+    wire_version: u8,
+    aead: rustler::Atom,
+    security_level: rustler::Atom,
+}
+
+fn local_capabilities() -> Capabilities {
+    Capabilities {
+        wire_versions: vec![wire::VERSION],
+        aeads: AEADS.into_iter().map(aead_atom).collect(),
+        security_levels: SECURITY_LEVELS.into_iter().map(level_atom).collect(),
+    }
+}
+
+/// The wire versions, payload AEADs and security levels this build supports,
+/// each strongest first, for agreeing on a suite with a peer
+/// Returns {ok, %{wire_versions, aeads, security_levels}}
+#[rustler::nif]
+fn capabilities() -> (rustler::Atom, Capabilities) {
+    (atoms::ok(), local_capabilities())
+}
+
+/// Pick the strongest wire version, AEAD and security level that both this
+/// build and a peer's capabilities list
+/// Returns {ok, %{wire_version, aead, security_level}} or {error, no_common_suite}
+#[rustler::nif]
+fn choose_suite(peer: Capabilities) -> NifResult<(rustler::Atom, Suite)> {
+    common_suite(&local_capabilities(), &peer)
+        .map(|suite| (atoms::ok(), suite))
+        .ok_or_else(|| Error::Term(Box::new(atoms::no_common_suite())))
+}
+
+/// The first entry of each local list, strongest first, that `peer` lists too
+fn common_suite(local: &Capabilities, peer: &Capabilities) -> Option<Suite> {
+    fn strongest<T: Copy + PartialEq>(local: &[T], peer: &[T]) -> Option<T> {
+        local.iter().copied().find(|item| peer.contains(item))
+    }
+
+    Some(Suite {
+        wire_version: strongest(&local.wire_versions, &peer.wire_versions)?,
+        aead: strongest(&local.aeads, &peer.aeads)?,
+        security_level: strongest(&local.security_levels, &peer.security_levels)?,
+    })
+}
+
+/// Run the known-answer tests in `selftest` against the compiled primitives
+/// Returns ok or {error, {kat_failed, check}}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn self_test() -> NifResult<rustler::Atom> {
+    selftest::run().map_err(|check| {
+        let check = match check {
+            selftest::Check::Keygen => atoms::keygen(),
+            selftest::Check::Encapsulation => atoms::encapsulation(),
+            selftest::Check::Signing => atoms::signing(),
+            selftest::Check::RoundTrip => atoms::round_trip(),
+        };
+        Error::Term(Box::new((atoms::kat_failed(), check)))
+    })?;
+    Ok(atoms::ok())
+}
+
+/// What `benchmark` reports.
+#[derive(rustler::NifMap)]
+struct BenchmarkReport {
+    ops_per_sec: f64,
+    p50_us: u64,
+    p99_us: u64,
+}
+
+/// Time `op`, encrypt or decrypt, over `iterations` runs with fresh keys and a
+/// random payload, see `bench`
+/// A run over `bench::MAX_WORK` is refused as too many iterations
+/// Returns {ok, %{ops_per_sec, p50_us, p99_us}} or {error, {invalid_option, name}}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn benchmark(
+    op: rustler::Atom,
+    payload_size: usize,
+    recipients: usize,
+    iterations: usize,
+) -> NifResult<(rustler::Atom, BenchmarkReport)> {
+    let op = if op == atoms::encrypt() {
+        bench::Op::Encrypt
+    } else if op == atoms::decrypt() {
+        bench::Op::Decrypt
+    } else {
+        return Err(error_term(atoms::invalid_option(), "op"));
+    };
+    if payload_size > bench::MAX_PAYLOAD_SIZE {
+        return Err(error_term(atoms::invalid_option(), "payload_size"));
+    }
+    if recipients == 0 || recipients > options::DEFAULT_MAX_RECIPIENTS {
+        return Err(error_term(atoms::invalid_option(), "recipients"));
+    }
+    if iterations == 0
+        || iterations > bench::MAX_ITERATIONS
+        || bench::work(payload_size, recipients, iterations).is_none_or(|w| w > bench::MAX_WORK)
+    {
+        return Err(error_term(atoms::invalid_option(), "iterations"));
+    }
+
+    let report = catch_panic(|| {
+        bench::run(op, payload_size, recipients, iterations).map_err(|failed| match failed {
+            bench::Failed(bench::Op::Encrypt, e) => error_term(atoms::encrypt_failed(), e),
+            bench::Failed(bench::Op::Decrypt, e) => decrypt_error(e),
+        })
+    })?;
+    Ok((
+        atoms::ok(),
+        BenchmarkReport {
+            ops_per_sec: report.ops_per_sec,
+            p50_us: micros(report.p50),
+            p99_us: micros(report.p99),
+        },
+    ))
+}
+
+/// Test hook: drop a sender key in place and report whether its secret key
+/// bytes read back as zero
+#[cfg(feature = "test-hooks")]
+#[rustler::nif]
+fn sender_key_wiped_on_drop() -> bool {
+    keys::sender_key_wiped_on_drop()
+}
+
+/// Test hook: how many KEM decapsulations and AEAD decryptions have run, see
+/// `crypto::OPENS`
+#[cfg(feature = "test-hooks")]
+#[rustler::nif]
+fn decrypt_operations() -> u64 {
+    crypto::OPENS.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+/// Test hook: encrypt with all randomness derived from a 32-byte seed and the
+/// timestamp fixed, so the same inputs give a byte-identical wire
+/// Returns {ok, ciphertext}, or {error, bad_seed_length}
+#[cfg(feature = "deterministic-rng")]
+#[rustler::nif(schedule = "DirtyCpu")]
+fn encrypt_deterministic<'a>(
+    env: Env<'a>,
+    seed: Binary,
+    sender: Term<'a>,
+    message: Binary,
+    recipient_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let seed = Zeroizing::new(
+        <[u8; 32]>::try_from(seed.as_slice())
+            .map_err(|_| Error::Term(Box::new(atoms::bad_seed_length())))?,
+    );
+    check_recipient_count(recipient_pub_keys.len(), options::DEFAULT_MAX_RECIPIENTS)?;
+    let recipients = decode_recipient_pubs(&recipient_pub_keys)?;
+    check_recipient_keys(&recipients)?;
+    check_recipient_levels(&recipients)?;
+
+    let wire = with_sender(sender, |sender| {
+        catch_panic(|| {
+            crypto::encrypt_deterministic(*seed, message.as_slice(), sender, &recipients)
+                .map_err(|e| error_term(atoms::encrypt_failed(), e))
+        })
+    })?;
+
+    Ok((atoms::ok(), into_binary(env, wire)))
+}
+
+/// Export the wire test vectors in `vectors` for other implementations to check
+/// themselves against
+/// Returns {ok, vectors_json}
+#[cfg(feature = "test-vectors")]
+#[rustler::nif(schedule = "DirtyCpu")]
+fn export_test_vectors(env: Env) -> NifResult<(rustler::Atom, Binary)> {
+    let json =
+        catch_panic(|| vectors::export().map_err(|e| error_term(atoms::encrypt_failed(), e)))?;
+    Ok((atoms::ok(), into_binary(env, json.into_bytes())))
+}
+
+/// Test hook: encrypt with a key commitment that does not match the CEK, but a
+/// valid signature, as a sender equivocating between recipients might
+/// Returns {ok, ciphertext}
+#[cfg(feature = "test-hooks")]
+#[rustler::nif(schedule = "DirtyCpu")]
+fn encrypt_wrong_key_commitment<'a>(
+    env: Env<'a>,
+    message: Binary,
+    sender: Term<'a>,
+    recipient_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let recipients = decode_recipient_pubs(&recipient_pub_keys)?;
+    check_recipient_keys(&recipients)?;
+    let wire = with_sender(sender, |sender| {
+        crypto::encrypt_wrong_key_commitment(message.as_slice(), sender, &recipients)
+            .map_err(|e| error_term(atoms::encrypt_failed(), e))
+    })?;
+
+    Ok((atoms::ok(), into_binary(env, wire)))
+}
+
+/// Test hook: drop the signature of a wire, optionally flagging it anonymous
+/// Returns {ok, ciphertext}
+#[cfg(feature = "test-hooks")]
+#[rustler::nif]
+fn strip_signature<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    anonymous: bool,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let wire =
+        crypto::strip_signature(wire_body(wire.as_slice())?, anonymous).map_err(decrypt_error)?;
+    Ok((atoms::ok(), into_binary(env, wire)))
+}
+
+/// Test hook: change one field of a wire, named as in its CBOR, keeping the
+/// signature
+/// Returns {ok, ciphertext} or {error, {invalid_option, field}}
+#[cfg(feature = "test-hooks")]
+#[rustler::nif]
+fn tamper_wire<'a>(
+    env: Env<'a>,
+    wire: Binary,
+    field: String,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let wire = crypto::tamper(wire_body(wire.as_slice())?, &field)
+        .map_err(decrypt_error)?
+        .ok_or_else(|| error_term(atoms::invalid_option(), &field))?;
+    Ok((atoms::ok(), into_binary(env, wire)))
+}
+
+/// Test hook: an export_store blob holding a new recipient keypair under `kid`
+/// and a new sender keypair under `sid`, neither of which is checked
+/// Returns {ok, encrypted_blob}
+#[cfg(feature = "test-hooks")]
+#[rustler::nif(schedule = "DirtyCpu")]
+fn export_store_as<'a>(
+    env: Env<'a>,
+    kid: String,
+    sid: String,
+    password: Binary,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let (_, priv_key) = keys::recipient_keypair(&kid, level::Level::RECIPIENT_DEFAULT, false);
+    let sender = keys::SenderKey::generate(&sid, level::Level::SENDER_DEFAULT);
+    let contents = store::encode(
+        &[keys::RecipientPrivCbor::from_priv(&priv_key)],
+        &[keys::SenderKeypairCbor::from_keypair(&sender)],
+    )
+    .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+    let blob = password::seal(password.as_slice(), &contents)
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+    Ok((atoms::ok(), to_binary(env, &blob)?))
+}
+
+/// Test hook: the key format of the recipient key stored under `kid`
+/// Returns {ok, format}
+#[cfg(feature = "test-hooks")]
+#[rustler::nif]
+fn recipient_key_format(kid: String) -> NifResult<(rustler::Atom, u8)> {
+    let recipient_keys = read_store(&RECIPIENT_KEYS);
+    let (_, priv_key) = recipient_keys
+        .get(&kid)
+        .ok_or_else(|| error_term(atoms::recipient_not_found(), &kid))?;
+    Ok((atoms::ok(), priv_key.format))
+}
+
+/// Test hook: panic inside `catch_panic`, as a crypto primitive might
+#[cfg(feature = "test-hooks")]
+#[rustler::nif]
+fn crypto_panic() -> NifResult<rustler::Atom> {
+    catch_panic(|| panic!("test hook"))
+}
+
+/// Test hook: panic inside `update_stream` on an encryption stream, as a crypto
+/// primitive might halfway through a frame
+#[cfg(feature = "test-hooks")]
+#[rustler::nif]
+fn stream_panic(stream: ResourceArc<EncryptStreamResource>) -> NifResult<rustler::Atom> {
+    update_stream(&stream.0, |_| panic!("test hook"))
+}
+
+/// Test hook: panic while holding the write locks of both key stores,
+/// poisoning them
+#[cfg(feature = "test-hooks")]
+#[rustler::nif]
+fn poison_key_stores() -> rustler::Atom {
+    let _ = std::panic::catch_unwind(|| {
+        let _recipient_keys = RECIPIENT_KEYS.write();
+        let _sender_keys = SENDER_KEYS.write();
+        panic!("test hook");
+    });
+    atoms::ok()
+}
+
+/// Test hook: panic while holding the lock of an encryption stream, poisoning it
+#[cfg(feature = "test-hooks")]
+#[rustler::nif]
+fn poison_stream(stream: ResourceArc<EncryptStreamResource>) -> rustler::Atom {
+    let _ = std::panic::catch_unwind(|| {
+        let _state = stream.0.lock();
+        panic!("test hook");
+    });
+    atoms::ok()
+}
+
+/// Test hook: return `size` bytes either copied into a new binary or handed over
+/// as a resource binary, for benchmarking the two output paths
+#[cfg(feature = "test-hooks")]
+#[rustler::nif(schedule = "DirtyCpu")]
+fn output_binary(env: Env, size: usize, copy: bool) -> NifResult<Binary> {
+    let bytes = vec![0xA5; size];
+    if copy {
+        to_binary(env, &bytes)
+    } else {
+        Ok(into_binary(env, bytes))
+    }
+}
+
+/// Test hook: allocate a binary of `size` bytes the way `to_binary` does, so
+/// that a failed allocation can be provoked without building the bytes first
+#[cfg(feature = "test-hooks")]
+#[rustler::nif]
+fn alloc_binary_of(size: usize) -> NifResult<(rustler::Atom, usize)> {
+    let bin = alloc_binary(size)?;
+    Ok((atoms::ok(), bin.len()))
+}
+
+/// Test hooks: re-encode a recipient or sender public key with `serde_cbor`,
+/// as releases before the move to ciborium wrote them
+/// Returns {ok, public_key_cbor}
+#[cfg(feature = "test-hooks")]
+#[rustler::nif]
+fn serde_cbor_recipient_pub<'a>(
+    env: Env<'a>,
+    pub_key: Binary,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let pub_key = decode_recipient_pubs(&[pub_key])?.remove(0);
+    let cbor =
+        serde_cbor::to_vec(&pub_key).map_err(|e| error_term(atoms::serialization_failed(), e))?;
+    Ok((atoms::ok(), to_binary(env, &cbor)?))
+}
+
+#[cfg(feature = "test-hooks")]
+#[rustler::nif]
+fn serde_cbor_sender_pub<'a>(
+    env: Env<'a>,
+    pub_key: Binary,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let sender_pub: tholos_pq::SenderPub = cbor::from_slice(pub_key.as_slice())
+        .map_err(|e| error_term(atoms::deserialize_sender(), e))?;
+    let cbor = serde_cbor::to_vec(&sender_pub)
+        .map_err(|e| error_term(atoms::serialization_failed(), e))?;
+    Ok((atoms::ok(), to_binary(env, &cbor)?))
+}
+
+/// Start an encryptor for `sender`, which the stream keeps its own copy of
+fn new_encryptor(sender: Term, recipient_pub_keys: &[Binary]) -> NifResult<stream::Encryptor> {
+    check_recipient_count(recipient_pub_keys.len(), options::DEFAULT_MAX_RECIPIENTS)?;
+    let recipients = decode_recipient_pubs(recipient_pub_keys)?;
+    let (recipients, escrow) = with_escrow(&recipients, options::DEFAULT_MAX_RECIPIENTS)?;
+    let sender = with_sender(sender, |sender| Ok(sender.clone()))?;
+
+    catch_panic(|| {
+        stream::Encryptor::new(sender, &recipients, escrow)
+            .map_err(|e| error_term(atoms::encrypt_failed(), e))
+    })
+}
+
+/// Start a decryptor for `recipient`, which the stream keeps its own copy of
+fn new_decryptor(
+    recipient: Term,
+    allowed_sender_pub_keys: &[Binary],
+) -> NifResult<stream::Decryptor> {
+    let allowed = decode_allowed_senders(allowed_sender_pub_keys)?;
+    let recipient = with_recipient(recipient, |kid, priv_key| {
+        Ok(keys::RecipientPriv {
+            kid: kid.to_string(),
+            ..priv_key.clone()
+        })
+    })?;
+
+    catch_panic(|| Ok(stream::Decryptor::new(recipient, allowed)))
+}
+
+/// Start a streaming encryption for multiple recipients
+/// The KEM work happens here; the sender keypair is copied into the stream, so
+/// later changes to the store do not affect it
+/// Returns {ok, stream}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn encrypt_init<'a>(
+    sender: Term<'a>,
+    recipient_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, ResourceArc<EncryptStreamResource>)> {
+    let encryptor = new_encryptor(sender, &recipient_pub_keys)?;
+
+    Ok((
+        atoms::ok(),
+        ResourceArc::new(EncryptStreamResource(Mutex::new(Some(encryptor)))),
+    ))
+}
+
+/// Encrypt the next chunk of a stream
+/// Returns {ok, ciphertext_chunk}, which may be empty while a frame fills up
+#[rustler::nif(schedule = "DirtyCpu")]
+fn encrypt_update<'a>(
+    env: Env<'a>,
+    stream: ResourceArc<EncryptStreamResource>,
+    chunk: Binary,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let out = update_stream(&stream.0, |encryptor| {
+        encryptor
+            .update(chunk.as_slice())
+            .map_err(|e| error_term(atoms::encrypt_failed(), e))
+    })?;
+
+    Ok((atoms::ok(), into_binary(env, out)))
+}
+
+/// Finish a streaming encryption, closing the stream
+/// Returns {ok, tail} with the last frame and the sender's signature
+#[rustler::nif(schedule = "DirtyCpu")]
+fn encrypt_final<'a>(
+    env: Env<'a>,
+    stream: ResourceArc<EncryptStreamResource>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let mut encryptor = stream_state(&stream.0)
+        .take()
+        .ok_or_else(|| Error::Term(Box::new(atoms::stream_closed())))?;
+
+    let tail = catch_panic(|| {
+        encryptor
+            .finish()
+            .map_err(|e| error_term(atoms::encrypt_failed(), e))
+    })?;
+
+    Ok((atoms::ok(), into_binary(env, tail)))
+}
+
+/// Start a streaming decryption for a recipient
+/// The recipient's private key is copied into the stream
+/// Returns {ok, stream}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn decrypt_init<'a>(
+    recipient: Term<'a>,
+    allowed_sender_pub_keys: Vec<Binary>,
+) -> NifResult<(rustler::Atom, ResourceArc<DecryptStreamResource>)> {
+    let decryptor = new_decryptor(recipient, &allowed_sender_pub_keys)?;
+
+    Ok((
+        atoms::ok(),
+        ResourceArc::new(DecryptStreamResource(Mutex::new(Some(decryptor)))),
+    ))
+}
+
+/// Decrypt the next chunk of a stream
+/// The plaintext is not known to come from the sender until decrypt_final succeeds
+/// Returns {ok, plaintext_chunk}, which may be empty while a frame fills up
+#[rustler::nif(schedule = "DirtyCpu")]
+fn decrypt_update<'a>(
+    env: Env<'a>,
+    stream: ResourceArc<DecryptStreamResource>,
+    chunk: Binary,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let out = update_stream(&stream.0, |decryptor| {
+        decryptor.update(chunk.as_slice()).map_err(decrypt_error)
+    })?;
+
+    Ok((atoms::ok(), into_binary(env, out)))
+}
+
+/// Finish a streaming decryption, verifying the sender's signature over the stream
+/// Returns {ok, tail} with the remaining plaintext
+#[rustler::nif(schedule = "DirtyCpu")]
+fn decrypt_final<'a>(
+    env: Env<'a>,
+    stream: ResourceArc<DecryptStreamResource>,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let mut decryptor = stream_state(&stream.0)
+        .take()
+        .ok_or_else(|| Error::Term(Box::new(atoms::stream_closed())))?;
+
+    let tail = catch_panic(|| decryptor.finish().map_err(decrypt_error))?;
+
+    Ok((atoms::ok(), into_binary(env, tail)))
+}
+
+/// Length of the preamble at the start of a stream, i.e. the offset of its
+/// first frame, from at least its first stream::HEAD_LEN bytes
+/// Returns {ok, bytes}
+#[rustler::nif]
+fn stream_preamble_size(head: Binary) -> NifResult<(rustler::Atom, usize)> {
+    match stream::preamble_len(head.as_slice()).map_err(decrypt_error)? {
+        Some(len) => Ok((atoms::ok(), len)),
+        None => Err(error_term(atoms::malformed_wire(), "truncated stream")),
+    }
+}
+
+fn chunk_error(e: stream::ChunkError) -> Error {
+    match e {
+        stream::ChunkError::PreamblePending => Error::Term(Box::new(atoms::preamble_pending())),
+        stream::ChunkError::OutOfRange => Error::Term(Box::new(atoms::chunk_out_of_range())),
+        stream::ChunkError::Crypto(e) => decrypt_error(e),
+    }
+}
+
+/// Byte range of frame `index` in a stream of `stream_size` bytes whose
+/// preamble was fed to decrypt_update
+/// Returns {ok, {offset, length}}
+#[rustler::nif]
+fn stream_chunk_range(
+    stream: ResourceArc<DecryptStreamResource>,
+    index: u32,
+    stream_size: u64,
+) -> NifResult<(rustler::Atom, (u64, u64))> {
+    let state = stream_state(&stream.0);
+    let decryptor = state
+        .as_ref()
+        .ok_or_else(|| Error::Term(Box::new(atoms::stream_closed())))?;
+
+    let range = decryptor
+        .chunk_range(index, stream_size)
+        .map_err(chunk_error)?;
+    Ok((atoms::ok(), range))
+}
+
+/// Decrypt frame `index` of a stream on its own, without the frames before it
+/// The stream is left as it was, and a failure does not close it
+/// Returns {ok, plaintext_chunk}
+#[rustler::nif(schedule = "DirtyCpu")]
+fn decrypt_chunk<'a>(
+    env: Env<'a>,
+    stream: ResourceArc<DecryptStreamResource>,
+    index: u32,
+    frame: Binary,
+) -> NifResult<(rustler::Atom, Binary<'a>)> {
+    let state = stream_state(&stream.0);
+    let decryptor = state
+        .as_ref()
+        .ok_or_else(|| Error::Term(Box::new(atoms::stream_closed())))?;
+
+    let plaintext = catch_panic(|| {
+        decryptor
+            .decrypt_chunk(index, frame.as_slice())
+            .map_err(chunk_error)
+    })?;
+    Ok((atoms::ok(), into_binary(env, plaintext)))
+}
+
+fn file_error(e: file::FileError, crypto_error: fn(tholos_pq::TholosError) -> Error) -> Error {
+    match e {
+        file::FileError::Io(detail) => error_term(atoms::io(), detail),
+        file::FileError::Crypto(e) => crypto_error(e),
+    }
+}
+
+/// Encrypt the file at input_path into output_path as a stream
+/// Returns ok, or {error, {io, reason}} when a file cannot be read or written
+#[rustler::nif(schedule = "DirtyIo")]
+fn encrypt_file(
+    input_path: String,
+    output_path: String,
+    sender: Term,
+    recipient_pub_keys: Vec<Binary>,
+) -> NifResult<rustler::Atom> {
+    let encryptor = new_encryptor(sender, &recipient_pub_keys)?;
+
+    catch_panic(|| {
+        file::encrypt_file(input_path.as_ref(), output_path.as_ref(), encryptor)
+            .map_err(|e| file_error(e, |e| error_term(atoms::encrypt_failed(), e)))
+    })?;
+
+    Ok(atoms::ok())
+}
+
+/// Decrypt the stream in input_path into output_path
+/// output_path is only created once the whole stream has verified
+/// Returns ok, or {error, {io, reason}} when a file cannot be read or written
+#[rustler::nif(schedule = "DirtyIo")]
+fn decrypt_file(
+    input_path: String,
+    output_path: String,
+    recipient: Term,
+    allowed_sender_pub_keys: Vec<Binary>,
+) -> NifResult<rustler::Atom> {
+    let decryptor = new_decryptor(recipient, &allowed_sender_pub_keys)?;
+
+    catch_panic(|| {
+        file::decrypt_file(input_path.as_ref(), output_path.as_ref(), decryptor)
+            .map_err(|e| file_error(e, decrypt_error))
+    })?;
 
-    Ok((atoms::ok(), plain_bin.release(env)))
+    Ok(atoms::ok())
 }
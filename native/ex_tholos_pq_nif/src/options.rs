@@ -0,0 +1,399 @@
+//! Options accepted as the last argument of the NIFs that take them.
+//!
+//! Options arrive as an Elixir keyword list. Where an argument used to be a
+//! single binary, that binary is still accepted on its own as shorthand.
+
+use crate::crypto::{self, Compression, PayloadAead};
+use crate::encoding::Encoding;
+use crate::keys::KeyFormat;
+use crate::level::Level;
+use crate::{atoms, error_term};
+use rustler::types::binary::Binary;
+use rustler::{Atom, NifResult, Term};
+
+/// Default cap on the number of recipients a single message can be encrypted
+/// for, and that a wire to decrypt may declare.
+pub const DEFAULT_MAX_RECIPIENTS: usize = 256;
+
+/// Longest public metadata a message can carry, see `wire::metadata`.
+pub const MAX_METADATA_LEN: usize = 1024;
+
+/// Options for `encrypt/4`.
+pub struct EncryptOptions<'a> {
+    /// Associated data to bind the message to.
+    pub aad: Option<Binary<'a>>,
+    /// Upper bound on the recipient count, checked before any KEM work.
+    pub max_recipients: usize,
+    /// Compression to apply to the plaintext before encryption.
+    pub compression: Compression,
+    /// Whether to embed the sender's public key in the header.
+    pub embed_sender_key: bool,
+    /// Encoding of the returned wire.
+    pub encoding: Encoding,
+    /// Whether to encapsulate to the X25519 key of hybrid recipient keys too.
+    pub hybrid: bool,
+    /// Whether to commit to the content key in the wire.
+    pub key_commitment: bool,
+    /// Time to live to record in the header.
+    pub ttl_seconds: Option<u64>,
+    /// Message id to record in the header instead of a random one.
+    pub message_id: Option<String>,
+    /// Public metadata to record in the header, left out when empty.
+    pub metadata: Option<Vec<u8>>,
+    /// AEAD to seal the payload with.
+    pub aead: PayloadAead,
+    /// Block size to pad the plaintext to a multiple of.
+    pub pad_to: Option<u32>,
+    /// Associated data for each recipient's envelope, in recipient order.
+    pub per_recipient_aad: Option<Vec<Binary<'a>>>,
+}
+
+impl Default for EncryptOptions<'_> {
+    fn default() -> Self {
+        EncryptOptions {
+            aad: None,
+            max_recipients: DEFAULT_MAX_RECIPIENTS,
+            compression: Compression::None,
+            embed_sender_key: false,
+            encoding: Encoding::Raw,
+            hybrid: false,
+            key_commitment: false,
+            ttl_seconds: None,
+            message_id: None,
+            metadata: None,
+            aead: PayloadAead::XChaCha20Poly1305,
+            pad_to: None,
+            per_recipient_aad: None,
+        }
+    }
+}
+
+impl<'a> EncryptOptions<'a> {
+    /// Decode a keyword list, or a bare binary taken as the associated data.
+    pub fn decode(term: Term<'a>) -> NifResult<Self> {
+        let mut opts = EncryptOptions::default();
+        if let Ok(aad) = term.decode::<Binary>() {
+            opts.aad = Some(aad);
+            return Ok(opts);
+        }
+
+        for (key, value) in term.decode::<Vec<(Atom, Term<'a>)>>()? {
+            if key == atoms::aad() {
+                opts.aad = Some(value.decode()?);
+            } else if key == atoms::max_recipients() {
+                opts.max_recipients = value.decode()?;
+            } else if key == atoms::compression() {
+                opts.compression = decode_compression(value.decode()?)?;
+            } else if key == atoms::embed_sender_key() {
+                opts.embed_sender_key = value.decode()?;
+            } else if key == atoms::encoding() {
+                opts.encoding = decode_encoding(value.decode()?)?;
+            } else if key == atoms::hybrid() {
+                opts.hybrid = value.decode()?;
+            } else if key == atoms::key_commitment() {
+                opts.key_commitment = value.decode()?;
+            } else if key == atoms::ttl_seconds() {
+                opts.ttl_seconds = Some(value.decode()?);
+            } else if key == atoms::message_id() {
+                let id: String = value.decode()?;
+                if id.is_empty() {
+                    return Err(error_term(atoms::invalid_option(), "message_id"));
+                }
+                opts.message_id = Some(id);
+            } else if key == atoms::metadata() {
+                let metadata: Binary = value.decode()?;
+                if metadata.len() > MAX_METADATA_LEN {
+                    return Err(error_term(atoms::invalid_option(), "metadata"));
+                }
+                opts.metadata = (!metadata.is_empty()).then(|| metadata.to_vec());
+            } else if key == atoms::aead() {
+                opts.aead = decode_aead(value.decode()?)?;
+            } else if key == atoms::pad_to() {
+                let block: u32 = value.decode()?;
+                if block == 0 {
+                    return Err(error_term(atoms::invalid_option(), "pad_to"));
+                }
+                opts.pad_to = Some(block);
+            } else if key == atoms::per_recipient_aad() {
+                opts.per_recipient_aad = Some(value.decode()?);
+            } else {
+                return Err(unknown_option(key, term));
+            }
+        }
+        Ok(opts)
+    }
+
+    pub fn aad(&self) -> &[u8] {
+        self.aad.as_ref().map_or(&[], |aad| aad.as_slice())
+    }
+}
+
+/// Options for `decrypt/4`.
+pub struct DecryptOptions<'a> {
+    /// Associated data the message was encrypted with.
+    pub aad: Option<Binary<'a>>,
+    /// Encoding of the wire passed in.
+    pub encoding: Encoding,
+    /// Oldest a wire may be, on top of its own time to live.
+    pub max_age_seconds: Option<u64>,
+    /// The current time in Unix seconds, which expiry is checked against.
+    pub now: Option<u64>,
+    /// Upper bound on the recipient count the wire declares, checked before it
+    /// is parsed.
+    pub max_recipients: usize,
+    /// Associated data the recipient's envelope was bound to.
+    pub recipient_aad: Option<Binary<'a>>,
+}
+
+impl Default for DecryptOptions<'_> {
+    fn default() -> Self {
+        DecryptOptions {
+            aad: None,
+            encoding: Encoding::Raw,
+            max_age_seconds: None,
+            now: None,
+            max_recipients: DEFAULT_MAX_RECIPIENTS,
+            recipient_aad: None,
+        }
+    }
+}
+
+impl<'a> DecryptOptions<'a> {
+    /// Decode a keyword list, or a bare binary taken as the associated data.
+    pub fn decode(term: Term<'a>) -> NifResult<Self> {
+        let mut opts = DecryptOptions::default();
+        if let Ok(aad) = term.decode::<Binary>() {
+            opts.aad = Some(aad);
+            return Ok(opts);
+        }
+
+        for (key, value) in term.decode::<Vec<(Atom, Term<'a>)>>()? {
+            if key == atoms::aad() {
+                opts.aad = Some(value.decode()?);
+            } else if key == atoms::encoding() {
+                opts.encoding = decode_encoding(value.decode()?)?;
+            } else if key == atoms::max_age_seconds() {
+                opts.max_age_seconds = Some(value.decode()?);
+            } else if key == atoms::now() {
+                opts.now = Some(value.decode()?);
+            } else if key == atoms::max_recipients() {
+                opts.max_recipients = value.decode()?;
+            } else if key == atoms::recipient_aad() {
+                opts.recipient_aad = Some(value.decode()?);
+            } else {
+                return Err(unknown_option(key, term));
+            }
+        }
+        // The NIF reads no clock of its own, so a maximum age needs the time
+        if opts.max_age_seconds.is_some() && opts.now.is_none() {
+            return Err(error_term(atoms::invalid_option(), "now"));
+        }
+        Ok(opts)
+    }
+
+    /// Both associated data, or `None` if neither was given.
+    pub fn aad(&self) -> Option<crypto::Aad<'_>> {
+        (self.aad.is_some() || self.recipient_aad.is_some()).then(|| crypto::Aad {
+            message: self.aad.as_ref().map_or(&[], |aad| aad.as_slice()),
+            recipient: self.recipient_aad.as_ref().map(|aad| aad.as_slice()),
+        })
+    }
+}
+
+/// Options for `add_recipient/5` and `rewrap_for_recipient/5`.
+#[derive(Default)]
+pub struct EditOptions<'a> {
+    /// Associated data the message was encrypted with.
+    pub aad: Option<Binary<'a>>,
+}
+
+impl<'a> EditOptions<'a> {
+    /// Decode a keyword list.
+    pub fn decode(term: Term<'a>) -> NifResult<Self> {
+        let mut opts = EditOptions::default();
+        for (key, value) in term.decode::<Vec<(Atom, Term<'a>)>>()? {
+            if key == atoms::aad() {
+                opts.aad = Some(value.decode()?);
+            } else {
+                return Err(unknown_option(key, term));
+            }
+        }
+        Ok(opts)
+    }
+
+    pub fn aad(&self) -> &[u8] {
+        self.aad.as_ref().map_or(&[], |aad| aad.as_slice())
+    }
+}
+
+/// Options for the keygen NIFs that take them.
+#[derive(Default)]
+pub struct KeygenOptions {
+    /// Security level of the key; unset means the default for its kind.
+    pub security_level: Option<Level>,
+    /// Serialization of the returned public key, only settable for recipients.
+    pub format: KeyFormat,
+    /// Whether to add an X25519 key, only settable for recipients.
+    pub hybrid: bool,
+    /// Whether to replace a key already stored under the same id.
+    pub overwrite: bool,
+    /// How long the stored key lives before `sweep_expired` removes it.
+    pub ttl_seconds: Option<u64>,
+}
+
+impl KeygenOptions {
+    /// Decode a keyword list.
+    pub fn decode(term: Term) -> NifResult<Self> {
+        let mut opts = KeygenOptions::default();
+        for (key, value) in term.decode::<Vec<(Atom, Term)>>()? {
+            if key == atoms::security_level() {
+                opts.security_level = Some(decode_level(value.decode()?)?);
+            } else if key == atoms::format() {
+                opts.format = decode_format(value.decode()?)?;
+            } else if key == atoms::hybrid() {
+                opts.hybrid = value.decode()?;
+            } else if key == atoms::overwrite() {
+                opts.overwrite = value.decode()?;
+            } else if key == atoms::ttl_seconds() {
+                opts.ttl_seconds = Some(value.decode()?);
+            } else {
+                return Err(unknown_option(key, term));
+            }
+        }
+        Ok(opts)
+    }
+
+    /// Decode a keyword list for a sender key, whose public key is only ever CBOR
+    /// and never hybrid.
+    pub fn decode_for_sender(term: Term) -> NifResult<Self> {
+        let opts = KeygenOptions::decode(term)?;
+        if opts.format != KeyFormat::Cbor {
+            return Err(error_term(atoms::invalid_option(), "format"));
+        }
+        if opts.hybrid {
+            return Err(error_term(atoms::invalid_option(), "hybrid"));
+        }
+        Ok(opts)
+    }
+
+    pub fn recipient_level(&self) -> Level {
+        self.security_level.unwrap_or(Level::RECIPIENT_DEFAULT)
+    }
+
+    pub fn sender_level(&self) -> Level {
+        self.security_level.unwrap_or(Level::SENDER_DEFAULT)
+    }
+
+    /// When a key generated now expires, if it has a time to live.
+    pub fn expires_at(&self) -> Option<u64> {
+        self.ttl_seconds
+            .map(|ttl| crypto::now().saturating_add(ttl))
+    }
+}
+
+/// Default bound on sender and recipient identifier length for `estimate_wire_size/3`.
+pub const DEFAULT_MAX_ID_LENGTH: u32 = 64;
+
+/// Options for `estimate_wire_size/3`, mirroring the encrypt options that
+/// change the size of a wire.
+pub struct EstimateOptions {
+    /// Longest sender or recipient identifier, in bytes.
+    pub max_id_length: u32,
+    pub compression: Compression,
+    pub embed_sender_key: bool,
+    /// Security level of the sender and recipient keys, set as for keygen.
+    pub security_level: KeygenOptions,
+}
+
+impl Default for EstimateOptions {
+    fn default() -> Self {
+        EstimateOptions {
+            max_id_length: DEFAULT_MAX_ID_LENGTH,
+            compression: Compression::None,
+            embed_sender_key: false,
+            security_level: KeygenOptions::default(),
+        }
+    }
+}
+
+impl EstimateOptions {
+    /// Decode a keyword list.
+    pub fn decode(term: Term) -> NifResult<Self> {
+        let mut opts = EstimateOptions::default();
+        for (key, value) in term.decode::<Vec<(Atom, Term)>>()? {
+            if key == atoms::max_id_length() {
+                opts.max_id_length = value.decode()?;
+            } else if key == atoms::compression() {
+                opts.compression = decode_compression(value.decode()?)?;
+            } else if key == atoms::embed_sender_key() {
+                opts.embed_sender_key = value.decode()?;
+            } else if key == atoms::security_level() {
+                opts.security_level.security_level = Some(decode_level(value.decode()?)?);
+            } else {
+                return Err(unknown_option(key, term));
+            }
+        }
+        Ok(opts)
+    }
+}
+
+fn decode_level(value: Atom) -> NifResult<Level> {
+    if value == atoms::level1() {
+        Ok(Level::L1)
+    } else if value == atoms::level3() {
+        Ok(Level::L3)
+    } else if value == atoms::level5() {
+        Ok(Level::L5)
+    } else {
+        Err(error_term(atoms::invalid_option(), "security_level"))
+    }
+}
+
+/// `:zstd` is only accepted when the NIF is built with the `zstd` feature.
+fn decode_compression(value: Atom) -> NifResult<Compression> {
+    if value == atoms::none() {
+        Ok(Compression::None)
+    } else if value == atoms::zstd() && cfg!(feature = "zstd") {
+        Ok(Compression::Zstd)
+    } else {
+        Err(error_term(atoms::invalid_option(), "compression"))
+    }
+}
+
+fn decode_aead(value: Atom) -> NifResult<PayloadAead> {
+    if value == atoms::xchacha20poly1305() {
+        Ok(PayloadAead::XChaCha20Poly1305)
+    } else if value == atoms::aes256gcm() {
+        Ok(PayloadAead::Aes256Gcm)
+    } else {
+        Err(error_term(atoms::invalid_option(), "aead"))
+    }
+}
+
+fn decode_format(value: Atom) -> NifResult<KeyFormat> {
+    if value == atoms::cbor() {
+        Ok(KeyFormat::Cbor)
+    } else if value == atoms::json() {
+        Ok(KeyFormat::Json)
+    } else {
+        Err(error_term(atoms::invalid_option(), "format"))
+    }
+}
+
+fn decode_encoding(value: Atom) -> NifResult<Encoding> {
+    if value == atoms::raw() {
+        Ok(Encoding::Raw)
+    } else if value == atoms::base64url() {
+        Ok(Encoding::Base64Url)
+    } else {
+        Err(error_term(atoms::invalid_option(), "encoding"))
+    }
+}
+
+fn unknown_option(key: Atom, term: Term) -> rustler::Error {
+    let name = key
+        .to_term(term.get_env())
+        .atom_to_string()
+        .unwrap_or_default();
+    error_term(atoms::invalid_option(), name)
+}
@@ -0,0 +1,121 @@
+//! Password-protected blobs for secret key material at rest.
+//!
+//! A blob is the CBOR of a header and a ciphertext. The header holds everything
+//! needed to derive the key again, i.e. the KDF and its parameters, the salt and
+//! the nonce, so opening a blob needs nothing but the password. The key is
+//! derived with Argon2id and the secret sealed under it with XChaCha20-Poly1305,
+//! with the header CBOR as associated data: raising or lowering the parameters
+//! stored in a blob makes it fail to open instead of weakening it.
+
+use crate::cbor;
+use crate::crypto::{aead_dec, aead_enc};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+const FORMAT_VERSION: u32 = 1;
+const KDF: &str = "argon2id";
+
+/// Argon2id memory cost in KiB, time cost and parallelism used for new blobs.
+const M_COST: u32 = 64 * 1024;
+const T_COST: u32 = 3;
+const P_COST: u32 = 1;
+
+/// Largest memory cost accepted when opening a blob, 1 GiB, so a crafted
+/// header cannot make import allocate without bound.
+const MAX_M_COST: u32 = 1024 * 1024;
+const MAX_T_COST: u32 = 64;
+
+const SALT_LEN: usize = 16;
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    v: u32,
+    kdf: String,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    #[serde(with = "serde_bytes")]
+    salt: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    nonce: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Blob {
+    header: Header,
+    #[serde(with = "serde_bytes")]
+    ciphertext: Vec<u8>,
+}
+
+/// Why a blob could not be opened.
+pub enum OpenError {
+    /// The blob is not a password blob this build understands.
+    Malformed(String),
+    /// Authentication failed: the password is wrong or the blob was altered.
+    BadPassword,
+}
+
+fn derive_key(password: &[u8], header: &Header) -> Result<Zeroizing<[u8; 32]>, String> {
+    let params = Params::new(header.m_cost, header.t_cost, header.p_cost, Some(32))
+        .map_err(|e| e.to_string())?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password, &header.salt, key.as_mut())
+        .map_err(|e| e.to_string())?;
+    Ok(key)
+}
+
+/// Seal `secret` under a key derived from `password` with fresh salt and nonce.
+pub fn seal(password: &[u8], secret: &[u8]) -> Result<Vec<u8>, String> {
+    let mut salt = vec![0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let mut nonce = [0u8; 24];
+    OsRng.fill_bytes(&mut nonce);
+
+    let header = Header {
+        v: FORMAT_VERSION,
+        kdf: KDF.to_string(),
+        m_cost: M_COST,
+        t_cost: T_COST,
+        p_cost: P_COST,
+        salt,
+        nonce: nonce.to_vec(),
+    };
+    let header_cbor = cbor::to_vec(&header).map_err(|e| e.to_string())?;
+    let key = derive_key(password, &header)?;
+    let ciphertext = aead_enc(&key, &nonce, &header_cbor, secret).map_err(|e| e.to_string())?;
+
+    cbor::to_vec(&Blob { header, ciphertext }).map_err(|e| e.to_string())
+}
+
+/// Open a blob made by `seal`.
+pub fn open(password: &[u8], blob: &[u8]) -> Result<Zeroizing<Vec<u8>>, OpenError> {
+    let blob: Blob = cbor::from_slice(blob).map_err(|e| OpenError::Malformed(e.to_string()))?;
+    let header = &blob.header;
+
+    if header.v != FORMAT_VERSION || header.kdf != KDF {
+        return Err(OpenError::Malformed(format!(
+            "unsupported format: version {}, kdf {}",
+            header.v, header.kdf
+        )));
+    }
+    if header.m_cost > MAX_M_COST || header.t_cost > MAX_T_COST {
+        return Err(OpenError::Malformed(
+            "kdf parameters exceed the accepted maximum".to_string(),
+        ));
+    }
+    let nonce: [u8; 24] = header
+        .nonce
+        .as_slice()
+        .try_into()
+        .map_err(|_| OpenError::Malformed("nonce length".to_string()))?;
+
+    let header_cbor = cbor::to_vec(header).map_err(|e| OpenError::Malformed(e.to_string()))?;
+    let key = derive_key(password, header).map_err(OpenError::Malformed)?;
+    aead_dec(&key, &nonce, &header_cbor, &blob.ciphertext)
+        .map(Zeroizing::new)
+        .map_err(|_| OpenError::BadPassword)
+}
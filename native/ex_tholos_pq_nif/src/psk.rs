@@ -0,0 +1,107 @@
+//! Pre-shared-key transport layer around a wire.
+//!
+//! For deployments moving off a symmetric system, a wire can travel sealed
+//! under a key both ends already share, so the old and the post-quantum path
+//! can run side by side. The layer adds nothing to the wire inside it: the
+//! wire is signed and encrypted for its recipients as usual, and opening the
+//! outer layer only yields that wire, which is then decrypted as any other.
+//!
+//! A wrapped wire is the CBOR of a header and a ciphertext, like a password
+//! blob. The key is derived from the PSK with HKDF-SHA256 under a fresh salt,
+//! and the wire sealed under it with XChaCha20-Poly1305 with the header CBOR as
+//! associated data. A PSK is key material, not a password, so it is not
+//! stretched and must be at least `MIN_PSK_LEN` bytes.
+
+use crate::cbor;
+use crate::crypto::{aead_dec, aead_enc};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+const FORMAT_VERSION: u32 = 1;
+const INFO: &[u8] = b"ex_tholos_pq psk v1";
+
+/// Shortest PSK accepted, that of AES-128.
+pub const MIN_PSK_LEN: usize = 16;
+
+const SALT_LEN: usize = 16;
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    v: u32,
+    #[serde(with = "serde_bytes")]
+    salt: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    nonce: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Wrapped {
+    header: Header,
+    #[serde(with = "serde_bytes")]
+    ciphertext: Vec<u8>,
+}
+
+/// Why a wrapped wire could not be opened.
+pub enum OpenError {
+    /// The input is not a wrapped wire this build understands.
+    Malformed(String),
+    /// Authentication failed: the PSK is wrong or the wrapped wire was altered.
+    BadPsk,
+}
+
+fn derive_key(psk: &[u8], salt: &[u8]) -> Zeroizing<[u8; 32]> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    // A 32-byte output is far below the HKDF-SHA256 limit of 8160 bytes
+    #[allow(clippy::expect_used)]
+    Hkdf::<Sha256>::new(Some(salt), psk)
+        .expand(INFO, key.as_mut_slice())
+        .expect("HKDF expand failed - this should never happen with 32-byte output");
+    key
+}
+
+/// Seal `wire` under a key derived from `psk` with fresh salt and nonce. The
+/// caller checks the PSK length.
+pub fn wrap(psk: &[u8], wire: &[u8]) -> Result<Vec<u8>, String> {
+    let mut salt = vec![0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let mut nonce = [0u8; 24];
+    OsRng.fill_bytes(&mut nonce);
+
+    let header = Header {
+        v: FORMAT_VERSION,
+        salt,
+        nonce: nonce.to_vec(),
+    };
+    let header_cbor = cbor::to_vec(&header).map_err(|e| e.to_string())?;
+    let key = derive_key(psk, &header.salt);
+    let ciphertext = aead_enc(&key, &nonce, &header_cbor, wire).map_err(|e| e.to_string())?;
+
+    cbor::to_vec(&Wrapped { header, ciphertext }).map_err(|e| e.to_string())
+}
+
+/// Recover the wire from the output of `wrap`.
+pub fn unwrap(psk: &[u8], wrapped: &[u8]) -> Result<Vec<u8>, OpenError> {
+    let wrapped: Wrapped =
+        cbor::from_slice(wrapped).map_err(|e| OpenError::Malformed(e.to_string()))?;
+    let header = &wrapped.header;
+
+    if header.v != FORMAT_VERSION {
+        return Err(OpenError::Malformed(format!(
+            "unsupported psk layer version {}",
+            header.v
+        )));
+    }
+    let nonce: [u8; 24] = header
+        .nonce
+        .as_slice()
+        .try_into()
+        .map_err(|_| OpenError::Malformed("nonce length".to_string()))?;
+
+    let header_cbor = cbor::to_vec(header).map_err(|e| OpenError::Malformed(e.to_string()))?;
+    let key = derive_key(psk, &header.salt);
+    aead_dec(&key, &nonce, &header_cbor, &wrapped.ciphertext).map_err(|_| OpenError::BadPsk)
+}
@@ -0,0 +1,130 @@
+//! Known-answer tests for the compiled primitives, run by `self_test`.
+//!
+//! ML-KEM keygen and encapsulation draw from a ChaCha20 stream keyed by a
+//! fixed seed, and PQClean's Dilithium signs deterministically, so each check
+//! compares the SHA-256 of what the primitive produced against the digest a
+//! known-good build produced. A miscompiled primitive or a broken SIMD backend
+//! gives other bytes and so another digest. Dilithium keygen reads the OS RNG
+//! and cannot be replayed, so the signing check uses the fixed keypair in
+//! `kat/` instead, and only the final round trip uses fresh keys.
+
+use crate::crypto::{self, Compression};
+use crate::keys::{self, SenderKey};
+use crate::level::{self, KemSecret, Level, SigKeys};
+use crate::wire;
+use rand_chacha::rand_core::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+
+const KEYGEN_SEED: [u8; 32] = [0x4b; 32];
+const ENCAPSULATION_SEED: [u8; 32] = [0x45; 32];
+const MESSAGE: &[u8] = b"ex_tholos_pq self test";
+
+/// Dilithium2 keypair the signing check signs `MESSAGE` with.
+const SIG_PK: &[u8] = include_bytes!("kat/dilithium2.pk");
+const SIG_SK: &[u8] = include_bytes!("kat/dilithium2.sk");
+
+/// ML-KEM-1024 encapsulation key generated from `KEYGEN_SEED`.
+const KEYGEN_DIGEST: [u8; 32] = [
+    0x38, 0x55, 0x96, 0xfc, 0xeb, 0xd4, 0xaa, 0x84, 0x06, 0x9f, 0xa7, 0x27, 0x79, 0x4f, 0x05, 0xeb,
+    0xb4, 0x5d, 0xf7, 0x0c, 0xfd, 0xa1, 0x68, 0x50, 0xa1, 0xaa, 0xfb, 0x2d, 0xcb, 0x1e, 0xbe, 0x1a,
+];
+/// Ciphertext followed by shared secret, encapsulated to that key from
+/// `ENCAPSULATION_SEED`.
+const ENCAPSULATION_DIGEST: [u8; 32] = [
+    0xea, 0x87, 0xcd, 0x71, 0xa2, 0x25, 0xcb, 0x56, 0x2a, 0x29, 0x3f, 0xd2, 0xd4, 0x28, 0x93, 0xd9,
+    0x28, 0xaf, 0xb4, 0x3a, 0xda, 0x66, 0x2f, 0xe1, 0x4e, 0x21, 0x87, 0x28, 0x57, 0x42, 0x1d, 0x15,
+];
+/// Signature over `MESSAGE` with `SIG_SK`.
+const SIGNING_DIGEST: [u8; 32] = [
+    0xd5, 0xbd, 0xa3, 0xf0, 0xf8, 0x5b, 0xdd, 0xfb, 0x54, 0x7a, 0x22, 0x00, 0x41, 0x8f, 0x8d, 0x9b,
+    0x4e, 0x2f, 0x3f, 0x8d, 0x2d, 0x55, 0xbf, 0xe9, 0x4f, 0xbe, 0x54, 0x83, 0x24, 0x32, 0xeb, 0xe8,
+];
+
+/// The check that failed.
+#[derive(Clone, Copy, Debug)]
+pub enum Check {
+    Keygen,
+    Encapsulation,
+    Signing,
+    RoundTrip,
+}
+
+fn digest(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+fn ensure(ok: bool, check: Check) -> Result<(), Check> {
+    if ok {
+        Ok(())
+    } else {
+        Err(check)
+    }
+}
+
+/// Run every check in turn, stopping at the first that fails.
+pub fn run() -> Result<(), Check> {
+    let (secret, pk) = KemSecret::generate(Level::L5, &mut ChaCha20Rng::from_seed(KEYGEN_SEED));
+    ensure(digest(&[&pk]) == KEYGEN_DIGEST, Check::Keygen)?;
+    ensure(secret.public_bytes() == pk, Check::Keygen)?;
+
+    let (ct, shared) = level::encapsulate(&mut ChaCha20Rng::from_seed(ENCAPSULATION_SEED), &pk)
+        .map_err(|_| Check::Encapsulation)?;
+    ensure(
+        digest(&[&ct, shared.as_slice()]) == ENCAPSULATION_DIGEST,
+        Check::Encapsulation,
+    )?;
+    let decapsulated = secret.decapsulate(&ct).map_err(|_| Check::Encapsulation)?;
+    ensure(*decapsulated == *shared, Check::Encapsulation)?;
+
+    let sig_keys = SigKeys::from_bytes(SIG_PK, SIG_SK).map_err(|_| Check::Signing)?;
+    let mut signature = sig_keys.sign(MESSAGE);
+    ensure(digest(&[&signature]) == SIGNING_DIGEST, Check::Signing)?;
+    ensure(
+        matches!(level::verify(SIG_PK, MESSAGE, &signature), Ok(true)),
+        Check::Signing,
+    )?;
+    signature[0] ^= 1;
+    ensure(
+        matches!(level::verify(SIG_PK, MESSAGE, &signature), Ok(false)),
+        Check::Signing,
+    )?;
+
+    round_trip().ok_or(Check::RoundTrip)
+}
+
+/// Encrypt and decrypt `MESSAGE` with fresh keys at the default levels.
+fn round_trip() -> Option<()> {
+    let (recipient_pub, recipient_priv) =
+        keys::recipient_keypair("self-test", Level::RECIPIENT_DEFAULT, false);
+    let sender = SenderKey::generate("self-test", Level::SENDER_DEFAULT);
+    let allowed = [(sender.sid.clone(), sender.public().pk_dilithium)];
+
+    let wire = crypto::encrypt(
+        MESSAGE,
+        &sender,
+        &[recipient_pub],
+        &[],
+        Compression::None,
+        false,
+        false,
+        false,
+        &crypto::HeaderFields::default(),
+    )
+    .ok()?;
+    let decrypted = crypto::decrypt(
+        wire::body(&wire).ok()?,
+        "self-test",
+        &recipient_priv,
+        allowed.as_slice(),
+        crypto::Aad::default(),
+        None,
+        crypto::Accept::default(),
+    )
+    .ok()?;
+    (decrypted.plaintext == MESSAGE).then_some(())
+}
@@ -0,0 +1,148 @@
+//! Standalone Dilithium signatures with sender keys.
+//!
+//! The same keys sign encrypted bundles, so a plain signature over caller bytes
+//! would also be a valid bundle signature whenever those bytes happen to be the
+//! CBOR of a bundle. Signing is therefore domain separated: what is actually
+//! signed is `CONTEXT || message`, which can never parse as bundle CBOR.
+//!
+//! Messages too large to hold in memory are signed by `Signer` over their
+//! SHA-512 instead, as `PREHASH_CONTEXT || SHA-512(message)`, and such
+//! signatures are marked `prehashed`. The two contexts differ, so a signature
+//! of one kind never verifies as the other. `verify` checks both kinds, while
+//! `Verifier` only sees the hash and so only checks prehashed ones.
+
+use crate::keys::SenderKey;
+use crate::level;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+
+const CONTEXT: &[u8] = b"ex_tholos_pq/sign/v1\0";
+const PREHASH_CONTEXT: &[u8] = b"ex_tholos_pq/sign-prehashed/v1\0";
+
+/// CBOR form of a detached signature.
+#[derive(Serialize, Deserialize)]
+pub struct SignatureCbor {
+    /// Sender identifier of the signing key.
+    pub sid: String,
+    /// Dilithium detached signature bytes, at the level of the signing key.
+    #[serde(with = "serde_bytes")]
+    pub sig_dilithium: Vec<u8>,
+    /// Whether the SHA-512 of the message was signed rather than the message.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub prehashed: bool,
+}
+
+fn signed_bytes(message: &[u8]) -> Vec<u8> {
+    [CONTEXT, message].concat()
+}
+
+fn prehashed_bytes(hasher: Sha512) -> Vec<u8> {
+    [PREHASH_CONTEXT, hasher.finalize().as_slice()].concat()
+}
+
+/// Sign `message` with `sender`.
+pub fn sign(sender: &SenderKey, message: &[u8]) -> SignatureCbor {
+    SignatureCbor {
+        sid: sender.sid.clone(),
+        sig_dilithium: sender.dilithium.sign(&signed_bytes(message)),
+        prehashed: false,
+    }
+}
+
+/// Signing side of a message fed in pieces.
+pub struct Signer {
+    sender: SenderKey,
+    hasher: Sha512,
+}
+
+impl Signer {
+    pub fn new(sender: SenderKey) -> Self {
+        Signer {
+            sender,
+            hasher: Sha512::new(),
+        }
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+    }
+
+    /// Sign everything fed so far, with a prehashed signature.
+    pub fn finish(self) -> SignatureCbor {
+        SignatureCbor {
+            sid: self.sender.sid.clone(),
+            sig_dilithium: self.sender.dilithium.sign(&prehashed_bytes(self.hasher)),
+            prehashed: true,
+        }
+    }
+}
+
+/// Why a signature could not be checked at all.
+pub enum VerifyError {
+    /// The public key is not a Dilithium public key.
+    PublicKey,
+    /// The signature is not a Dilithium signature.
+    Signature,
+    /// The signature is over the message itself, which `Verifier` never sees.
+    NotPrehashed,
+}
+
+/// Check that `signature` is `sender`'s signature over `message`.
+///
+/// A well-formed signature that does not verify, including one made under a
+/// different sid or by a key of another level, is `Ok(false)`; only key or
+/// signature bytes of no level's size are errors.
+pub fn verify(
+    sender: &tholos_pq::SenderPub,
+    message: &[u8],
+    signature: &SignatureCbor,
+) -> Result<bool, VerifyError> {
+    let signed = if signature.prehashed {
+        prehashed_bytes(Sha512::new_with_prefix(message))
+    } else {
+        signed_bytes(message)
+    };
+    check(sender, &signed, signature)
+}
+
+fn check(
+    sender: &tholos_pq::SenderPub,
+    signed: &[u8],
+    signature: &SignatureCbor,
+) -> Result<bool, VerifyError> {
+    let valid = level::verify(&sender.pk_dilithium, signed, &signature.sig_dilithium);
+    match valid {
+        Ok(valid) => Ok(valid && signature.sid == sender.sid),
+        Err(level::VerifyError::Mismatch) => Ok(false),
+        Err(level::VerifyError::PublicKey) => Err(VerifyError::PublicKey),
+        Err(level::VerifyError::Signature) => Err(VerifyError::Signature),
+    }
+}
+
+/// Verifying side of a message fed in pieces.
+pub struct Verifier {
+    sender: tholos_pq::SenderPub,
+    hasher: Sha512,
+}
+
+impl Verifier {
+    pub fn new(sender: tholos_pq::SenderPub) -> Self {
+        Verifier {
+            sender,
+            hasher: Sha512::new(),
+        }
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+    }
+
+    /// Check that `signature` is a prehashed signature of the sender over
+    /// everything fed so far, as `verify` would.
+    pub fn finish(self, signature: &SignatureCbor) -> Result<bool, VerifyError> {
+        if !signature.prehashed {
+            return Err(VerifyError::NotPrehashed);
+        }
+        check(&self.sender, &prehashed_bytes(self.hasher), signature)
+    }
+}
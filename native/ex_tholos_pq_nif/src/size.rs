@@ -0,0 +1,113 @@
+//! Upper bounds on the size of a wire, computed from the sizes of its parts
+//! without encrypting anything.
+//!
+//! A wire is the version byte, the CBOR self-describe tag and the CBOR of a
+//! `BundleSigned`, whose structs serialize as maps keyed by field name. Every
+//! part has a fixed size except the identifiers, which are bounded by
+//! `max_id_len`, and the timestamp, which is counted at its widest encoding.
+//! With identifiers of exactly `max_id_len` bytes the bound is only the few
+//! bytes of that timestamp slack above the real size.
+
+use crate::level::Level;
+use tholos_pq::SUITE_V1;
+
+/// XChaCha20-Poly1305 nonce and tag.
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+/// The CEK a recipient envelope wraps.
+const CEK_LEN: usize = 32;
+/// A hyphenated UUID, the message id.
+const MSG_ID_LEN: usize = 36;
+/// `"zstd"`, the longest compression name.
+const COMPRESSION_NAME_LEN: usize = 4;
+
+/// What `estimate` should assume about the wire.
+pub struct Estimate {
+    pub plaintext_len: usize,
+    pub recipient_count: usize,
+    /// Longest sender or recipient identifier, in bytes.
+    pub max_id_len: usize,
+    pub compression: bool,
+    pub embed_sender_key: bool,
+    /// Level of the recipient keys, which sets the KEM ciphertext size.
+    pub recipient_level: Level,
+    /// Level of the sender key, which sets the signature and public key size.
+    pub sender_level: Level,
+}
+
+/// Length of a CBOR item head carrying `arg`.
+fn head(arg: usize) -> usize {
+    match arg {
+        0..=23 => 1,
+        24..=0xff => 2,
+        0x100..=0xffff => 3,
+        0x1_0000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
+
+/// A text or byte string of `len` bytes.
+fn string(len: usize) -> usize {
+    head(len) + len
+}
+
+/// A map with `fields`, given as key and encoded value length.
+fn map(fields: &[(&str, usize)]) -> usize {
+    head(fields.len())
+        + fields
+            .iter()
+            .map(|(key, value)| string(key.len()) + value)
+            .sum::<usize>()
+}
+
+fn header(e: &Estimate) -> usize {
+    let mut fields = vec![
+        ("v", 1),
+        ("suite", string(SUITE_V1.len())),
+        ("sender", string(e.max_id_len)),
+        (
+            "recipients",
+            head(e.recipient_count) + e.recipient_count * string(e.max_id_len),
+        ),
+        ("msg_id", string(MSG_ID_LEN)),
+        // A u64 at its widest
+        ("timestamp_unix", 9),
+    ];
+    if e.compression {
+        fields.push(("compression", string(COMPRESSION_NAME_LEN)));
+    }
+    if e.embed_sender_key {
+        fields.push(("sender_pk", string(e.sender_level.sig_pk_len())));
+    }
+    map(&fields)
+}
+
+fn envelope(e: &Estimate) -> usize {
+    map(&[
+        ("kid", string(e.max_id_len)),
+        ("kem_ct", string(e.recipient_level.kem_ct_len())),
+        ("wrap_nonce", string(NONCE_LEN)),
+        ("wrapped_cek", string(CEK_LEN + TAG_LEN)),
+    ])
+}
+
+/// Upper bound on the length of the wire `encrypt` produces. Compression only
+/// ever shrinks the payload, so it is counted at the plaintext length.
+pub fn estimate(e: &Estimate) -> usize {
+    let inner = map(&[
+        ("header", header(e)),
+        ("pay_nonce", string(NONCE_LEN)),
+        ("ciphertext", string(e.plaintext_len + TAG_LEN)),
+        (
+            "recipients",
+            head(e.recipient_count) + e.recipient_count * envelope(e),
+        ),
+    ]);
+    let bundle = map(&[
+        ("inner", inner),
+        ("sig_dilithium", string(e.sender_level.sig_len())),
+    ]);
+
+    // Version byte and the three bytes of the self-describe tag
+    1 + 3 + bundle
+}
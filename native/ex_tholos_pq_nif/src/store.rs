@@ -0,0 +1,122 @@
+//! Serialized form of the whole key store, sealed by `password`.
+//!
+//! Layout, all integers big-endian:
+//!
+//! ```text
+//! u8 version | u32 count | count × (u32 len | RecipientPrivCbor)
+//!            | u32 count | count × (u32 len | SenderKeypairCbor)
+//! ```
+//!
+//! Public keys are not stored; they are derived from the private keys again on
+//! import. Every length is checked against the bytes that remain, so a
+//! truncated or padded dump is rejected as a whole and nothing is imported.
+
+use crate::cbor;
+use crate::keys::{RecipientPriv, RecipientPrivCbor, SenderKey, SenderKeypairCbor};
+use zeroize::Zeroizing;
+
+const VERSION: u8 = 1;
+
+type Entries = Vec<Zeroizing<Vec<u8>>>;
+
+fn serialize_all<T: serde::Serialize>(entries: &[T]) -> Result<Entries, String> {
+    entries
+        .iter()
+        .map(|entry| {
+            cbor::to_vec(entry)
+                .map(Zeroizing::new)
+                .map_err(|e| e.to_string())
+        })
+        .collect()
+}
+
+fn put_entries(out: &mut Vec<u8>, entries: &Entries) -> Result<(), String> {
+    out.extend_from_slice(&len_u32(entries.len())?.to_be_bytes());
+    for bytes in entries {
+        out.extend_from_slice(&len_u32(bytes.len())?.to_be_bytes());
+        out.extend_from_slice(bytes);
+    }
+    Ok(())
+}
+
+fn len_u32(len: usize) -> Result<u32, String> {
+    u32::try_from(len).map_err(|_| format!("{} does not fit a length prefix", len))
+}
+
+/// Serialize the store contents.
+pub fn encode(
+    recipients: &[RecipientPrivCbor],
+    senders: &[SenderKeypairCbor],
+) -> Result<Zeroizing<Vec<u8>>, String> {
+    let recipients = serialize_all(recipients)?;
+    let senders = serialize_all(senders)?;
+    let len = 1 + [&recipients, &senders]
+        .iter()
+        .map(|entries| 4 + entries.iter().map(|b| 4 + b.len()).sum::<usize>())
+        .sum::<usize>();
+
+    // Sized up front so that growing it never leaves copies of the secrets behind
+    let mut out = Zeroizing::new(Vec::with_capacity(len));
+    out.push(VERSION);
+    put_entries(&mut out, &recipients)?;
+    put_entries(&mut out, &senders)?;
+    Ok(out)
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < n {
+            return Err(format!(
+                "truncated: needed {} bytes, {} left",
+                n,
+                self.0.len()
+            ));
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<usize, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    fn entries<T: serde::de::DeserializeOwned>(&mut self) -> Result<Vec<T>, String> {
+        let count = self.u32()?;
+        // Not preallocated: the count is only trusted as far as entries arrive
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let len = self.u32()?;
+            entries.push(cbor::from_slice(self.take(len)?).map_err(|e| e.to_string())?);
+        }
+        Ok(entries)
+    }
+}
+
+/// Parse store contents made by `encode`.
+pub fn decode(bytes: &[u8]) -> Result<(Vec<RecipientPriv>, Vec<SenderKey>), String> {
+    let mut reader = Reader(bytes);
+    let version = reader.take(1)?[0];
+    if version != VERSION {
+        return Err(format!("unsupported store version {}", version));
+    }
+
+    let recipients = reader
+        .entries::<RecipientPrivCbor>()?
+        .into_iter()
+        .map(RecipientPrivCbor::into_priv)
+        .collect::<Result<Vec<_>, _>>()?;
+    let senders = reader
+        .entries::<SenderKeypairCbor>()?
+        .into_iter()
+        .map(SenderKeypairCbor::into_keypair)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if !reader.0.is_empty() {
+        return Err(format!("{} trailing bytes", reader.0.len()));
+    }
+    Ok((recipients, senders))
+}
@@ -0,0 +1,489 @@
+//! Chunked encryption for messages too large to hold in memory at once.
+//!
+//! A stream is encrypted under a single CEK, wrapped for every recipient once up
+//! front exactly like a tholos-pq envelope, and then sealed frame by frame:
+//!
+//! ```text
+//! "TPQS" 0x01 | u32 BE preamble length | preamble | frame* | last frame | signature
+//! ```
+//!
+//! * The preamble is the canonical CBOR of `SignedPreamble`: the header (sender,
+//!   recipient kids, chunk size and a random 19-byte nonce prefix), the recipient
+//!   envelopes and the sender's Dilithium signature over both.
+//! * Frame `i` is XChaCha20-Poly1305 of up to `chunk_size` plaintext bytes with
+//!   nonce `prefix || i as u32 BE || last`, where `last` is 1 only for the final
+//!   frame. Every frame except the last holds exactly `chunk_size` bytes, and the
+//!   last one may be empty, so truncating or reordering frames fails to decrypt.
+//! * The trailing signature covers a SHA-256 of everything before it, binding the
+//!   frames to the sender. A recipient knows the CEK and could otherwise swap in
+//!   frames of its own. Its length is that of the sender key's security level.
+//!
+//! Decrypted frames are handed out as they arrive, before the trailing signature
+//! has been checked; only a successful `Decryptor::finish` proves the whole
+//! stream came from the sender.
+//!
+//! Since every frame but the last has the same length, frame `i` starts at a
+//! fixed offset after the preamble, and once the preamble has been read any
+//! single frame can be opened on its own with `Decryptor::decrypt_chunk`. No
+//! index is stored for that: the chunk size in the signed header is all that
+//! is needed. A frame opened this way is authenticated by its AEAD tag and its
+//! counter, so it cannot be altered or moved, but only a read of the whole
+//! stream checks the trailing signature and whether frames were cut off.
+
+use crate::cbor;
+use crate::crypto::{aead_dec, aead_enc, find_envelope, unwrap_cek, wrap_cek};
+use crate::keys::{RecipientPriv, SenderKey};
+use crate::level::{self, Level};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tholos_pq::{RecipientEnvelope, RecipientPub, TholosError};
+use time::OffsetDateTime;
+use zeroize::Zeroizing;
+
+const MAGIC: &[u8; 5] = b"TPQS\x01";
+/// Length of the magic and the preamble length in front of the preamble.
+pub const HEAD_LEN: usize = MAGIC.len() + LENGTH_PREFIX_LEN;
+/// Names the default parameter sets; streams between keys of other security
+/// levels (see `level`) keep it, since the key sizes tell the levels apart.
+const SUITE: &str = "TPQ-STREAM-1:ML-KEM-1024+XChaCha20-Poly1305+Dilithium3";
+const PREAMBLE_CONTEXT: &[u8] = b"ex_tholos_pq/stream/preamble/v1\0";
+const TRAILER_CONTEXT: &[u8] = b"ex_tholos_pq/stream/trailer/v1\0";
+
+/// Plaintext bytes per frame.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+/// Upper bound on the preamble, which grows with the number of recipients.
+const MAX_PREAMBLE_LEN: usize = 4 * 1024 * 1024;
+const TAG_LEN: usize = 16;
+const NONCE_PREFIX_LEN: usize = 19;
+const LENGTH_PREFIX_LEN: usize = 4;
+
+#[derive(Serialize, Deserialize)]
+struct StreamHeader {
+    v: u8,
+    suite: String,
+    sender: String,
+    recipients: Vec<String>,
+    msg_id: String,
+    timestamp_unix: u64,
+    chunk_size: u32,
+    #[serde(with = "serde_bytes")]
+    nonce_prefix: Vec<u8>,
+    /// Kid of the recipient that is the node's escrow key, as in a wire header.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    escrow: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Preamble {
+    header: StreamHeader,
+    envelopes: Vec<RecipientEnvelope>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignedPreamble {
+    inner: Preamble,
+    #[serde(with = "serde_bytes")]
+    sig_dilithium: Vec<u8>,
+}
+
+/// Check a stream signature, preamble or trailer, against `pk`.
+fn verify(pk: &[u8], message: &[u8], signature: &[u8]) -> Result<(), TholosError> {
+    match level::verify(pk, message, signature) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(TholosError::BadSignature),
+        Err(level::VerifyError::PublicKey) => Err(TholosError::Malformed("dilithium pk")),
+        Err(level::VerifyError::Signature) => Err(TholosError::Malformed("signature")),
+        Err(level::VerifyError::Mismatch) => Err(TholosError::Malformed(level::MISMATCH)),
+    }
+}
+
+fn frame_nonce(prefix: &[u8; NONCE_PREFIX_LEN], counter: u32, last: bool) -> [u8; 24] {
+    let mut nonce = [0u8; 24];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..23].copy_from_slice(&counter.to_be_bytes());
+    nonce[23] = last as u8;
+    nonce
+}
+
+fn next_counter(counter: &mut u32) -> Result<u32, TholosError> {
+    let current = *counter;
+    *counter = counter
+        .checked_add(1)
+        .ok_or(TholosError::Malformed("stream too long"))?;
+    Ok(current)
+}
+
+/// Length of everything in front of the first frame, read from the first
+/// `HEAD_LEN` bytes of a stream; `None` while fewer have arrived.
+pub fn preamble_len(stream: &[u8]) -> Result<Option<usize>, TholosError> {
+    if stream.len() < HEAD_LEN {
+        return Ok(None);
+    }
+    if &stream[..MAGIC.len()] != MAGIC {
+        return Err(TholosError::Malformed("stream magic"));
+    }
+    let len_bytes: [u8; LENGTH_PREFIX_LEN] = stream[MAGIC.len()..HEAD_LEN]
+        .try_into()
+        .map_err(|_| TholosError::Malformed("stream preamble length"))?;
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    if len > MAX_PREAMBLE_LEN {
+        return Err(TholosError::Malformed("stream preamble length"));
+    }
+    Ok(Some(HEAD_LEN + len))
+}
+
+/// Why a single frame of a stream could not be located or opened.
+pub enum ChunkError {
+    /// The preamble has not been read yet.
+    PreamblePending,
+    /// The stream has no frame with this index.
+    OutOfRange,
+    /// The frame or the stream length is invalid.
+    Crypto(TholosError),
+}
+
+impl From<TholosError> for ChunkError {
+    fn from(e: TholosError) -> Self {
+        ChunkError::Crypto(e)
+    }
+}
+
+/// Encrypting side of a stream.
+pub struct Encryptor {
+    sender: SenderKey,
+    cek: Zeroizing<[u8; 32]>,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    counter: u32,
+    /// Plaintext not framed yet, at most `CHUNK_SIZE` bytes.
+    pending: Vec<u8>,
+    /// Preamble bytes, emitted in front of the first output.
+    preamble: Option<Vec<u8>>,
+    transcript: Sha256,
+}
+
+impl Encryptor {
+    /// Start a stream from `sender` to `recipients`, doing all KEM work up front.
+    /// `escrow` is the kid the header names as the escrow recipient, one of
+    /// `recipients`.
+    pub fn new(
+        sender: SenderKey,
+        recipients: &[RecipientPub],
+        escrow: Option<String>,
+    ) -> Result<Self, TholosError> {
+        let mut rng = OsRng;
+        let mut cek = Zeroizing::new([0u8; 32]);
+        rng.fill_bytes(cek.as_mut());
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        rng.fill_bytes(&mut nonce_prefix);
+
+        let header = StreamHeader {
+            v: 1,
+            suite: SUITE.to_string(),
+            sender: sender.sid.clone(),
+            recipients: recipients.iter().map(|r| r.kid.clone()).collect(),
+            msg_id: uuid::Uuid::new_v4().to_string(),
+            timestamp_unix: OffsetDateTime::now_utc().unix_timestamp() as u64,
+            chunk_size: CHUNK_SIZE as u32,
+            nonce_prefix: nonce_prefix.to_vec(),
+            escrow,
+        };
+        let header_cbor = cbor::to_vec_tagged(&header)?;
+        let envelopes = recipients
+            .iter()
+            .map(|r| wrap_cek(&mut rng, &cek, r, &header_cbor, &header_cbor, false))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let inner = Preamble { header, envelopes };
+        let inner_cbor = cbor::to_vec_tagged(&inner)?;
+        let sig_dilithium = sender
+            .dilithium
+            .sign(&[PREAMBLE_CONTEXT, &inner_cbor].concat());
+        let preamble_cbor = cbor::to_vec_tagged(&SignedPreamble {
+            inner,
+            sig_dilithium,
+        })?;
+
+        let mut preamble =
+            Vec::with_capacity(MAGIC.len() + LENGTH_PREFIX_LEN + preamble_cbor.len());
+        preamble.extend_from_slice(MAGIC);
+        preamble.extend_from_slice(&(preamble_cbor.len() as u32).to_be_bytes());
+        preamble.extend_from_slice(&preamble_cbor);
+
+        Ok(Encryptor {
+            sender,
+            cek,
+            nonce_prefix,
+            counter: 0,
+            pending: Vec::with_capacity(CHUNK_SIZE),
+            preamble: Some(preamble),
+            transcript: Sha256::new(),
+        })
+    }
+
+    fn start_output(&mut self, capacity: usize) -> Vec<u8> {
+        match self.preamble.take() {
+            Some(mut preamble) => {
+                self.transcript.update(&preamble);
+                preamble.reserve(capacity);
+                preamble
+            }
+            None => Vec::with_capacity(capacity),
+        }
+    }
+
+    fn seal(&mut self, plaintext: &[u8], last: bool, out: &mut Vec<u8>) -> Result<(), TholosError> {
+        let counter = next_counter(&mut self.counter)?;
+        let nonce = frame_nonce(&self.nonce_prefix, counter, last);
+        let frame = aead_enc(&self.cek, &nonce, &[], plaintext)?;
+        self.transcript.update(&frame);
+        out.extend_from_slice(&frame);
+        Ok(())
+    }
+
+    /// Encrypt the next piece of plaintext, returning every frame it completes.
+    ///
+    /// A frame is only sealed once it is known not to be the last one, so up to
+    /// `CHUNK_SIZE` bytes stay buffered until more input or `finish` arrives.
+    pub fn update(&mut self, mut input: &[u8]) -> Result<Vec<u8>, TholosError> {
+        let frames = (self.pending.len() + input.len()).saturating_sub(1) / CHUNK_SIZE;
+        let mut out = self.start_output(frames * (CHUNK_SIZE + TAG_LEN));
+
+        while self.pending.len() + input.len() > CHUNK_SIZE {
+            if self.pending.is_empty() {
+                let (chunk, rest) = input.split_at(CHUNK_SIZE);
+                self.seal(chunk, false, &mut out)?;
+                input = rest;
+            } else {
+                let (head, rest) = input.split_at(CHUNK_SIZE - self.pending.len());
+                let mut chunk = std::mem::take(&mut self.pending);
+                chunk.extend_from_slice(head);
+                self.seal(&chunk, false, &mut out)?;
+                chunk.clear();
+                self.pending = chunk;
+                input = rest;
+            }
+        }
+        self.pending.extend_from_slice(input);
+        Ok(out)
+    }
+
+    /// Seal the last frame and append the trailing signature.
+    pub fn finish(&mut self) -> Result<Vec<u8>, TholosError> {
+        let sig_len = self.sender.dilithium.level().sig_len();
+        let mut out = self.start_output(self.pending.len() + TAG_LEN + sig_len);
+        let pending = std::mem::take(&mut self.pending);
+        self.seal(&pending, true, &mut out)?;
+
+        let digest = self.transcript.clone().finalize();
+        let sig = self
+            .sender
+            .dilithium
+            .sign(&[TRAILER_CONTEXT, digest.as_slice()].concat());
+        out.extend_from_slice(&sig);
+        Ok(out)
+    }
+}
+
+struct Body {
+    cek: Zeroizing<[u8; 32]>,
+    /// Offset of the first frame in the stream.
+    frames_start: usize,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    frame_len: usize,
+    counter: u32,
+    sender_pk: Vec<u8>,
+    /// Length of the trailing signature, fixed by the level of `sender_pk`.
+    sig_len: usize,
+}
+
+/// Decrypting side of a stream.
+pub struct Decryptor {
+    recipient: RecipientPriv,
+    allowed_senders: Vec<(String, Vec<u8>)>,
+    /// Received bytes not consumed yet.
+    buffer: Vec<u8>,
+    /// Set once the preamble has been read and verified.
+    body: Option<Body>,
+    transcript: Sha256,
+}
+
+impl Decryptor {
+    pub fn new(recipient: RecipientPriv, allowed_senders: Vec<(String, Vec<u8>)>) -> Self {
+        Decryptor {
+            recipient,
+            allowed_senders,
+            buffer: Vec::new(),
+            body: None,
+            transcript: Sha256::new(),
+        }
+    }
+
+    /// Read and verify the preamble once it has fully arrived, returning how many
+    /// bytes of the buffer it took up.
+    fn read_preamble(&mut self) -> Result<Option<usize>, TholosError> {
+        let Some(frames_start) = preamble_len(&self.buffer)? else {
+            return Ok(None);
+        };
+        if self.buffer.len() < frames_start {
+            return Ok(None);
+        }
+
+        let signed: SignedPreamble = cbor::from_slice(&self.buffer[HEAD_LEN..frames_start])?;
+
+        // Verify sender + signature
+        let sender_sid = &signed.inner.header.sender;
+        let Some((_, pk_bytes)) = self
+            .allowed_senders
+            .iter()
+            .find(|(sid, _)| sid == sender_sid)
+        else {
+            return Err(TholosError::BadSignature);
+        };
+        let sender_pk = pk_bytes.clone();
+        let inner_cbor = cbor::to_vec_tagged(&signed.inner)?;
+        verify(
+            &sender_pk,
+            &[PREAMBLE_CONTEXT, &inner_cbor].concat(),
+            &signed.sig_dilithium,
+        )?;
+        let sig_len = Level::of_sig_pk(sender_pk.len())
+            .ok_or(TholosError::Malformed("dilithium pk"))?
+            .sig_len();
+
+        let header = &signed.inner.header;
+        if header.v != 1 || header.suite != SUITE {
+            return Err(TholosError::Malformed("stream suite"));
+        }
+        let nonce_prefix: [u8; NONCE_PREFIX_LEN] = header
+            .nonce_prefix
+            .as_slice()
+            .try_into()
+            .map_err(|_| TholosError::Malformed("stream nonce prefix"))?;
+        if header.chunk_size == 0 {
+            return Err(TholosError::Malformed("stream chunk size"));
+        }
+
+        // Find my envelope and recover the CEK
+        let kid = &self.recipient.kid;
+        let env = find_envelope(&signed.inner.envelopes, kid, &self.recipient.sk_kyber)?;
+        let header_cbor = cbor::to_vec_tagged(header)?;
+        let cek = unwrap_cek(env, kid, &self.recipient, &header_cbor, &header_cbor, false)?;
+
+        self.body = Some(Body {
+            cek: Zeroizing::new(cek),
+            frames_start,
+            nonce_prefix,
+            frame_len: header.chunk_size as usize + TAG_LEN,
+            counter: 0,
+            sender_pk,
+            sig_len,
+        });
+        self.transcript.update(&self.buffer[..frames_start]);
+        Ok(Some(frames_start))
+    }
+
+    fn open(body: &mut Body, frame: &[u8], last: bool) -> Result<Vec<u8>, TholosError> {
+        let counter = next_counter(&mut body.counter)?;
+        let nonce = frame_nonce(&body.nonce_prefix, counter, last);
+        aead_dec(&body.cek, &nonce, &[], frame)
+    }
+
+    /// Take in the next piece of the stream, returning the plaintext of every
+    /// frame it completes.
+    ///
+    /// The last frame and the signature can only be told apart at the end, so
+    /// the final `frame_len + signature_len` bytes are held back for `finish`.
+    pub fn update(&mut self, input: &[u8]) -> Result<Vec<u8>, TholosError> {
+        self.buffer.extend_from_slice(input);
+
+        let mut consumed = 0;
+        if self.body.is_none() {
+            match self.read_preamble()? {
+                Some(len) => consumed = len,
+                None => return Ok(Vec::new()),
+            }
+        }
+
+        let mut out = Vec::new();
+        if let Some(body) = self.body.as_mut() {
+            let held_back = body.frame_len + body.sig_len;
+            while self.buffer.len() - consumed > held_back {
+                let frame = &self.buffer[consumed..consumed + body.frame_len];
+                out.extend_from_slice(&Self::open(body, frame, false)?);
+                self.transcript.update(frame);
+                consumed += body.frame_len;
+            }
+        }
+        self.buffer.drain(..consumed);
+        Ok(out)
+    }
+
+    /// Byte range of frame `index` in a stream of `stream_len` bytes, as
+    /// `(offset, length)`, clipped to end before the trailing signature.
+    pub fn chunk_range(&self, index: u32, stream_len: u64) -> Result<(u64, u64), ChunkError> {
+        let body = self.body.as_ref().ok_or(ChunkError::PreamblePending)?;
+        let frames_len = stream_len
+            .checked_sub((body.frames_start + body.sig_len) as u64)
+            .filter(|&len| len >= TAG_LEN as u64)
+            .ok_or(TholosError::Malformed("truncated stream"))?;
+        let frame_len = body.frame_len as u64;
+        let offset = u64::from(index) * frame_len;
+        if offset >= frames_len {
+            return Err(ChunkError::OutOfRange);
+        }
+        let length = frame_len.min(frames_len - offset);
+        if length < TAG_LEN as u64 {
+            return Err(TholosError::Malformed("truncated stream").into());
+        }
+        Ok((body.frames_start as u64 + offset, length))
+    }
+
+    /// Decrypt frame `index` on its own, leaving the sequential state alone.
+    ///
+    /// A frame of full length may be the last one as well, so if it does not
+    /// open as an inner frame it is tried as the last.
+    pub fn decrypt_chunk(&self, index: u32, frame: &[u8]) -> Result<Vec<u8>, ChunkError> {
+        let body = self.body.as_ref().ok_or(ChunkError::PreamblePending)?;
+        if frame.len() < TAG_LEN || frame.len() > body.frame_len {
+            return Err(TholosError::Malformed("stream frame length").into());
+        }
+        let open = |last| {
+            let nonce = frame_nonce(&body.nonce_prefix, index, last);
+            aead_dec(&body.cek, &nonce, &[], frame)
+        };
+        let plaintext = if frame.len() == body.frame_len {
+            open(false).or_else(|_| open(true))?
+        } else {
+            open(true)?
+        };
+        Ok(plaintext)
+    }
+
+    /// Decrypt the last frame and verify the trailing signature.
+    pub fn finish(&mut self) -> Result<Vec<u8>, TholosError> {
+        let Some(body) = self.body.as_mut() else {
+            return Err(TholosError::Malformed("truncated stream"));
+        };
+        let sig_len = body.sig_len;
+        let rest = self.buffer.len();
+        if rest < TAG_LEN + sig_len || rest > body.frame_len + sig_len {
+            return Err(TholosError::Malformed("truncated stream"));
+        }
+
+        let (frame, sig) = self.buffer.split_at(rest - sig_len);
+        let plaintext = Self::open(body, frame, true)?;
+        self.transcript.update(frame);
+
+        let digest = self.transcript.clone().finalize();
+        verify(
+            &body.sender_pk,
+            &[TRAILER_CONTEXT, digest.as_slice()].concat(),
+            sig,
+        )?;
+
+        self.buffer.clear();
+        Ok(plaintext)
+    }
+}
@@ -0,0 +1,291 @@
+//! Test vectors for other implementations of the wire format.
+//!
+//! Every vector encrypts a fixed plaintext with `crypto::encrypt_deterministic`
+//! for recipients derived from fixed seeds, signed with the Dilithium2 keypair
+//! in `kat/`, so every build exports the same JSON. Next to the wire, each
+//! vector lays out its parts: the version byte, the header whose tagged CBOR
+//! every AEAD operation is bound to, the payload, one KEM envelope per
+//! recipient in recipient order, and the signature with the bytes it covers.
+//! `test/fixtures/test_vectors.json` is the committed export; the test suite
+//! fails if the two ever differ, so a change to the format cannot slip by.
+//!
+//! Byte strings are lowercase hex. CBOR writes the argument of every head
+//! big-endian in its shortest form, so no byte depends on the host.
+
+use crate::cbor;
+use crate::crypto;
+use crate::keys::{self, KeyFormat, SenderKey};
+use crate::level::{self, Level, SigKeys};
+use crate::wire;
+use ciborium::Value;
+use serde::{Deserialize, Serialize};
+use tholos_pq::{RecipientEnvelope, TholosError};
+
+/// Bumped whenever a vector is added, changed or removed.
+const FORMAT: u32 = 1;
+
+const SIG_PK: &[u8] = include_bytes!("kat/dilithium2.pk");
+const SIG_SK: &[u8] = include_bytes!("kat/dilithium2.sk");
+const SENDER_SID: &str = "vector-sender";
+const PLAINTEXT: &[u8] = b"ex_tholos_pq test vector";
+
+struct Spec {
+    name: &'static str,
+    description: &'static str,
+    encryption_seed: [u8; 32],
+    plaintext: &'static [u8],
+    /// Kid, seed and level of every recipient.
+    recipients: &'static [(&'static str, [u8; 32], Level)],
+}
+
+const ALICE: (&str, [u8; 32], Level) = ("vector-alice", [0x01; 32], Level::L5);
+const BOB: (&str, [u8; 32], Level) = ("vector-bob", [0x02; 32], Level::L5);
+const CAROL: (&str, [u8; 32], Level) = ("vector-carol", [0x03; 32], Level::L1);
+
+const SPECS: &[Spec] = &[
+    Spec {
+        name: "one-recipient",
+        description: "One ML-KEM-1024 recipient, the default level",
+        encryption_seed: [0x11; 32],
+        plaintext: PLAINTEXT,
+        recipients: &[ALICE],
+    },
+    Spec {
+        name: "two-recipients",
+        description: "Two recipients sharing one content key, envelopes in recipient order",
+        encryption_seed: [0x12; 32],
+        plaintext: PLAINTEXT,
+        recipients: &[ALICE, BOB],
+    },
+    Spec {
+        name: "ml-kem-512",
+        description: "One ML-KEM-512 recipient, whose envelope has a shorter KEM ciphertext",
+        encryption_seed: [0x13; 32],
+        plaintext: PLAINTEXT,
+        recipients: &[CAROL],
+    },
+    Spec {
+        name: "empty-plaintext",
+        description: "An empty payload, whose ciphertext is only the AEAD tag",
+        encryption_seed: [0x14; 32],
+        plaintext: b"",
+        recipients: &[ALICE],
+    },
+];
+
+#[derive(Serialize)]
+struct Export {
+    format: u32,
+    sender: Sender,
+    vectors: Vec<Vector>,
+}
+
+#[derive(Serialize)]
+struct Sender {
+    sid: &'static str,
+    algorithm: &'static str,
+    public_key: String,
+    /// The public key as `gen_sender_keypair` returns it.
+    public_key_cbor: String,
+    secret_key: String,
+}
+
+#[derive(Serialize)]
+struct Recipient {
+    kid: &'static str,
+    algorithm: &'static str,
+    /// The seed given to `gen_recipient_keypair_from_seed`.
+    seed: String,
+    /// The public key as `gen_recipient_keypair_from_seed` returns it.
+    public_key_cbor: String,
+    /// The ML-KEM decapsulation key.
+    secret_key: String,
+}
+
+#[derive(Serialize)]
+struct Vector {
+    name: &'static str,
+    description: &'static str,
+    encryption_seed: String,
+    plaintext: String,
+    recipients: Vec<Recipient>,
+    wire: String,
+    layout: Layout,
+}
+
+#[derive(Serialize)]
+struct Layout {
+    /// The first byte of the wire; the bundle CBOR follows it.
+    version: u8,
+    /// Self-describe tag and CBOR of the header, the associated data of the
+    /// payload AEAD and of every CEK wrap.
+    header_cbor: String,
+    header: HeaderFields,
+    pay_nonce: String,
+    ciphertext: String,
+    envelopes: Vec<Envelope>,
+    /// Self-describe tag and CBOR of the unsigned bundle, what the signature
+    /// covers.
+    signed: String,
+    signature: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HeaderFields {
+    v: u32,
+    suite: String,
+    sender: String,
+    recipients: Vec<String>,
+    msg_id: String,
+    timestamp_unix: u64,
+}
+
+#[derive(Serialize)]
+struct Envelope {
+    kid: String,
+    kem_ct: String,
+    wrap_nonce: String,
+    wrapped_cek: String,
+}
+
+/// The signed bundle, with the unsigned part kept as decoded so it can be
+/// re-encoded to the bytes that were signed.
+#[derive(Deserialize)]
+struct SignedView {
+    inner: Value,
+    #[serde(with = "serde_bytes")]
+    sig_dilithium: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct InnerView {
+    header: Value,
+    #[serde(with = "serde_bytes")]
+    pay_nonce: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    ciphertext: Vec<u8>,
+    recipients: Vec<RecipientEnvelope>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Every vector as pretty-printed JSON, ending in a newline. Each wire is
+/// checked to verify under the sender key and to decrypt for each of its
+/// recipients before it is exported.
+pub fn export() -> Result<String, TholosError> {
+    let sender = SenderKey {
+        sid: SENDER_SID.to_string(),
+        dilithium: SigKeys::from_bytes(SIG_PK, SIG_SK).map_err(TholosError::Ser)?,
+        expires_at: None,
+    };
+    let export = Export {
+        format: FORMAT,
+        sender: Sender {
+            sid: SENDER_SID,
+            algorithm: sender.dilithium.level().sig_name(),
+            public_key: hex(SIG_PK),
+            public_key_cbor: hex(&cbor::canonical(&sender.public())?),
+            secret_key: hex(SIG_SK),
+        },
+        vectors: SPECS
+            .iter()
+            .map(|spec| vector(spec, &sender))
+            .collect::<Result<_, _>>()?,
+    };
+    let json =
+        serde_json::to_string_pretty(&export).map_err(|e| TholosError::Ser(e.to_string()))?;
+    Ok(json + "\n")
+}
+
+fn vector(spec: &Spec, sender: &SenderKey) -> Result<Vector, TholosError> {
+    let keypairs: Vec<_> = spec
+        .recipients
+        .iter()
+        .map(|&(kid, seed, level)| keys::recipient_keypair_from_seed(kid, seed, level, false))
+        .collect();
+    let pubs: Vec<_> = keypairs
+        .iter()
+        .map(|(pub_key, _)| pub_key.clone())
+        .collect();
+    let wire = crypto::encrypt_deterministic(spec.encryption_seed, spec.plaintext, sender, &pubs)?;
+    let body = wire::body(&wire).map_err(|_| TholosError::Malformed("wire version"))?;
+
+    let allowed = [(sender.sid.clone(), sender.public().pk_dilithium)];
+    for (pub_key, priv_key) in &keypairs {
+        let decrypted = crypto::decrypt(
+            body,
+            &pub_key.kid,
+            priv_key,
+            allowed.as_slice(),
+            crypto::Aad::default(),
+            None,
+            crypto::Accept::default(),
+        )?;
+        if decrypted.plaintext != spec.plaintext {
+            return Err(TholosError::Malformed("vector plaintext"));
+        }
+    }
+
+    let recipients = spec
+        .recipients
+        .iter()
+        .zip(&keypairs)
+        .map(|(&(kid, seed, level), (pub_key, priv_key))| {
+            Ok(Recipient {
+                kid,
+                algorithm: level.kem_name(),
+                seed: hex(&seed),
+                public_key_cbor: hex(&keys::encode_recipient_pub(pub_key, KeyFormat::Cbor)
+                    .map_err(TholosError::Ser)?),
+                secret_key: hex(&priv_key.sk_kyber.to_bytes()),
+            })
+        })
+        .collect::<Result<_, TholosError>>()?;
+
+    Ok(Vector {
+        name: spec.name,
+        description: spec.description,
+        encryption_seed: hex(&spec.encryption_seed),
+        plaintext: hex(spec.plaintext),
+        recipients,
+        layout: layout(&wire, &sender.public().pk_dilithium)?,
+        wire: hex(&wire),
+    })
+}
+
+/// Split `wire` into its parts, checking that the re-encoded unsigned bundle is
+/// what the signature covers.
+fn layout(wire: &[u8], sender_pk: &[u8]) -> Result<Layout, TholosError> {
+    let signed: SignedView = cbor::from_slice(&wire[1..])?;
+    let signed_cbor = cbor::to_vec_tagged(&signed.inner)?;
+    if !matches!(
+        level::verify(sender_pk, &signed_cbor, &signed.sig_dilithium),
+        Ok(true)
+    ) {
+        return Err(TholosError::BadSignature);
+    }
+
+    let inner: InnerView = cbor::from_slice(&signed_cbor)?;
+    let header_cbor = cbor::to_vec_tagged(&inner.header)?;
+    Ok(Layout {
+        version: wire[0],
+        header: cbor::from_slice(&header_cbor)?,
+        header_cbor: hex(&header_cbor),
+        pay_nonce: hex(&inner.pay_nonce),
+        ciphertext: hex(&inner.ciphertext),
+        envelopes: inner
+            .recipients
+            .iter()
+            .map(|e| Envelope {
+                kid: e.kid.clone(),
+                kem_ct: hex(&e.kem_ct),
+                wrap_nonce: hex(&e.wrap_nonce),
+                wrapped_cek: hex(&e.wrapped_cek),
+            })
+            .collect(),
+        signed: hex(&signed_cbor),
+        signature: hex(&signed.sig_dilithium),
+    })
+}
@@ -0,0 +1,264 @@
+//! Read-only views of a tholos-pq wire.
+//!
+//! `tholos_pq` only offers full deserialization into `BundleSigned`, which
+//! copies the payload ciphertext and every envelope just to look at a few
+//! strings. These views pick out the fields they need and skip the rest while
+//! parsing. Nothing here verifies the signature, so every value read from a
+//! view is whatever the wire claims until `decrypt` succeeds.
+//!
+//! Wires written by this library start with a format version byte followed by
+//! the tholos-pq bundle CBOR. Wires from tholos-pq itself, and from releases
+//! before the version byte, start directly with the CBOR self-describe tag and
+//! are read as version 0. A leading byte only counts as a version when that tag
+//! follows it, so arbitrary garbage is still malformed rather than a version.
+//!
+//! CBOR heads declare the length of every string and the size of every array
+//! and map up front, and a decoder may size its buffers by them. `check` walks
+//! the heads of a bundle before anything decodes it and rejects one that
+//! declares more than its remaining bytes can hold, or more recipients than
+//! allowed, without allocating.
+
+use crate::cbor;
+use serde::Deserialize;
+
+/// Version byte written in front of every wire.
+pub const VERSION: u8 = 1;
+
+/// Version reported for wires without a version byte.
+const UNVERSIONED: u8 = 0;
+
+/// The format version of a wire, or `None` if it has no recognizable start.
+pub fn version(wire: &[u8]) -> Option<u8> {
+    if wire.starts_with(&cbor::SELF_DESCRIBE_TAG) {
+        Some(UNVERSIONED)
+    } else if wire
+        .get(1..)
+        .is_some_and(|rest| rest.starts_with(&cbor::SELF_DESCRIBE_TAG))
+    {
+        Some(wire[0])
+    } else {
+        None
+    }
+}
+
+/// The bundle CBOR of a wire, or `Err(version)` for a version this build cannot
+/// read. A wire without a recognizable start is returned as is, so it fails as
+/// malformed CBOR wherever it is parsed.
+pub fn body(wire: &[u8]) -> Result<&[u8], u8> {
+    match version(wire) {
+        Some(VERSION) => Ok(&wire[1..]),
+        Some(UNVERSIONED) | None => Ok(wire),
+        Some(v) => Err(v),
+    }
+}
+
+#[derive(Deserialize)]
+struct WireView {
+    inner: InnerView,
+}
+
+#[derive(Deserialize)]
+struct InnerView {
+    header: HeaderView,
+    recipients: Vec<EnvelopeView>,
+    #[serde(default)]
+    signer: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct HeaderView {
+    sender: String,
+    msg_id: String,
+    #[serde(default, with = "serde_bytes")]
+    metadata: Option<Vec<u8>>,
+    #[serde(default)]
+    escrow: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct EnvelopeView {
+    kid: String,
+}
+
+fn parse(wire_cbor: &[u8]) -> Result<WireView, cbor::Error> {
+    cbor::from_slice(wire_cbor)
+}
+
+/// The kids of the per-recipient envelopes, in wire order.
+pub fn recipient_kids(wire_cbor: &[u8]) -> Result<Vec<String>, cbor::Error> {
+    Ok(parse(wire_cbor)?
+        .inner
+        .recipients
+        .into_iter()
+        .map(|e| e.kid)
+        .collect())
+}
+
+/// The kids of the per-recipient envelopes in wire order, each with whether it
+/// is the escrow recipient the header names. Unverified like `claimed_sender`.
+pub fn recipients(wire_cbor: &[u8]) -> Result<Vec<(String, bool)>, cbor::Error> {
+    let inner = parse(wire_cbor)?.inner;
+    let escrow = inner.header.escrow;
+    Ok(inner
+        .recipients
+        .into_iter()
+        .map(|e| {
+            let is_escrow = escrow.as_ref() == Some(&e.kid);
+            (e.kid, is_escrow)
+        })
+        .collect())
+}
+
+/// The sid of the sender that signed the wire: the one named in the header, or
+/// the one that re-signed it. Unverified: anyone can put any sid here.
+pub fn claimed_sender(wire_cbor: &[u8]) -> Result<String, cbor::Error> {
+    let inner = parse(wire_cbor)?.inner;
+    Ok(inner.signer.unwrap_or(inner.header.sender))
+}
+
+/// The message id in the header. Unverified like `claimed_sender`, but bound to
+/// the signature and the payload, so a wire whose id was changed fails to
+/// decrypt.
+pub fn message_id(wire_cbor: &[u8]) -> Result<String, cbor::Error> {
+    Ok(parse(wire_cbor)?.inner.header.msg_id)
+}
+
+/// The public metadata in the header, empty if it has none. It is sent in the
+/// clear for intermediaries to route by, and unverified like `message_id`: the
+/// signature covers it, so a wire whose metadata was changed fails to decrypt.
+pub fn metadata(wire_cbor: &[u8]) -> Result<Vec<u8>, cbor::Error> {
+    Ok(parse(wire_cbor)?.inner.header.metadata.unwrap_or_default())
+}
+
+/// Why `check` rejected a wire.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Malformed {
+    /// A head that is cut off, reserved or indefinite, or that declares more
+    /// than the bytes after it hold.
+    Structure(&'static str),
+    /// A recipient list, in the header or of envelopes, longer than allowed.
+    TooManyRecipients(u64),
+}
+
+/// Deepest nesting a bundle may have. A bundle itself is five levels deep.
+const MAX_DEPTH: usize = 16;
+
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+const MAJOR_TAG: u8 = 6;
+
+/// An array or map `check` is inside of.
+#[derive(Clone, Copy, Default)]
+struct Nesting {
+    /// Items still to come, counting keys and values of a map separately.
+    left: u64,
+    map: bool,
+    /// Whether the key just read is `recipients`, so its value is a recipient
+    /// list.
+    recipients_next: bool,
+}
+
+/// Check that every head in the bundle CBOR `wire_cbor` fits in the bytes
+/// after it, and that no `recipients` array has more than `max_recipients`
+/// entries. Every item needs at least one byte, so no valid wire declares more
+/// items than it has bytes left.
+pub fn check(wire_cbor: &[u8], max_recipients: usize) -> Result<(), Malformed> {
+    let mut stack = [Nesting::default(); MAX_DEPTH];
+    stack[0].left = 1;
+    let mut depth = 1;
+    let mut pos = 0;
+
+    while depth > 0 {
+        let top = depth - 1;
+        if stack[top].left == 0 {
+            depth -= 1;
+            continue;
+        }
+        let is_key = stack[top].map && stack[top].left % 2 == 0;
+        let recipients = !is_key && std::mem::take(&mut stack[top].recipients_next);
+        stack[top].left -= 1;
+
+        let (major, arg, head_len) = head(&wire_cbor[pos..])?;
+        pos += head_len;
+        let remaining = (wire_cbor.len() - pos) as u64;
+        match major {
+            MAJOR_BYTES | MAJOR_TEXT => {
+                if arg > remaining {
+                    return Err(Malformed::Structure("length exceeds input"));
+                }
+                let end = pos + arg as usize;
+                if is_key {
+                    stack[top].recipients_next =
+                        major == MAJOR_TEXT && &wire_cbor[pos..end] == b"recipients";
+                }
+                pos = end;
+            }
+            MAJOR_ARRAY | MAJOR_MAP => {
+                let items = if major == MAJOR_MAP {
+                    arg.saturating_mul(2)
+                } else {
+                    arg
+                };
+                if items > remaining {
+                    return Err(Malformed::Structure("count exceeds input"));
+                }
+                if recipients && major == MAJOR_ARRAY && arg > max_recipients as u64 {
+                    return Err(Malformed::TooManyRecipients(arg));
+                }
+                if depth == MAX_DEPTH {
+                    return Err(Malformed::Structure("nesting too deep"));
+                }
+                stack[depth] = Nesting {
+                    left: items,
+                    map: major == MAJOR_MAP,
+                    recipients_next: false,
+                };
+                depth += 1;
+            }
+            // The tagged item takes the place of the tag, as a key or a value
+            MAJOR_TAG => {
+                stack[top].left += 1;
+                stack[top].recipients_next = recipients;
+            }
+            _ => {}
+        }
+    }
+
+    if pos != wire_cbor.len() {
+        return Err(Malformed::Structure("trailing data"));
+    }
+    Ok(())
+}
+
+/// The major type and argument of the CBOR head at the start of `bytes`, and
+/// the length of the head.
+fn head(bytes: &[u8]) -> Result<(u8, u64, usize), Malformed> {
+    let first = *bytes
+        .first()
+        .ok_or(Malformed::Structure("truncated head"))?;
+    let extra = match first & 0x1f {
+        info @ 0..=23 => return Ok((first >> 5, info.into(), 1)),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        // Nothing writes indefinite lengths into a wire
+        31 => return Err(Malformed::Structure("indefinite length")),
+        _ => return Err(Malformed::Structure("reserved head")),
+    };
+    let arg = bytes
+        .get(1..=extra)
+        .ok_or(Malformed::Structure("truncated head"))?;
+    Ok((first >> 5, read_be(arg), 1 + extra))
+}
+
+/// The unsigned integer in `bytes`, one to eight of them, most significant
+/// first. CBOR writes every head argument, i.e. every length, count and tag in
+/// a wire, in network byte order whatever the host's.
+fn read_be(bytes: &[u8]) -> u64 {
+    let mut be = [0u8; 8];
+    be[8 - bytes.len()..].copy_from_slice(bytes);
+    u64::from_be_bytes(be)
+}
@@ -4,6 +4,12 @@ defmodule ExTholosPqPropertyTest do
 
   import StreamData
 
+  # Keygen rejects ids that are already stored, and generated ids repeat
+  # across runs, so every run that generates keys starts from an empty store
+  setup do
+    :ok = ExTholosPq.clear_all_keys()
+  end
+
   # Helper to generate unique identifiers
   defp unique_id, do: string(:alphanumeric, min_length: 1, max_length: 255)
 
@@ -22,6 +28,8 @@ defmodule ExTholosPqPropertyTest do
             message <- message_data(),
             max_runs: 200
           ) do
+      :ok = ExTholosPq.clear_all_keys()
+
       # Generate keys
       {:ok, {returned_kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair(kid)
       {:ok, {returned_sid, sender_pub}} = ExTholosPq.gen_sender_keypair(sid)
@@ -47,6 +55,8 @@ defmodule ExTholosPqPropertyTest do
             message <- message_data(),
             max_runs: 100
           ) do
+      :ok = ExTholosPq.clear_all_keys()
+
       # Generate recipient keys
       recipients =
         Enum.map(1..num_recipients, fn i ->
@@ -81,6 +91,8 @@ defmodule ExTholosPqPropertyTest do
             message <- message_data(),
             max_runs: 100
           ) do
+      :ok = ExTholosPq.clear_all_keys()
+
       {:ok, {returned_kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair(kid)
       assert returned_kid == kid
       {:ok, {returned_sid, sender_pub}} = ExTholosPq.gen_sender_keypair(sid)
@@ -105,10 +117,13 @@ defmodule ExTholosPqPropertyTest do
     check all(
             kid1 <- unique_id(),
             kid2 <- unique_id(),
+            kid2 != kid1,
             sid <- unique_id(),
             message <- message_data(),
             max_runs: 100
           ) do
+      :ok = ExTholosPq.clear_all_keys()
+
       # Generate keys for two different recipients
       {:ok, {returned_kid1, pub1}} = ExTholosPq.gen_recipient_keypair(kid1)
       assert returned_kid1 == kid1
@@ -126,8 +141,139 @@ defmodule ExTholosPqPropertyTest do
     end
   end
 
+  property "decrypt returns an error term for mutated or truncated wires" do
+    {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("mutated_wire_rcpt")
+    {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("mutated_wire_sender")
+    {:ok, ciphertext} = ExTholosPq.encrypt("mutate me", sid, [pub])
+
+    check all(
+            position <- integer(0..(byte_size(ciphertext) - 1)),
+            mask <- integer(1..255),
+            truncate? <- boolean(),
+            max_runs: 200
+          ) do
+      <<head::binary-size(position), byte, tail::binary>> = ciphertext
+
+      wire =
+        if truncate?, do: head, else: <<head::binary, Bitwise.bxor(byte, mask), tail::binary>>
+
+      # A flip can turn a CBOR head into another encoding of the same value, which
+      # still verifies; anything else has to come back as an error term, not raise
+      case ExTholosPq.decrypt(wire, kid, [sender_pub]) do
+        {:ok, plaintext} -> assert plaintext == "mutate me"
+        result -> assert {:error, _reason} = result
+      end
+    end
+  end
+
+  # Every parser of untrusted wires has to answer malformed input with an error
+  # term: a raise would take the caller down, and a Rust panic would surface as
+  # :internal_crypto_panic, or crash the node in a function without a guard
+  defp wire_parsers(kid, sender_pub) do
+    [
+      &ExTholosPq.decrypt(&1, kid, [sender_pub]),
+      &ExTholosPq.decrypt_with_sender(&1, kid, []),
+      &ExTholosPq.wire_recipients/1,
+      &ExTholosPq.wire_sender_unverified/1,
+      &ExTholosPq.wire_message_id/1,
+      &ExTholosPq.wire_metadata/1
+    ]
+  end
+
+  defp assert_clean_error(result) do
+    assert {:error, reason} = result
+    refute reason == :internal_crypto_panic
+  end
+
+  # Random bytes, bare or behind the version byte and CBOR self-describe tag a
+  # wire starts with, so the parsers get past the version check too
+  defp garbage_wire do
+    one_of([
+      binary(max_length: 512),
+      map(binary(max_length: 512), &(<<0xD9, 0xD9, 0xF7>> <> &1)),
+      map(binary(max_length: 512), &(<<1, 0xD9, 0xD9, 0xF7>> <> &1))
+    ])
+  end
+
+  property "wire parsers return an error term for random bytes" do
+    {:ok, {kid, _pub}} = ExTholosPq.gen_recipient_keypair("garbage_wire_rcpt")
+    {:ok, {_sid, sender_pub}} = ExTholosPq.gen_sender_keypair("garbage_wire_sender")
+    parsers = wire_parsers(kid, sender_pub)
+
+    check all(wire <- garbage_wire(), max_runs: 500) do
+      for parse <- parsers, do: assert_clean_error(parse.(wire))
+    end
+  end
+
+  property "wire parsers never panic on mutated wires" do
+    {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("mutated_view_rcpt")
+    {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("mutated_view_sender")
+    {:ok, ciphertext} = ExTholosPq.encrypt("mutate me", sid, [pub], metadata: "meta")
+    parsers = wire_parsers(kid, sender_pub)
+
+    check all(
+            mutations <-
+              list_of({integer(0..(byte_size(ciphertext) - 1)), byte()},
+                min_length: 1,
+                max_length: 4
+              ),
+            cut <- integer(0..byte_size(ciphertext)),
+            max_runs: 300
+          ) do
+      wire =
+        mutations
+        |> Enum.reduce(ciphertext, fn {position, value}, acc ->
+          <<head::binary-size(position), _, tail::binary>> = acc
+          <<head::binary, value, tail::binary>>
+        end)
+        |> binary_part(0, cut)
+
+      # The views do not verify the signature, so a mutation outside the fields
+      # they read still parses; only a panic or a raise fails the property
+      for parse <- parsers do
+        case parse.(wire) do
+          {:ok, _} -> :ok
+          result -> assert_clean_error(result)
+        end
+      end
+    end
+  end
+
+  test "wire parsers return an error term for hand-picked malformed wires" do
+    {:ok, {kid, _pub}} = ExTholosPq.gen_recipient_keypair("seed_wire_rcpt")
+    {:ok, {_sid, sender_pub}} = ExTholosPq.gen_sender_keypair("seed_wire_sender")
+    tag = <<1, 0xD9, 0xD9, 0xF7>>
+    max_u64 = :binary.copy(<<0xFF>>, 8)
+
+    seeds = [
+      # Empty, a lone version byte and a version byte with only the tag
+      "",
+      <<1>>,
+      tag,
+      # A byte string, a text string, an array and a map declaring 2^64 - 1 items
+      tag <> <<0x5B>> <> max_u64,
+      tag <> <<0x7B>> <> max_u64,
+      tag <> <<0x9B>> <> max_u64,
+      tag <> <<0xBB>> <> max_u64,
+      # Indefinite-length map that is never closed
+      tag <> <<0xBF, 0x65, "inner">>,
+      # Arrays nested far deeper than any wire
+      tag <> :binary.copy(<<0x81>>, 10_000),
+      # A bundle whose parts have the wrong types
+      tag <> <<0xA2, 0x65, "inner", 0xF6, 0x6D, "sig_dilithium", 0x40>>,
+      # An unknown version byte in front of the tag
+      <<0x7F, 0xD9, 0xD9, 0xF7, 0xA0>>
+    ]
+
+    for wire <- seeds, parse <- wire_parsers(kid, sender_pub) do
+      assert_clean_error(parse.(wire))
+    end
+  end
+
   property "keypair generation produces valid keys" do
     check all(kid <- unique_id(), max_runs: 50) do
+      :ok = ExTholosPq.clear_all_keys()
+
       {:ok, {returned_kid, public_key}} = ExTholosPq.gen_recipient_keypair(kid)
       assert returned_kid == kid
 
@@ -139,6 +285,8 @@ defmodule ExTholosPqPropertyTest do
 
   property "sender keypair generation produces valid keys" do
     check all(sid <- unique_id(), max_runs: 50) do
+      :ok = ExTholosPq.clear_all_keys()
+
       {:ok, {returned_sid, public_key}} = ExTholosPq.gen_sender_keypair(sid)
       assert returned_sid == sid
 
@@ -154,6 +302,8 @@ defmodule ExTholosPqPropertyTest do
             id2 <- unique_id(),
             max_runs: 100
           ) do
+      :ok = ExTholosPq.clear_all_keys()
+
       # Ensure different IDs
       if id1 != id2 do
         {:ok, {returned_id1, pub1}} = ExTholosPq.gen_recipient_keypair(id1)
@@ -172,6 +322,8 @@ defmodule ExTholosPqPropertyTest do
             sid <- unique_id(),
             max_runs: 100
           ) do
+      :ok = ExTholosPq.clear_all_keys()
+
       {:ok, {returned_kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair(kid)
       assert returned_kid == kid
       {:ok, {returned_sid, sender_pub}} = ExTholosPq.gen_sender_keypair(sid)
@@ -194,6 +346,8 @@ defmodule ExTholosPqPropertyTest do
             message <- binary(min_length: 0, max_length: 10_000),
             max_runs: 100
           ) do
+      :ok = ExTholosPq.clear_all_keys()
+
       {:ok, {returned_kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair(kid)
       assert returned_kid == kid
       {:ok, {returned_sid, sender_pub}} = ExTholosPq.gen_sender_keypair(sid)
@@ -213,6 +367,8 @@ defmodule ExTholosPqPropertyTest do
             message <- message_data(),
             max_runs: 100
           ) do
+      :ok = ExTholosPq.clear_all_keys()
+
       # Generate multiple recipients
       recipients =
         Enum.map(1..num_recipients, fn i ->
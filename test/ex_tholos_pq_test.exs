@@ -1,5 +1,13 @@
 defmodule ExTholosPqTest do
   use ExUnit.Case
+
+  # Keygen rejects ids that are already stored, so every test and doctest
+  # starts from an empty store, and without an escrow recipient
+  setup do
+    :ok = ExTholosPq.clear_all_keys()
+    :ok = ExTholosPq.clear_escrow_recipient()
+  end
+
   doctest ExTholosPq
 
   describe "gen_recipient_keypair/1" do
@@ -16,6 +24,158 @@ defmodule ExTholosPqTest do
       assert kid1 != kid2
       assert pk1 != pk2
     end
+
+    test "rejects a kid that is already stored and keeps its key" do
+      {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("ExistingRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("ExistingRecipientSender")
+
+      assert {:error, {:kid_exists, ^kid}} = ExTholosPq.gen_recipient_keypair(kid)
+      assert {:error, {:kid_exists, ^kid}} = ExTholosPq.gen_recipient_keypair_exported(kid)
+
+      assert {:error, {:kid_exists, ^kid}} =
+               ExTholosPq.gen_recipient_keypair_from_seed(kid, :crypto.strong_rand_bytes(32))
+
+      {:ok, ciphertext} = ExTholosPq.encrypt("kept", sid, [pub])
+      assert {:ok, "kept"} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+    end
+
+    test "replaces a stored key with overwrite: true" do
+      {:ok, {kid, old_pub}} = ExTholosPq.gen_recipient_keypair("OverwrittenRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("OverwrittenRecipientSender")
+      {:ok, old_ciphertext} = ExTholosPq.encrypt("old", sid, [old_pub])
+
+      assert {:ok, {^kid, new_pub}} = ExTholosPq.gen_recipient_keypair(kid, overwrite: true)
+      assert new_pub != old_pub
+
+      {:ok, new_ciphertext} = ExTholosPq.encrypt("new", sid, [new_pub])
+      assert {:ok, "new"} = ExTholosPq.decrypt(new_ciphertext, kid, [sender_pub])
+      assert {:error, _reason} = ExTholosPq.decrypt(old_ciphertext, kid, [sender_pub])
+    end
+
+    test "rejects overwrite for key resources" do
+      assert {:error, {:invalid_option, "overwrite"}} =
+               ExTholosPq.gen_recipient_key_resource("ResourceRecipient", overwrite: true)
+    end
+  end
+
+  describe "gen_recipient_keypairs/1" do
+    test "generates and stores 100 keys in one call" do
+      kids = for i <- 1..100, do: "BulkRecipient#{i}"
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("BulkSender")
+
+      assert {:ok, results} = ExTholosPq.gen_recipient_keypairs(kids)
+      assert length(results) == 100
+
+      for {kid, result} <- Enum.zip(kids, results) do
+        assert {:ok, {^kid, pub}} = result
+        {:ok, wire} = ExTholosPq.encrypt("provisioned", sid, [pub])
+        assert {:ok, "provisioned"} = ExTholosPq.decrypt(wire, kid, [sender_pub])
+      end
+    end
+
+    test "reports duplicates per kid without replacing stored keys" do
+      {:ok, {"BulkExisting", existing_pub}} = ExTholosPq.gen_recipient_keypair("BulkExisting")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("BulkDuplicateSender")
+
+      assert {:ok, [first, second, third]} =
+               ExTholosPq.gen_recipient_keypairs(["BulkNew", "BulkExisting", "BulkNew"])
+
+      assert {:ok, {"BulkNew", _pub}} = first
+      assert {:error, {:kid_exists, "BulkExisting"}} = second
+      assert {:error, {:kid_exists, "BulkNew"}} = third
+      {:ok, wire} = ExTholosPq.encrypt("kept", sid, [existing_pub])
+      assert {:ok, "kept"} = ExTholosPq.decrypt(wire, "BulkExisting", [sender_pub])
+    end
+  end
+
+  describe "gen_recipient_keypair_from_seed/2" do
+    test "the same seed yields identical public keys" do
+      seed = :crypto.strong_rand_bytes(32)
+
+      {:ok, {_kid, pub1}} = ExTholosPq.gen_recipient_keypair_from_seed("SeededRecipient", seed)
+
+      {:ok, {_kid, pub2}} =
+        ExTholosPq.gen_recipient_keypair_from_seed("SeededRecipient", seed, overwrite: true)
+
+      {:ok, {_kid, other}} =
+        ExTholosPq.gen_recipient_keypair_from_seed("SeededRecipient", <<1::256>>,
+          overwrite: true
+        )
+
+      assert pub1 == pub2
+      assert pub1 != other
+    end
+
+    test "stores a key that decrypts" do
+      seed = :crypto.strong_rand_bytes(32)
+      {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair_from_seed("SeededRecipient", seed)
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("SeededSender")
+
+      {:ok, ciphertext} = ExTholosPq.encrypt("derived", sid, [pub])
+
+      # Replace the stored key, then restore it from the seed
+      {:ok, _} = ExTholosPq.gen_recipient_keypair(kid, overwrite: true)
+      assert {:error, _reason} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+      {:ok, _} = ExTholosPq.gen_recipient_keypair_from_seed(kid, seed, overwrite: true)
+      assert {:ok, "derived"} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+    end
+
+    test "rejects seeds that are not 32 bytes" do
+      for seed <- ["", :crypto.strong_rand_bytes(31), :crypto.strong_rand_bytes(33)] do
+        assert {:error, :bad_seed_length} =
+                 ExTholosPq.gen_recipient_keypair_from_seed("SeededRecipient", seed)
+      end
+    end
+  end
+
+  describe "gen_recipient_keypair_exported/1" do
+    test "returns a private key usable outside the key store" do
+      {:ok, {kid, recipient_pub, private_key}} =
+        ExTholosPq.gen_recipient_keypair_exported("ExportedAtBirth")
+
+      assert kid == "ExportedAtBirth"
+      assert is_binary(private_key)
+      assert {:ok, ^private_key} = ExTholosPq.export_recipient_key(kid)
+
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender1")
+      {:ok, ciphertext} = ExTholosPq.encrypt("persisted", sid, [recipient_pub])
+
+      assert {:ok, "persisted"} =
+               ExTholosPq.decrypt_with_key(ciphertext, kid, private_key, [sender_pub])
+
+      assert {:ok, "persisted"} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+    end
+  end
+
+  describe "gen_sender_keypair_exported/1" do
+    test "returns a keypair that signs verifiable wires after reimport" do
+      {:ok, {sid, sender_pub, keypair}} =
+        ExTholosPq.gen_sender_keypair_exported("ExportedSenderAtBirth")
+
+      assert sid == "ExportedSenderAtBirth"
+      assert {:ok, ^keypair} = ExTholosPq.export_sender_keypair(sid)
+
+      # Stand in for another node, which only has what was exported
+      :ok = ExTholosPq.clear_all_keys()
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("ReimportRecipient")
+      assert :ok = ExTholosPq.load_sender_keypair(sid, sender_pub, keypair)
+
+      {:ok, ciphertext} = ExTholosPq.encrypt("from afar", sid, [recipient_pub])
+      assert {:ok, "from afar"} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+
+      {:ok, with_keys} = ExTholosPq.encrypt_with_keys("from afar", keypair, [recipient_pub])
+      assert {:ok, "from afar"} = ExTholosPq.decrypt(with_keys, kid, [sender_pub])
+    end
+
+    test "takes the keygen options and rejects a stored sid" do
+      {:ok, {sid, _pub, _keypair}} =
+        ExTholosPq.gen_sender_keypair_exported("ExportedLevel5", security_level: :level5)
+
+      assert {:error, {:sid_exists, ^sid}} = ExTholosPq.gen_sender_keypair_exported(sid)
+
+      assert {:ok, {^sid, _, _}} =
+               ExTholosPq.gen_sender_keypair_exported(sid, overwrite: true)
+    end
   end
 
   describe "gen_sender_keypair/1" do
@@ -32,6 +192,372 @@ defmodule ExTholosPqTest do
       assert sid1 != sid2
       assert pk1 != pk2
     end
+
+    test "rejects a sid that is already stored and keeps its key" do
+      {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("ExistingSenderRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("ExistingSender")
+
+      assert {:error, {:sid_exists, ^sid}} = ExTholosPq.gen_sender_keypair(sid)
+
+      assert {:error, {:sid_exists, ^sid}} =
+               ExTholosPq.gen_sender_keypair(sid, security_level: :level5)
+
+      {:ok, ciphertext} = ExTholosPq.encrypt("kept", sid, [pub])
+      assert {:ok, "kept"} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+    end
+
+    test "replaces a stored key with overwrite: true" do
+      {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("OverwrittenSenderRecipient")
+      {:ok, {sid, old_sender_pub}} = ExTholosPq.gen_sender_keypair("OverwrittenSender")
+
+      assert {:ok, {^sid, new_sender_pub}} = ExTholosPq.gen_sender_keypair(sid, overwrite: true)
+      assert new_sender_pub != old_sender_pub
+
+      {:ok, ciphertext} = ExTholosPq.encrypt("new", sid, [pub])
+      assert {:ok, "new"} = ExTholosPq.decrypt(ciphertext, kid, [new_sender_pub])
+      assert {:error, _reason} = ExTholosPq.decrypt(ciphertext, kid, [old_sender_pub])
+    end
+  end
+
+  describe "identifiers" do
+    @longest :binary.copy("k", 256)
+    @too_long :binary.copy("k", 257)
+
+    test "keygen rejects empty and oversized kids and sids" do
+      for id <- ["", @too_long] do
+        assert {:error, {:invalid_identifier, _}} = ExTholosPq.gen_recipient_keypair(id)
+        assert {:error, {:invalid_identifier, _}} = ExTholosPq.gen_sender_keypair(id)
+        assert {:error, {:invalid_identifier, _}} = ExTholosPq.gen_recipient_key_resource(id)
+        assert {:error, {:invalid_identifier, _}} = ExTholosPq.gen_sender_key_resource(id)
+
+        assert {:error, {:invalid_identifier, _}} =
+                 ExTholosPq.gen_recipient_keypair_from_seed(id, :binary.copy(<<1>>, 32))
+      end
+
+      assert {:error, {:invalid_identifier, "empty"}} = ExTholosPq.gen_recipient_keypair("")
+
+      assert {:error, {:invalid_identifier, "longer than 256 bytes"}} =
+               ExTholosPq.gen_sender_keypair(@too_long)
+
+      assert {:error, {:invalid_identifier, "contains a NUL byte"}} =
+               ExTholosPq.gen_recipient_keypair("nul\0kid")
+
+      assert {:ok, []} = ExTholosPq.list_recipient_kids()
+      assert {:ok, []} = ExTholosPq.list_sender_sids()
+    end
+
+    test "accepts an identifier of exactly 256 bytes" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair(@longest)
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair(@longest)
+      {:ok, ciphertext} = ExTholosPq.encrypt("long ids", sid, [recipient_pub])
+
+      assert {:ok, "long ids"} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+    end
+
+    test "are checked per kid in gen_recipient_keypairs/1" do
+      assert {:ok, [{:ok, {"BatchOk", _}}, {:error, {:invalid_identifier, "empty"}}]} =
+               ExTholosPq.gen_recipient_keypairs(["BatchOk", ""])
+    end
+
+    test "encrypt and decrypt reject them before any lookup or crypto" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("IdRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("IdSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("x", sid, [recipient_pub])
+
+      assert {:error, {:invalid_identifier, "empty"}} =
+               ExTholosPq.encrypt("x", "", [recipient_pub])
+
+      assert {:error, {:invalid_identifier, _}} =
+               ExTholosPq.decrypt(ciphertext, @too_long, [sender_pub])
+
+      for bad_kid <- ["", "nul\0kid", @too_long] do
+        assert {:error, {:invalid_identifier, _}} =
+                 ExTholosPq.decrypt_many([ciphertext], bad_kid, [sender_pub])
+      end
+
+      # A kid is written into every header, so one from a foreign public key is checked too
+      {:ok, json} = ExTholosPq.recipient_pub_to_json(recipient_pub)
+      oversized_json = String.replace(json, kid, @too_long)
+      {:ok, oversized_pub} = ExTholosPq.recipient_pub_from_json(oversized_json)
+
+      assert {:error, {:invalid_identifier, "longer than 256 bytes"}} =
+               ExTholosPq.encrypt("x", sid, [oversized_pub])
+    end
+  end
+
+  describe "security levels" do
+    test "every recipient level round trips with every sender level" do
+      for recipient_level <- [:level1, :level3, :level5],
+          sender_level <- [:level1, :level3, :level5] do
+        name = "Level-#{recipient_level}-#{sender_level}"
+        {:ok, {kid, pub}} =
+          ExTholosPq.gen_recipient_keypair(name, security_level: recipient_level)
+
+        {:ok, {sid, sender_pub}} =
+          ExTholosPq.gen_sender_keypair(name <> "-sender", security_level: sender_level)
+
+        {:ok, ct} = ExTholosPq.encrypt("leveled", sid, [pub])
+        assert {:ok, "leveled"} = ExTholosPq.decrypt(ct, kid, [sender_pub])
+      end
+    end
+
+    test "the level sets the public key size" do
+      sizes =
+        for level <- [:level1, :level3, :level5] do
+          {:ok, {_kid, pub}} =
+            ExTholosPq.gen_recipient_keypair("LevelSize", security_level: level, overwrite: true)
+
+          byte_size(pub)
+        end
+
+      assert sizes == Enum.sort(sizes)
+      assert Enum.uniq(sizes) == sizes
+    end
+
+    test "a level5 wire cannot be decrypted with a level1 key" do
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("LevelMismatchSender")
+      {:ok, {kid, pub}} =
+        ExTholosPq.gen_recipient_keypair("LevelMismatch", security_level: :level5)
+
+      {:ok, ct} = ExTholosPq.encrypt("level5 only", sid, [pub])
+
+      {:ok, {^kid, _}} =
+        ExTholosPq.gen_recipient_keypair(kid, security_level: :level1, overwrite: true)
+
+      assert {:error, {:security_level_mismatch, _}} =
+               ExTholosPq.decrypt(ct, kid, [sender_pub])
+    end
+
+    test "a sender key of another level does not verify the wire" do
+      {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("SenderLevelRecipient")
+
+      {:ok, {sid, _level5_pub}} =
+        ExTholosPq.gen_sender_keypair("SenderLevelMismatch", security_level: :level5)
+
+      {:ok, ct} = ExTholosPq.encrypt("signed at level5", sid, [pub])
+
+      {:ok, {^sid, level1_pub}} =
+        ExTholosPq.gen_sender_keypair("SenderLevelMismatch",
+          security_level: :level1,
+          overwrite: true
+        )
+
+      assert {:error, {:security_level_mismatch, _}} =
+               ExTholosPq.decrypt(ct, kid, [level1_pub])
+    end
+
+    test "exported keys and rotations keep their level" do
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("LevelExportSender")
+
+      {:ok, {kid, pub, priv}} =
+        ExTholosPq.gen_recipient_keypair_exported("LevelExport", security_level: :level3)
+
+      {:ok, ct} = ExTholosPq.encrypt("exported", sid, [pub])
+      assert {:ok, "exported"} = ExTholosPq.decrypt_with_key(ct, kid, priv, [sender_pub])
+
+      {:ok, {2, v2_pub}} = ExTholosPq.rotate_recipient_key(kid)
+      assert byte_size(v2_pub) == byte_size(pub)
+    end
+
+    test "an unknown level is rejected" do
+      assert {:error, {:invalid_option, "security_level"}} =
+               ExTholosPq.gen_sender_keypair("BadLevel", security_level: :level2)
+    end
+
+    test "recipients of different levels are rejected before encrypting" do
+      {:ok, {sid, _}} = ExTholosPq.gen_sender_keypair("MixedLevelSender")
+      {:ok, {_, level1}} = ExTholosPq.gen_recipient_keypair("MixedL1", security_level: :level1)
+      {:ok, {_, level3}} = ExTholosPq.gen_recipient_keypair("MixedL3", security_level: :level3)
+
+      assert {:error, {:param_mismatch, :level1, :level3}} =
+               ExTholosPq.encrypt("mixed", sid, [level1, level3])
+
+      assert {:error, {:param_mismatch, :level3, :level1}} =
+               ExTholosPq.encrypt_batch(sid, ["a", "b"], [level3, level1])
+
+      assert {:error, {:param_mismatch, :level1, :level3}} =
+               ExTholosPq.encrypt_init(sid, [level1, level3])
+    end
+  end
+
+  describe "JSON public keys" do
+    test "round-trip through JSON and encrypt" do
+      {:ok, {kid, json}} = ExTholosPq.gen_recipient_keypair("JsonRecipient", format: :json)
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("JsonSender")
+
+      assert json =~ ~r/^\{"kid":"JsonRecipient","pk_kyber":"[A-Za-z0-9_-]+"\}$/
+      assert {:ok, pub} = ExTholosPq.recipient_pub_from_json(json)
+      assert {:ok, ^json} = ExTholosPq.recipient_pub_to_json(pub)
+
+      {:ok, ciphertext} = ExTholosPq.encrypt("via json", sid, [pub])
+      assert {:ok, "via json"} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+    end
+
+    test "rejects invalid JSON and formats" do
+      assert {:error, {:deserialize_recipient, _}} = ExTholosPq.recipient_pub_from_json("{}")
+      assert {:error, {:deserialize_recipient, _}} =
+               ExTholosPq.recipient_pub_from_json(~s({"kid":"a","pk_kyber":"not base64"}))
+
+      assert {:error, {:invalid_option, "format"}} =
+               ExTholosPq.gen_recipient_keypair("JsonRecipient", format: :xml)
+
+      assert {:error, {:invalid_option, "format"}} =
+               ExTholosPq.gen_sender_keypair("JsonSender", format: :json)
+    end
+  end
+
+  describe "raw recipient public keys" do
+    test "round-trip at every level and encrypt like the CBOR form" do
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("RawSender")
+
+      for {level, pk_size} <- [level1: 800, level3: 1184, level5: 1568] do
+        {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("RawRcpt", security_level: level)
+
+        assert {:ok, raw} = ExTholosPq.recipient_pub_to_raw(pub)
+        assert <<^pk_size::16, _pk::binary-size(pk_size), "RawRcpt">> = raw
+        assert byte_size(raw) < byte_size(pub)
+        assert {:ok, ^pub} = ExTholosPq.recipient_pub_from_raw(raw)
+
+        {:ok, raw_pub} = ExTholosPq.recipient_pub_from_raw(raw)
+        {:ok, ciphertext} = ExTholosPq.encrypt("via raw", sid, [raw_pub])
+        assert {:ok, "via raw"} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+      end
+    end
+
+    test "rejects truncated input and invalid kids" do
+      {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("RawRecipient")
+      {:ok, raw} = ExTholosPq.recipient_pub_to_raw(pub)
+      <<len::16, pk::binary-size(len), _kid::binary>> = raw
+
+      assert {:error, {:deserialize_recipient, _}} = ExTholosPq.recipient_pub_from_raw(<<1>>)
+
+      assert {:error, {:deserialize_recipient, _}} =
+               ExTholosPq.recipient_pub_from_raw(<<len::16, binary_part(pk, 0, 10)::binary>>)
+
+      assert {:error, {:deserialize_recipient, _}} =
+               ExTholosPq.recipient_pub_from_raw(<<len::16, pk::binary, 0xFF>>)
+
+      assert {:error, {:invalid_identifier, "empty"}} =
+               ExTholosPq.recipient_pub_from_raw(<<len::16, pk::binary>>)
+    end
+  end
+
+  describe "validate_recipient_pub/1" do
+    test "accepts generated keys at every level" do
+      for level <- [:level1, :level3, :level5] do
+        {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("ValidRcpt", security_level: level)
+        assert {:ok, :valid} = ExTholosPq.validate_recipient_pub(pub)
+      end
+    end
+
+    test "rejects a truncated key, also in encrypt" do
+      {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("TruncatedRcpt")
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("TruncatedSender")
+      {:ok, json} = ExTholosPq.recipient_pub_to_json(pub)
+      [_, encoded] = Regex.run(~r/"pk_kyber":"([^"]+)"/, json)
+      pk = Base.url_decode64!(encoded, padding: false)
+
+      truncated = binary_part(pk, 0, byte_size(pk) - 1)
+      # A coefficient of 0xFFF, above the modulus, in an otherwise intact key
+      <<_::24, rest::binary>> = pk
+      unreduced = <<0xFF, 0xFF, 0xFF, rest::binary>>
+
+      for bad_pk <- [truncated, unreduced] do
+        json = ~s({"kid":"#{kid}","pk_kyber":"#{Base.url_encode64(bad_pk, padding: false)}"})
+        {:ok, bad_pub} = ExTholosPq.recipient_pub_from_json(json)
+
+        assert {:error, :invalid_recipient_key} = ExTholosPq.validate_recipient_pub(bad_pub)
+        assert {:error, :invalid_recipient_key} =
+                 ExTholosPq.encrypt("secret", sid, [pub, bad_pub])
+      end
+    end
+  end
+
+  describe "public key fingerprints" do
+    test "are the same for every serialization of a key" do
+      {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("FpRecipient")
+      {:ok, json} = ExTholosPq.recipient_pub_to_json(pub)
+      [_, encoded] = Regex.run(~r/"pk_kyber":"([^"]+)"/, json)
+      pk = Base.url_decode64!(encoded, padding: false)
+
+      # Fields in the opposite order, and the kid length in a wider head than needed
+      reordered =
+        <<0xA2, 0x68, "pk_kyber", 0x59, byte_size(pk)::16, pk::binary, 0x63, "kid", 0x78,
+          byte_size(kid), kid::binary>>
+
+      assert reordered != pub
+      assert {:ok, fingerprint} = ExTholosPq.recipient_pub_fingerprint(pub)
+      assert {:ok, ^fingerprint} = ExTholosPq.recipient_pub_fingerprint(reordered)
+      assert fingerprint =~ ~r/^[0-9a-f]{64}$/
+    end
+
+    test "differ between keys" do
+      {:ok, {_kid, pub1}} = ExTholosPq.gen_recipient_keypair("FpRecipient1")
+      {:ok, {_kid, pub2}} = ExTholosPq.gen_recipient_keypair("FpRecipient2")
+      {:ok, {_sid, sender_pub}} = ExTholosPq.gen_sender_keypair("FpSender")
+
+      {:ok, fp1} = ExTholosPq.recipient_pub_fingerprint(pub1)
+      {:ok, fp2} = ExTholosPq.recipient_pub_fingerprint(pub2)
+      {:ok, sender_fp} = ExTholosPq.sender_pub_fingerprint(sender_pub)
+
+      assert fp1 != fp2
+      assert {:ok, ^sender_fp} = ExTholosPq.sender_pub_fingerprint(sender_pub)
+      assert {:error, {:deserialize_sender, _}} = ExTholosPq.sender_pub_fingerprint(pub1)
+    end
+  end
+
+  describe "recipient_pub_to_canonical_cbor/1" do
+    test "encodes the same key to the same bytes every time" do
+      seed = :crypto.strong_rand_bytes(32)
+      {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair_from_seed("CanonicalSeeded", seed)
+
+      {:ok, {^kid, regenerated}} =
+        ExTholosPq.gen_recipient_keypair_from_seed(kid, seed, overwrite: true)
+
+      {:ok, json} = ExTholosPq.recipient_pub_to_json(pub)
+
+      assert regenerated == pub
+      assert {:ok, ^pub} = ExTholosPq.recipient_pub_from_json(json)
+      assert {:ok, ^pub} = ExTholosPq.recipient_pub_to_canonical_cbor(pub)
+      assert {:ok, ^pub} = ExTholosPq.recipient_pub_to_canonical_cbor(pub)
+    end
+
+    test "a decode and re-encode round trip gives the canonical bytes" do
+      {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("CanonicalRoundTrip")
+      {:ok, json} = ExTholosPq.recipient_pub_to_json(pub)
+      [_, encoded] = Regex.run(~r/"pk_kyber":"([^"]+)"/, json)
+      pk = Base.url_decode64!(encoded, padding: false)
+
+      # Fields in the opposite order, and the kid length in a wider head than needed
+      reordered =
+        <<0xA2, 0x68, "pk_kyber", 0x59, byte_size(pk)::16, pk::binary, 0x63, "kid", 0x78,
+          byte_size(kid), kid::binary>>
+
+      assert {:ok, ^pub} = ExTholosPq.recipient_pub_to_canonical_cbor(reordered)
+      {:ok, canonical} = ExTholosPq.recipient_pub_to_canonical_cbor(pub)
+      assert {:ok, ^canonical} = ExTholosPq.recipient_pub_to_canonical_cbor(canonical)
+    end
+
+    test "rejects an input that is not a recipient public key" do
+      assert {:error, {:deserialize_recipient, _}} =
+               ExTholosPq.recipient_pub_to_canonical_cbor("not cbor")
+    end
+  end
+
+  describe "public keys written by serde_cbor" do
+    test "decode and re-encode to the same bytes" do
+      {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("SerdeCborRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("SerdeCborSender")
+      {:ok, legacy_pub} = ExTholosPq.serde_cbor_recipient_pub(pub)
+      {:ok, legacy_sender_pub} = ExTholosPq.serde_cbor_sender_pub(sender_pub)
+
+      assert legacy_pub == pub
+      assert legacy_sender_pub == sender_pub
+      assert {:ok, ^pub} = ExTholosPq.recipient_pub_to_canonical_cbor(legacy_pub)
+
+      {:ok, ciphertext} = ExTholosPq.encrypt("written before", sid, [legacy_pub])
+      assert {:ok, "written before"} = ExTholosPq.decrypt(ciphertext, kid, [legacy_sender_pub])
+    end
   end
 
   describe "encrypt/3 and decrypt/3" do
@@ -84,6 +610,164 @@ defmodule ExTholosPqTest do
     end
   end
 
+  describe "encrypt/4 and decrypt/4" do
+    test "round-trips with matching associated data" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("AadRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("AadSender")
+
+      aad = "tenant-42:invoice"
+      {:ok, ciphertext} = ExTholosPq.encrypt("bound to context", sid, [recipient_pub], aad)
+
+      assert {:ok, "bound to context"} =
+               ExTholosPq.decrypt(ciphertext, kid, [sender_pub], aad)
+    end
+
+    test "flipping one byte of the associated data fails decryption" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("AadRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("AadSender")
+
+      aad = "tenant-42:invoice"
+      {:ok, ciphertext} = ExTholosPq.encrypt("bound to context", sid, [recipient_pub], aad)
+
+      <<first, rest::binary>> = aad
+      flipped = <<Bitwise.bxor(first, 1), rest::binary>>
+
+      assert {:error, :aad_mismatch} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub], flipped)
+      assert {:error, :aad_mismatch} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub], "")
+      assert {:error, _reason} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+    end
+
+    test "empty associated data matches encrypt/3" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("AadRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("AadSender")
+
+      {:ok, ciphertext} = ExTholosPq.encrypt("no context", sid, [recipient_pub])
+      assert {:ok, "no context"} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub], "")
+    end
+  end
+
+  describe "encrypt_timed/3" do
+    test "reports a positive duration for every phase" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("TimedRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("TimedSender")
+
+      assert {:ok, {ciphertext, timings}} =
+               ExTholosPq.encrypt_timed("timed", sid, [recipient_pub])
+
+      assert %{kem_us: kem_us, aead_us: aead_us, sign_us: sign_us} = timings
+      assert map_size(timings) == 3
+      assert Enum.all?([kem_us, aead_us, sign_us], &(is_integer(&1) and &1 > 0))
+      assert {:ok, "timed"} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+    end
+
+    test "fails like encrypt/3" do
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("TimedSender")
+
+      assert {:error, {:deserialize_recipient, _}} =
+               ExTholosPq.encrypt_timed("timed", sid, ["not cbor"])
+    end
+  end
+
+  describe "compression" do
+    setup :sender_and_recipient
+
+    test "compressible payloads shrink and decrypt transparently", ctx do
+      json =
+        1..2000
+        |> Enum.map_join(",", &~s({"id":#{&1},"status":"active","tags":["a","b"]}))
+        |> then(&"[#{&1}]")
+
+      {:ok, compressed} =
+        ExTholosPq.encrypt(json, ctx.sid, [ctx.recipient_pub], compression: :zstd)
+      {:ok, plain} = ExTholosPq.encrypt(json, ctx.sid, [ctx.recipient_pub], compression: :none)
+
+      assert byte_size(compressed) * 4 < byte_size(plain)
+      assert {:ok, ^json} = ExTholosPq.decrypt(compressed, ctx.kid, [ctx.sender_pub])
+      assert {:ok, {^json, _sid}} =
+               ExTholosPq.decrypt_with_sender(compressed, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "an incompressible payload does not grow", ctx do
+      random = :crypto.strong_rand_bytes(1024 * 1024)
+
+      {:ok, compressed} =
+        ExTholosPq.encrypt(random, ctx.sid, [ctx.recipient_pub], compression: :zstd)
+
+      {:ok, plain} = ExTholosPq.encrypt(random, ctx.sid, [ctx.recipient_pub])
+
+      assert byte_size(compressed) <= byte_size(plain) + 16
+      assert {:ok, ^random} = ExTholosPq.decrypt(compressed, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "combines with associated data", ctx do
+      message = String.duplicate("compress me ", 1000)
+
+      {:ok, ciphertext} =
+        ExTholosPq.encrypt(message, ctx.sid, [ctx.recipient_pub], aad: "ctx", compression: :zstd)
+
+      assert {:ok, ^message} = ExTholosPq.decrypt(ciphertext, ctx.kid, [ctx.sender_pub], "ctx")
+      assert {:error, :aad_mismatch} =
+               ExTholosPq.decrypt(ciphertext, ctx.kid, [ctx.sender_pub], "")
+    end
+
+    @tag timeout: 300_000
+    test "refuses a message that inflates past 1 GiB", ctx do
+      bomb = :binary.copy(<<0>>, 1024 * 1024 * 1024 + 1)
+
+      {:ok, ciphertext} =
+        ExTholosPq.encrypt(bomb, ctx.sid, [ctx.recipient_pub], compression: :zstd)
+
+      assert byte_size(ciphertext) < 1024 * 1024
+      assert {:error, {:malformed_wire, _}} =
+               ExTholosPq.decrypt(ciphertext, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "rejects unknown algorithms", ctx do
+      assert {:error, {:invalid_option, "compression"}} =
+               ExTholosPq.encrypt("hello", ctx.sid, [ctx.recipient_pub], compression: :gzip)
+    end
+  end
+
+  describe "base64url encoding" do
+    setup :sender_and_recipient
+
+    test "round-trips through decrypt", ctx do
+      {:ok, encoded} =
+        ExTholosPq.encrypt("as text", ctx.sid, [ctx.recipient_pub], encoding: :base64url)
+
+      assert encoded =~ ~r/^[A-Za-z0-9_-]+$/
+      assert {:ok, "as text"} =
+               ExTholosPq.decrypt(encoded, ctx.kid, [ctx.sender_pub], encoding: :base64url)
+
+      {:ok, raw} = Base.url_decode64(encoded, padding: false)
+      assert {:ok, "as text"} = ExTholosPq.decrypt(raw, ctx.kid, [ctx.sender_pub])
+      assert {:ok, "as text"} =
+               ExTholosPq.decrypt(Base.url_encode64(raw), ctx.kid, [ctx.sender_pub],
+                 encoding: :base64url
+               )
+    end
+
+    test "combines with associated data", ctx do
+      opts = [aad: "ctx", encoding: :base64url]
+      {:ok, encoded} = ExTholosPq.encrypt("bound", ctx.sid, [ctx.recipient_pub], opts)
+
+      assert {:ok, "bound"} = ExTholosPq.decrypt(encoded, ctx.kid, [ctx.sender_pub], opts)
+      assert {:error, :aad_mismatch} =
+               ExTholosPq.decrypt(encoded, ctx.kid, [ctx.sender_pub],
+                 aad: "other",
+                 encoding: :base64url
+               )
+    end
+
+    test "rejects invalid text and unknown encodings", ctx do
+      assert {:error, {:malformed_wire, "base64url"}} =
+               ExTholosPq.decrypt("not+base64/", ctx.kid, [ctx.sender_pub], encoding: :base64url)
+
+      assert {:error, {:invalid_option, "encoding"}} =
+               ExTholosPq.encrypt("hello", ctx.sid, [ctx.recipient_pub], encoding: :hex)
+    end
+  end
+
   describe "complete encryption workflow" do
     test "full multi-recipient encryption with authentication" do
       # Setup: Generate keys for 3 recipients and 2 senders
@@ -109,5 +793,3626 @@ defmodule ExTholosPqTest do
       assert plain_b == message
       assert plain_c == message
     end
+
+    # The test build enables the parallel feature, which encapsulates to 8 or
+    # more recipients on the rayon pool and to fewer on the calling thread
+    test "serial and parallel encapsulation give equivalent ciphertexts" do
+      recipients =
+        for i <- 1..40 do
+          {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("FanOut#{i}")
+          {kid, pub}
+        end
+
+      {kids, pubs} = Enum.unzip(recipients)
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("FanOutSender")
+      message = "to everyone"
+
+      {:ok, serial} = ExTholosPq.encrypt(message, sid, Enum.take(pubs, 3))
+      {:ok, parallel} = ExTholosPq.encrypt(message, sid, pubs)
+      {:ok, [batched]} = ExTholosPq.encrypt_batch(sid, [message], pubs)
+
+      assert {:ok, Enum.take(kids, 3)} == ExTholosPq.wire_recipients(serial)
+      assert {:ok, kids} == ExTholosPq.wire_recipients(parallel)
+      assert {:ok, kids} == ExTholosPq.wire_recipients(batched)
+
+      for kid <- Enum.take(kids, 3) do
+        assert {:ok, ^message} = ExTholosPq.decrypt(serial, kid, [sender_pub])
+      end
+
+      for kid <- kids, ciphertext <- [parallel, batched] do
+        assert {:ok, ^message} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+      end
+    end
+  end
+
+  describe "resource-backed keypairs" do
+    test "encrypts and decrypts with key references" do
+      {:ok, {recipient_ref, recipient_pub}} = ExTholosPq.gen_recipient_key_resource("ResAlice")
+      {:ok, {sender_ref, sender_pub}} = ExTholosPq.gen_sender_key_resource("ResSender")
+
+      assert is_reference(recipient_ref)
+      assert is_reference(sender_ref)
+
+      message = "Scoped to this process"
+      {:ok, ciphertext} = ExTholosPq.encrypt(message, sender_ref, [recipient_pub])
+      {:ok, plaintext} = ExTholosPq.decrypt(ciphertext, recipient_ref, [sender_pub])
+
+      assert plaintext == message
+    end
+
+    test "resource-backed keys are not added to the global key store" do
+      {:ok, {_recipient_ref, recipient_pub}} =
+        ExTholosPq.gen_recipient_key_resource("ResOnlyRecipient")
+
+      {:ok, {sender_ref, sender_pub}} = ExTholosPq.gen_sender_key_resource("ResOnlySender")
+
+      {:ok, ciphertext} = ExTholosPq.encrypt("secret", sender_ref, [recipient_pub])
+
+      assert {:error, _reason} = ExTholosPq.encrypt("secret", "ResOnlySender", [recipient_pub])
+
+      assert {:error, _reason} =
+               ExTholosPq.decrypt(ciphertext, "ResOnlyRecipient", [sender_pub])
+    end
+
+    test "resource and store keys interoperate" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("StoreRecipient")
+      {:ok, {sender_ref, sender_pub}} = ExTholosPq.gen_sender_key_resource("MixedSender")
+
+      {:ok, ciphertext} = ExTholosPq.encrypt("mixed", sender_ref, [recipient_pub])
+      assert {:ok, "mixed"} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+    end
+  end
+
+  describe "signature verification order" do
+    setup do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("OrderRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("OrderSender")
+      {:ok, {_, other_pub}} = ExTholosPq.gen_sender_keypair("OrderOther")
+      {:ok, ciphertext} = ExTholosPq.encrypt("ordered", sid, [recipient_pub])
+      %{kid: kid, sender_pub: sender_pub, other_pub: other_pub, ciphertext: ciphertext}
+    end
+
+    test "a disallowed sender is rejected before any KEM or AEAD work", ctx do
+      before = ExTholosPq.decrypt_operations()
+
+      assert {:error, :signature_invalid} =
+               ExTholosPq.decrypt(ctx.ciphertext, ctx.kid, [ctx.other_pub])
+
+      assert ExTholosPq.decrypt_operations() == before
+    end
+
+    test "a signature that does not verify is rejected before any KEM or AEAD work", ctx do
+      {:ok, unsigned} = ExTholosPq.strip_signature(ctx.ciphertext, false)
+      size = byte_size(ctx.ciphertext) - 1
+      <<head::binary-size(size), last>> = ctx.ciphertext
+      tampered = <<head::binary, Bitwise.bxor(last, 1)>>
+      before = ExTholosPq.decrypt_operations()
+
+      for wire <- [unsigned, tampered] do
+        assert {:error, _} = ExTholosPq.decrypt(wire, ctx.kid, [ctx.sender_pub])
+      end
+
+      assert ExTholosPq.decrypt_operations() == before
+    end
+
+    test "an allowed sender gets one decapsulation and two AEAD decryptions", ctx do
+      before = ExTholosPq.decrypt_operations()
+
+      assert {:ok, "ordered"} = ExTholosPq.decrypt(ctx.ciphertext, ctx.kid, [ctx.sender_pub])
+      assert ExTholosPq.decrypt_operations() == before + 3
+    end
+  end
+
+  describe "signature coverage" do
+    setup do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("CoveredRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("CoveredSender")
+
+      {:ok, ciphertext} =
+        ExTholosPq.encrypt("covered", sid, [recipient_pub],
+          metadata: "meta",
+          ttl_seconds: 60,
+          key_commitment: true,
+          pad_to: 16
+        )
+
+      %{kid: kid, sender_pub: sender_pub, ciphertext: ciphertext}
+    end
+
+    # Every field of the header and the signed bundle, named as in the CBOR
+    @fields ~w(v suite sender recipients msg_id timestamp_unix compression aead hybrid
+               anonymous ttl_seconds metadata pad_to per_recipient_aad escrow sender_pk
+               pay_nonce ciphertext kid kem_ct wrap_nonce wrapped_cek signer signer_pk
+               key_commitment)
+
+    test "changing any one field breaks the signature", ctx do
+      for field <- @fields do
+        {:ok, tampered} = ExTholosPq.tamper_wire(ctx.ciphertext, field)
+        assert tampered != ctx.ciphertext
+
+        result = ExTholosPq.decrypt(tampered, ctx.kid, [ctx.sender_pub])
+        assert match?({:error, :signature_invalid}, result), "#{field}: #{inspect(result)}"
+      end
+
+      assert {:error, {:invalid_option, "no_such_field"}} =
+               ExTholosPq.tamper_wire(ctx.ciphertext, "no_such_field")
+    end
+
+    test "the unsigned version byte carries no meaning", ctx do
+      <<1, bundle::binary>> = ctx.ciphertext
+
+      assert {:ok, "covered"} =
+               ExTholosPq.decrypt(bundle, ctx.kid, [ctx.sender_pub], now: System.os_time(:second))
+
+      assert {:error, {:unsupported_wire_version, 2}} =
+               ExTholosPq.decrypt(<<2, bundle::binary>>, ctx.kid, [ctx.sender_pub])
+    end
+  end
+
+  describe "encrypt_self/2 and decrypt_self/2" do
+    setup do
+      {:ok, {"Self", recipient_pub, sender_pub}} = ExTholosPq.gen_self_keypair("Self")
+      %{sender_pub: sender_pub, recipient_pub: recipient_pub}
+    end
+
+    test "round-trips a message to oneself", ctx do
+      {:ok, ciphertext} = ExTholosPq.encrypt_self("note to self", "Self")
+
+      assert {:ok, "note to self"} = ExTholosPq.decrypt_self(ciphertext, "Self")
+      assert {:ok, ["Self"]} = ExTholosPq.wire_recipients(ciphertext)
+      assert {:ok, "Self"} = ExTholosPq.wire_sender_unverified(ciphertext)
+      assert {:ok, "note to self"} = ExTholosPq.decrypt(ciphertext, "Self", [ctx.sender_pub])
+    end
+
+    test "only accepts messages signed by the own sender key", ctx do
+      {:ok, {sid, _}} = ExTholosPq.gen_sender_keypair("NotSelf")
+      {:ok, ciphertext} = ExTholosPq.encrypt("forged note", sid, [ctx.recipient_pub])
+
+      assert {:error, :signature_invalid} = ExTholosPq.decrypt_self(ciphertext, "Self")
+    end
+
+    test "needs both keypairs under the id" do
+      {:ok, _} = ExTholosPq.gen_sender_keypair("OnlySender")
+      {:ok, _} = ExTholosPq.gen_recipient_keypair("OnlyRecipient")
+
+      assert {:error, {:recipient_not_found, "OnlySender"}} =
+               ExTholosPq.encrypt_self("x", "OnlySender")
+
+      assert {:error, {:sender_not_found, "OnlyRecipient"}} =
+               ExTholosPq.encrypt_self("x", "OnlyRecipient")
+
+      {:ok, ciphertext} = ExTholosPq.encrypt_self("x", "Self")
+
+      assert {:error, {:sender_not_found, "OnlyRecipient"}} =
+               ExTholosPq.decrypt_self(ciphertext, "OnlyRecipient")
+    end
+
+    test "rejects an invalid id" do
+      assert {:error, {:invalid_identifier, "empty"}} = ExTholosPq.encrypt_self("x", "")
+
+      assert {:error, {:invalid_identifier, "longer than 256 bytes"}} =
+               ExTholosPq.encrypt_self("x", String.duplicate("a", 257))
+    end
+
+    test "gen_self_keypair/1 stores neither half if either is taken" do
+      {:ok, _} = ExTholosPq.gen_sender_keypair("OnlySender")
+      {:ok, _} = ExTholosPq.gen_recipient_keypair("OnlyRecipient")
+
+      assert {:error, {:kid_exists, "OnlyRecipient"}} =
+               ExTholosPq.gen_self_keypair("OnlyRecipient")
+
+      assert {:error, {:sid_exists, "OnlySender"}} = ExTholosPq.gen_self_keypair("OnlySender")
+      refute ExTholosPq.has_recipient_key("OnlySender")
+      assert {:error, {:kid_exists, "Self"}} = ExTholosPq.gen_self_keypair("Self")
+    end
+  end
+
+  describe "decrypt_classified/3" do
+    setup do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("ClassifiedRecipient")
+      {:ok, {other_kid, _pub}} = ExTholosPq.gen_recipient_keypair("ClassifiedOther")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("ClassifiedSender")
+      {:ok, {_sid, stranger_pub}} = ExTholosPq.gen_sender_keypair("ClassifiedStranger")
+      {:ok, ciphertext} = ExTholosPq.encrypt("classified", sid, [recipient_pub])
+
+      %{
+        kid: kid,
+        recipient_pub: recipient_pub,
+        other_kid: other_kid,
+        sid: sid,
+        sender_pub: sender_pub,
+        stranger_pub: stranger_pub,
+        ciphertext: ciphertext
+      }
+    end
+
+    test "returns the plaintext like decrypt/3", ctx do
+      assert {:ok, "classified"} =
+               ExTholosPq.decrypt_classified(ctx.ciphertext, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "puts a sender outside the allow-list under authz", ctx do
+      assert {:error, {:authz, :signature_invalid}} =
+               ExTholosPq.decrypt_classified(ctx.ciphertext, ctx.kid, [ctx.stranger_pub])
+
+      {:ok, anonymous} = ExTholosPq.encrypt_anonymous("anonymous", [ctx.recipient_pub])
+
+      assert {:error, {:authz, :signature_invalid}} =
+               ExTholosPq.decrypt_classified(anonymous, ctx.kid, [ctx.sender_pub])
+
+      # Without an embedded key an empty allow-list has nothing to verify against
+      assert {:error, {:authz, :signature_invalid}} =
+               ExTholosPq.decrypt_classified(ctx.ciphertext, ctx.kid, [])
+
+      {:ok, renamed} = ExTholosPq.tamper_wire(ctx.ciphertext, "sender")
+
+      assert {:error, {:authz, :signature_invalid}} =
+               ExTholosPq.decrypt_classified(renamed, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "puts tampering under integrity", ctx do
+      for field <- ~w(ciphertext msg_id kem_ct wrapped_cek) do
+        {:ok, tampered} = ExTholosPq.tamper_wire(ctx.ciphertext, field)
+        result = ExTholosPq.decrypt_classified(tampered, ctx.kid, [ctx.sender_pub])
+        assert match?({:error, {:integrity, :signature_invalid}}, result), field
+      end
+
+      {:ok, equivocal} =
+        ExTholosPq.encrypt_wrong_key_commitment("equivocal", ctx.sid, [ctx.recipient_pub])
+
+      assert {:error, {:integrity, :key_commitment_failed}} =
+               ExTholosPq.decrypt_classified(equivocal, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "puts unparseable input under format", ctx do
+      assert {:error, {:format, {:malformed_wire, _}}} =
+               ExTholosPq.decrypt_classified("not a wire", ctx.kid, [ctx.sender_pub])
+
+      <<1, bundle::binary>> = ctx.ciphertext
+
+      assert {:error, {:format, {:unsupported_wire_version, 2}}} =
+               ExTholosPq.decrypt_classified(<<2, bundle::binary>>, ctx.kid, [ctx.sender_pub])
+
+      assert {:error, {:format, {:deserialize_sender, _}}} =
+               ExTholosPq.decrypt_classified(ctx.ciphertext, ctx.kid, ["not a key"])
+    end
+
+    test "puts a missing or wrong recipient key under key", ctx do
+      assert {:error, {:key, {:recipient_not_found, "NoSuchKid"}}} =
+               ExTholosPq.decrypt_classified(ctx.ciphertext, "NoSuchKid", [ctx.sender_pub])
+
+      assert {:error, {:key, :no_envelope_for_recipient}} =
+               ExTholosPq.decrypt_classified(ctx.ciphertext, ctx.other_kid, [ctx.sender_pub])
+    end
+  end
+
+  describe "decrypt_any/3" do
+    setup do
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("AnyOfSender")
+
+      pubs =
+        for kid <- ["HeldFirst", "HeldSecond", "HeldThird"] do
+          {:ok, {^kid, pub}} = ExTholosPq.gen_recipient_keypair(kid)
+          pub
+        end
+
+      %{sid: sid, sender_pub: sender_pub, pubs: pubs}
+    end
+
+    test "finds the one held key the wire is addressed to", ctx do
+      {:ok, {_, outsider_pub}} = ExTholosPq.gen_recipient_keypair("NotHeld")
+      {:ok, wire} = ExTholosPq.encrypt("second", ctx.sid, [outsider_pub, Enum.at(ctx.pubs, 1)])
+      held = ["HeldFirst", "HeldSecond", "HeldThird"]
+
+      assert {:ok, {"second", "HeldSecond", "AnyOfSender"}} =
+               ExTholosPq.decrypt_any(wire, held, [ctx.sender_pub])
+
+      # Only the matching key was used
+      assert {:ok, 0} = ExTholosPq.recipient_key_usage("HeldFirst")
+      assert {:ok, 1} = ExTholosPq.recipient_key_usage("HeldSecond")
+    end
+
+    test "matches any stored version of a rotated kid", ctx do
+      {:ok, {2, rotated_pub}} = ExTholosPq.rotate_recipient_key("HeldThird")
+      {:ok, wire} = ExTholosPq.encrypt("rotated", ctx.sid, [rotated_pub])
+
+      assert {:ok, {"rotated", "HeldThird", _sid}} =
+               ExTholosPq.decrypt_any(wire, ["HeldFirst", "HeldThird"], [ctx.sender_pub])
+    end
+
+    test "fails when no held key applies", ctx do
+      {:ok, {_, outsider_pub}} = ExTholosPq.gen_recipient_keypair("NotHeldEither")
+      {:ok, wire} = ExTholosPq.encrypt("elsewhere", ctx.sid, [outsider_pub])
+
+      assert {:error, :no_matching_key} =
+               ExTholosPq.decrypt_any(wire, ["HeldFirst", "HeldSecond"], [ctx.sender_pub])
+
+      assert {:error, :no_matching_key} = ExTholosPq.decrypt_any(wire, [], [ctx.sender_pub])
+      assert {:error, :no_matching_key} = ExTholosPq.decrypt_any(wire, ["Unknown"], [])
+
+      assert {:error, :malformed_wire} =
+               ExTholosPq.decrypt_any("not a wire", ["HeldFirst"], [ctx.sender_pub])
+    end
+  end
+
+  describe "decrypt_with_resource/3" do
+    test "decrypts with a key reference alone" do
+      {:ok, {recipient_ref, recipient_pub}} =
+        ExTholosPq.gen_recipient_key_resource("ResourceDecryptRecipient")
+
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("ResourceDecryptSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("by reference", sid, [recipient_pub])
+
+      assert {:ok, "by reference"} =
+               ExTholosPq.decrypt_with_resource(ciphertext, recipient_ref, [sender_pub])
+
+      # The kid of the resource is not in the store, and no kid is taken
+      assert {:ok, []} = ExTholosPq.list_recipient_kids()
+
+      assert_raise ArgumentError, fn ->
+        ExTholosPq.decrypt_with_resource(ciphertext, "ResourceDecryptRecipient", [sender_pub])
+      end
+    end
+
+    test "fails like decrypt/3 for a sender not allowed" do
+      {:ok, {recipient_ref, recipient_pub}} =
+        ExTholosPq.gen_recipient_key_resource("ResourceDeniedRecipient")
+
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("ResourceDeniedSender")
+      {:ok, {_other_sid, other_pub}} = ExTholosPq.gen_sender_keypair("ResourceOtherSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("denied", sid, [recipient_pub])
+
+      assert ExTholosPq.decrypt_with_resource(ciphertext, recipient_ref, [other_pub]) ==
+               ExTholosPq.decrypt(ciphertext, recipient_ref, [other_pub])
+
+      assert {:error, _reason} =
+               ExTholosPq.decrypt_with_resource(ciphertext, recipient_ref, [other_pub])
+    end
+  end
+
+  describe "encrypt_with_keys/3" do
+    test "encrypts with a keypair exported on another node" do
+      # "Node A" generates the sender key and exports it
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("NodeASender")
+      {:ok, keypair} = ExTholosPq.export_sender_keypair(sid)
+
+      # "Node B" only has the exported keypair and the recipient public key
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("NodeBRecipient")
+      message = "Encrypted away from the keygen node"
+      {:ok, ciphertext} = ExTholosPq.encrypt_with_keys(message, keypair, [recipient_pub])
+
+      assert {:ok, ^message} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+    end
+
+    test "rejects a malformed keypair" do
+      {:ok, {_kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+
+      assert {:error, _reason} =
+               ExTholosPq.encrypt_with_keys("secret", "not cbor", [recipient_pub])
+    end
+
+    test "export fails for an unknown sender" do
+      assert {:error, _reason} = ExTholosPq.export_sender_keypair("NoSuchSender")
+    end
+  end
+
+  describe "decrypt_with_key/4" do
+    test "decrypts with an exported private key" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("KeyedRecipient")
+      {:ok, private_key} = ExTholosPq.export_recipient_key(kid)
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("KeyedSender")
+
+      message = "Loaded from the vault"
+      {:ok, ciphertext} = ExTholosPq.encrypt(message, sid, [recipient_pub])
+
+      assert {:ok, ^message} =
+               ExTholosPq.decrypt_with_key(ciphertext, kid, private_key, [sender_pub])
+    end
+
+    test "reports a malformed private key" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("KeyedRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("KeyedSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("secret", sid, [recipient_pub])
+
+      assert {:error, {:invalid_private_key, _detail}} =
+               ExTholosPq.decrypt_with_key(ciphertext, kid, "not cbor", [sender_pub])
+    end
+
+    test "reports a decryption failure for the wrong recipient" do
+      {:ok, {_kid_a, pub_a}} = ExTholosPq.gen_recipient_keypair("KeyedAlice")
+      {:ok, {kid_b, _pub_b}} = ExTholosPq.gen_recipient_keypair("KeyedBob")
+      {:ok, private_key_b} = ExTholosPq.export_recipient_key(kid_b)
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("KeyedSender")
+
+      {:ok, ciphertext} = ExTholosPq.encrypt("Only for Alice", sid, [pub_a])
+
+      assert {:error, :no_envelope_for_recipient} =
+               ExTholosPq.decrypt_with_key(ciphertext, kid_b, private_key_b, [sender_pub])
+    end
+  end
+
+  describe "wrap_content_key/3 and unwrap_content_key/3" do
+    setup do
+      recipients =
+        for name <- ["WrapAlice", "WrapBob", "WrapCarol"] do
+          {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair(name)
+          {:ok, priv} = ExTholosPq.export_recipient_key(kid)
+          {kid, pub, priv}
+        end
+
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("WrapSender")
+      %{recipients: recipients, sid: sid, content_key: :crypto.strong_rand_bytes(32)}
+    end
+
+    test "every recipient unwraps the same content key", ctx do
+      pubs = for {_kid, pub, _priv} <- ctx.recipients, do: pub
+      assert {:ok, wrapped} = ExTholosPq.wrap_content_key(ctx.sid, ctx.content_key, pubs)
+
+      for {kid, _pub, priv} <- ctx.recipients do
+        assert {:ok, content_key} = ExTholosPq.unwrap_content_key(wrapped, kid, priv)
+        assert content_key == ctx.content_key
+      end
+    end
+
+    test "only the recipients it was wrapped for can unwrap it", ctx do
+      [{kid_a, pub_a, _priv_a}, {kid_b, _pub_b, priv_b} | _] = ctx.recipients
+      {:ok, wrapped} = ExTholosPq.wrap_content_key(ctx.sid, ctx.content_key, [pub_a])
+
+      assert {:error, :no_envelope_for_recipient} =
+               ExTholosPq.unwrap_content_key(wrapped, kid_b, priv_b)
+
+      assert {:error, :auth_tag_mismatch} =
+               ExTholosPq.unwrap_content_key(wrapped, kid_a, priv_b)
+    end
+
+    test "a wrapped key is not a ciphertext", ctx do
+      [{kid, pub, priv} | _] = ctx.recipients
+      {:ok, wrapped} = ExTholosPq.wrap_content_key(ctx.sid, ctx.content_key, [pub])
+
+      assert {:error, _} = ExTholosPq.decrypt(wrapped, kid, [])
+      assert {:error, _} = ExTholosPq.decrypt_with_key(wrapped, kid, priv, [])
+    end
+
+    test "rejects a content key that is not 32 bytes", ctx do
+      [{_kid, pub, _priv} | _] = ctx.recipients
+
+      assert {:error, :bad_content_key_length} =
+               ExTholosPq.wrap_content_key(ctx.sid, :crypto.strong_rand_bytes(16), [pub])
+    end
+  end
+
+  describe "encrypt_psk_wrapped/4 and decrypt_psk_wrapped/4" do
+    setup :sender_and_recipient
+
+    setup do
+      %{psk: :crypto.strong_rand_bytes(32)}
+    end
+
+    test "round-trips a message through the outer layer", ctx do
+      {:ok, wrapped} =
+        ExTholosPq.encrypt_psk_wrapped(ctx.sid, "legacy", [ctx.recipient_pub], ctx.psk)
+
+      assert {:ok, "legacy"} =
+               ExTholosPq.decrypt_psk_wrapped(wrapped, ctx.kid, [ctx.sender_pub], ctx.psk)
+
+      # The layer hides the wire, so it is not a ciphertext on its own
+      assert {:error, _} = ExTholosPq.decrypt(wrapped, ctx.kid, [ctx.sender_pub])
+
+      short = :crypto.strong_rand_bytes(16)
+      {:ok, wrapped} =
+        ExTholosPq.encrypt_psk_wrapped(ctx.sid, "aes-128", [ctx.recipient_pub], short)
+
+      assert {:ok, "aes-128"} =
+               ExTholosPq.decrypt_psk_wrapped(wrapped, ctx.kid, [ctx.sender_pub], short)
+    end
+
+    test "rejects a wrong PSK and an altered layer", ctx do
+      {:ok, wrapped} =
+        ExTholosPq.encrypt_psk_wrapped(ctx.sid, "legacy", [ctx.recipient_pub], ctx.psk)
+      wrong = :crypto.strong_rand_bytes(32)
+
+      assert {:error, :bad_psk} =
+               ExTholosPq.decrypt_psk_wrapped(wrapped, ctx.kid, [ctx.sender_pub], wrong)
+
+      size = byte_size(wrapped) - 1
+      <<head::binary-size(size), last>> = wrapped
+      altered = <<head::binary, Bitwise.bxor(last, 1)>>
+
+      assert {:error, :bad_psk} =
+               ExTholosPq.decrypt_psk_wrapped(altered, ctx.kid, [ctx.sender_pub], ctx.psk)
+
+      assert {:error, {:malformed_wire, _}} =
+               ExTholosPq.decrypt_psk_wrapped("not wrapped", ctx.kid, [ctx.sender_pub], ctx.psk)
+    end
+
+    test "still checks the sender and the recipient of the wire inside", ctx do
+      {:ok, {other_kid, _pub}} = ExTholosPq.gen_recipient_keypair("PskOther")
+      {:ok, {_sid, stranger_pub}} = ExTholosPq.gen_sender_keypair("PskStranger")
+      {:ok, wrapped} =
+        ExTholosPq.encrypt_psk_wrapped(ctx.sid, "legacy", [ctx.recipient_pub], ctx.psk)
+
+      assert {:error, :signature_invalid} =
+               ExTholosPq.decrypt_psk_wrapped(wrapped, ctx.kid, [stranger_pub], ctx.psk)
+
+      assert {:error, :no_envelope_for_recipient} =
+               ExTholosPq.decrypt_psk_wrapped(wrapped, other_kid, [ctx.sender_pub], ctx.psk)
+    end
+
+    test "rejects a PSK shorter than 16 bytes", ctx do
+      assert {:error, :bad_psk_length} =
+               ExTholosPq.encrypt_psk_wrapped(ctx.sid, "x", [ctx.recipient_pub], "too short")
+
+      assert {:error, :bad_psk_length} =
+               ExTholosPq.decrypt_psk_wrapped("x", ctx.kid, [ctx.sender_pub], "")
+    end
+  end
+
+  describe "hybrid keys" do
+    setup do
+      seed = :crypto.strong_rand_bytes(32)
+
+      {:ok, {kid, hybrid_pub}} =
+        ExTholosPq.gen_recipient_keypair_from_seed("HybridRecipient", seed, hybrid: true)
+
+      {:ok, hybrid_priv} = ExTholosPq.export_recipient_key(kid)
+      {:ok, {^kid, plain_pub}} =
+        ExTholosPq.gen_recipient_keypair_from_seed(kid, seed, overwrite: true)
+
+      {:ok, plain_priv} = ExTholosPq.export_recipient_key(kid)
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("HybridSender")
+
+      %{
+        kid: kid,
+        sid: sid,
+        sender_pub: sender_pub,
+        hybrid_pub: hybrid_pub,
+        hybrid_priv: hybrid_priv,
+        plain_pub: plain_pub,
+        plain_priv: plain_priv
+      }
+    end
+
+    test "a hybrid ciphertext round-trips", ctx do
+      assert byte_size(ctx.hybrid_pub) == byte_size(ctx.plain_pub) + 32
+
+      {:ok, ct} = ExTholosPq.encrypt("both ways", ctx.sid, [ctx.hybrid_pub], hybrid: true)
+
+      assert {:ok, "both ways"} =
+               ExTholosPq.decrypt_with_key(ct, ctx.kid, ctx.hybrid_priv, [ctx.sender_pub])
+    end
+
+    test "a key without its X25519 half cannot decrypt a hybrid ciphertext", ctx do
+      {:ok, ct} = ExTholosPq.encrypt("both ways", ctx.sid, [ctx.hybrid_pub], hybrid: true)
+
+      # Same kid and ML-KEM key, so only the missing X25519 half is to blame
+      assert {:error, {:hybrid_mismatch, _}} =
+               ExTholosPq.decrypt_with_key(ct, ctx.kid, ctx.plain_priv, [ctx.sender_pub])
+    end
+
+    test "a hybrid key also decrypts plain ciphertexts", ctx do
+      {:ok, ct} = ExTholosPq.encrypt("one way", ctx.sid, [ctx.hybrid_pub])
+
+      assert {:ok, "one way"} =
+               ExTholosPq.decrypt_with_key(ct, ctx.kid, ctx.hybrid_priv, [ctx.sender_pub])
+
+      assert {:ok, "one way"} =
+               ExTholosPq.decrypt_with_key(ct, ctx.kid, ctx.plain_priv, [ctx.sender_pub])
+    end
+
+    test "hybrid encryption rejects keys that are not hybrid", ctx do
+      assert {:error, :hybrid_mismatch} =
+               ExTholosPq.encrypt("x", ctx.sid, [ctx.hybrid_pub, ctx.plain_pub], hybrid: true)
+    end
+
+    test "sender keys cannot be hybrid" do
+      assert {:error, {:invalid_option, "hybrid"}} =
+               ExTholosPq.gen_sender_keypair("HybridSenderOpt", hybrid: true)
+    end
+  end
+
+  describe "message expiry" do
+    setup :sender_and_recipient
+
+    test "accepts a message within its time to live", ctx do
+      {:ok, wire} = ExTholosPq.encrypt("fresh", ctx.sid, [ctx.recipient_pub], ttl_seconds: 60)
+      now = System.os_time(:second)
+
+      assert {:ok, "fresh"} = ExTholosPq.decrypt(wire, ctx.kid, [ctx.sender_pub], now: now)
+      assert {:ok, "fresh"} = ExTholosPq.decrypt(wire, ctx.kid, [ctx.sender_pub], now: now + 30)
+    end
+
+    test "rejects a message past its time to live", ctx do
+      {:ok, wire} = ExTholosPq.encrypt("stale", ctx.sid, [ctx.recipient_pub], ttl_seconds: 60)
+      later = System.os_time(:second) + 120
+
+      assert {:error, :expired} = ExTholosPq.decrypt(wire, ctx.kid, [ctx.sender_pub], now: later)
+    end
+
+    test "refuses a message with a time to live without the time to check it", ctx do
+      {:ok, wire} = ExTholosPq.encrypt("timed", ctx.sid, [ctx.recipient_pub], ttl_seconds: 60)
+
+      assert {:error, :now_required} = ExTholosPq.decrypt(wire, ctx.kid, [ctx.sender_pub])
+      assert {:error, :now_required} = ExTholosPq.decrypt(wire, ctx.kid, [ctx.sender_pub], [])
+      assert {:error, :now_required} =
+               ExTholosPq.decrypt_with_sender(wire, ctx.kid, [ctx.sender_pub])
+      assert {:ok, 0} = ExTholosPq.recipient_key_usage(ctx.kid)
+
+      # A message without one needs no time
+      {:ok, untimed} = ExTholosPq.encrypt("untimed", ctx.sid, [ctx.recipient_pub])
+      assert {:ok, "untimed"} = ExTholosPq.decrypt(untimed, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "enforces the recipient's own maximum age", ctx do
+      {:ok, wire} = ExTholosPq.encrypt("no ttl", ctx.sid, [ctx.recipient_pub])
+      later = System.os_time(:second) + 120
+      sender_pubs = [ctx.sender_pub]
+
+      assert {:ok, "no ttl"} =
+               ExTholosPq.decrypt(wire, ctx.kid, sender_pubs, now: later, max_age_seconds: 300)
+
+      assert {:error, :expired} =
+               ExTholosPq.decrypt(wire, ctx.kid, sender_pubs, now: later, max_age_seconds: 60)
+    end
+
+    test "an expired message does not use the recipient key", ctx do
+      {:ok, wire} = ExTholosPq.encrypt("stale", ctx.sid, [ctx.recipient_pub], ttl_seconds: 60)
+      later = System.os_time(:second) + 120
+
+      assert {:error, :expired} = ExTholosPq.decrypt(wire, ctx.kid, [ctx.sender_pub], now: later)
+      assert {:ok, 0} = ExTholosPq.recipient_key_usage(ctx.kid)
+      now = System.os_time(:second)
+      assert {:ok, "stale"} = ExTholosPq.decrypt(wire, ctx.kid, [ctx.sender_pub], now: now)
+      assert {:ok, 1} = ExTholosPq.recipient_key_usage(ctx.kid)
+    end
+
+    test "requires now with a maximum age", ctx do
+      {:ok, wire} = ExTholosPq.encrypt("no ttl", ctx.sid, [ctx.recipient_pub])
+
+      assert {:error, {:invalid_option, "now"}} =
+               ExTholosPq.decrypt(wire, ctx.kid, [ctx.sender_pub], max_age_seconds: 60)
+    end
+  end
+
+  describe "key commitment" do
+    setup :sender_and_recipient
+
+    test "a committed ciphertext round-trips", ctx do
+      {:ok, plain} = ExTholosPq.encrypt("committed", ctx.sid, [ctx.recipient_pub])
+
+      {:ok, committed} =
+        ExTholosPq.encrypt("committed", ctx.sid, [ctx.recipient_pub], key_commitment: true)
+
+      assert byte_size(committed) > byte_size(plain) + 32
+      assert {:ok, "committed"} = ExTholosPq.decrypt(committed, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "rejects a signed ciphertext whose commitment does not match", ctx do
+      {:ok, ciphertext} =
+        ExTholosPq.encrypt_wrong_key_commitment("equivocal", ctx.sid, [ctx.recipient_pub])
+
+      assert {:ok, {:valid, "Sender"}} =
+               ExTholosPq.validate_wire(ciphertext, [ctx.sender_pub])
+
+      assert {:error, :key_commitment_failed} =
+               ExTholosPq.decrypt(ciphertext, ctx.kid, [ctx.sender_pub])
+    end
+  end
+
+  describe "recipient_pub_from_priv/1 and sender_pub_from_priv/1" do
+    test "rederives the published recipient key from an imported private key" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("DerivedRecipient")
+      {:ok, blob} = ExTholosPq.export_recipient_key_encrypted(kid, "hunter2")
+      :ok = ExTholosPq.delete_recipient_key(kid)
+      {:ok, _} = ExTholosPq.import_recipient_key_encrypted(kid, blob, "hunter2")
+
+      assert {:ok, derived} = ExTholosPq.recipient_pub_from_priv(kid)
+      assert derived == recipient_pub
+
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("DerivedSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("to the derived key", sid, [derived])
+      assert {:ok, "to the derived key"} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+    end
+
+    test "rederives a hybrid recipient key" do
+      {:ok, {kid, recipient_pub}} =
+        ExTholosPq.gen_recipient_keypair("DerivedHybrid", hybrid: true)
+
+      assert {:ok, ^recipient_pub} = ExTholosPq.recipient_pub_from_priv(kid)
+    end
+
+    test "returns the sender key a wire verifies under" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("DerivedSenderRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("DerivedSigner")
+
+      assert {:ok, derived} = ExTholosPq.sender_pub_from_priv(sid)
+      assert derived == sender_pub
+
+      {:ok, ciphertext} = ExTholosPq.encrypt("signed", sid, [recipient_pub])
+      assert {:ok, "signed"} = ExTholosPq.decrypt(ciphertext, kid, [derived])
+    end
+
+    test "unknown identifiers" do
+      assert {:error, {:recipient_not_found, "NoSuchKid"}} =
+               ExTholosPq.recipient_pub_from_priv("NoSuchKid")
+
+      assert {:error, {:sender_not_found, "NoSuchSid"}} =
+               ExTholosPq.sender_pub_from_priv("NoSuchSid")
+    end
+  end
+
+  describe "export_recipient_key_encrypted/2 and import_recipient_key_encrypted/3" do
+    setup do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("VaultRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("VaultSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("kept on disk", sid, [recipient_pub])
+      {:ok, blob} = ExTholosPq.export_recipient_key_encrypted(kid, "hunter2")
+
+      %{
+        kid: kid,
+        recipient_pub: recipient_pub,
+        sender_pub: sender_pub,
+        ciphertext: ciphertext,
+        blob: blob
+      }
+    end
+
+    test "round-trips a key through the store", ctx do
+      :ok = ExTholosPq.delete_recipient_key(ctx.kid)
+
+      assert {:ok, {kid, pub}} =
+               ExTholosPq.import_recipient_key_encrypted(ctx.kid, ctx.blob, "hunter2")
+      assert kid == ctx.kid
+      assert pub == ctx.recipient_pub
+      assert {:ok, "kept on disk"} = ExTholosPq.decrypt(ctx.ciphertext, kid, [ctx.sender_pub])
+    end
+
+    test "rejects a wrong password", ctx do
+      assert {:error, :bad_password} =
+               ExTholosPq.import_recipient_key_encrypted(ctx.kid, ctx.blob, "hunter3")
+    end
+
+    test "rejects an altered blob", ctx do
+      size = byte_size(ctx.blob)
+      <<head::binary-size(size - 1), last>> = ctx.blob
+      tampered = <<head::binary, Bitwise.bxor(last, 1)>>
+
+      assert {:error, :bad_password} =
+               ExTholosPq.import_recipient_key_encrypted(ctx.kid, tampered, "hunter2")
+
+      assert {:error, {:invalid_private_key, _detail}} =
+               ExTholosPq.import_recipient_key_encrypted(ctx.kid, "not a blob", "hunter2")
+    end
+
+    test "salts every export", ctx do
+      {:ok, again} = ExTholosPq.export_recipient_key_encrypted(ctx.kid, "hunter2")
+      assert again != ctx.blob
+    end
+
+    test "unknown kids are not found" do
+      assert {:error, {:recipient_not_found, "missing"}} =
+               ExTholosPq.export_recipient_key_encrypted("missing", "hunter2")
+    end
+  end
+
+  describe "load_recipient_keypair/3 and load_sender_keypair/3" do
+    test "a loaded recipient keypair decrypts wires made for its public key" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("LoadedRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("LoadedRecipientSender")
+      {:ok, recipient_priv} = ExTholosPq.export_recipient_key(kid)
+      {:ok, ciphertext} = ExTholosPq.encrypt("after boot", sid, [recipient_pub])
+      :ok = ExTholosPq.clear_all_keys()
+
+      assert :ok = ExTholosPq.load_recipient_keypair(kid, recipient_pub, recipient_priv)
+      assert {:ok, "after boot"} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+    end
+
+    test "a loaded sender keypair signs wires its public key verifies" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("LoadedSenderRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("LoadedSender")
+      {:ok, keypair} = ExTholosPq.export_sender_keypair(sid)
+      :ok = ExTholosPq.delete_sender_key(sid)
+
+      assert :ok = ExTholosPq.load_sender_keypair(sid, sender_pub, keypair)
+      {:ok, ciphertext} = ExTholosPq.encrypt("from a loaded key", sid, [recipient_pub])
+      assert {:ok, "from a loaded key"} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+    end
+
+    test "rejects halves of different keypairs" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("MismatchedRecipient")
+      {:ok, {other_kid, _other_pub}} = ExTholosPq.gen_recipient_keypair("OtherRecipient")
+      {:ok, other_priv} = ExTholosPq.export_recipient_key(other_kid)
+
+      assert {:error, :key_mismatch} =
+               ExTholosPq.load_recipient_keypair(kid, recipient_pub, other_priv)
+
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("MismatchedSender")
+      {:ok, {other_sid, _other_pub}} = ExTholosPq.gen_sender_keypair("OtherSender")
+      {:ok, other_keypair} = ExTholosPq.export_sender_keypair(other_sid)
+
+      assert {:error, :key_mismatch} =
+               ExTholosPq.load_sender_keypair(sid, sender_pub, other_keypair)
+    end
+
+    test "rejects a public key for another id" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("NamedRecipient")
+      {:ok, recipient_priv} = ExTholosPq.export_recipient_key(kid)
+
+      assert {:error, {:kid_mismatch, "Renamed", ^kid}} =
+               ExTholosPq.load_recipient_keypair("Renamed", recipient_pub, recipient_priv)
+
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("NamedSender")
+      {:ok, keypair} = ExTholosPq.export_sender_keypair(sid)
+
+      assert {:error, {:sid_mismatch, "Renamed", ^sid}} =
+               ExTholosPq.load_sender_keypair("Renamed", sender_pub, keypair)
+    end
+
+    test "rejects a sender keypair exported under another sid" do
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("NamedSender")
+      {:ok, keypair} = ExTholosPq.export_sender_keypair(sid)
+      {:ok, {_sid, renamed_pub}} = ExTholosPq.gen_sender_keypair("Renamed")
+
+      # The public half names Renamed, but the private half still names NamedSender
+      assert {:error, {:sid_mismatch, "Renamed", ^sid}} =
+               ExTholosPq.load_sender_keypair("Renamed", renamed_pub, keypair)
+    end
+
+    test "rejects halves that cannot be deserialized" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("UnreadableRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("UnreadableSender")
+
+      assert {:error, {:invalid_private_key, _}} =
+               ExTholosPq.load_recipient_keypair(kid, recipient_pub, "not cbor")
+
+      assert {:error, {:deserialize_recipient, _}} =
+               ExTholosPq.load_recipient_keypair(kid, "not cbor", "not cbor")
+
+      assert {:error, {:invalid_private_key, _}} =
+               ExTholosPq.load_sender_keypair(sid, sender_pub, "not cbor")
+    end
+  end
+
+  describe "export_store/1 and import_store/2" do
+    setup do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("DumpRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("DumpSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("survives a restart", sid, [recipient_pub])
+      {:ok, blob} = ExTholosPq.export_store("hunter2")
+
+      %{
+        kid: kid,
+        recipient_pub: recipient_pub,
+        sid: sid,
+        sender_pub: sender_pub,
+        ciphertext: ciphertext,
+        blob: blob
+      }
+    end
+
+    test "restores recipient and sender keys", ctx do
+      {:ok, kids} = ExTholosPq.list_recipient_kids()
+      {:ok, sids} = ExTholosPq.list_sender_sids()
+      for kid <- kids, do: :ok = ExTholosPq.delete_recipient_key(kid)
+      for sid <- sids, do: :ok = ExTholosPq.delete_sender_key(sid)
+
+      assert {:ok, {recipients, senders}} = ExTholosPq.import_store(ctx.blob, "hunter2")
+      assert recipients == length(kids)
+      assert senders == length(sids)
+
+      assert {:ok, "survives a restart"} =
+               ExTholosPq.decrypt(ctx.ciphertext, ctx.kid, [ctx.sender_pub])
+      assert {:ok, ciphertext} = ExTholosPq.encrypt("still signing", ctx.sid, [ctx.recipient_pub])
+      assert {:ok, "still signing"} = ExTholosPq.decrypt(ciphertext, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "rejects a wrong password", ctx do
+      assert {:error, :bad_password} = ExTholosPq.import_store(ctx.blob, "hunter3")
+    end
+
+    test "rejects truncated blobs without importing anything", ctx do
+      :ok = ExTholosPq.delete_recipient_key(ctx.kid)
+
+      for size <- [0, 1, div(byte_size(ctx.blob), 2), byte_size(ctx.blob) - 1] do
+        assert {:error, :corrupt_store} =
+                 ExTholosPq.import_store(binary_part(ctx.blob, 0, size), "hunter2")
+      end
+
+      {:ok, kids} = ExTholosPq.list_recipient_kids()
+      refute ctx.kid in kids
+    end
+
+    test "rejects a blob with an invalid kid or sid" do
+      long = String.duplicate("a", 257)
+
+      for {kid, sid, reason} <- [
+            {"", "StoreSender", "empty"},
+            {"StoreRecipient", "", "empty"},
+            {long, "StoreSender", "longer than 256 bytes"},
+            {"StoreRecipient", long, "longer than 256 bytes"}
+          ] do
+        {:ok, blob} = ExTholosPq.export_store_as(kid, sid, "hunter2")
+        assert {:error, {:invalid_identifier, ^reason}} = ExTholosPq.import_store(blob, "hunter2")
+      end
+
+      refute ExTholosPq.has_recipient_key("StoreRecipient")
+      refute ExTholosPq.has_sender_key("StoreSender")
+    end
+  end
+
+  describe "rotate_recipient_key/1" do
+    setup do
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("RotationSender")
+      %{sid: sid, sender_pub: sender_pub}
+    end
+
+    test "a message encrypted to v1 still decrypts after rotation to v2", ctx do
+      {:ok, {kid, v1_pub}} = ExTholosPq.gen_recipient_keypair("RotatingRecipient")
+      {:ok, old_ct} = ExTholosPq.encrypt("before rotation", ctx.sid, [v1_pub])
+
+      assert {:ok, {2, v2_pub}} = ExTholosPq.rotate_recipient_key(kid)
+      {:ok, new_ct} = ExTholosPq.encrypt("after rotation", ctx.sid, [v2_pub])
+
+      assert {:ok, "before rotation"} = ExTholosPq.decrypt(old_ct, kid, [ctx.sender_pub])
+      assert {:ok, "after rotation"} = ExTholosPq.decrypt(new_ct, kid, [ctx.sender_pub])
+      assert {:ok, ["RotatingRecipient#v2"]} = ExTholosPq.wire_recipients(new_ct)
+    end
+
+    test "versions count up and old ones can be deleted", ctx do
+      {:ok, {kid, _pub}} = ExTholosPq.gen_recipient_keypair("MultiRotatedRecipient")
+      {:ok, {2, _}} = ExTholosPq.rotate_recipient_key(kid)
+      {:ok, {3, v3_pub}} = ExTholosPq.rotate_recipient_key(kid)
+      assert {:ok, [1, 2, 3]} = ExTholosPq.list_key_versions(kid)
+
+      :ok = ExTholosPq.delete_recipient_key(kid <> "#v2")
+      assert {:ok, [1, 3]} = ExTholosPq.list_key_versions(kid)
+
+      {:ok, ct} = ExTholosPq.encrypt("latest", ctx.sid, [v3_pub])
+      assert {:ok, "latest"} = ExTholosPq.decrypt(ct, kid, [ctx.sender_pub])
+    end
+
+    test "a new kid cannot pass for a version of another", ctx do
+      {:ok, {kid, _pub}} = ExTholosPq.gen_recipient_keypair("Tenant")
+
+      assert {:error, {:invalid_identifier, "ends in a key version suffix"}} =
+               ExTholosPq.gen_recipient_keypair(kid <> "#v2")
+
+      {:ok, _} = ExTholosPq.gen_recipient_keypair("Stranger")
+      {:ok, blob} = ExTholosPq.export_recipient_key_encrypted("Stranger", "hunter2")
+
+      assert {:error, {:invalid_identifier, "ends in a key version suffix"}} =
+               ExTholosPq.import_recipient_key_encrypted(kid <> "#v2", blob, "hunter2")
+
+      assert {:ok, [1]} = ExTholosPq.list_key_versions(kid)
+    end
+
+    test "a rotated kid moves between stores with its versions", ctx do
+      {:ok, {kid, _pub}} = ExTholosPq.gen_recipient_keypair("ExportedRotation")
+      {:ok, {2, v2_pub}} = ExTholosPq.rotate_recipient_key(kid)
+      {:ok, ct} = ExTholosPq.encrypt("to v2", ctx.sid, [v2_pub])
+      {:ok, store} = ExTholosPq.export_store("hunter2")
+
+      :ok = ExTholosPq.clear_all_keys()
+      assert {:ok, {2, 1}} = ExTholosPq.import_store(store, "hunter2")
+      assert {:ok, [1, 2]} = ExTholosPq.list_key_versions(kid)
+      assert {:ok, "to v2"} = ExTholosPq.decrypt(ct, kid, [ctx.sender_pub])
+    end
+
+    test "unknown kids have no versions to rotate" do
+      assert {:ok, []} = ExTholosPq.list_key_versions("NeverStoredRecipient")
+
+      assert {:error, {:recipient_not_found, "NeverStoredRecipient"}} =
+               ExTholosPq.rotate_recipient_key("NeverStoredRecipient")
+    end
+  end
+
+  describe "delete_recipient_key/1 and delete_sender_key/1" do
+    test "deleted recipient keys can no longer decrypt" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("DeletedRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender1")
+      {:ok, ciphertext} = ExTholosPq.encrypt("secret", sid, [recipient_pub])
+
+      assert :ok = ExTholosPq.delete_recipient_key(kid)
+      assert {:error, {:recipient_not_found, ^kid}} =
+               ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+    end
+
+    test "deleted sender keys can no longer encrypt" do
+      {:ok, {_kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("Alice")
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("DeletedSender")
+
+      assert :ok = ExTholosPq.delete_sender_key(sid)
+      assert {:error, {:sender_not_found, ^sid}} =
+               ExTholosPq.encrypt("secret", sid, [recipient_pub])
+    end
+
+    test "deleting an unknown key reports not_found" do
+      assert {:error, :not_found} = ExTholosPq.delete_recipient_key("NeverGenerated")
+      assert {:error, :not_found} = ExTholosPq.delete_sender_key("NeverGenerated")
+    end
+  end
+
+  describe "key zeroization" do
+    test "a dropped sender key has its secret key wiped" do
+      assert ExTholosPq.sender_key_wiped_on_drop()
+    end
+
+    test "deleted and re-exported keys stay independent" do
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("WipedSender")
+      {:ok, exported} = ExTholosPq.export_sender_keypair(sid)
+
+      :ok = ExTholosPq.delete_sender_key(sid)
+
+      # The exported copy belongs to the caller and is unaffected by the wipe
+      {:ok, {_kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("WipedRecipient")
+      assert {:ok, _ciphertext} =
+               ExTholosPq.encrypt_with_keys("secret", exported, [recipient_pub])
+    end
+  end
+
+  describe "list_recipient_kids/0 and list_sender_sids/0" do
+    test "list identifiers in sorted order" do
+      {:ok, _} = ExTholosPq.gen_recipient_keypair("ListedZed")
+      {:ok, _} = ExTholosPq.gen_recipient_keypair("ListedAda")
+      {:ok, _} = ExTholosPq.gen_sender_keypair("ListedSenderZed")
+      {:ok, _} = ExTholosPq.gen_sender_keypair("ListedSenderAda")
+
+      {:ok, kids} = ExTholosPq.list_recipient_kids()
+      {:ok, sids} = ExTholosPq.list_sender_sids()
+
+      assert kids == Enum.sort(kids)
+      assert sids == Enum.sort(sids)
+      assert "ListedAda" in kids and "ListedZed" in kids
+      assert "ListedSenderAda" in sids and "ListedSenderZed" in sids
+    end
+
+    test "deleted keys are no longer listed" do
+      {:ok, {kid, _pub}} = ExTholosPq.gen_recipient_keypair("ListedThenDeleted")
+      :ok = ExTholosPq.delete_recipient_key(kid)
+
+      {:ok, kids} = ExTholosPq.list_recipient_kids()
+      refute kid in kids
+    end
+  end
+
+  describe "has_recipient_key/1 and has_sender_key/1" do
+    test "report stored keys, and only in their own store" do
+      {:ok, {kid, _pub}} = ExTholosPq.gen_recipient_keypair("HeldKey")
+      {:ok, {sid, _pub}} = ExTholosPq.gen_sender_keypair("HeldSenderKey")
+
+      assert ExTholosPq.has_recipient_key(kid)
+      assert ExTholosPq.has_sender_key(sid)
+      refute ExTholosPq.has_recipient_key(sid)
+      refute ExTholosPq.has_sender_key(kid)
+      refute ExTholosPq.has_recipient_key("NotHeld")
+      refute ExTholosPq.has_sender_key("NotHeld")
+    end
+
+    test "turn false once a key is deleted" do
+      {:ok, {kid, _pub}} = ExTholosPq.gen_recipient_keypair("HeldThenDeleted")
+      {:ok, {sid, _pub}} = ExTholosPq.gen_sender_keypair("HeldSenderThenDeleted")
+      :ok = ExTholosPq.delete_recipient_key(kid)
+      :ok = ExTholosPq.delete_sender_key(sid)
+
+      refute ExTholosPq.has_recipient_key(kid)
+      refute ExTholosPq.has_sender_key(sid)
+    end
+  end
+
+  describe "recipient_key_usage/1" do
+    setup do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("UsageRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("UsageSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("counted", sid, [recipient_pub])
+      %{kid: kid, sender_pub: sender_pub, ciphertext: ciphertext}
+    end
+
+    test "counts every successful decrypt", ctx do
+      assert {:ok, 0} = ExTholosPq.recipient_key_usage(ctx.kid)
+
+      for _ <- 1..5 do
+        {:ok, "counted"} = ExTholosPq.decrypt(ctx.ciphertext, ctx.kid, [ctx.sender_pub])
+      end
+
+      assert {:ok, 5} = ExTholosPq.recipient_key_usage(ctx.kid)
+
+      {:ok, [{:ok, _}, {:ok, _}]} =
+        ExTholosPq.decrypt_many([ctx.ciphertext, ctx.ciphertext], ctx.kid, [ctx.sender_pub])
+
+      assert {:ok, 7} = ExTholosPq.recipient_key_usage(ctx.kid)
+    end
+
+    test "does not count failed decrypts", ctx do
+      {:ok, {_, stranger_pub}} = ExTholosPq.gen_sender_keypair("UsageStranger")
+
+      assert {:error, _} = ExTholosPq.decrypt(ctx.ciphertext, ctx.kid, [stranger_pub])
+      assert {:error, _} = ExTholosPq.decrypt("garbage", ctx.kid, [ctx.sender_pub])
+      assert {:ok, 0} = ExTholosPq.recipient_key_usage(ctx.kid)
+    end
+
+    test "resets when the key is deleted", ctx do
+      {:ok, "counted"} = ExTholosPq.decrypt(ctx.ciphertext, ctx.kid, [ctx.sender_pub])
+      :ok = ExTholosPq.delete_recipient_key(ctx.kid)
+
+      assert {:error, {:recipient_not_found, "UsageRecipient"}} =
+               ExTholosPq.recipient_key_usage(ctx.kid)
+
+      {:ok, _} = ExTholosPq.gen_recipient_keypair(ctx.kid)
+      assert {:ok, 0} = ExTholosPq.recipient_key_usage(ctx.kid)
+    end
+  end
+
+  describe "migrate_recipient_key/1" do
+    setup do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("MigratedRecipient")
+      {:ok, exported} = ExTholosPq.export_recipient_key(kid)
+
+      # The same export without its trailing `format: 1` entry, as releases
+      # before the key format was recorded wrote it.
+      <<0xA3, rest::binary>> = exported
+      <<fields::binary-size(byte_size(rest) - 8), 0x66, "format", 0x01>> = rest
+      untagged = <<0xA2, fields::binary>>
+
+      %{kid: kid, recipient_pub: recipient_pub, exported: exported, untagged: untagged}
+    end
+
+    test "upgrades a key loaded from an old export", ctx do
+      :ok = ExTholosPq.delete_recipient_key(ctx.kid)
+      :ok = ExTholosPq.load_recipient_keypair(ctx.kid, ctx.recipient_pub, ctx.untagged)
+      assert {:ok, 0} = ExTholosPq.recipient_key_format(ctx.kid)
+
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("MigrationSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("before and after", sid, [ctx.recipient_pub])
+      assert {:ok, "before and after"} = ExTholosPq.decrypt(ciphertext, ctx.kid, [sender_pub])
+
+      assert {:ok, :migrated} = ExTholosPq.migrate_recipient_key(ctx.kid)
+      assert {:ok, 1} = ExTholosPq.recipient_key_format(ctx.kid)
+      assert {:ok, "before and after"} = ExTholosPq.decrypt(ciphertext, ctx.kid, [sender_pub])
+
+      assert {:ok, :already_current} = ExTholosPq.migrate_recipient_key(ctx.kid)
+      assert {:ok, ctx.exported} == ExTholosPq.export_recipient_key(ctx.kid)
+    end
+
+    test "leaves a current key alone", ctx do
+      assert {:ok, :already_current} = ExTholosPq.migrate_recipient_key(ctx.kid)
+      assert {:ok, ctx.exported} == ExTholosPq.export_recipient_key(ctx.kid)
+    end
+
+    test "refuses a key from a newer format", ctx do
+      <<0xA3, rest::binary>> = ctx.exported
+      newer = <<0xA3, binary_part(rest, 0, byte_size(rest) - 1)::binary, 0x02>>
+      :ok = ExTholosPq.delete_recipient_key(ctx.kid)
+
+      assert {:error, {:invalid_private_key, "unsupported key format 2"}} =
+               ExTholosPq.load_recipient_keypair(ctx.kid, ctx.recipient_pub, newer)
+    end
+
+    test "unknown kid" do
+      assert {:error, {:recipient_not_found, "NoSuchKid"}} =
+               ExTholosPq.migrate_recipient_key("NoSuchKid")
+    end
+  end
+
+  describe "set_log_target/1" do
+    setup do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("LoggedRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("LoggedSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("observed", sid, [recipient_pub])
+
+      :ok = ExTholosPq.set_log_target(self())
+      on_exit(fn -> ExTholosPq.set_log_target(nil) end)
+
+      %{kid: kid, recipient_pub: recipient_pub, sender_pub: sender_pub, ciphertext: ciphertext}
+    end
+
+    test "reports a failed decrypt", ctx do
+      <<head::binary-size(byte_size(ctx.ciphertext) - 1), last>> = ctx.ciphertext
+      tampered = <<head::binary, Bitwise.bxor(last, 1)>>
+
+      assert {:error, reason} = ExTholosPq.decrypt(tampered, ctx.kid, [ctx.sender_pub])
+      assert_received {:tholos_event, event, %{kid: "LoggedRecipient", reason: ^reason}}
+      assert event in [:decrypt_failed, :signature_invalid]
+    end
+
+    test "reports a wire from a sender that is not allowed", ctx do
+      {:ok, {_, stranger_pub}} = ExTholosPq.gen_sender_keypair("LoggedStranger")
+
+      assert {:error, reason} = ExTholosPq.decrypt(ctx.ciphertext, ctx.kid, [stranger_pub])
+
+      assert_received {:tholos_event, :signature_invalid,
+                       %{kid: "LoggedRecipient", reason: ^reason}}
+    end
+
+    test "reports a wire with no envelope for the key", ctx do
+      {:ok, {other_kid, _}} = ExTholosPq.gen_recipient_keypair("LoggedOther")
+
+      assert {:error, :no_envelope_for_recipient = reason} =
+               ExTholosPq.decrypt(ctx.ciphertext, other_kid, [ctx.sender_pub])
+
+      assert_received {:tholos_event, :decrypt_failed, %{kid: "LoggedOther", reason: ^reason}}
+    end
+
+    test "reports loaded keys", ctx do
+      {:ok, exported} = ExTholosPq.export_recipient_key(ctx.kid)
+      :ok = ExTholosPq.load_recipient_keypair(ctx.kid, ctx.recipient_pub, exported)
+      assert_received {:tholos_event, :key_loaded, %{kid: "LoggedRecipient"}}
+
+      {:ok, blob} = ExTholosPq.export_store("hunter2")
+      {:ok, {1, 1}} = ExTholosPq.import_store(blob, "hunter2")
+      assert_received {:tholos_event, :key_loaded, %{kid: "LoggedRecipient"}}
+      assert_received {:tholos_event, :key_loaded, %{sid: "LoggedSender"}}
+    end
+
+    test "sends nothing for a successful decrypt or without a target", ctx do
+      {:ok, "observed"} = ExTholosPq.decrypt(ctx.ciphertext, ctx.kid, [ctx.sender_pub])
+      refute_received {:tholos_event, _, _}
+
+      {:ok, {_, stranger_pub}} = ExTholosPq.gen_sender_keypair("UnloggedStranger")
+      :ok = ExTholosPq.set_log_target(nil)
+      {:error, _} = ExTholosPq.decrypt(ctx.ciphertext, ctx.kid, [stranger_pub])
+      refute_received {:tholos_event, _, _}
+    end
+  end
+
+  describe "clear_all_keys/0 and key_store_stats/0" do
+    test "clearing empties both stores" do
+      {:ok, _} = ExTholosPq.gen_recipient_keypair("ClearedRecipient")
+      {:ok, _} = ExTholosPq.gen_sender_keypair("ClearedSender")
+
+      assert :ok = ExTholosPq.clear_all_keys()
+      assert {:ok, []} = ExTholosPq.list_recipient_kids()
+      assert {:ok, []} = ExTholosPq.list_sender_sids()
+
+      assert {:ok, %{recipients: 0, senders: 0, approx_bytes: 0}} =
+               ExTholosPq.key_store_stats()
+    end
+
+    test "stats count stored keys and grow with them" do
+      :ok = ExTholosPq.clear_all_keys()
+      {:ok, _} = ExTholosPq.gen_recipient_keypair("CountedRecipient")
+      {:ok, _} = ExTholosPq.gen_sender_keypair("CountedSender")
+
+      assert {:ok, %{recipients: 1, senders: 1, approx_bytes: bytes}} =
+               ExTholosPq.key_store_stats()
+
+      {:ok, _} = ExTholosPq.gen_recipient_keypair("CountedRecipient2")
+      assert {:ok, %{recipients: 2, approx_bytes: more_bytes}} = ExTholosPq.key_store_stats()
+      assert more_bytes > bytes
+    end
+
+    test "key resources survive clearing the store" do
+      {:ok, {key, recipient_pub}} = ExTholosPq.gen_recipient_key_resource("ClearedResource")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("ClearedResourceSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("kept", sid, [recipient_pub])
+
+      :ok = ExTholosPq.clear_all_keys()
+
+      assert {:ok, "kept"} = ExTholosPq.decrypt(ciphertext, key, [sender_pub])
+    end
+  end
+
+  describe "sweep_expired/1" do
+    test "removes keys past their time to live and keeps the rest" do
+      now = System.os_time(:second)
+      {:ok, _} = ExTholosPq.gen_recipient_keypair("SessionRecipient", ttl_seconds: 1)
+      {:ok, _} = ExTholosPq.gen_sender_keypair("SessionSender", ttl_seconds: 1)
+      {:ok, _} = ExTholosPq.gen_sender_keypair_exported("SessionSender2", ttl_seconds: 1)
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("LongLivedRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("LongLivedSender")
+
+      assert {:ok, {0, 0}} = ExTholosPq.sweep_expired(now - 1)
+      assert {:ok, {1, 2}} = ExTholosPq.sweep_expired(now + 3600)
+      assert {:ok, {0, 0}} = ExTholosPq.sweep_expired(now + 3600)
+
+      assert {:ok, [^kid]} = ExTholosPq.list_recipient_kids()
+      assert {:ok, [^sid]} = ExTholosPq.list_sender_sids()
+
+      {:ok, ciphertext} = ExTholosPq.encrypt("kept", sid, [recipient_pub])
+      assert {:ok, "kept"} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+    end
+
+    test "an expired key keeps working until it is swept" do
+      {:ok, {kid, recipient_pub}} =
+        ExTholosPq.gen_recipient_keypair("UnsweptRecipient", ttl_seconds: 0)
+
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("UnsweptSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("late", sid, [recipient_pub])
+
+      assert {:ok, "late"} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+      assert {:ok, {1, 0}} = ExTholosPq.sweep_expired(System.os_time(:second) + 1)
+
+      assert {:error, {:recipient_not_found, ^kid}} =
+               ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+    end
+
+    test "key references take no time to live" do
+      assert {:error, {:invalid_option, "ttl_seconds"}} =
+               ExTholosPq.gen_recipient_key_resource("SessionResource", ttl_seconds: 60)
+
+      assert {:error, {:invalid_option, "ttl_seconds"}} =
+               ExTholosPq.gen_sender_key_resource("SessionResource", ttl_seconds: 60)
+    end
+  end
+
+  describe "encrypt_anonymous/2" do
+    setup :sender_and_recipient
+
+    test "round-trips through decrypt_with_sender/3 only", ctx do
+      {:ok, ciphertext} = ExTholosPq.encrypt_anonymous("no name", [ctx.recipient_pub])
+
+      assert {:ok, {"no name", :anonymous}} =
+               ExTholosPq.decrypt_with_sender(ciphertext, ctx.kid, [])
+
+      assert {:ok, {"no name", :anonymous}} =
+               ExTholosPq.decrypt_with_sender(ciphertext, ctx.kid, [ctx.sender_pub])
+
+      assert {:error, :signature_invalid} =
+               ExTholosPq.decrypt(ciphertext, ctx.kid, [ctx.sender_pub])
+
+      assert {:ok, :invalid_signature} = ExTholosPq.validate_wire(ciphertext, [ctx.sender_pub])
+    end
+
+    test "a signed wire cannot be downgraded to anonymous", ctx do
+      {:ok, ciphertext} = ExTholosPq.encrypt("signed", ctx.sid, [ctx.recipient_pub])
+
+      # Without the flag the empty signature is rejected, with it the header no longer matches
+      {:ok, stripped} = ExTholosPq.strip_signature(ciphertext, false)
+      {:ok, flagged} = ExTholosPq.strip_signature(ciphertext, true)
+
+      assert {:error, {:malformed_wire, _}} =
+               ExTholosPq.decrypt_with_sender(stripped, ctx.kid, [ctx.sender_pub])
+
+      assert {:error, :auth_tag_mismatch} =
+               ExTholosPq.decrypt_with_sender(flagged, ctx.kid, [ctx.sender_pub])
+    end
+  end
+
+  describe "decrypt_with_sender/3" do
+    test "returns the sid of whichever allowed sender signed" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("AuditRecipient")
+      {:ok, {sid_a, sender_pub_a}} = ExTholosPq.gen_sender_keypair("AuditSenderA")
+      {:ok, {sid_b, sender_pub_b}} = ExTholosPq.gen_sender_keypair("AuditSenderB")
+      allowed = [sender_pub_a, sender_pub_b]
+
+      {:ok, from_a} = ExTholosPq.encrypt("from a", sid_a, [recipient_pub])
+      {:ok, from_b} = ExTholosPq.encrypt("from b", sid_b, [recipient_pub])
+
+      assert {:ok, {"from a", ^sid_a}} = ExTholosPq.decrypt_with_sender(from_a, kid, allowed)
+      assert {:ok, {"from b", ^sid_b}} = ExTholosPq.decrypt_with_sender(from_b, kid, allowed)
+    end
+
+    test "fails like decrypt/3 for a sender outside the allow-list" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("AuditRecipient")
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("AuditSenderA")
+      {:ok, {_other_sid, other_pub}} = ExTholosPq.gen_sender_keypair("AuditSenderB")
+      {:ok, ciphertext} = ExTholosPq.encrypt("from a", sid, [recipient_pub])
+
+      assert {:error, :signature_invalid} =
+               ExTholosPq.decrypt_with_sender(ciphertext, kid, [other_pub])
+    end
+
+    test "an empty allow-list returns the sender identity embedded in the wire" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("AuditRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("UnknownAuditSender")
+
+      {:ok, ciphertext} =
+        ExTholosPq.encrypt("from anyone", sid, [recipient_pub], embed_sender_key: true)
+
+      assert {:ok, {"from anyone", "UnknownAuditSender", ^sender_pub}} =
+               ExTholosPq.decrypt_with_sender(ciphertext, kid, [])
+
+      # A non-empty allow-list still decides on its own
+      assert {:ok, {"from anyone", ^sid}} =
+               ExTholosPq.decrypt_with_sender(ciphertext, kid, [sender_pub])
+
+      {:ok, {_other_sid, other_pub}} = ExTholosPq.gen_sender_keypair("OtherAuditSender")
+
+      assert {:error, :signature_invalid} =
+               ExTholosPq.decrypt_with_sender(ciphertext, kid, [other_pub])
+    end
+
+    test "only decrypt_with_sender/3 accepts an embedded sender key" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("AuditRecipient")
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("ForgingSender")
+
+      {:ok, ciphertext} =
+        ExTholosPq.encrypt("forged", sid, [recipient_pub], embed_sender_key: true)
+
+      assert {:error, :signature_invalid} = ExTholosPq.decrypt(ciphertext, kid, [])
+
+      assert {:error, {:authz, :signature_invalid}} =
+               ExTholosPq.decrypt_classified(ciphertext, kid, [])
+
+      assert {:ok, :invalid_signature} = ExTholosPq.validate_wire(ciphertext, [])
+    end
+
+    test "an empty allow-list rejects a wire without an embedded sender key" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("AuditRecipient")
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("AuditSenderA")
+      {:ok, ciphertext} = ExTholosPq.encrypt("from a", sid, [recipient_pub])
+
+      assert {:error, :signature_invalid} =
+               ExTholosPq.decrypt_with_sender(ciphertext, kid, [])
+    end
+  end
+
+  describe "decrypt_with_allowlist_ref/3" do
+    test "decrypts against an allow-list of 1000 senders" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("AllowlistRecipient")
+
+      senders =
+        for i <- 1..1000 do
+          {:ok, {sender_ref, sender_pub}} = ExTholosPq.gen_sender_key_resource("Member#{i}")
+          {sender_ref, sender_pub}
+        end
+
+      sender_pubs = Enum.map(senders, &elem(&1, 1))
+      {:ok, allowlist} = ExTholosPq.build_allowlist(sender_pubs)
+
+      for {sender_ref, _sender_pub} <- Enum.take_every(senders, 50) do
+        {:ok, wire} = ExTholosPq.encrypt("to the list", sender_ref, [recipient_pub])
+        assert {:ok, "to the list"} = ExTholosPq.decrypt_with_allowlist_ref(wire, kid, allowlist)
+        assert {:ok, "to the list"} = ExTholosPq.decrypt(wire, kid, sender_pubs)
+      end
+    end
+
+    test "rejects senders missing from the allow-list" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("AllowlistRecipient2")
+      {:ok, {_member_ref, member_pub}} = ExTholosPq.gen_sender_key_resource("ListMember")
+      {:ok, {outsider_ref, _outsider_pub}} = ExTholosPq.gen_sender_key_resource("ListOutsider")
+
+      {:ok, allowlist} = ExTholosPq.build_allowlist([member_pub])
+      {:ok, wire} = ExTholosPq.encrypt("hello", outsider_ref, [recipient_pub])
+
+      assert {:error, :signature_invalid} =
+               ExTholosPq.decrypt_with_allowlist_ref(wire, kid, allowlist)
+    end
+
+    test "reports undecodable keys" do
+      assert {:error, {:deserialize_sender, _detail}} = ExTholosPq.build_allowlist(["not cbor"])
+    end
+  end
+
+  describe "decrypt_many/3" do
+    test "returns a result per wire, in order" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("InboxRecipient")
+      {:ok, {_other_kid, other_pub}} = ExTholosPq.gen_recipient_keypair("InboxBystander")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("InboxSender")
+      {:ok, {stranger, _stranger_pub}} = ExTholosPq.gen_sender_keypair("InboxStranger")
+
+      {:ok, first} = ExTholosPq.encrypt("first", sid, [recipient_pub])
+      {:ok, unsigned} = ExTholosPq.encrypt("unsigned", stranger, [recipient_pub])
+      {:ok, elsewhere} = ExTholosPq.encrypt("elsewhere", sid, [other_pub])
+      {:ok, second} = ExTholosPq.encrypt("second", sid, [recipient_pub])
+      wires = [first, unsigned, elsewhere, "garbage", second]
+
+      assert {:ok, results} = ExTholosPq.decrypt_many(wires, kid, [sender_pub])
+
+      assert [
+               {:ok, "first"},
+               {:error, :signature_invalid},
+               {:error, :no_envelope_for_recipient},
+               {:error, {:unsupported_wire_version, _}},
+               {:ok, "second"}
+             ] = results
+
+      # Each result matches what decrypt/3 returns for the same wire
+      assert results == Enum.map(wires, &ExTholosPq.decrypt(&1, kid, [sender_pub]))
+    end
+
+    test "works with a key resource and an empty batch" do
+      {:ok, {key, recipient_pub}} = ExTholosPq.gen_recipient_key_resource("InboxResource")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("InboxResourceSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("to a resource", sid, [recipient_pub])
+
+      assert {:ok, [{:ok, "to a resource"}]} =
+               ExTholosPq.decrypt_many([ciphertext], key, [sender_pub])
+
+      assert {:ok, []} = ExTholosPq.decrypt_many([], key, [sender_pub])
+    end
+
+    test "fails as a whole for an unreadable allow-list" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("InboxBadAllowList")
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("InboxBadAllowListSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("hello", sid, [recipient_pub])
+
+      assert {:error, {:deserialize_sender, _}} =
+               ExTholosPq.decrypt_many([ciphertext], kid, ["not cbor"])
+    end
+  end
+
+  describe "decrypt_with_digest/4" do
+    setup do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("DigestRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("DigestSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("abc", sid, [recipient_pub])
+      %{kid: kid, sender_pub: sender_pub, ciphertext: ciphertext}
+    end
+
+    test "returns the SHA-256 of the plaintext by default", ctx do
+      expected =
+        Base.decode16!("BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD")
+
+      assert {:ok, {"abc", ^expected}} =
+               ExTholosPq.decrypt_with_digest(ctx.ciphertext, ctx.kid, [ctx.sender_pub])
+
+      assert {:ok, {"abc", ^expected}} =
+               ExTholosPq.decrypt_with_digest(ctx.ciphertext, ctx.kid, [ctx.sender_pub], :sha256)
+    end
+
+    test "returns the BLAKE3 of the plaintext", ctx do
+      expected =
+        Base.decode16!("6437B3AC38465133FFB63B75273A8DB548C558465D79DB03FD359C6CD5BD9D85")
+
+      assert {:ok, {"abc", ^expected}} =
+               ExTholosPq.decrypt_with_digest(ctx.ciphertext, ctx.kid, [ctx.sender_pub], :blake3)
+    end
+
+    test "rejects an unknown algorithm", ctx do
+      assert {:error, {:invalid_option, "digest"}} =
+               ExTholosPq.decrypt_with_digest(ctx.ciphertext, ctx.kid, [ctx.sender_pub], :md5)
+    end
+  end
+
+  describe "encrypt_to_map/3" do
+    setup do
+      {:ok, {alice, alice_pub}} = ExTholosPq.gen_recipient_keypair("MapAlice")
+      {:ok, {bob, bob_pub}} = ExTholosPq.gen_recipient_keypair("MapBob")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("MapSender")
+
+      %{
+        alice: alice,
+        alice_pub: alice_pub,
+        bob: bob,
+        bob_pub: bob_pub,
+        sid: sid,
+        sender_pub: sender_pub
+      }
+    end
+
+    test "encrypts for every kid in the map", ctx do
+      recipients = %{ctx.alice => ctx.alice_pub, ctx.bob => ctx.bob_pub}
+      assert {:ok, ciphertext} = ExTholosPq.encrypt_to_map("mapped", ctx.sid, recipients)
+
+      assert {:ok, ["MapAlice", "MapBob"]} = ExTholosPq.wire_recipients(ciphertext)
+      assert {:ok, "mapped"} = ExTholosPq.decrypt(ciphertext, ctx.alice, [ctx.sender_pub])
+      assert {:ok, "mapped"} = ExTholosPq.decrypt(ciphertext, ctx.bob, [ctx.sender_pub])
+    end
+
+    test "rejects a public key stored under the wrong kid", ctx do
+      recipients = %{ctx.alice => ctx.alice_pub, ctx.bob => ctx.alice_pub}
+
+      assert {:error, {:kid_mismatch, "MapBob", "MapAlice"}} =
+               ExTholosPq.encrypt_to_map("mapped", ctx.sid, recipients)
+    end
+  end
+
+  describe "encrypt_partial/3" do
+    setup do
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("PartialSender")
+      %{sid: sid, sender_pub: sender_pub}
+    end
+
+    test "encrypts for the good keys and reports the corrupt ones", ctx do
+      {:ok, {alice, alice_pub}} = ExTholosPq.gen_recipient_keypair("PartialAlice")
+      {:ok, {bob, bob_pub}} = ExTholosPq.gen_recipient_keypair("PartialBob")
+
+      truncated = binary_part(bob_pub, 0, div(byte_size(bob_pub), 2))
+      # Well-formed CBOR for a recipient whose Kyber key is only 10 bytes long
+      short_key = <<0xA2, 0x63, "kid", 0x68, "ShortKey", 0x68, "pk_kyber", 0x4A, 0::80>>
+
+      assert {:ok, {ciphertext, [1, 3, 4]}} =
+               ExTholosPq.encrypt_partial("bulk", ctx.sid, [
+                 alice_pub,
+                 truncated,
+                 bob_pub,
+                 short_key,
+                 "garbage"
+               ])
+
+      assert {:ok, ["PartialAlice", "PartialBob"]} = ExTholosPq.wire_recipients(ciphertext)
+      assert {:ok, "bulk"} = ExTholosPq.decrypt(ciphertext, alice, [ctx.sender_pub])
+      assert {:ok, "bulk"} = ExTholosPq.decrypt(ciphertext, bob, [ctx.sender_pub])
+    end
+
+    test "skips keys of another security level than the first", ctx do
+      {:ok, {alice, alice_pub}} = ExTholosPq.gen_recipient_keypair("PartialAlice")
+
+      {:ok, {_, level1_pub}} =
+        ExTholosPq.gen_recipient_keypair("PartialLevel1", security_level: :level1)
+
+      assert {:ok, {ciphertext, [1]}} =
+               ExTholosPq.encrypt_partial("bulk", ctx.sid, [alice_pub, level1_pub])
+
+      assert {:ok, ["PartialAlice"]} = ExTholosPq.wire_recipients(ciphertext)
+      assert {:ok, "bulk"} = ExTholosPq.decrypt(ciphertext, alice, [ctx.sender_pub])
+    end
+
+    test "fails when no key can be used", ctx do
+      assert {:error, :no_valid_recipients} =
+               ExTholosPq.encrypt_partial("bulk", ctx.sid, ["garbage", "more garbage"])
+
+      assert {:error, :no_valid_recipients} = ExTholosPq.encrypt_partial("bulk", ctx.sid, [])
+    end
+  end
+
+  describe "recipient limit" do
+    setup :sender_and_recipient
+
+    test "rejects 1000 recipients before doing any crypto", ctx do
+      recipients = List.duplicate(ctx.recipient_pub, 1000)
+
+      {elapsed_us, result} = :timer.tc(fn -> ExTholosPq.encrypt("x", ctx.sid, recipients) end)
+
+      assert {:error, {:too_many_recipients, 1000}} = result
+      # 1000 Kyber encapsulations take hundreds of milliseconds
+      assert elapsed_us < 50_000
+    end
+
+    test "rejects an empty recipient list", ctx do
+      assert {:error, :no_recipients} = ExTholosPq.encrypt("x", ctx.sid, [])
+      assert {:error, :no_recipients} = ExTholosPq.encrypt("x", ctx.sid, [], aad: "ctx")
+
+      {:ok, keypair} = ExTholosPq.export_sender_keypair(ctx.sid)
+      assert {:error, :no_recipients} = ExTholosPq.encrypt_with_keys("x", keypair, [])
+    end
+
+    test "the default limit also applies to encrypt_with_keys/3", ctx do
+      {:ok, keypair} = ExTholosPq.export_sender_keypair(ctx.sid)
+      recipients = List.duplicate(ctx.recipient_pub, 257)
+
+      assert {:error, {:too_many_recipients, 257}} =
+               ExTholosPq.encrypt_with_keys("x", keypair, recipients)
+    end
+
+    test "max_recipients raises or lowers the limit", ctx do
+      recipients = List.duplicate(ctx.recipient_pub, 3)
+
+      assert {:error, {:too_many_recipients, 3}} =
+               ExTholosPq.encrypt("x", ctx.sid, recipients, max_recipients: 2)
+
+      assert {:ok, ciphertext} = ExTholosPq.encrypt("x", ctx.sid, recipients, max_recipients: 3)
+      assert {:ok, "x"} = ExTholosPq.decrypt(ciphertext, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "combines with associated data", ctx do
+      {:ok, ciphertext} =
+        ExTholosPq.encrypt("x", ctx.sid, [ctx.recipient_pub], aad: "ctx", max_recipients: 1)
+
+      assert {:ok, "x"} = ExTholosPq.decrypt(ciphertext, ctx.kid, [ctx.sender_pub], "ctx")
+    end
+
+    test "rejects unknown options", ctx do
+      assert {:error, {:invalid_option, "max_recipient"}} =
+               ExTholosPq.encrypt("x", ctx.sid, [ctx.recipient_pub], max_recipient: 1)
+    end
+  end
+
+  describe "wire structure limits" do
+    setup :sender_and_recipient
+
+    test "rejects absurd declared lengths without allocating for them", ctx do
+      # Version 1, the self-describe tag, then a map whose one value claims 2^64 - 1 bytes
+      huge_bytes = <<1, 0xD9, 0xD9, 0xF7, 0xA1, 0x65, "inner", 0x5B, -1::64>>
+      huge_array = <<1, 0xD9, 0xD9, 0xF7, 0xA1, 0x65, "inner", 0x9B, -1::64>>
+      huge_map = <<1, 0xD9, 0xD9, 0xF7, 0xBB, -1::64>>
+
+      for wire <- [huge_bytes, huge_array, huge_map] do
+        {elapsed_us, result} =
+          :timer.tc(fn -> ExTholosPq.decrypt(wire, ctx.kid, [ctx.sender_pub]) end)
+
+        assert {:error, {:malformed_wire, _detail}} = result
+        assert elapsed_us < 50_000
+        assert {:error, :malformed_wire} = ExTholosPq.wire_recipients(wire)
+      end
+    end
+
+    test "rejects a length one byte longer than the wire", ctx do
+      {:ok, ciphertext} = ExTholosPq.encrypt("x", ctx.sid, [ctx.recipient_pub])
+      truncated = binary_part(ciphertext, 0, byte_size(ciphertext) - 1)
+
+      assert {:error, {:malformed_wire, _detail}} =
+               ExTholosPq.decrypt(truncated, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "max_recipients limits the recipients a wire may declare", ctx do
+      {:ok, {_, other_pub}} = ExTholosPq.gen_recipient_keypair("StructureOther")
+      {:ok, {_, third_pub}} = ExTholosPq.gen_recipient_keypair("StructureThird")
+      recipients = [ctx.recipient_pub, other_pub, third_pub]
+      {:ok, ciphertext} = ExTholosPq.encrypt("x", ctx.sid, recipients)
+
+      assert {:error, {:malformed_wire, "3 recipients, more than the 2 allowed"}} =
+               ExTholosPq.decrypt(ciphertext, ctx.kid, [ctx.sender_pub], max_recipients: 2)
+
+      assert {:ok, "x"} =
+               ExTholosPq.decrypt(ciphertext, ctx.kid, [ctx.sender_pub], max_recipients: 3)
+
+      assert {:ok, "x"} = ExTholosPq.decrypt(ciphertext, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "the default allows as many recipients as encrypt does", ctx do
+      recipients = List.duplicate(ctx.recipient_pub, 257)
+      {:ok, ciphertext} = ExTholosPq.encrypt("x", ctx.sid, recipients, max_recipients: 257)
+
+      assert {:error, {:malformed_wire, "257 recipients, more than the 256 allowed"}} =
+               ExTholosPq.decrypt(ciphertext, ctx.kid, [ctx.sender_pub])
+
+      assert {:ok, "x"} =
+               ExTholosPq.decrypt(ciphertext, ctx.kid, [ctx.sender_pub], max_recipients: 257)
+    end
+  end
+
+  describe "wire_recipients/1" do
+    test "returns the kids of every recipient envelope" do
+      {:ok, {kid1, pub1}} = ExTholosPq.gen_recipient_keypair("WireRecipient1")
+      {:ok, {kid2, pub2}} = ExTholosPq.gen_recipient_keypair("WireRecipient2")
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("WireSender")
+
+      {:ok, ciphertext} = ExTholosPq.encrypt("routing", sid, [pub1, pub2])
+
+      assert {:ok, [^kid1, ^kid2]} = ExTholosPq.wire_recipients(ciphertext)
+    end
+
+    test "rejects truncated and garbage input" do
+      {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("WireRecipient")
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("WireSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("routing", sid, [pub])
+
+      truncated = binary_part(ciphertext, 0, div(byte_size(ciphertext), 2))
+
+      assert {:error, :malformed_wire} = ExTholosPq.wire_recipients(truncated)
+      assert {:error, :malformed_wire} = ExTholosPq.wire_recipients("not a wire")
+      assert {:error, :malformed_wire} = ExTholosPq.wire_recipients("")
+    end
+  end
+
+  describe "escrow recipient" do
+    setup do
+      {:ok, {escrow_kid, escrow_pub}} = ExTholosPq.gen_recipient_keypair("ComplianceEscrow")
+      {:ok, {kid_a, pub_a}} = ExTholosPq.gen_recipient_keypair("EscrowedAlice")
+      {:ok, {kid_b, pub_b}} = ExTholosPq.gen_recipient_keypair("EscrowedBob")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("EscrowedSender")
+
+      %{
+        escrow_kid: escrow_kid,
+        escrow_pub: escrow_pub,
+        kid_a: kid_a,
+        pub_a: pub_a,
+        kid_b: kid_b,
+        pub_b: pub_b,
+        sid: sid,
+        sender_pub: sender_pub
+      }
+    end
+
+    test "is none until one is set", ctx do
+      assert {:ok, :none} = ExTholosPq.escrow_recipient()
+
+      assert :ok = ExTholosPq.set_escrow_recipient(ctx.escrow_pub)
+      assert {:ok, "ComplianceEscrow"} = ExTholosPq.escrow_recipient()
+
+      assert :ok = ExTholosPq.clear_escrow_recipient()
+      assert {:ok, :none} = ExTholosPq.escrow_recipient()
+      assert :ok = ExTholosPq.clear_escrow_recipient()
+    end
+
+    test "can decrypt every wire while it is set", ctx do
+      :ok = ExTholosPq.set_escrow_recipient(ctx.escrow_pub)
+
+      wires = [
+        ExTholosPq.encrypt("one", ctx.sid, [ctx.pub_a]),
+        ExTholosPq.encrypt("two", ctx.sid, [ctx.pub_a, ctx.pub_b]),
+        ExTholosPq.encrypt("three", ctx.sid, [ctx.pub_b], pad_to: 64),
+        ExTholosPq.encrypt_to_map("four", ctx.sid, %{ctx.kid_a => ctx.pub_a})
+      ]
+
+      for {{:ok, wire}, plaintext} <- Enum.zip(wires, ["one", "two", "three", "four"]) do
+        assert {:ok, ^plaintext} = ExTholosPq.decrypt(wire, ctx.escrow_kid, [ctx.sender_pub])
+      end
+
+      {:ok, two} = Enum.at(wires, 1)
+      assert {:ok, "two"} = ExTholosPq.decrypt(two, ctx.kid_a, [ctx.sender_pub])
+      assert {:ok, "two"} = ExTholosPq.decrypt(two, ctx.kid_b, [ctx.sender_pub])
+
+      {:ok, with_aad} = ExTholosPq.encrypt("five", ctx.sid, [ctx.pub_a], aad: "ctx")
+
+      assert {:ok, "five"} =
+               ExTholosPq.decrypt(with_aad, ctx.escrow_kid, [ctx.sender_pub], "ctx")
+    end
+
+    test "is added by every encrypt function", ctx do
+      :ok = ExTholosPq.set_escrow_recipient(ctx.escrow_pub)
+      {:ok, keypair} = ExTholosPq.export_sender_keypair(ctx.sid)
+      {:ok, cached} = ExTholosPq.encrypt_cached_init(ctx.sid, "cached")
+      {:ok, [batched, _]} = ExTholosPq.encrypt_batch(ctx.sid, ["batched", "x"], [ctx.pub_a])
+      {:ok, {partial, []}} = ExTholosPq.encrypt_partial("partial", ctx.sid, [ctx.pub_a])
+      {:ok, {timed, _}} = ExTholosPq.encrypt_timed("timed", ctx.sid, [ctx.pub_a])
+      {:ok, with_keys} = ExTholosPq.encrypt_with_keys("with keys", keypair, [ctx.pub_a])
+
+      wires = [
+        {batched, "batched"},
+        {partial, "partial"},
+        {timed, "timed"},
+        {with_keys, "with keys"},
+        {elem(ExTholosPq.encrypt_cached_for(cached, [ctx.pub_a]), 1), "cached"}
+      ]
+
+      for {wire, plaintext} <- wires do
+        assert {:ok, ^plaintext} = ExTholosPq.decrypt(wire, ctx.escrow_kid, [ctx.sender_pub])
+        assert {:ok, recipients} = ExTholosPq.wire_recipients(wire)
+        assert {:escrow, ctx.escrow_kid} in recipients
+      end
+
+      {:ok, anonymous} = ExTholosPq.encrypt_anonymous("anonymous", [ctx.pub_a])
+
+      assert {:ok, {"anonymous", :anonymous}} =
+               ExTholosPq.decrypt_with_sender(anonymous, ctx.escrow_kid, [])
+
+      {:ok, enc} = ExTholosPq.encrypt_init(ctx.sid, [ctx.pub_a])
+      {:ok, head} = ExTholosPq.encrypt_update(enc, "streamed")
+      {:ok, tail} = ExTholosPq.encrypt_final(enc)
+      {:ok, dec} = ExTholosPq.decrypt_init(ctx.escrow_kid, [ctx.sender_pub])
+      {:ok, opened} = ExTholosPq.decrypt_update(dec, head <> tail)
+      {:ok, rest} = ExTholosPq.decrypt_final(dec)
+      assert opened <> rest == "streamed"
+
+      dir = Path.join(System.tmp_dir!(), "ex_tholos_pq_#{System.unique_integer([:positive])}")
+      File.mkdir_p!(dir)
+      on_exit(fn -> File.rm_rf!(dir) end)
+      [plain, sealed, opened] = Enum.map(~w(plain sealed opened), &Path.join(dir, &1))
+      File.write!(plain, "filed")
+
+      :ok = ExTholosPq.encrypt_file(plain, sealed, ctx.sid, [ctx.pub_a])
+      assert :ok = ExTholosPq.decrypt_file(sealed, opened, ctx.escrow_kid, [ctx.sender_pub])
+      assert File.read!(opened) == "filed"
+    end
+
+    test "is fixed when a cached message is sealed", ctx do
+      {:ok, unescrowed} = ExTholosPq.encrypt_cached_init(ctx.sid, "sealed before")
+      :ok = ExTholosPq.set_escrow_recipient(ctx.escrow_pub)
+      {:ok, escrowed} = ExTholosPq.encrypt_cached_init(ctx.sid, "sealed while set")
+      :ok = ExTholosPq.clear_escrow_recipient()
+
+      {:ok, before} = ExTholosPq.encrypt_cached_for(unescrowed, [ctx.pub_a])
+      {:ok, during} = ExTholosPq.encrypt_cached_for(escrowed, [ctx.pub_a])
+
+      assert {:ok, [ctx.kid_a]} == ExTholosPq.wire_recipients(before)
+      assert {:ok, [ctx.kid_a, {:escrow, ctx.escrow_kid}]} == ExTholosPq.wire_recipients(during)
+      assert {:ok, "sealed while set"} =
+               ExTholosPq.decrypt(during, ctx.escrow_kid, [ctx.sender_pub])
+    end
+
+    test "cannot decrypt wires made after it is cleared", ctx do
+      :ok = ExTholosPq.set_escrow_recipient(ctx.escrow_pub)
+      {:ok, before} = ExTholosPq.encrypt("before", ctx.sid, [ctx.pub_a])
+      :ok = ExTholosPq.clear_escrow_recipient()
+      {:ok, later} = ExTholosPq.encrypt("after", ctx.sid, [ctx.pub_a])
+
+      assert {:ok, [kid_a]} = ExTholosPq.wire_recipients(later)
+      assert kid_a == ctx.kid_a
+
+      assert {:error, :no_envelope_for_recipient} =
+               ExTholosPq.decrypt(later, ctx.escrow_kid, [ctx.sender_pub])
+
+      assert {:ok, "after"} = ExTholosPq.decrypt(later, ctx.kid_a, [ctx.sender_pub])
+      assert {:ok, "before"} = ExTholosPq.decrypt(before, ctx.escrow_kid, [ctx.sender_pub])
+    end
+
+    test "is flagged in wire_recipients/1", ctx do
+      :ok = ExTholosPq.set_escrow_recipient(ctx.escrow_pub)
+      {:ok, wire} = ExTholosPq.encrypt("flagged", ctx.sid, [ctx.pub_a, ctx.pub_b])
+
+      assert {:ok, [ctx.kid_a, ctx.kid_b, {:escrow, ctx.escrow_kid}]} ==
+               ExTholosPq.wire_recipients(wire)
+    end
+
+    test "gets no second envelope when the caller already encrypts for it", ctx do
+      :ok = ExTholosPq.set_escrow_recipient(ctx.escrow_pub)
+      {:ok, wire} = ExTholosPq.encrypt("once", ctx.sid, [ctx.escrow_pub, ctx.pub_a])
+
+      assert {:ok, [{:escrow, ctx.escrow_kid}, ctx.kid_a]} == ExTholosPq.wire_recipients(wire)
+      assert {:ok, "once"} = ExTholosPq.decrypt(wire, ctx.escrow_kid, [ctx.sender_pub])
+    end
+
+    test "opens under an empty recipient AAD with per_recipient_aad", ctx do
+      :ok = ExTholosPq.set_escrow_recipient(ctx.escrow_pub)
+      {:ok, wire} = ExTholosPq.encrypt("bound", ctx.sid, [ctx.pub_a], per_recipient_aad: ["a"])
+
+      assert {:ok, "bound"} =
+               ExTholosPq.decrypt(wire, ctx.escrow_kid, [ctx.sender_pub], recipient_aad: "")
+
+      assert {:ok, "bound"} =
+               ExTholosPq.decrypt(wire, ctx.kid_a, [ctx.sender_pub], recipient_aad: "a")
+    end
+
+    test "counts towards max_recipients", ctx do
+      :ok = ExTholosPq.set_escrow_recipient(ctx.escrow_pub)
+
+      assert {:error, {:too_many_recipients, 2}} =
+               ExTholosPq.encrypt("x", ctx.sid, [ctx.pub_a], max_recipients: 1)
+    end
+
+    test "rejects a key that is not a recipient public key", ctx do
+      assert {:error, {:deserialize_recipient, _}} =
+               ExTholosPq.set_escrow_recipient("not cbor")
+
+      assert {:error, {:deserialize_recipient, _}} =
+               ExTholosPq.set_escrow_recipient(ctx.sender_pub)
+
+      assert {:ok, :none} = ExTholosPq.escrow_recipient()
+    end
+  end
+
+  describe "wire_sender_unverified/1" do
+    test "returns the sid that encrypted the wire" do
+      {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("ClaimRecipient")
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("ClaimSender")
+
+      {:ok, ciphertext} = ExTholosPq.encrypt("prefilter", sid, [pub])
+
+      assert {:ok, ^sid} = ExTholosPq.wire_sender_unverified(ciphertext)
+    end
+
+    test "lets a gateway drop senders outside its allow-list before decrypting" do
+      {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("ClaimRecipient")
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("UnknownSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("prefilter", sid, [pub])
+
+      {:ok, claimed} = ExTholosPq.wire_sender_unverified(ciphertext)
+      refute claimed in ["KnownSender"]
+    end
+
+    test "rejects garbage input" do
+      assert {:error, :malformed_wire} = ExTholosPq.wire_sender_unverified("not a wire")
+      assert {:error, :malformed_wire} = ExTholosPq.wire_sender_unverified("")
+    end
+  end
+
+  describe "wire_message_id/1" do
+    setup :sender_and_recipient
+
+    test "reads a caller-supplied id without decrypting", ctx do
+      {:ok, wire} =
+        ExTholosPq.encrypt("once", ctx.sid, [ctx.recipient_pub], message_id: "msg-0001")
+
+      assert {:ok, "msg-0001"} = ExTholosPq.wire_message_id(wire)
+      assert {:ok, "once"} = ExTholosPq.decrypt(wire, ctx.kid, [ctx.sender_pub])
+      assert {:ok, "msg-0001"} = ExTholosPq.wire_message_id(wire)
+    end
+
+    test "gives every wire a random id by default", ctx do
+      {:ok, first} = ExTholosPq.encrypt("same", ctx.sid, [ctx.recipient_pub])
+      {:ok, second} = ExTholosPq.encrypt("same", ctx.sid, [ctx.recipient_pub])
+
+      {:ok, first_id} = ExTholosPq.wire_message_id(first)
+      {:ok, second_id} = ExTholosPq.wire_message_id(second)
+      assert first_id != second_id
+      assert first_id =~ ~r/\A[0-9a-f]{8}-[0-9a-f]{4}-4[0-9a-f]{3}-[89ab][0-9a-f]{3}-/
+    end
+
+    test "breaks decryption when the id is changed", ctx do
+      {:ok, wire} =
+        ExTholosPq.encrypt("once", ctx.sid, [ctx.recipient_pub], message_id: "msg-0001")
+
+      tampered = :binary.replace(wire, "msg-0001", "msg-0002")
+
+      assert {:ok, "msg-0002"} = ExTholosPq.wire_message_id(tampered)
+      assert {:error, :signature_invalid} =
+               ExTholosPq.decrypt(tampered, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "rejects an empty id and garbage input", ctx do
+      assert {:error, {:invalid_option, "message_id"}} =
+               ExTholosPq.encrypt("once", ctx.sid, [ctx.recipient_pub], message_id: "")
+
+      assert {:error, :malformed_wire} = ExTholosPq.wire_message_id("not a wire")
+    end
+  end
+
+  describe "frame/1 and unframe/1" do
+    setup do
+      {:ok, {_kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("FrameRecipient")
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("FrameSender")
+
+      wires =
+        for message <- ["first", "second", ""] do
+          {:ok, wire} = ExTholosPq.encrypt(message, sid, [recipient_pub])
+          wire
+        end
+
+      %{wires: wires}
+    end
+
+    test "splits several frames in one buffer", ctx do
+      buffer =
+        IO.iodata_to_binary(
+          for wire <- ctx.wires do
+            {:ok, framed} = ExTholosPq.frame(wire)
+            framed
+          end
+        )
+
+      assert unframe_all(buffer) == ctx.wires
+    end
+
+    test "reports a partial buffer as incomplete", ctx do
+      {:ok, framed} = ExTholosPq.frame(hd(ctx.wires))
+
+      for size <- [0, 3, 4, byte_size(framed) - 1] do
+        assert {:error, :incomplete} = ExTholosPq.unframe(binary_part(framed, 0, size))
+      end
+
+      assert {:ok, {wire, ""}} = ExTholosPq.unframe(framed)
+      assert wire == hd(ctx.wires)
+    end
+  end
+
+  describe "wire_metadata/1" do
+    setup :sender_and_recipient
+
+    test "round-trips without a key and leaves decryption unchanged", ctx do
+      metadata = <<"application/json", 0, 255>>
+
+      {:ok, wire} =
+        ExTholosPq.encrypt("routed", ctx.sid, [ctx.recipient_pub], metadata: metadata)
+
+      assert {:ok, ^metadata} = ExTholosPq.wire_metadata(wire)
+      assert {:ok, "routed"} = ExTholosPq.decrypt(wire, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "is empty when none was given", ctx do
+      {:ok, plain} = ExTholosPq.encrypt("routed", ctx.sid, [ctx.recipient_pub])
+      {:ok, empty} = ExTholosPq.encrypt("routed", ctx.sid, [ctx.recipient_pub], metadata: "")
+
+      assert {:ok, ""} = ExTholosPq.wire_metadata(plain)
+      assert {:ok, ""} = ExTholosPq.wire_metadata(empty)
+      assert byte_size(empty) == byte_size(plain)
+    end
+
+    test "breaks decryption when the metadata is changed", ctx do
+      {:ok, wire} =
+        ExTholosPq.encrypt("routed", ctx.sid, [ctx.recipient_pub], metadata: "queue-a")
+
+      tampered = :binary.replace(wire, "queue-a", "queue-b")
+
+      assert {:ok, "queue-b"} = ExTholosPq.wire_metadata(tampered)
+
+      assert {:error, :signature_invalid} =
+               ExTholosPq.decrypt(tampered, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "rejects metadata over 1024 bytes and garbage input", ctx do
+      assert {:ok, _} =
+               ExTholosPq.encrypt("x", ctx.sid, [ctx.recipient_pub],
+                 metadata: :binary.copy("m", 1024)
+               )
+
+      assert {:error, {:invalid_option, "metadata"}} =
+               ExTholosPq.encrypt("x", ctx.sid, [ctx.recipient_pub],
+                 metadata: :binary.copy("m", 1025)
+               )
+
+      assert {:error, :malformed_wire} = ExTholosPq.wire_metadata("not a wire")
+    end
+  end
+
+  describe "encrypt/4 with :aead" do
+    setup :sender_and_recipient
+
+    test "round-trips with each cipher", ctx do
+      for aead <- [:xchacha20poly1305, :aes256gcm] do
+        {:ok, wire} = ExTholosPq.encrypt("sealed", ctx.sid, [ctx.recipient_pub], aead: aead)
+
+        assert {:ok, "sealed"} = ExTholosPq.decrypt(wire, ctx.kid, [ctx.sender_pub])
+
+        assert {:ok, {"sealed", _sid}} =
+                 ExTholosPq.decrypt_with_sender(wire, ctx.kid, [ctx.sender_pub])
+      end
+    end
+
+    test "the header selects the cipher on decrypt", ctx do
+      {:ok, default} = ExTholosPq.encrypt("sealed", ctx.sid, [ctx.recipient_pub])
+
+      {:ok, explicit} =
+        ExTholosPq.encrypt("sealed", ctx.sid, [ctx.recipient_pub], aead: :xchacha20poly1305)
+
+      {:ok, aes} = ExTholosPq.encrypt("sealed", ctx.sid, [ctx.recipient_pub], aead: :aes256gcm)
+
+      # The default cipher is left out of the header, so those wires are unchanged
+      refute default =~ "aead"
+      refute explicit =~ "aead"
+      assert aes =~ "aes256gcm"
+      assert byte_size(explicit) == byte_size(default)
+
+      assert {:ok, "sealed"} = ExTholosPq.decrypt(aes, ctx.kid, [ctx.sender_pub], [])
+
+      tampered = :binary.replace(aes, "aes256gcm", "aes256gcx")
+
+      assert {:error, _} = ExTholosPq.decrypt(tampered, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "rejects an unknown cipher", ctx do
+      assert {:error, {:invalid_option, "aead"}} =
+               ExTholosPq.encrypt("x", ctx.sid, [ctx.recipient_pub], aead: :chacha20poly1305)
+    end
+  end
+
+  describe "encrypt/4 with :pad_to" do
+    setup :sender_and_recipient
+
+    test "messages padding to the same block give wires of the same size", ctx do
+      wires =
+        for message <- ["yes", "no", "maybe later", ""] do
+          {:ok, wire} = ExTholosPq.encrypt(message, ctx.sid, [ctx.recipient_pub], pad_to: 32)
+          assert {:ok, ^message} = ExTholosPq.decrypt(wire, ctx.kid, [ctx.sender_pub])
+          wire
+        end
+
+      assert [_] = wires |> Enum.map(&byte_size/1) |> Enum.uniq()
+
+      {:ok, short} = ExTholosPq.encrypt("yes", ctx.sid, [ctx.recipient_pub])
+      {:ok, long} = ExTholosPq.encrypt("maybe later", ctx.sid, [ctx.recipient_pub])
+      assert byte_size(short) != byte_size(long)
+    end
+
+    test "a message that fills its blocks gains a whole one", ctx do
+      {:ok, under} =
+        ExTholosPq.encrypt(:binary.copy("a", 15), ctx.sid, [ctx.recipient_pub], pad_to: 16)
+
+      {:ok, full} =
+        ExTholosPq.encrypt(:binary.copy("a", 16), ctx.sid, [ctx.recipient_pub], pad_to: 16)
+
+      assert byte_size(full) == byte_size(under) + 16
+
+      assert {:ok, message} = ExTholosPq.decrypt(full, ctx.kid, [ctx.sender_pub])
+      assert message == :binary.copy("a", 16)
+    end
+
+    test "padding ending in the pad byte is stripped exactly", ctx do
+      for message <- [<<0x80>>, <<0x80, 0>>, <<0, 0, 0>>, <<1, 0x80, 0, 0>>] do
+        {:ok, wire} = ExTholosPq.encrypt(message, ctx.sid, [ctx.recipient_pub], pad_to: 4)
+        assert {:ok, ^message} = ExTholosPq.decrypt(wire, ctx.kid, [ctx.sender_pub])
+      end
+    end
+
+    test "rejects a bad block size or a message too large to pad", ctx do
+      assert {:error, {:invalid_option, "pad_to"}} =
+               ExTholosPq.encrypt("x", ctx.sid, [ctx.recipient_pub], pad_to: 0)
+
+      assert {:error, :plaintext_too_large_for_padding} =
+               ExTholosPq.encrypt("x", ctx.sid, [ctx.recipient_pub], pad_to: 0x80000000)
+    end
+  end
+
+  describe "encrypt/4 with :per_recipient_aad" do
+    setup do
+      {:ok, {kid_a, pub_a}} = ExTholosPq.gen_recipient_keypair("RoutedA")
+      {:ok, {kid_b, pub_b}} = ExTholosPq.gen_recipient_keypair("RoutedB")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("RoutingSender")
+
+      {:ok, wire} =
+        ExTholosPq.encrypt("multicast", sid, [pub_a, pub_b],
+          per_recipient_aad: ["route-a", "route-b"]
+        )
+
+      %{
+        kid_a: kid_a,
+        kid_b: kid_b,
+        pub_a: pub_a,
+        sid: sid,
+        sender_pub: sender_pub,
+        wire: wire
+      }
+    end
+
+    test "each recipient decrypts with its own aad", ctx do
+      assert {:ok, "multicast"} =
+               ExTholosPq.decrypt(ctx.wire, ctx.kid_a, [ctx.sender_pub], recipient_aad: "route-a")
+
+      assert {:ok, "multicast"} =
+               ExTholosPq.decrypt(ctx.wire, ctx.kid_b, [ctx.sender_pub], recipient_aad: "route-b")
+    end
+
+    test "a recipient cannot decrypt with another recipient's aad", ctx do
+      assert {:error, :aad_mismatch} =
+               ExTholosPq.decrypt(ctx.wire, ctx.kid_b, [ctx.sender_pub], recipient_aad: "route-a")
+    end
+
+    test "the recipient aad is required exactly when the wire binds one", ctx do
+      assert {:error, :aad_mismatch} = ExTholosPq.decrypt(ctx.wire, ctx.kid_a, [ctx.sender_pub])
+
+      {:ok, plain} = ExTholosPq.encrypt("unbound", ctx.sid, [ctx.pub_a])
+
+      assert {:error, :aad_mismatch} =
+               ExTholosPq.decrypt(plain, ctx.kid_a, [ctx.sender_pub], recipient_aad: "route-a")
+    end
+
+    test "is bound apart from the message aad", ctx do
+      {:ok, wire} =
+        ExTholosPq.encrypt("both", ctx.sid, [ctx.pub_a], aad: "ab", per_recipient_aad: [""])
+
+      assert {:ok, "both"} =
+               ExTholosPq.decrypt(wire, ctx.kid_a, [ctx.sender_pub], aad: "ab", recipient_aad: "")
+
+      assert {:error, :aad_mismatch} =
+               ExTholosPq.decrypt(wire, ctx.kid_a, [ctx.sender_pub], aad: "a", recipient_aad: "b")
+    end
+
+    test "needs one entry per recipient", ctx do
+      assert {:error, {:invalid_option, "per_recipient_aad"}} =
+               ExTholosPq.encrypt("x", ctx.sid, [ctx.pub_a], per_recipient_aad: ["a", "b"])
+    end
+  end
+
+  describe "wire_version/1" do
+    setup do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("VersionRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("VersionSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("versioned", sid, [recipient_pub])
+      %{kid: kid, sender_pub: sender_pub, ciphertext: ciphertext}
+    end
+
+    test "reports the current version", ctx do
+      assert {:ok, 1} = ExTholosPq.wire_version(ctx.ciphertext)
+    end
+
+    test "still decrypts wires without a version byte", ctx do
+      <<1, bundle::binary>> = ctx.ciphertext
+
+      assert {:ok, 0} = ExTholosPq.wire_version(bundle)
+      assert {:ok, "versioned"} = ExTholosPq.decrypt(bundle, ctx.kid, [ctx.sender_pub])
+      assert {:ok, ["VersionRecipient"]} = ExTholosPq.wire_recipients(bundle)
+    end
+
+    test "rejects unknown versions cleanly", ctx do
+      <<1, bundle::binary>> = ctx.ciphertext
+      future = <<0xFF, bundle::binary>>
+
+      assert {:ok, 255} = ExTholosPq.wire_version(future)
+
+      assert {:error, {:unsupported_wire_version, 255}} =
+               ExTholosPq.decrypt(future, ctx.kid, [ctx.sender_pub])
+
+      assert {:error, {:unsupported_wire_version, 255}} = ExTholosPq.wire_recipients(future)
+    end
+
+    test "rejects garbage input" do
+      assert {:error, :malformed_wire} = ExTholosPq.wire_version("not a wire")
+      assert {:error, :malformed_wire} = ExTholosPq.wire_version("")
+    end
+  end
+
+  describe "sign/2 and verify/3" do
+    test "verifies a signature from the same sender" do
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("HandshakeSigner")
+
+      {:ok, signature} = ExTholosPq.sign(sid, "handshake")
+
+      assert {:ok, :valid} = ExTholosPq.verify(sender_pub, "handshake", signature)
+    end
+
+    test "signs with a sender key resource" do
+      {:ok, {sender_ref, sender_pub}} = ExTholosPq.gen_sender_key_resource("ResSigner")
+
+      {:ok, signature} = ExTholosPq.sign(sender_ref, "handshake")
+
+      assert {:ok, :valid} = ExTholosPq.verify(sender_pub, "handshake", signature)
+    end
+
+    test "rejects a different message or sender" do
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("HandshakeSigner")
+      {:ok, {_other_sid, other_pub}} = ExTholosPq.gen_sender_keypair("OtherSigner")
+
+      {:ok, signature} = ExTholosPq.sign(sid, "handshake")
+
+      assert {:ok, :invalid} = ExTholosPq.verify(sender_pub, "handshake!", signature)
+      assert {:ok, :invalid} = ExTholosPq.verify(other_pub, "handshake", signature)
+    end
+
+    test "verifies after the signer's key is gone from the store" do
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("DepartedSigner")
+      {:ok, signature} = ExTholosPq.sign(sid, "handshake")
+      :ok = ExTholosPq.delete_sender_key(sid)
+
+      assert {:ok, :valid} = ExTholosPq.verify(sender_pub, "handshake", signature)
+    end
+
+    test "reports a truncated signature as malformed" do
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("HandshakeSigner")
+      {:ok, signature} = ExTholosPq.sign(sid, "handshake")
+
+      truncated = binary_part(signature, 0, byte_size(signature) - 10)
+
+      assert {:error, :malformed_signature} =
+               ExTholosPq.verify(sender_pub, "handshake", truncated)
+    end
+
+    test "reports unknown senders and undecodable input" do
+      {:ok, {_sid, sender_pub}} = ExTholosPq.gen_sender_keypair("HandshakeSigner")
+
+      assert {:error, {:sender_not_found, "NoSuchSigner"}} =
+               ExTholosPq.sign("NoSuchSigner", "handshake")
+
+      assert {:error, :malformed_signature} =
+               ExTholosPq.verify(sender_pub, "handshake", "not cbor")
+
+      assert {:error, {:deserialize_sender, _detail}} =
+               ExTholosPq.verify("not cbor", "handshake", "not cbor")
+    end
+  end
+
+  describe "streaming sign and verify" do
+    setup do
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("StreamSigner")
+      %{sid: sid, sender_pub: sender_pub}
+    end
+
+    @tag timeout: 300_000
+    test "a 500 MB message verified in chunks matches a one-shot verify", ctx do
+      chunk_size = 1024 * 1024
+      message = :binary.copy(:crypto.strong_rand_bytes(chunk_size), 500)
+
+      {:ok, signer} = ExTholosPq.sign_init(ctx.sid)
+      for i <- 0..499 do
+        :ok = ExTholosPq.sign_update(signer, chunk_at(message, i, chunk_size))
+      end
+
+      {:ok, signature} = ExTholosPq.sign_final(signer)
+
+      {:ok, verifier} = ExTholosPq.verify_init(ctx.sender_pub)
+
+      for i <- 0..499 do
+        :ok = ExTholosPq.verify_update(verifier, chunk_at(message, i, chunk_size))
+      end
+
+      assert {:ok, :valid} = streamed = ExTholosPq.verify_final(verifier, signature)
+      assert ^streamed = ExTholosPq.verify(ctx.sender_pub, message, signature)
+    end
+
+    test "rejects a different message", ctx do
+      {:ok, signer} = ExTholosPq.sign_init(ctx.sid)
+      :ok = ExTholosPq.sign_update(signer, "handshake")
+      {:ok, signature} = ExTholosPq.sign_final(signer)
+
+      {:ok, verifier} = ExTholosPq.verify_init(ctx.sender_pub)
+      :ok = ExTholosPq.verify_update(verifier, "handshake!")
+
+      assert {:ok, :invalid} = ExTholosPq.verify_final(verifier, signature)
+      assert {:ok, :invalid} = ExTholosPq.verify(ctx.sender_pub, "handshake!", signature)
+    end
+
+    test "keeps one-shot and prehashed signatures apart", ctx do
+      {:ok, one_shot} = ExTholosPq.sign(ctx.sid, "handshake")
+      {:ok, verifier} = ExTholosPq.verify_init(ctx.sender_pub)
+      :ok = ExTholosPq.verify_update(verifier, "handshake")
+
+      assert {:error, :not_prehashed} = ExTholosPq.verify_final(verifier, one_shot)
+    end
+
+    test "closes the stream once finished", ctx do
+      {:ok, signer} = ExTholosPq.sign_init(ctx.sid)
+      {:ok, signature} = ExTholosPq.sign_final(signer)
+      {:ok, verifier} = ExTholosPq.verify_init(ctx.sender_pub)
+      {:ok, :valid} = ExTholosPq.verify_final(verifier, signature)
+
+      assert {:error, :stream_closed} = ExTholosPq.sign_update(signer, "more")
+      assert {:error, :stream_closed} = ExTholosPq.sign_final(signer)
+      assert {:error, :stream_closed} = ExTholosPq.verify_update(verifier, "more")
+      assert {:error, :stream_closed} = ExTholosPq.verify_final(verifier, signature)
+    end
+
+    test "reports unknown senders and undecodable keys" do
+      assert {:error, {:sender_not_found, "NoSuchSigner"}} = ExTholosPq.sign_init("NoSuchSigner")
+      assert {:error, {:deserialize_sender, _detail}} = ExTholosPq.verify_init("not cbor")
+    end
+  end
+
+  describe "streaming encryption" do
+    setup :sender_and_recipient
+
+    test "round-trips a large message split differently on each side", ctx do
+      message = :crypto.strong_rand_bytes(3 * 1024 * 1024 + 17)
+
+      ciphertext = stream_encrypt(message, 100_000, ctx.sid, [ctx.recipient_pub])
+
+      assert ^message = stream_decrypt(ciphertext, 33_333, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "round-trips empty and frame-sized messages", ctx do
+      for size <- [0, 1, 65_535, 65_536, 65_537, 131_072] do
+        message = :crypto.strong_rand_bytes(size)
+        ciphertext = stream_encrypt(message, 65_536, ctx.sid, [ctx.recipient_pub])
+        assert ^message = stream_decrypt(ciphertext, 4096, ctx.kid, [ctx.sender_pub])
+      end
+    end
+
+    test "works for every recipient", ctx do
+      {:ok, {other_kid, other_pub}} = ExTholosPq.gen_recipient_keypair("OtherStreamRecipient")
+      message = :crypto.strong_rand_bytes(200_000)
+
+      ciphertext = stream_encrypt(message, 50_000, ctx.sid, [ctx.recipient_pub, other_pub])
+
+      assert ^message = stream_decrypt(ciphertext, 70_000, ctx.kid, [ctx.sender_pub])
+      assert ^message = stream_decrypt(ciphertext, 70_000, other_kid, [ctx.sender_pub])
+    end
+
+    test "detects truncation and tampering", ctx do
+      message = :crypto.strong_rand_bytes(300_000)
+      ciphertext = stream_encrypt(message, 300_000, ctx.sid, [ctx.recipient_pub])
+      size = byte_size(ciphertext)
+
+      {:ok, dec} = ExTholosPq.decrypt_init(ctx.kid, [ctx.sender_pub])
+      {:ok, _partial} = ExTholosPq.decrypt_update(dec, binary_part(ciphertext, 0, size - 100))
+      assert {:error, :auth_tag_mismatch} = ExTholosPq.decrypt_final(dec)
+
+      {:ok, dec} = ExTholosPq.decrypt_init(ctx.kid, [ctx.sender_pub])
+      {:ok, ""} = ExTholosPq.decrypt_update(dec, binary_part(ciphertext, 0, 3))
+      assert {:error, {:malformed_wire, _detail}} = ExTholosPq.decrypt_final(dec)
+
+      <<head::binary-size(size - 1), last>> = ciphertext
+      {:ok, dec} = ExTholosPq.decrypt_init(ctx.kid, [ctx.sender_pub])
+      {:ok, _partial} = ExTholosPq.decrypt_update(dec, <<head::binary, Bitwise.bxor(last, 1)>>)
+      assert {:error, :signature_invalid} = ExTholosPq.decrypt_final(dec)
+
+      <<head::binary-size(div(size, 2)), byte, rest::binary>> = ciphertext
+      {:ok, dec} = ExTholosPq.decrypt_init(ctx.kid, [ctx.sender_pub])
+
+      assert {:error, :auth_tag_mismatch} =
+               ExTholosPq.decrypt_update(
+                 dec,
+                 <<head::binary, Bitwise.bxor(byte, 1), rest::binary>>
+               )
+
+      assert {:error, :stream_closed} = ExTholosPq.decrypt_final(dec)
+    end
+
+    test "rejects senders outside the allow-list", ctx do
+      {:ok, {_sid, other_pub}} = ExTholosPq.gen_sender_keypair("OtherStreamSender")
+      ciphertext = stream_encrypt("hello", 5, ctx.sid, [ctx.recipient_pub])
+
+      {:ok, dec} = ExTholosPq.decrypt_init(ctx.kid, [other_pub])
+      assert {:error, :signature_invalid} = ExTholosPq.decrypt_update(dec, ciphertext)
+    end
+
+    test "a finished stream is closed", ctx do
+      {:ok, enc} = ExTholosPq.encrypt_init(ctx.sid, [ctx.recipient_pub])
+      {:ok, _tail} = ExTholosPq.encrypt_final(enc)
+
+      assert {:error, :stream_closed} = ExTholosPq.encrypt_update(enc, "more")
+      assert {:error, :stream_closed} = ExTholosPq.encrypt_final(enc)
+    end
+
+    test "stream ciphertext is not a regular ciphertext", ctx do
+      ciphertext = stream_encrypt("hello", 5, ctx.sid, [ctx.recipient_pub])
+
+      assert {:error, {:malformed_wire, _detail}} =
+               ExTholosPq.decrypt(ciphertext, ctx.kid, [ctx.sender_pub])
+    end
+  end
+
+  describe "random access to streams" do
+    setup do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("ChunkRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("ChunkSender")
+      message = :crypto.strong_rand_bytes(8 * 65_536 + 100)
+      ciphertext = stream_encrypt(message, 100_000, sid, [recipient_pub])
+
+      {:ok, preamble_size} = ExTholosPq.stream_preamble_size(binary_part(ciphertext, 0, 9))
+      {:ok, dec} = ExTholosPq.decrypt_init(kid, [sender_pub])
+      {:ok, ""} = ExTholosPq.decrypt_update(dec, binary_part(ciphertext, 0, preamble_size))
+
+      %{message: message, ciphertext: ciphertext, preamble_size: preamble_size, dec: dec}
+    end
+
+    test "reads chunk 5 without chunks 0-4", ctx do
+      size = byte_size(ctx.ciphertext)
+      assert {:ok, {offset, length}} = ExTholosPq.stream_chunk_range(ctx.dec, 5, size)
+
+      # Chunks 0-4 are zeroed, so they could not even be decrypted
+      preamble_size = ctx.preamble_size
+      zeroed_size = offset - preamble_size
+      <<preamble::binary-size(preamble_size), _::binary-size(zeroed_size), rest::binary>> =
+        ctx.ciphertext
+
+      ciphertext = <<preamble::binary, 0::size(zeroed_size)-unit(8), rest::binary>>
+
+      assert {:ok, chunk} =
+               ExTholosPq.decrypt_chunk(ctx.dec, 5, binary_part(ciphertext, offset, length))
+
+      assert chunk == binary_part(ctx.message, 5 * 65_536, 65_536)
+    end
+
+    test "reads the short last chunk and nothing past it", ctx do
+      size = byte_size(ctx.ciphertext)
+      assert {:ok, {offset, length}} = ExTholosPq.stream_chunk_range(ctx.dec, 8, size)
+
+      assert {:ok, chunk} =
+               ExTholosPq.decrypt_chunk(ctx.dec, 8, binary_part(ctx.ciphertext, offset, length))
+
+      assert chunk == binary_part(ctx.message, 8 * 65_536, 100)
+      assert {:error, :chunk_out_of_range} = ExTholosPq.stream_chunk_range(ctx.dec, 9, size)
+    end
+
+    test "rejects a chunk read at another index", ctx do
+      size = byte_size(ctx.ciphertext)
+      {:ok, {offset, length}} = ExTholosPq.stream_chunk_range(ctx.dec, 5, size)
+      frame = binary_part(ctx.ciphertext, offset, length)
+
+      assert {:error, :auth_tag_mismatch} = ExTholosPq.decrypt_chunk(ctx.dec, 4, frame)
+      assert {:ok, _chunk} = ExTholosPq.decrypt_chunk(ctx.dec, 5, frame)
+    end
+
+    test "needs the preamble first", ctx do
+      {:ok, dec} = ExTholosPq.decrypt_init("ChunkRecipient", [])
+
+      assert {:error, :preamble_pending} =
+               ExTholosPq.stream_chunk_range(dec, 0, byte_size(ctx.ciphertext))
+
+      assert {:error, :preamble_pending} = ExTholosPq.decrypt_chunk(dec, 0, "frame")
+    end
+  end
+
+  describe "encrypt_file/4 and decrypt_file/4" do
+    setup :sender_and_recipient
+
+    setup do
+      dir = Path.join(System.tmp_dir!(), "ex_tholos_pq_#{System.unique_integer([:positive])}")
+      File.mkdir_p!(dir)
+      on_exit(fn -> File.rm_rf!(dir) end)
+      %{dir: dir}
+    end
+
+    @tag timeout: 300_000
+    test "round-trips a file of a few hundred MB", ctx do
+      plain = Path.join(ctx.dir, "plain")
+      sealed = Path.join(ctx.dir, "sealed")
+      opened = Path.join(ctx.dir, "opened")
+
+      File.open!(plain, [:write, :binary], fn file ->
+        for _ <- 1..300, do: IO.binwrite(file, :crypto.strong_rand_bytes(1024 * 1024))
+      end)
+
+      assert :ok = ExTholosPq.encrypt_file(plain, sealed, ctx.sid, [ctx.recipient_pub])
+      assert :ok = ExTholosPq.decrypt_file(sealed, opened, ctx.kid, [ctx.sender_pub])
+
+      assert File.stat!(opened).size == 300 * 1024 * 1024
+      assert file_hash(opened) == file_hash(plain)
+    end
+
+    test "is compatible with the streaming functions", ctx do
+      message = :crypto.strong_rand_bytes(200_000)
+      plain = Path.join(ctx.dir, "plain")
+      sealed = Path.join(ctx.dir, "sealed")
+      File.write!(plain, message)
+
+      :ok = ExTholosPq.encrypt_file(plain, sealed, ctx.sid, [ctx.recipient_pub])
+
+      assert ^message = stream_decrypt(File.read!(sealed), 50_000, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "returns io errors for unreadable and unwritable paths", ctx do
+      missing = Path.join(ctx.dir, "missing")
+      plain = Path.join(ctx.dir, "plain")
+      File.write!(plain, "hello")
+
+      assert {:error, {:io, detail}} =
+               ExTholosPq.encrypt_file(missing, plain <> ".out", ctx.sid, [ctx.recipient_pub])
+
+      assert detail =~ missing
+
+      assert {:error, {:io, _detail}} =
+               ExTholosPq.encrypt_file(plain, Path.join(missing, "out"), ctx.sid, [
+                 ctx.recipient_pub
+               ])
+
+      assert {:error, {:io, _detail}} =
+               ExTholosPq.decrypt_file(missing, plain <> ".out", ctx.kid, [ctx.sender_pub])
+    end
+
+    test "leaves no output behind when decryption fails", ctx do
+      {:ok, {_sid, other_pub}} = ExTholosPq.gen_sender_keypair("OtherFileSender")
+      plain = Path.join(ctx.dir, "plain")
+      sealed = Path.join(ctx.dir, "sealed")
+      opened = Path.join(ctx.dir, "opened")
+      File.write!(plain, :crypto.strong_rand_bytes(200_000))
+
+      :ok = ExTholosPq.encrypt_file(plain, sealed, ctx.sid, [ctx.recipient_pub])
+
+      assert {:error, :signature_invalid} =
+               ExTholosPq.decrypt_file(sealed, opened, ctx.kid, [other_pub])
+
+      File.write!(sealed, binary_part(File.read!(sealed), 0, 150_000))
+
+      assert {:error, :auth_tag_mismatch} =
+               ExTholosPq.decrypt_file(sealed, opened, ctx.kid, [ctx.sender_pub])
+
+      refute File.exists?(opened)
+      refute File.exists?(opened <> ".partial")
+    end
+  end
+
+  describe "encrypt_deterministic/4" do
+    test "gives byte-identical wires for the same seed" do
+      {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("SeededEncryptRcpt")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("SeededEncryptSender")
+      seed = :binary.copy(<<7>>, 32)
+
+      {:ok, wire} = ExTholosPq.encrypt_deterministic(seed, sid, "fixed", [pub])
+      assert {:ok, ^wire} = ExTholosPq.encrypt_deterministic(seed, sid, "fixed", [pub])
+
+      other_seed = :binary.copy(<<8>>, 32)
+      {:ok, other} = ExTholosPq.encrypt_deterministic(other_seed, sid, "fixed", [pub])
+      assert other != wire
+      assert {:ok, "fixed"} = ExTholosPq.decrypt(wire, kid, [sender_pub])
+
+      assert {:error, :bad_seed_length} =
+               ExTholosPq.encrypt_deterministic(<<7>>, sid, "fixed", [pub])
+    end
+  end
+
+  describe "export_test_vectors/0" do
+    @fixture Path.expand("fixtures/test_vectors.json", __DIR__)
+
+    test "matches the committed fixture" do
+      assert {:ok, json} = ExTholosPq.export_test_vectors()
+      # Regenerate the fixture as the README describes only if the format changed on purpose
+      assert json == File.read!(@fixture)
+    end
+
+    test "the first wire decrypts for vector-alice, whose seed is 32 bytes of 1" do
+      json = File.read!(@fixture)
+      # The sender comes first, then the one-recipient vector
+      [sender_pub, wire] =
+        for field <- ["public_key_cbor", "wire"] do
+          [hex] = Regex.run(~r/"#{field}": "([0-9a-f]+)"/, json, capture: :all_but_first)
+          Base.decode16!(hex, case: :lower)
+        end
+
+      {:ok, _} =
+        ExTholosPq.gen_recipient_keypair_from_seed("vector-alice", :binary.copy(<<1>>, 32))
+
+      assert {:ok, "ex_tholos_pq test vector"} =
+               ExTholosPq.decrypt(wire, "vector-alice", [sender_pub])
+    end
+  end
+
+  describe "panics in the crypto primitives" do
+    test "are returned as an error term" do
+      assert {:error, :internal_crypto_panic} = ExTholosPq.crypto_panic()
+      # The NIF keeps working afterwards
+      {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("AfterPanicRcpt")
+      assert {:ok, :valid} = ExTholosPq.validate_recipient_pub(pub)
+    end
+
+    test "close the stream they happen in" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("PanicStreamRcpt")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("PanicStreamSender")
+      {:ok, enc} = ExTholosPq.encrypt_init(sid, [recipient_pub])
+
+      assert {:error, :internal_crypto_panic} = ExTholosPq.stream_panic(enc)
+      assert {:error, :stream_closed} = ExTholosPq.encrypt_update(enc, "more")
+      assert {:error, :stream_closed} = ExTholosPq.encrypt_final(enc)
+
+      # Other streams keep working
+      {:ok, enc} = ExTholosPq.encrypt_init(sid, [recipient_pub])
+      {:ok, head} = ExTholosPq.encrypt_update(enc, "after the panic")
+      {:ok, tail} = ExTholosPq.encrypt_final(enc)
+      {:ok, dec} = ExTholosPq.decrypt_init(kid, [sender_pub])
+      {:ok, plain} = ExTholosPq.decrypt_update(dec, head <> tail)
+      {:ok, rest} = ExTholosPq.decrypt_final(dec)
+      assert plain <> rest == "after the panic"
+    end
+  end
+
+  describe "poisoned locks" do
+    test "leave the key stores usable" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("BeforePoisonRcpt")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("BeforePoisonSender")
+
+      :ok = ExTholosPq.poison_key_stores()
+
+      {:ok, wire} = ExTholosPq.encrypt("still here", sid, [recipient_pub])
+      assert {:ok, "still here"} = ExTholosPq.decrypt(wire, kid, [sender_pub])
+      assert {:ok, {_kid, _pub}} = ExTholosPq.gen_recipient_keypair("AfterPoisonRcpt")
+      assert :ok = ExTholosPq.delete_recipient_key("AfterPoisonRcpt")
+    end
+
+    test "close a stream" do
+      {:ok, {_kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("PoisonStreamRcpt")
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("PoisonStreamSender")
+      {:ok, enc} = ExTholosPq.encrypt_init(sid, [recipient_pub])
+
+      :ok = ExTholosPq.poison_stream(enc)
+
+      assert {:error, :stream_closed} = ExTholosPq.encrypt_update(enc, "more")
+      assert {:error, :stream_closed} = ExTholosPq.encrypt_final(enc)
+    end
+  end
+
+  describe "allocation failures" do
+    test "report the size that could not be allocated" do
+      assert {:ok, 16} = ExTholosPq.alloc_binary_of(16)
+
+      size = Bitwise.bsl(1, 62)
+      assert {:error, {:allocation_failed, ^size}} = ExTholosPq.alloc_binary_of(size)
+    end
+  end
+
+  describe "structured errors" do
+    setup do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("ErrorRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("ErrorSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("secret", sid, [recipient_pub])
+
+      %{
+        kid: kid,
+        recipient_pub: recipient_pub,
+        sid: sid,
+        sender_pub: sender_pub,
+        ciphertext: ciphertext
+      }
+    end
+
+    test "unknown sender", %{recipient_pub: recipient_pub} do
+      assert {:error, {:sender_not_found, "NoSuchSender"}} =
+               ExTholosPq.encrypt("secret", "NoSuchSender", [recipient_pub])
+
+      assert {:error, {:sender_not_found, "NoSuchSender"}} =
+               ExTholosPq.export_sender_keypair("NoSuchSender")
+    end
+
+    test "unknown recipient", %{ciphertext: ciphertext, sender_pub: sender_pub} do
+      assert {:error, {:recipient_not_found, "NoSuchRecipient"}} =
+               ExTholosPq.decrypt(ciphertext, "NoSuchRecipient", [sender_pub])
+
+      assert {:error, {:recipient_not_found, "NoSuchRecipient"}} =
+               ExTholosPq.export_recipient_key("NoSuchRecipient")
+    end
+
+    test "undecodable public keys", ctx do
+      assert {:error, {:deserialize_recipient, detail}} =
+               ExTholosPq.encrypt("secret", ctx.sid, ["not cbor"])
+
+      assert is_binary(detail)
+
+      assert {:error, {:deserialize_sender, _detail}} =
+               ExTholosPq.decrypt(ctx.ciphertext, ctx.kid, ["not cbor"])
+
+      assert {:error, {:deserialize_sender, _detail}} =
+               ExTholosPq.encrypt_with_keys("secret", "not cbor", [ctx.recipient_pub])
+    end
+
+    test "sender outside the allow-list", ctx do
+      {:ok, {_sid, other_pub}} = ExTholosPq.gen_sender_keypair("OtherErrorSender")
+
+      assert {:error, :signature_invalid} =
+               ExTholosPq.decrypt(ctx.ciphertext, ctx.kid, [other_pub])
+
+      assert {:error, :signature_invalid} =
+               ExTholosPq.decrypt(ctx.ciphertext, ctx.kid, [])
+    end
+
+    test "recipient without an envelope", ctx do
+      {:ok, {other_kid, _pub}} = ExTholosPq.gen_recipient_keypair("OtherErrorRecipient")
+
+      assert {:error, :no_envelope_for_recipient} =
+               ExTholosPq.decrypt(ctx.ciphertext, other_kid, [ctx.sender_pub])
+    end
+
+    test "recipient key of the wrong size", ctx do
+      # CBOR map %{"kid" => "K", "pk_kyber" => <<1, 2, 3, 4>>}
+      short_pub = <<0xA2, 0x63, "kid", 0x61, "K", 0x68, "pk_kyber", 0x44, 1, 2, 3, 4>>
+
+      assert {:error, :invalid_recipient_key} = ExTholosPq.encrypt("secret", ctx.sid, [short_pub])
+    end
+
+    test "ciphertext that fails authentication", ctx do
+      # Replacing the stored key under the same kid leaves an envelope that
+      # decapsulates to the wrong key
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("RotatedErrorRecipient")
+      {:ok, ciphertext} = ExTholosPq.encrypt("secret", ctx.sid, [recipient_pub])
+      {:ok, {^kid, _new_pub}} = ExTholosPq.gen_recipient_keypair(kid, overwrite: true)
+
+      assert {:error, :auth_tag_mismatch} =
+               ExTholosPq.decrypt(ciphertext, kid, [ctx.sender_pub])
+    end
+
+    test "unparseable ciphertext", ctx do
+      assert {:error, {:malformed_wire, _detail}} =
+               ExTholosPq.decrypt("not a wire", ctx.kid, [ctx.sender_pub])
+    end
+
+    test "forgery, tampering and misdelivery each have their own error", ctx do
+      # Most of the wire is the payload, so its middle byte is a payload byte
+      {:ok, ciphertext} =
+        ExTholosPq.encrypt(:binary.copy("a", 65_536), ctx.sid, [ctx.recipient_pub])
+
+      middle = div(byte_size(ciphertext), 2)
+      <<head::binary-size(middle), byte, tail::binary>> = ciphertext
+      tampered = <<head::binary, Bitwise.bxor(byte, 1), tail::binary>>
+
+      assert {:error, :signature_invalid} =
+               ExTholosPq.decrypt(tampered, ctx.kid, [ctx.sender_pub])
+
+      {:ok, {_sid, forger_pub}} = ExTholosPq.gen_sender_keypair("Forger")
+
+      assert {:error, :signature_invalid} =
+               ExTholosPq.decrypt(ciphertext, ctx.kid, [forger_pub])
+
+      # The same wire unaltered is only ever misdelivered or undecryptable
+      {:ok, {other_kid, _pub}} = ExTholosPq.gen_recipient_keypair("TamperBystander")
+
+      assert {:error, :no_envelope_for_recipient} =
+               ExTholosPq.decrypt(ciphertext, other_kid, [ctx.sender_pub])
+
+      {:ok, {_kid, _new_pub}} = ExTholosPq.gen_recipient_keypair(ctx.kid, overwrite: true)
+
+      assert {:error, :auth_tag_mismatch} =
+               ExTholosPq.decrypt(ciphertext, ctx.kid, [ctx.sender_pub])
+    end
+  end
+
+  describe "encrypt_batch/3" do
+    test "every element decrypts independently for every recipient" do
+      {:ok, {kid_a, pub_a}} = ExTholosPq.gen_recipient_keypair("BatchA")
+      {:ok, {kid_b, pub_b}} = ExTholosPq.gen_recipient_keypair("BatchB")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("BatchSender")
+
+      messages = for i <- 1..50, do: "message #{i} " <> :crypto.strong_rand_bytes(i)
+      assert {:ok, ciphertexts} = ExTholosPq.encrypt_batch(sid, messages, [pub_a, pub_b])
+      assert length(ciphertexts) == length(messages)
+      assert length(Enum.uniq(ciphertexts)) == length(messages)
+
+      for {ciphertext, message} <- Enum.shuffle(Enum.zip(ciphertexts, messages)) do
+        assert {:ok, ^message} = ExTholosPq.decrypt(ciphertext, kid_a, [sender_pub])
+        assert {:ok, ^message} = ExTholosPq.decrypt(ciphertext, kid_b, [sender_pub])
+      end
+    end
+
+    test "an empty batch is empty" do
+      {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("BatchA")
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("BatchSender")
+
+      assert {:ok, []} = ExTholosPq.encrypt_batch(sid, [], [pub])
+    end
+
+    test "tampering with one element does not affect the others" do
+      {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("BatchA")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("BatchSender")
+      {:ok, [first, second]} = ExTholosPq.encrypt_batch(sid, ["first", "second"], [pub])
+
+      size = byte_size(first)
+      <<head::binary-size(size - 1), last>> = first
+      tampered = <<head::binary, Bitwise.bxor(last, 1)>>
+
+      assert {:error, _reason} = ExTholosPq.decrypt(tampered, kid, [sender_pub])
+      assert {:ok, "second"} = ExTholosPq.decrypt(second, kid, [sender_pub])
+    end
+  end
+
+  describe "encrypt_cached_init/2 and encrypt_cached_for/2" do
+    test "wires from one ctx decrypt to the same plaintext" do
+      {:ok, {kid_a, pub_a}} = ExTholosPq.gen_recipient_keypair("CachedA")
+      {:ok, {kid_b, pub_b}} = ExTholosPq.gen_recipient_keypair("CachedB")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("CachedSender")
+      message = :crypto.strong_rand_bytes(4096)
+
+      assert {:ok, ctx} = ExTholosPq.encrypt_cached_init(sid, message)
+      assert {:ok, first} = ExTholosPq.encrypt_cached_for(ctx, [pub_a])
+      assert {:ok, second} = ExTholosPq.encrypt_cached_for(ctx, [pub_a, pub_b])
+      assert first != second
+
+      assert {:ok, ^message} = ExTholosPq.decrypt(first, kid_a, [sender_pub])
+      assert {:ok, ^message} = ExTholosPq.decrypt(second, kid_a, [sender_pub])
+      assert {:ok, ^message} = ExTholosPq.decrypt(second, kid_b, [sender_pub])
+      assert {:error, :no_envelope_for_recipient} = ExTholosPq.decrypt(first, kid_b, [sender_pub])
+      assert {:ok, [^kid_a, ^kid_b]} = ExTholosPq.wire_recipients(second)
+    end
+
+    test "the ctx outlives the sender keypair in the store" do
+      {:ok, {kid, pub}} = ExTholosPq.gen_recipient_keypair("CachedA")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("CachedSender")
+      {:ok, ctx} = ExTholosPq.encrypt_cached_init(sid, "kept")
+      :ok = ExTholosPq.delete_sender_key(sid)
+
+      assert {:ok, ciphertext} = ExTholosPq.encrypt_cached_for(ctx, [pub])
+      assert {:ok, "kept"} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+    end
+
+    test "rejects an unknown sender and an empty recipient list" do
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("CachedSender")
+      {:ok, ctx} = ExTholosPq.encrypt_cached_init(sid, "x")
+
+      assert {:error, {:sender_not_found, "Nobody"}} =
+               ExTholosPq.encrypt_cached_init("Nobody", "x")
+
+      assert {:error, :no_recipients} = ExTholosPq.encrypt_cached_for(ctx, [])
+    end
+  end
+
+  describe "add_recipient/4" do
+    setup do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("OriginalRecipient")
+      {:ok, {new_kid, new_pub}} = ExTholosPq.gen_recipient_keypair("LateRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("GrantingSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("granted later", sid, [recipient_pub])
+
+      %{
+        kid: kid,
+        recipient_pub: recipient_pub,
+        new_kid: new_kid,
+        new_pub: new_pub,
+        sid: sid,
+        sender_pub: sender_pub,
+        ciphertext: ciphertext
+      }
+    end
+
+    test "a recipient added after the fact can decrypt", ctx do
+      assert {:error, :no_envelope_for_recipient} =
+               ExTholosPq.decrypt(ctx.ciphertext, ctx.new_kid, [ctx.sender_pub])
+
+      assert {:ok, granted} =
+               ExTholosPq.add_recipient(ctx.ciphertext, ctx.sid, ctx.new_pub, ctx.kid)
+
+      assert {:ok, "granted later"} = ExTholosPq.decrypt(granted, ctx.new_kid, [ctx.sender_pub])
+      assert {:ok, "granted later"} = ExTholosPq.decrypt(granted, ctx.kid, [ctx.sender_pub])
+      assert {:ok, ["OriginalRecipient", "LateRecipient"]} = ExTholosPq.wire_recipients(granted)
+    end
+
+    test "only the signing sender can add recipients", ctx do
+      {:ok, {other_sid, _other_pub}} = ExTholosPq.gen_sender_keypair("OtherGrantingSender")
+
+      assert {:error, :signature_invalid} =
+               ExTholosPq.add_recipient(ctx.ciphertext, other_sid, ctx.new_pub, ctx.kid)
+
+      assert {:error, {:sender_not_found, "NoSuchSender"}} =
+               ExTholosPq.add_recipient(ctx.ciphertext, "NoSuchSender", ctx.new_pub, ctx.kid)
+    end
+
+    test "needs an existing recipient and a new one", ctx do
+      assert {:error, :no_envelope_for_recipient} =
+               ExTholosPq.add_recipient(ctx.ciphertext, ctx.sid, ctx.new_pub, ctx.new_kid)
+
+      {:ok, granted} = ExTholosPq.add_recipient(ctx.ciphertext, ctx.sid, ctx.new_pub, ctx.kid)
+
+      assert {:error, :recipient_already_present} =
+               ExTholosPq.add_recipient(granted, ctx.sid, ctx.new_pub, ctx.kid)
+    end
+
+    test "needs the associated data a ciphertext was encrypted with", ctx do
+      {:ok, bound} = ExTholosPq.encrypt("bound", ctx.sid, [ctx.recipient_pub], aad: "ctx")
+
+      assert {:error, :aad_mismatch} =
+               ExTholosPq.add_recipient(bound, ctx.sid, ctx.new_pub, ctx.kid)
+
+      assert {:error, :aad_mismatch} =
+               ExTholosPq.add_recipient(bound, ctx.sid, ctx.new_pub, ctx.kid, aad: "other")
+
+      assert {:ok, granted} =
+               ExTholosPq.add_recipient(bound, ctx.sid, ctx.new_pub, ctx.kid, aad: "ctx")
+
+      assert {:ok, "bound"} = ExTholosPq.decrypt(granted, ctx.new_kid, [ctx.sender_pub], "ctx")
+      assert {:error, :aad_mismatch} =
+               ExTholosPq.decrypt(granted, ctx.new_kid, [ctx.sender_pub], "")
+
+      assert {:error, {:invalid_option, "padding"}} =
+               ExTholosPq.add_recipient(bound, ctx.sid, ctx.new_pub, ctx.kid, padding: 16)
+    end
+  end
+
+  describe "remove_recipient/3" do
+    setup do
+      {:ok, {kept_kid, kept_pub}} = ExTholosPq.gen_recipient_keypair("KeptRecipient")
+      {:ok, {removed_kid, removed_pub}} = ExTholosPq.gen_recipient_keypair("RemovedRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("RevokingSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("revoked", sid, [kept_pub, removed_pub])
+
+      %{
+        kept_kid: kept_kid,
+        removed_kid: removed_kid,
+        sid: sid,
+        sender_pub: sender_pub,
+        ciphertext: ciphertext
+      }
+    end
+
+    test "the removed recipient can no longer decrypt the new wire", ctx do
+      assert {:ok, removed} =
+               ExTholosPq.remove_recipient(ctx.ciphertext, ctx.sid, ctx.removed_kid)
+
+      assert {:error, :no_envelope_for_recipient} =
+               ExTholosPq.decrypt(removed, ctx.removed_kid, [ctx.sender_pub])
+
+      assert {:ok, "revoked"} = ExTholosPq.decrypt(removed, ctx.kept_kid, [ctx.sender_pub])
+      assert {:ok, ["KeptRecipient"]} = ExTholosPq.wire_recipients(removed)
+
+      # The old wire is untouched
+      assert {:ok, "revoked"} =
+               ExTholosPq.decrypt(ctx.ciphertext, ctx.removed_kid, [ctx.sender_pub])
+    end
+
+    test "rejects recipients that are not present and foreign senders", ctx do
+      {:ok, removed} = ExTholosPq.remove_recipient(ctx.ciphertext, ctx.sid, ctx.removed_kid)
+
+      assert {:error, :recipient_not_present} =
+               ExTholosPq.remove_recipient(removed, ctx.sid, ctx.removed_kid)
+
+      {:ok, {other_sid, _other_pub}} = ExTholosPq.gen_sender_keypair("OtherRevokingSender")
+
+      assert {:error, :signature_invalid} =
+               ExTholosPq.remove_recipient(ctx.ciphertext, other_sid, ctx.removed_kid)
+    end
+  end
+
+  describe "rewrap_for_recipient/4" do
+    setup do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("RewrappedRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("RewrappingSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("rewrapped", sid, [recipient_pub])
+
+      %{
+        kid: kid,
+        recipient_pub: recipient_pub,
+        sid: sid,
+        sender_pub: sender_pub,
+        ciphertext: ciphertext
+      }
+    end
+
+    test "the rotated key decrypts the rewrapped wire once the old key is gone", ctx do
+      {:ok, {2, new_pub}} = ExTholosPq.rotate_recipient_key(ctx.kid)
+      new_kid = ctx.kid <> "#v2"
+
+      assert {:ok, rewrapped} =
+               ExTholosPq.rewrap_for_recipient(ctx.ciphertext, ctx.kid, new_pub, ctx.sid)
+
+      assert {:ok, [^new_kid]} = ExTholosPq.wire_recipients(rewrapped)
+      :ok = ExTholosPq.delete_recipient_key(ctx.kid)
+
+      assert {:ok, "rewrapped"} = ExTholosPq.decrypt(rewrapped, new_kid, [ctx.sender_pub])
+      assert {:ok, "rewrapped"} = ExTholosPq.decrypt(rewrapped, ctx.kid, [ctx.sender_pub])
+
+      assert {:error, {:recipient_not_found, _}} =
+               ExTholosPq.decrypt(ctx.ciphertext, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "keeps the other recipients' envelopes", ctx do
+      {:ok, {other_kid, other_pub}} = ExTholosPq.gen_recipient_keypair("OtherRecipient")
+      {:ok, ciphertext} = ExTholosPq.encrypt("shared", ctx.sid, [ctx.recipient_pub, other_pub])
+      {:ok, {new_kid, new_pub}} = ExTholosPq.gen_recipient_keypair("NewRecipientKey")
+
+      {:ok, rewrapped} = ExTholosPq.rewrap_for_recipient(ciphertext, ctx.kid, new_pub, ctx.sid)
+
+      assert {:ok, [^new_kid, ^other_kid]} = ExTholosPq.wire_recipients(rewrapped)
+      assert {:ok, "shared"} = ExTholosPq.decrypt(rewrapped, other_kid, [ctx.sender_pub])
+      assert {:ok, "shared"} = ExTholosPq.decrypt(rewrapped, new_kid, [ctx.sender_pub])
+    end
+
+    test "rejects a kid already present, a missing envelope and a foreign sender", ctx do
+      {:ok, {other_kid, other_pub}} = ExTholosPq.gen_recipient_keypair("OtherRecipient")
+      {:ok, ciphertext} = ExTholosPq.encrypt("shared", ctx.sid, [ctx.recipient_pub, other_pub])
+      {:ok, {_new_kid, new_pub}} = ExTholosPq.gen_recipient_keypair("NewRecipientKey")
+      {:ok, {_kid, stranger_pub}} = ExTholosPq.gen_recipient_keypair("Stranger")
+      {:ok, {other_sid, _other_pub}} = ExTholosPq.gen_sender_keypair("OtherSender")
+
+      assert {:error, :recipient_already_present} =
+               ExTholosPq.rewrap_for_recipient(ciphertext, ctx.kid, other_pub, ctx.sid)
+
+      assert {:error, :no_envelope_for_recipient} =
+               ExTholosPq.rewrap_for_recipient(ctx.ciphertext, other_kid, stranger_pub, ctx.sid)
+
+      assert {:error, :signature_invalid} =
+               ExTholosPq.rewrap_for_recipient(ciphertext, ctx.kid, new_pub, other_sid)
+    end
+
+    test "needs the associated data a ciphertext was encrypted with", ctx do
+      {:ok, bound} = ExTholosPq.encrypt("bound", ctx.sid, [ctx.recipient_pub], aad: "ctx")
+      {:ok, {new_kid, new_pub}} = ExTholosPq.gen_recipient_keypair("NewRecipientKey")
+
+      assert {:error, :aad_mismatch} =
+               ExTholosPq.rewrap_for_recipient(bound, ctx.kid, new_pub, ctx.sid)
+
+      assert {:ok, rewrapped} =
+               ExTholosPq.rewrap_for_recipient(bound, ctx.kid, new_pub, ctx.sid, aad: "ctx")
+
+      assert {:ok, "bound"} = ExTholosPq.decrypt(rewrapped, new_kid, [ctx.sender_pub], "ctx")
+    end
+  end
+
+  describe "validate_wire/2" do
+    setup do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("GatewayRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("GatewayCheckedSender")
+      {:ok, ciphertext} =
+        ExTholosPq.encrypt(:crypto.strong_rand_bytes(4096), sid, [recipient_pub])
+
+      %{kid: kid, sid: sid, sender_pub: sender_pub, ciphertext: ciphertext}
+    end
+
+    test "accepts a good wire without a recipient key", ctx do
+      :ok = ExTholosPq.delete_recipient_key(ctx.kid)
+
+      assert {:ok, {:valid, "GatewayCheckedSender"}} =
+               ExTholosPq.validate_wire(ctx.ciphertext, [ctx.sender_pub])
+    end
+
+    test "rejects a tampered wire and senders outside the allow-list", ctx do
+      size = byte_size(ctx.ciphertext) - 1
+      <<head::binary-size(size), last>> = ctx.ciphertext
+      tampered = <<head::binary, Bitwise.bxor(last, 1)>>
+
+      assert {:ok, :invalid_signature} = ExTholosPq.validate_wire(tampered, [ctx.sender_pub])
+
+      {:ok, {_other_sid, other_pub}} = ExTholosPq.gen_sender_keypair("GatewayOtherSender")
+      assert {:ok, :invalid_signature} = ExTholosPq.validate_wire(ctx.ciphertext, [other_pub])
+    end
+
+    test "reports wires that cannot be parsed", ctx do
+      assert {:error, {:malformed_wire, _}} =
+               ExTholosPq.validate_wire("garbage", [ctx.sender_pub])
+    end
+  end
+
+  describe "decrypt_verify_only/3" do
+    setup do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("DryRunRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("DryRunCheckedSender")
+      plaintext = :crypto.strong_rand_bytes(4096)
+      {:ok, ciphertext} = ExTholosPq.encrypt(plaintext, sid, [recipient_pub])
+
+      %{kid: kid, sender_pub: sender_pub, plaintext: plaintext, ciphertext: ciphertext}
+    end
+
+    test "reports the sender and length but not the plaintext", ctx do
+      assert {:ok, {:valid, "DryRunCheckedSender", 4096} = result} =
+               ExTholosPq.decrypt_verify_only(ctx.ciphertext, ctx.kid, [ctx.sender_pub])
+
+      refute ctx.plaintext in Tuple.to_list(result)
+    end
+
+    test "rejects a tampered payload", ctx do
+      size = byte_size(ctx.ciphertext) - 1
+      <<head::binary-size(size), last>> = ctx.ciphertext
+      tampered = <<head::binary, Bitwise.bxor(last, 1)>>
+
+      assert {:error, :signature_invalid} =
+               ExTholosPq.decrypt_verify_only(tampered, ctx.kid, [ctx.sender_pub])
+    end
+
+    test "fails where validate_wire/2 passes but the wire is for another recipient", ctx do
+      {:ok, {other_kid, _other_pub}} = ExTholosPq.gen_recipient_keypair("DryRunOther")
+
+      assert {:ok, {:valid, _}} = ExTholosPq.validate_wire(ctx.ciphertext, [ctx.sender_pub])
+
+      assert {:error, :no_envelope_for_recipient} =
+               ExTholosPq.decrypt_verify_only(ctx.ciphertext, other_kid, [ctx.sender_pub])
+    end
+  end
+
+  describe "crypto_info/0" do
+    test "describes default keys and the wire they produce" do
+      {:ok, info} = ExTholosPq.crypto_info()
+      assert %{kem: "ML-KEM-1024", sig: "Dilithium3", tholos_pq_version: version} = info
+      assert version =~ ~r/^\d+\.\d+\.\d+/
+
+      {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("InfoRcpt")
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("InfoSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("info", sid, [pub])
+      assert {:ok, info.wire_version} == ExTholosPq.wire_version(ciphertext)
+    end
+  end
+
+  describe "capabilities/0 and choose_suite/1" do
+    test "a build agrees with itself on its strongest suite" do
+      {:ok, capabilities} = ExTholosPq.capabilities()
+      assert {:ok, suite} = ExTholosPq.choose_suite(capabilities)
+
+      {:ok, info} = ExTholosPq.crypto_info()
+      assert suite == %{
+               wire_version: info.wire_version,
+               aead: :xchacha20poly1305,
+               security_level: :level5
+             }
+    end
+
+    test "the strongest common entry wins, whatever the peer's order" do
+      peer = %{
+        wire_versions: [2, 1],
+        aeads: [:aes256gcm, :xchacha20poly1305, :aes128gcm],
+        security_levels: [:level1, :level3]
+      }
+
+      assert {:ok, %{wire_version: 1, aead: :xchacha20poly1305, security_level: :level3}} =
+               ExTholosPq.choose_suite(peer)
+    end
+
+    test "the chosen suite encrypts and decrypts" do
+      peer = %{wire_versions: [1], aeads: [:aes256gcm], security_levels: [:level3]}
+      {:ok, suite} = ExTholosPq.choose_suite(peer)
+
+      {:ok, {kid, pub}} =
+        ExTholosPq.gen_recipient_keypair("SuiteRecipient", security_level: suite.security_level)
+
+      {:ok, {sid, sender_pub}} =
+        ExTholosPq.gen_sender_keypair("SuiteSender", security_level: suite.security_level)
+
+      {:ok, wire} = ExTholosPq.encrypt("agreed", sid, [pub], aead: suite.aead)
+      assert {:ok, suite.wire_version} == ExTholosPq.wire_version(wire)
+      assert {:ok, "agreed"} = ExTholosPq.decrypt(wire, kid, [sender_pub])
+    end
+
+    test "disjoint capabilities have no common suite" do
+      {:ok, capabilities} = ExTholosPq.capabilities()
+
+      for {key, unknown} <- [wire_versions: [99], aeads: [:rot13], security_levels: []] do
+        assert {:error, :no_common_suite} =
+                 ExTholosPq.choose_suite(Map.put(capabilities, key, unknown))
+      end
+    end
+  end
+
+  describe "self_test/0" do
+    test "passes on this build" do
+      assert :ok = ExTholosPq.self_test()
+    end
+  end
+
+  describe "benchmark/4" do
+    test "reports positive throughput and ordered percentiles" do
+      for op <- [:encrypt, :decrypt] do
+        assert {:ok, %{ops_per_sec: ops, p50_us: p50, p99_us: p99}} =
+                 ExTholosPq.benchmark(op, 256, 2, 5)
+
+        assert is_float(ops) and ops > 0
+        assert p50 > 0 and p50 <= p99
+      end
+    end
+
+    test "touches no stored keys" do
+      {:ok, _} = ExTholosPq.benchmark(:encrypt, 0, 1, 1)
+      assert {:ok, %{recipients: 0, senders: 0}} = ExTholosPq.key_store_stats()
+    end
+
+    test "rejects arguments out of range" do
+      assert {:error, {:invalid_option, "op"}} = ExTholosPq.benchmark(:sign, 16, 1, 1)
+      assert {:error, {:invalid_option, "recipients"}} = ExTholosPq.benchmark(:encrypt, 16, 0, 1)
+      assert {:error, {:invalid_option, "iterations"}} = ExTholosPq.benchmark(:encrypt, 16, 1, 0)
+
+      assert {:error, {:invalid_option, "payload_size"}} =
+               ExTholosPq.benchmark(:encrypt, 65 * 1024 * 1024, 1, 1)
+    end
+
+    test "rejects runs over the work limit" do
+      assert {:error, {:invalid_option, "iterations"}} =
+               ExTholosPq.benchmark(:encrypt, 64 * 1024 * 1024, 1, 257)
+
+      assert {:error, {:invalid_option, "iterations"}} =
+               ExTholosPq.benchmark(:decrypt, 0, 256, 1_000_000)
+
+      assert {:error, {:invalid_option, "iterations"}} =
+               ExTholosPq.benchmark(:decrypt, 0, 1, 32_769)
+    end
+  end
+
+  describe "estimate_wire_size/2,3" do
+    test "is a tight upper bound on the encrypt output" do
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("EstimateSender")
+
+      for count <- [1, 2, 5, 20], len <- [0, 100, 10_000] do
+        pubs =
+          for i <- 1..count do
+            kid = "EstimateRcpt" <> String.pad_leading("#{i}", 2, "0")
+            {:ok, {^kid, pub}} = ExTholosPq.gen_recipient_keypair(kid, overwrite: true)
+            pub
+          end
+
+        {:ok, ciphertext} = ExTholosPq.encrypt(:crypto.strong_rand_bytes(len), sid, pubs)
+        {:ok, bound} = ExTholosPq.estimate_wire_size(len, count, max_id_length: 14)
+
+        assert byte_size(ciphertext) <= bound
+        # Only the timestamp is counted wider than it is encoded
+        assert bound - byte_size(ciphertext) <= 8
+      end
+    end
+
+    test "counts an embedded sender key" do
+      {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("EstimateRcpt01")
+      {:ok, {sid, _sender_pub}} = ExTholosPq.gen_sender_keypair("EstimateSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("embedded", sid, [pub], embed_sender_key: true)
+
+      {:ok, bound} =
+        ExTholosPq.estimate_wire_size(8, 1, max_id_length: 14, embed_sender_key: true)
+
+      assert byte_size(ciphertext) <= bound
+      assert bound - byte_size(ciphertext) <= 8
+    end
+
+    test "defaults to identifiers of up to 64 bytes" do
+      assert {:ok, bound} = ExTholosPq.estimate_wire_size(100, 3)
+      assert {:ok, ^bound} = ExTholosPq.estimate_wire_size(100, 3, max_id_length: 64)
+      assert {:error, {:invalid_option, "level"}} =
+               ExTholosPq.estimate_wire_size(100, 3, level: 1)
+    end
+  end
+
+  describe "resign_wire/3" do
+    setup do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("ResignRecipient")
+      {:ok, {old_sid, old_pub}} = ExTholosPq.gen_sender_keypair("ResignOldSender")
+      {:ok, {new_sid, new_pub}} = ExTholosPq.gen_sender_keypair("ResignNewSender")
+      {:ok, ciphertext} = ExTholosPq.encrypt("re-signed", old_sid, [recipient_pub])
+
+      %{
+        kid: kid,
+        old_sid: old_sid,
+        old_pub: old_pub,
+        new_sid: new_sid,
+        new_pub: new_pub,
+        ciphertext: ciphertext
+      }
+    end
+
+    test "verifies against the new sender and no longer against the old one", ctx do
+      assert {:ok, resigned} = ExTholosPq.resign_wire(ctx.ciphertext, ctx.old_sid, ctx.new_sid)
+
+      assert {:ok, {:valid, "ResignNewSender"}} =
+               ExTholosPq.validate_wire(resigned, [ctx.new_pub])
+      assert {:ok, :invalid_signature} = ExTholosPq.validate_wire(resigned, [ctx.old_pub])
+
+      assert {:ok, {"re-signed", "ResignNewSender"}} =
+               ExTholosPq.decrypt_with_sender(resigned, ctx.kid, [ctx.new_pub])
+
+      assert {:error, :signature_invalid} = ExTholosPq.decrypt(resigned, ctx.kid, [ctx.old_pub])
+      assert {:ok, "ResignNewSender"} = ExTholosPq.wire_sender_unverified(resigned)
+    end
+
+    test "requires the wire to be signed by the old sender", ctx do
+      {:ok, resigned} = ExTholosPq.resign_wire(ctx.ciphertext, ctx.old_sid, ctx.new_sid)
+
+      assert {:error, :signature_invalid} =
+               ExTholosPq.resign_wire(resigned, ctx.old_sid, ctx.new_sid)
+
+      assert {:error, {:sender_not_found, "NoSuchSender"}} =
+               ExTholosPq.resign_wire(ctx.ciphertext, ctx.old_sid, "NoSuchSender")
+    end
+  end
+
+  describe "dirty scheduling" do
+    @tag timeout: 180_000
+    test "large multi-recipient encryption does not block other processes" do
+      recipient_pubs =
+        for i <- 1..50 do
+          {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("dirty_recipient_#{i}")
+          pub
+        end
+
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("dirty_sender")
+      message = :crypto.strong_rand_bytes(10 * 1024 * 1024)
+
+      echo = spawn_link(fn -> echo_loop() end)
+      task = Task.async(fn -> ExTholosPq.encrypt(message, sid, recipient_pubs) end)
+
+      {result, latencies} = ping_while_running(task, echo, [])
+
+      assert {:ok, ciphertext} = result
+      assert latencies != []
+      assert Enum.max(latencies) < 100
+
+      {:ok, plaintext} = ExTholosPq.decrypt(ciphertext, "dirty_recipient_50", [sender_pub])
+      assert plaintext == message
+    end
+  end
+
+  describe "output binaries" do
+    test "resource-backed output matches copied output" do
+      assert ExTholosPq.output_binary(1024, false) == ExTholosPq.output_binary(1024, true)
+      assert ExTholosPq.output_binary(0, false) == ""
+    end
+
+    test "ciphertexts outlive the process that produced them" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("OutputRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("OutputSender")
+      message = :crypto.strong_rand_bytes(5 * 1024 * 1024)
+
+      ciphertext =
+        Task.async(fn ->
+          {:ok, ciphertext} = ExTholosPq.encrypt(message, sid, [recipient_pub])
+          ciphertext
+        end)
+        |> Task.await(60_000)
+
+      :erlang.garbage_collect()
+
+      assert {:ok, ^message} = ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+    end
+  end
+
+  describe "concurrent key store access" do
+    @tag timeout: 180_000
+    test "encrypts from many processes in parallel" do
+      recipient_pubs =
+        for i <- 1..8 do
+          {:ok, {_kid, pub}} = ExTholosPq.gen_recipient_keypair("parallel_recipient_#{i}")
+          pub
+        end
+
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("parallel_sender")
+      message = :crypto.strong_rand_bytes(1024 * 1024)
+      encrypt = fn -> ExTholosPq.encrypt(message, sid, recipient_pubs) end
+      calls = 16
+
+      {sequential_us, _} = :timer.tc(fn -> for _ <- 1..calls, do: encrypt.() end)
+
+      {concurrent_us, results} =
+        :timer.tc(fn ->
+          1..calls
+          |> Enum.map(fn _ -> Task.async(encrypt) end)
+          |> Task.await_many(120_000)
+        end)
+
+      for result <- results do
+        assert {:ok, ciphertext} = result
+
+        assert {:ok, ^message} =
+                 ExTholosPq.decrypt(ciphertext, "parallel_recipient_1", [sender_pub])
+      end
+
+      # Readers share the store lock, so with more than one dirty scheduler the
+      # calls overlap instead of queueing behind each other
+      if :erlang.system_info(:dirty_cpu_schedulers_online) > 1 do
+        assert concurrent_us < sequential_us * 0.8
+      end
+    end
+
+    test "keygen and delete interleave with readers" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("interleaved_recipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("interleaved_sender")
+
+      readers =
+        for _ <- 1..20 do
+          Task.async(fn ->
+            {:ok, ciphertext} = ExTholosPq.encrypt("shared", sid, [recipient_pub])
+            ExTholosPq.decrypt(ciphertext, kid, [sender_pub])
+          end)
+        end
+
+      writers =
+        for i <- 1..20 do
+          Task.async(fn ->
+            {:ok, {churn_kid, _pub}} = ExTholosPq.gen_recipient_keypair("churn_#{i}")
+            ExTholosPq.delete_recipient_key(churn_kid)
+          end)
+        end
+
+      assert Enum.all?(Task.await_many(readers, 60_000), &match?({:ok, "shared"}, &1))
+      assert Enum.all?(Task.await_many(writers, 60_000), &(&1 == :ok))
+    end
+  end
+
+  describe "error paths" do
+    @tag timeout: 180_000
+    test "failing thousands of times leaks no binaries and releases every lock" do
+      {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("SoakRecipient")
+      {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("SoakSender")
+      message = :crypto.strong_rand_bytes(64 * 1024)
+      {:ok, ciphertext} = ExTholosPq.encrypt(message, sid, [recipient_pub])
+      size = byte_size(ciphertext)
+      <<head::binary-size(size - 1), last>> = ciphertext
+      tampered = <<head::binary, Bitwise.bxor(last, 1)>>
+
+      fail = fn ->
+        {:error, {:sender_not_found, _}} =
+          ExTholosPq.encrypt(message, "NoSuchSender", [recipient_pub])
+
+        {:error, {:deserialize_recipient, _}} = ExTholosPq.encrypt(message, sid, ["not cbor"])
+        {:error, _reason} = ExTholosPq.decrypt(tampered, kid, [sender_pub])
+      end
+
+      for _ <- 1..100, do: fail.()
+      before = binary_memory()
+
+      1..4
+      |> Enum.map(fn _ -> Task.async(fn -> for _ <- 1..1_250, do: fail.() end) end)
+      |> Task.await_many(120_000)
+
+      assert binary_memory() - before < 1024 * 1024
+
+      # Every store lock taken by a failed call was given back, or these would block
+      assert {:ok, _} = ExTholosPq.gen_sender_keypair("SoakSender2")
+      assert :ok = ExTholosPq.delete_recipient_key(kid)
+      assert {:ok, {^kid, _pub}} = ExTholosPq.gen_recipient_keypair(kid)
+    end
+  end
+
+  defp sender_and_recipient(_ctx) do
+    {:ok, {kid, recipient_pub}} = ExTholosPq.gen_recipient_keypair("Recipient")
+    {:ok, {sid, sender_pub}} = ExTholosPq.gen_sender_keypair("Sender")
+    %{kid: kid, recipient_pub: recipient_pub, sid: sid, sender_pub: sender_pub}
+  end
+
+  defp unframe_all(""), do: []
+
+  defp unframe_all(buffer) do
+    {:ok, {wire, rest}} = ExTholosPq.unframe(buffer)
+    [wire | unframe_all(rest)]
+  end
+
+  defp stream_encrypt(message, piece_size, sid, recipient_pubs) do
+    {:ok, enc} = ExTholosPq.encrypt_init(sid, recipient_pubs)
+
+    parts =
+      for piece <- pieces(message, piece_size) do
+        {:ok, part} = ExTholosPq.encrypt_update(enc, piece)
+        part
+      end
+
+    {:ok, tail} = ExTholosPq.encrypt_final(enc)
+    IO.iodata_to_binary([parts, tail])
+  end
+
+  defp stream_decrypt(ciphertext, piece_size, kid, allowed) do
+    {:ok, dec} = ExTholosPq.decrypt_init(kid, allowed)
+
+    parts =
+      for piece <- pieces(ciphertext, piece_size) do
+        {:ok, part} = ExTholosPq.decrypt_update(dec, piece)
+        part
+      end
+
+    {:ok, tail} = ExTholosPq.decrypt_final(dec)
+    IO.iodata_to_binary([parts, tail])
+  end
+
+  defp file_hash(path) do
+    path
+    |> File.stream!(1024 * 1024)
+    |> Enum.reduce(:crypto.hash_init(:sha256), &:crypto.hash_update(&2, &1))
+    |> :crypto.hash_final()
+  end
+
+  defp pieces(<<>>, _size), do: []
+
+  defp pieces(binary, size) when byte_size(binary) <= size, do: [binary]
+
+  defp pieces(binary, size) do
+    <<piece::binary-size(size), rest::binary>> = binary
+    [piece | pieces(rest, size)]
+  end
+
+  defp echo_loop do
+    receive do
+      {:ping, from} ->
+        send(from, :pong)
+        echo_loop()
+    end
+  end
+
+  # Round-trips a message through `echo` until `task` finishes, collecting the
+  # latency of each round trip in milliseconds.
+  defp ping_while_running(task, echo, latencies) do
+    case Task.yield(task, 0) do
+      nil ->
+        started = System.monotonic_time(:millisecond)
+        send(echo, {:ping, self()})
+
+        receive do
+          :pong -> :ok
+        end
+
+        elapsed = System.monotonic_time(:millisecond) - started
+        ping_while_running(task, echo, [elapsed | latencies])
+
+      {:ok, result} ->
+        {result, latencies}
+    end
+  end
+
+  defp chunk_at(message, index, chunk_size) do
+    binary_part(message, index * chunk_size, chunk_size)
+  end
+
+  defp binary_memory do
+    Enum.each(Process.list(), &:erlang.garbage_collect/1)
+    :erlang.memory(:binary)
   end
 end